@@ -0,0 +1,168 @@
+use std::error::Error;
+
+use alaric_lib::database::{
+    ClientGroupAddOutcome, ClientGroupRemoveOutcome, Database, GroupCreateOutcome,
+    GroupDeleteOutcome,
+};
+use clap::{Args, Subcommand};
+
+#[derive(Args, Debug)]
+pub(super) struct ClientGroupCommand {
+    #[command(subcommand)]
+    command: ClientGroupSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ClientGroupSubcommand {
+    Create(CreateCommand),
+    Add(MemberCommand),
+    Remove(MemberCommand),
+    Delete(DeleteCommand),
+    List,
+}
+
+#[derive(Args, Debug)]
+struct CreateCommand {
+    group_id: String,
+
+    #[arg(long = "display-name")]
+    display_name: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct MemberCommand {
+    group_id: String,
+    client_id: String,
+}
+
+#[derive(Args, Debug)]
+struct DeleteCommand {
+    group_id: String,
+}
+
+pub(super) async fn run(
+    database: &Database,
+    command: ClientGroupCommand,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match command.command {
+        ClientGroupSubcommand::Create(command) => {
+            let outcome = database
+                .admin_create_client_group(&command.group_id, command.display_name.as_deref())
+                .await?;
+
+            match outcome {
+                GroupCreateOutcome::Created => {
+                    println!("client group '{}' created", command.group_id);
+                }
+                GroupCreateOutcome::AlreadyExists => {
+                    println!(
+                        "client group '{}' already exists, no action performed",
+                        command.group_id
+                    );
+                }
+            }
+        }
+        ClientGroupSubcommand::Add(command) => {
+            let outcome = database
+                .admin_add_client_to_group(&command.group_id, &command.client_id)
+                .await?;
+
+            match outcome {
+                ClientGroupAddOutcome::Added => {
+                    println!(
+                        "client '{}' added to '{}'",
+                        command.client_id, command.group_id
+                    );
+                }
+                ClientGroupAddOutcome::AlreadyMember => {
+                    println!(
+                        "client '{}' already in '{}', no action performed",
+                        command.client_id, command.group_id
+                    );
+                }
+                ClientGroupAddOutcome::GroupNotFound => {
+                    println!("client group '{}' not found", command.group_id);
+                }
+                ClientGroupAddOutcome::ClientNotFound => {
+                    println!("client '{}' not found or disabled", command.client_id);
+                }
+            }
+        }
+        ClientGroupSubcommand::Remove(command) => {
+            let outcome = database
+                .admin_remove_client_from_group(&command.group_id, &command.client_id)
+                .await?;
+
+            match outcome {
+                ClientGroupRemoveOutcome::Removed => {
+                    println!(
+                        "client '{}' removed from '{}'",
+                        command.client_id, command.group_id
+                    );
+                }
+                ClientGroupRemoveOutcome::NotMember => {
+                    println!(
+                        "client '{}' not in '{}', no action performed",
+                        command.client_id, command.group_id
+                    );
+                }
+                ClientGroupRemoveOutcome::GroupNotFound => {
+                    println!("client group '{}' not found", command.group_id);
+                }
+                ClientGroupRemoveOutcome::ClientNotFound => {
+                    println!("client '{}' not found", command.client_id);
+                }
+            }
+        }
+        ClientGroupSubcommand::Delete(command) => {
+            let outcome = database
+                .admin_delete_client_group(&command.group_id)
+                .await?;
+            match outcome {
+                GroupDeleteOutcome::Deleted => {
+                    println!("client group '{}' deleted", command.group_id);
+                }
+                GroupDeleteOutcome::NotFound => {
+                    println!("client group '{}' not found", command.group_id);
+                }
+            }
+        }
+        ClientGroupSubcommand::List => {
+            let groups = database.admin_list_client_groups().await?;
+            if groups.is_empty() {
+                println!("no client groups found");
+                return Ok(());
+            }
+
+            println!("group_id\tmembers\tdisplay_name\tcreated_at");
+            for group in groups {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    group.external_id,
+                    group.member_client_ids.join(","),
+                    group.display_name.unwrap_or_default(),
+                    group.created_at.to_rfc3339(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::Cli;
+
+    #[test]
+    fn parses_client_group_add() {
+        let cli = Cli::try_parse_from(["aadmin", "client-group", "add", "operators", "client-a"])
+            .expect("client-group add should parse");
+        assert!(matches!(
+            cli.command,
+            crate::Command::ClientGroup(super::ClientGroupCommand { .. })
+        ));
+    }
+}
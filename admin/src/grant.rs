@@ -0,0 +1,242 @@
+use std::error::Error;
+
+use alaric_lib::database::{
+    Database, GrantRevokeOutcome, GrantSetOutcome, GrantSubject, PolicyTarget,
+};
+use clap::{Args, Subcommand};
+
+#[derive(Args, Debug)]
+pub(super) struct GrantCommand {
+    #[command(subcommand)]
+    command: GrantSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum GrantSubcommand {
+    Set(SetCommand),
+    Revoke(RevokeCommand),
+    List,
+}
+
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct SubjectArgs {
+    #[arg(long = "client")]
+    client_id: Option<String>,
+
+    #[arg(long = "client-group")]
+    client_group_id: Option<String>,
+}
+
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct TargetArgs {
+    #[arg(long = "agent")]
+    agent_id: Option<String>,
+
+    #[arg(long = "agent-group")]
+    agent_group_id: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct SetCommand {
+    #[command(flatten)]
+    subject: SubjectArgs,
+
+    #[command(flatten)]
+    target: TargetArgs,
+
+    // Without any `--command` the grant covers every command and file transfers.
+    #[arg(long = "command")]
+    command_ids: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct RevokeCommand {
+    #[command(flatten)]
+    subject: SubjectArgs,
+
+    #[command(flatten)]
+    target: TargetArgs,
+}
+
+pub(super) async fn run(
+    database: &Database,
+    command: GrantCommand,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match command.command {
+        GrantSubcommand::Set(command) => {
+            let (subject_kind, subject_id, subject) = command.subject.resolve();
+            let (target_kind, target_id, target) = command.target.resolve();
+            let outcome = database
+                .admin_set_client_grant(subject, target, &command.command_ids)
+                .await?;
+
+            let commands = describe_commands(&command.command_ids);
+            match outcome {
+                GrantSetOutcome::Granted => {
+                    println!(
+                        "{} '{}' granted {} on {} '{}'",
+                        subject_kind, subject_id, commands, target_kind, target_id
+                    );
+                }
+                GrantSetOutcome::Replaced => {
+                    println!(
+                        "grant for {} '{}' on {} '{}' replaced with {}",
+                        subject_kind, subject_id, target_kind, target_id, commands
+                    );
+                }
+                GrantSetOutcome::SubjectNotFound => {
+                    println!("{} '{}' not found", subject_kind, subject_id);
+                }
+                GrantSetOutcome::TargetNotFound => {
+                    println!("{} '{}' not found", target_kind, target_id);
+                }
+            }
+        }
+        GrantSubcommand::Revoke(command) => {
+            let (subject_kind, subject_id, subject) = command.subject.resolve();
+            let (target_kind, target_id, target) = command.target.resolve();
+            let outcome = database.admin_revoke_client_grant(subject, target).await?;
+
+            match outcome {
+                GrantRevokeOutcome::Revoked => {
+                    println!(
+                        "grant for {} '{}' on {} '{}' revoked",
+                        subject_kind, subject_id, target_kind, target_id
+                    );
+                }
+                GrantRevokeOutcome::NotGranted => {
+                    println!(
+                        "no grant for {} '{}' on {} '{}', no action performed",
+                        subject_kind, subject_id, target_kind, target_id
+                    );
+                }
+                GrantRevokeOutcome::SubjectNotFound => {
+                    println!("{} '{}' not found", subject_kind, subject_id);
+                }
+                GrantRevokeOutcome::TargetNotFound => {
+                    println!("{} '{}' not found", target_kind, target_id);
+                }
+            }
+        }
+        GrantSubcommand::List => {
+            let grants = database.admin_list_client_grants().await?;
+            if grants.is_empty() {
+                println!("no grants found");
+                return Ok(());
+            }
+
+            println!("subject\ttarget\tcommands\tupdated_at");
+            for grant in grants {
+                let subject = match (grant.client_id, grant.client_group_id) {
+                    (Some(client_id), _) => format!("client:{}", client_id),
+                    (None, group_id) => format!("client-group:{}", group_id.unwrap_or_default()),
+                };
+                let target = match (grant.agent_id, grant.agent_group_id) {
+                    (Some(agent_id), _) => format!("agent:{}", agent_id),
+                    (None, group_id) => format!("agent-group:{}", group_id.unwrap_or_default()),
+                };
+                let commands = if grant.command_ids.is_empty() {
+                    "*".to_string()
+                } else {
+                    grant.command_ids.join(",")
+                };
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    subject,
+                    target,
+                    commands,
+                    grant.updated_at.to_rfc3339(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl SubjectArgs {
+    // clap guarantees exactly one of the two is set.
+    fn resolve(&self) -> (&'static str, &str, GrantSubject<'_>) {
+        match &self.client_id {
+            Some(client_id) => ("client", client_id, GrantSubject::Client(client_id)),
+            None => {
+                let group_id = self.client_group_id.as_deref().unwrap_or_default();
+                (
+                    "client group",
+                    group_id,
+                    GrantSubject::ClientGroup(group_id),
+                )
+            }
+        }
+    }
+}
+
+impl TargetArgs {
+    // clap guarantees exactly one of the two is set.
+    fn resolve(&self) -> (&'static str, &str, PolicyTarget<'_>) {
+        match &self.agent_id {
+            Some(agent_id) => ("agent", agent_id, PolicyTarget::Agent(agent_id)),
+            None => {
+                let group_id = self.agent_group_id.as_deref().unwrap_or_default();
+                ("group", group_id, PolicyTarget::Group(group_id))
+            }
+        }
+    }
+}
+
+fn describe_commands(command_ids: &[String]) -> String {
+    if command_ids.is_empty() {
+        "all commands".to_string()
+    } else {
+        format!("commands [{}]", command_ids.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::Cli;
+
+    #[test]
+    fn parses_grant_set_with_commands() {
+        let cli = Cli::try_parse_from([
+            "aadmin",
+            "grant",
+            "set",
+            "--client-group",
+            "operators",
+            "--agent-group",
+            "ca-west-prod01",
+            "--command",
+            "uptime",
+            "--command",
+            "restart_nginx",
+        ])
+        .expect("grant set should parse");
+        assert!(matches!(
+            cli.command,
+            crate::Command::Grant(super::GrantCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_grant_with_two_subjects() {
+        assert!(
+            Cli::try_parse_from([
+                "aadmin",
+                "grant",
+                "revoke",
+                "--client",
+                "client-a",
+                "--client-group",
+                "operators",
+                "--agent",
+                "agent-a",
+            ])
+            .is_err()
+        );
+    }
+}
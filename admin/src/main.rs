@@ -3,8 +3,11 @@ use std::{env, error::Error, io, time::Duration};
 
 use alaric_lib::database::{Database, DatabaseConfig};
 
+mod client_group;
+mod grant;
 mod group;
 mod key;
+mod policy;
 mod principal;
 
 #[derive(Parser, Debug)]
@@ -22,17 +25,30 @@ enum Command {
     Key(key::KeyCommand),
     #[command(arg_required_else_help = true)]
     Group(group::GroupCommand),
+    #[command(arg_required_else_help = true)]
+    Policy(policy::PolicyCommand),
+    #[command(arg_required_else_help = true)]
+    ClientGroup(client_group::ClientGroupCommand),
+    #[command(arg_required_else_help = true)]
+    Grant(grant::GrantCommand),
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let cli = Cli::parse();
+    // Policy commands only connect to the database when publishing.
+    if let Command::Policy(command) = cli.command {
+        return policy::run(command).await;
+    }
     let database = connect_env().await?;
 
     match cli.command {
         Command::Principal(command) => principal::run(&database, command).await?,
         Command::Key(command) => key::run(&database, command).await?,
         Command::Group(command) => group::run(&database, command).await?,
+        Command::ClientGroup(command) => client_group::run(&database, command).await?,
+        Command::Grant(command) => grant::run(&database, command).await?,
+        Command::Policy(_) => {}
     }
 
     database.close().await;
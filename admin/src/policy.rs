@@ -0,0 +1,675 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use alaric_agent::policy::{Policy, SignedPolicyBundle, TrustedPolicyKeys};
+use alaric_lib::database::{PolicyPublishOutcome, PolicyTarget, PolicyUnpublishOutcome};
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::connect_env;
+
+const SIGNING_PRIVATE_KEY_ENV: &str = "POLICY_SIGNING_PRIVATE_KEY";
+const DEFAULT_EXPIRES_IN_SECS: u64 = 60 * 60 * 24 * 30;
+
+#[derive(Args, Debug)]
+pub(super) struct PolicyCommand {
+    #[command(subcommand)]
+    command: PolicySubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum PolicySubcommand {
+    Sign(SignCommand),
+    Verify(VerifyCommand),
+    Show(ShowCommand),
+    Diff(DiffCommand),
+    Publish(PublishCommand),
+    Unpublish(UnpublishCommand),
+}
+
+#[derive(Args, Debug)]
+struct SignCommand {
+    policy_path: PathBuf,
+
+    #[arg(long = "key-id")]
+    key_id: String,
+
+    #[arg(
+        long = "expires-at",
+        value_name = "UNIX_SECS",
+        conflicts_with = "expires_in"
+    )]
+    expires_at: Option<u64>,
+
+    #[arg(long = "expires-in", value_name = "SECS")]
+    expires_in: Option<u64>,
+
+    // Defaults to the signing time, which keeps serials increasing across signers.
+    #[arg(long = "serial")]
+    serial: Option<u64>,
+
+    #[arg(long = "output", short = 'o')]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct VerifyCommand {
+    bundle_path: PathBuf,
+
+    #[arg(long = "keys")]
+    keys_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct ShowCommand {
+    path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct DiffCommand {
+    old_path: PathBuf,
+    new_path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct TargetArgs {
+    #[arg(long = "agent")]
+    agent_id: Option<String>,
+
+    #[arg(long = "group")]
+    group_id: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct PublishCommand {
+    bundle_path: PathBuf,
+
+    #[command(flatten)]
+    target: TargetArgs,
+
+    #[arg(long = "keys")]
+    keys_path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct UnpublishCommand {
+    #[command(flatten)]
+    target: TargetArgs,
+}
+
+// A policy file is either a signed bundle or the bare policy it wraps.
+struct PolicyFile {
+    policy: Policy,
+    bundle: Option<SignedPolicyBundle>,
+}
+
+pub(super) async fn run(command: PolicyCommand) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match command.command {
+        PolicySubcommand::Sign(command) => {
+            let secret_key = env::var(SIGNING_PRIVATE_KEY_ENV)
+                .map_err(|_| format!("{} must be set to sign policies", SIGNING_PRIVATE_KEY_ENV))?;
+            let expires_at_unix = match command.expires_at {
+                Some(expires_at) => expires_at,
+                None => current_unix_timestamp()?
+                    .saturating_add(command.expires_in.unwrap_or(DEFAULT_EXPIRES_IN_SECS)),
+            };
+
+            let serial = match command.serial {
+                Some(serial) => serial,
+                None => current_unix_timestamp()?,
+            };
+
+            let policy = read_policy_file(&command.policy_path)?.policy;
+            let bundle = SignedPolicyBundle::sign(
+                policy,
+                &command.key_id,
+                &secret_key,
+                expires_at_unix,
+                serial,
+            )?;
+            let encoded = serde_json::to_string_pretty(&bundle)?;
+            match &command.output {
+                Some(output) => {
+                    fs::write(output, format!("{}\n", encoded))?;
+                    println!(
+                        "signed policy bundle written to '{}' (key_id={}, serial={}, expires_at_unix={})",
+                        output.display(),
+                        command.key_id,
+                        serial,
+                        expires_at_unix
+                    );
+                }
+                None => println!("{}", encoded),
+            }
+        }
+        PolicySubcommand::Verify(command) => {
+            let bundle = load_verified_bundle(&command.bundle_path, command.keys_path.as_deref())?;
+            println!(
+                "policy bundle '{}' is valid (key_id={}, serial={}, expires_at_unix={}, {} commands)",
+                command.bundle_path.display(),
+                bundle.signature.key_id,
+                bundle.serial,
+                bundle.expires_at_unix,
+                bundle.policy.commands.len()
+            );
+        }
+        PolicySubcommand::Show(command) => {
+            let file = read_policy_file(&command.path)?;
+            for line in describe_policy_file(&file) {
+                println!("{}", line);
+            }
+        }
+        PolicySubcommand::Diff(command) => {
+            let old = read_policy_file(&command.old_path)?;
+            let new = read_policy_file(&command.new_path)?;
+            let mut lines = Vec::new();
+            if let (Some(old_bundle), Some(new_bundle)) = (&old.bundle, &new.bundle) {
+                diff_field(
+                    "bundle",
+                    "expires_at_unix",
+                    Some(&Value::from(old_bundle.expires_at_unix)),
+                    Some(&Value::from(new_bundle.expires_at_unix)),
+                    &mut lines,
+                );
+                diff_field(
+                    "bundle",
+                    "serial",
+                    Some(&Value::from(old_bundle.serial)),
+                    Some(&Value::from(new_bundle.serial)),
+                    &mut lines,
+                );
+                diff_field(
+                    "bundle",
+                    "key_id",
+                    Some(&Value::from(old_bundle.signature.key_id.as_str())),
+                    Some(&Value::from(new_bundle.signature.key_id.as_str())),
+                    &mut lines,
+                );
+            }
+            lines.extend(diff_policies(&old.policy, &new.policy));
+
+            if lines.is_empty() {
+                println!("no differences");
+            }
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        PolicySubcommand::Publish(command) => {
+            // Agents verify again on receipt; checking here keeps bad bundles out of the store.
+            load_verified_bundle(&command.bundle_path, command.keys_path.as_deref())?;
+            let bundle = fs::read_to_string(&command.bundle_path)?;
+            let (kind, target_id, target) = command.target.resolve();
+            let database = connect_env().await?;
+            let outcome = database.admin_publish_policy_bundle(target, &bundle).await;
+            database.close().await;
+
+            match outcome? {
+                PolicyPublishOutcome::Published => {
+                    println!("policy bundle published to {} '{}'", kind, target_id);
+                }
+                PolicyPublishOutcome::Replaced => {
+                    println!("policy bundle for {} '{}' replaced", kind, target_id);
+                }
+                PolicyPublishOutcome::TargetNotFound => {
+                    println!("{} '{}' not found", kind, target_id);
+                }
+            }
+        }
+        PolicySubcommand::Unpublish(command) => {
+            let (kind, target_id, target) = command.target.resolve();
+            let database = connect_env().await?;
+            let outcome = database.admin_unpublish_policy_bundle(target).await;
+            database.close().await;
+
+            match outcome? {
+                PolicyUnpublishOutcome::Removed => {
+                    println!("policy bundle for {} '{}' removed", kind, target_id);
+                }
+                PolicyUnpublishOutcome::NotPublished => {
+                    println!(
+                        "no policy bundle published to {} '{}', no action performed",
+                        kind, target_id
+                    );
+                }
+                PolicyUnpublishOutcome::TargetNotFound => {
+                    println!("{} '{}' not found", kind, target_id);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl TargetArgs {
+    // clap guarantees exactly one of the two is set.
+    fn resolve(&self) -> (&'static str, &str, PolicyTarget<'_>) {
+        match &self.agent_id {
+            Some(agent_id) => ("agent", agent_id, PolicyTarget::Agent(agent_id)),
+            None => {
+                let group_id = self.group_id.as_deref().unwrap_or_default();
+                ("group", group_id, PolicyTarget::Group(group_id))
+            }
+        }
+    }
+}
+
+fn load_verified_bundle(
+    path: &Path,
+    keys_path: Option<&Path>,
+) -> Result<SignedPolicyBundle, Box<dyn Error + Send + Sync>> {
+    let trusted_keys = match keys_path {
+        Some(path) => TrustedPolicyKeys::load_from_path(path)?,
+        None => TrustedPolicyKeys::load_default()?,
+    };
+    let bundle = SignedPolicyBundle::load(path)
+        .and_then(|bundle| bundle.verify(&trusted_keys).map(|()| bundle))
+        .map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "policy bundle '{}' would be rejected by agents: {}",
+                    path.display(),
+                    err
+                ),
+            )
+        })?;
+    Ok(bundle)
+}
+
+fn read_policy_file(path: &Path) -> Result<PolicyFile, Box<dyn Error + Send + Sync>> {
+    let raw = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read '{}': {}", path.display(), err))?;
+    if let Ok(bundle) = serde_json::from_str::<SignedPolicyBundle>(&raw) {
+        return Ok(PolicyFile {
+            policy: bundle.policy.clone(),
+            bundle: Some(bundle),
+        });
+    }
+
+    let policy = serde_json::from_str::<Policy>(&raw).map_err(|err| {
+        format!(
+            "'{}' is neither a signed policy bundle nor a policy: {}",
+            path.display(),
+            err
+        )
+    })?;
+    Ok(PolicyFile {
+        policy,
+        bundle: None,
+    })
+}
+
+fn describe_policy_file(file: &PolicyFile) -> Vec<String> {
+    let policy = &file.policy;
+    let mut lines = Vec::new();
+    match &file.bundle {
+        Some(bundle) => lines.push(format!(
+            "signed bundle: version={}, key_id={}, algorithm={}, serial={}, expires_at_unix={}",
+            bundle.bundle_version,
+            bundle.signature.key_id,
+            bundle.signature.algorithm,
+            bundle.serial,
+            bundle.expires_at_unix
+        )),
+        None => lines.push("unsigned policy".to_string()),
+    }
+    lines.push(format!(
+        "policy version={}, default_timeout_secs={}, max_output_bytes={}, max_input_bytes={}",
+        policy.version,
+        policy.default_timeout_secs,
+        policy.max_output_bytes,
+        policy
+            .max_input_bytes
+            .map_or_else(|| "default".to_string(), |bytes| bytes.to_string())
+    ));
+    if let Err(err) = policy.validate() {
+        lines.push(format!("warning: {}", err));
+    }
+
+    lines.push(format!("commands ({}):", policy.commands.len()));
+    for command in &policy.commands {
+        let mut program = command.program.clone();
+        for arg in &command.fixed_args {
+            program.push(' ');
+            program.push_str(arg);
+        }
+        let args = command
+            .arg_specs
+            .iter()
+            .map(|arg| {
+                if arg.required {
+                    arg.name.clone()
+                } else {
+                    format!("{}?", arg.name)
+                }
+            })
+            .collect::<Vec<_>>();
+        lines.push(format!(
+            "  {}: {} [args: {}]",
+            command.id,
+            program,
+            if args.is_empty() {
+                "none".to_string()
+            } else {
+                args.join(", ")
+            }
+        ));
+        if command.drops_privileges() {
+            lines.push(format!(
+                "    runs as user {}, group {}, supplementary groups [{}]",
+                command.run_as_user.as_deref().unwrap_or("-"),
+                command.run_as_group.as_deref().unwrap_or("-"),
+                command.supplementary_groups.join(", ")
+            ));
+        }
+        if !command.limits.is_empty() {
+            lines.push(format!(
+                "    limits: {}",
+                serde_json::to_string(&command.limits).unwrap_or_default()
+            ));
+        }
+        if !command.sandbox.is_empty() {
+            lines.push(format!(
+                "    sandbox: {}",
+                serde_json::to_string(&command.sandbox).unwrap_or_default()
+            ));
+        }
+        if let Some(dir) = &command.working_dir {
+            lines.push(format!("    working dir: {}", dir));
+        }
+        if !command.env.is_empty()
+            || !command.env_passthrough.is_empty()
+            || !command.env_specs.is_empty()
+        {
+            let fixed = command.env.keys().cloned().collect::<Vec<_>>();
+            let client = command
+                .env_specs
+                .iter()
+                .map(|spec| {
+                    if spec.required {
+                        spec.name.clone()
+                    } else {
+                        format!("{}?", spec.name)
+                    }
+                })
+                .collect::<Vec<_>>();
+            lines.push(format!(
+                "    env: fixed [{}], passthrough [{}], client [{}]",
+                fixed.join(", "),
+                command.env_passthrough.join(", "),
+                client.join(", ")
+            ));
+        }
+    }
+
+    if !policy.fetch.is_empty() {
+        lines.push(format!("fetch ({}):", policy.fetch.len()));
+        for rule in &policy.fetch {
+            lines.push(format!("  {} (max {} bytes)", rule.path, rule.max_bytes));
+        }
+    }
+    if !policy.put.is_empty() {
+        lines.push(format!("put ({}):", policy.put.len()));
+        for rule in &policy.put {
+            lines.push(format!(
+                "  {} (max {} bytes, mode {:04o}, owner {}, group {})",
+                rule.directory,
+                rule.max_bytes,
+                rule.effective_mode(),
+                rule.owner.as_deref().unwrap_or("-"),
+                rule.group.as_deref().unwrap_or("-")
+            ));
+        }
+    }
+    lines
+}
+
+fn diff_policies(old: &Policy, new: &Policy) -> Vec<String> {
+    let mut lines = Vec::new();
+    let old_settings = to_object(&PolicySettings::from(old));
+    let new_settings = to_object(&PolicySettings::from(new));
+    diff_objects("policy", &old_settings, &new_settings, &mut lines);
+
+    diff_keyed(
+        "command",
+        &old.commands,
+        &new.commands,
+        |command| command.id.as_str(),
+        &mut lines,
+    );
+    diff_keyed(
+        "fetch",
+        &old.fetch,
+        &new.fetch,
+        |rule| rule.path.as_str(),
+        &mut lines,
+    );
+    diff_keyed(
+        "put",
+        &old.put,
+        &new.put,
+        |rule| rule.directory.as_str(),
+        &mut lines,
+    );
+    lines
+}
+
+#[derive(Serialize)]
+struct PolicySettings {
+    version: u16,
+    default_timeout_secs: u64,
+    max_output_bytes: usize,
+    max_input_bytes: Option<usize>,
+}
+
+impl From<&Policy> for PolicySettings {
+    fn from(policy: &Policy) -> Self {
+        Self {
+            version: policy.version,
+            default_timeout_secs: policy.default_timeout_secs,
+            max_output_bytes: policy.max_output_bytes,
+            max_input_bytes: policy.max_input_bytes,
+        }
+    }
+}
+
+fn diff_keyed<T: Serialize>(
+    section: &str,
+    old: &[T],
+    new: &[T],
+    key: impl Fn(&T) -> &str,
+    lines: &mut Vec<String>,
+) {
+    let old_items = old
+        .iter()
+        .map(|item| (key(item), to_object(item)))
+        .collect::<BTreeMap<_, _>>();
+    let new_items = new
+        .iter()
+        .map(|item| (key(item), to_object(item)))
+        .collect::<BTreeMap<_, _>>();
+
+    for (name, old_item) in &old_items {
+        match new_items.get(name) {
+            Some(new_item) => {
+                diff_objects(
+                    &format!("{} '{}'", section, name),
+                    old_item,
+                    new_item,
+                    lines,
+                );
+            }
+            None => lines.push(format!("- {} '{}'", section, name)),
+        }
+    }
+    for name in new_items.keys() {
+        if !old_items.contains_key(name) {
+            lines.push(format!("+ {} '{}'", section, name));
+        }
+    }
+}
+
+fn diff_objects(
+    label: &str,
+    old: &serde_json::Map<String, Value>,
+    new: &serde_json::Map<String, Value>,
+    lines: &mut Vec<String>,
+) {
+    let mut fields = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    fields.sort();
+    fields.dedup();
+    for field in fields {
+        diff_field(label, field, old.get(field), new.get(field), lines);
+    }
+}
+
+fn diff_field(
+    label: &str,
+    field: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    lines: &mut Vec<String>,
+) {
+    let old = old.filter(|value| !value.is_null());
+    let new = new.filter(|value| !value.is_null());
+    if old == new {
+        return;
+    }
+    lines.push(format!(
+        "~ {} {}: {} -> {}",
+        label,
+        field,
+        old.map_or_else(|| "unset".to_string(), Value::to_string),
+        new.map_or_else(|| "unset".to_string(), Value::to_string)
+    ));
+}
+
+fn to_object<T: Serialize>(value: &T) -> serde_json::Map<String, Value> {
+    match serde_json::to_value(value) {
+        Ok(Value::Object(map)) => map,
+        _ => serde_json::Map::new(),
+    }
+}
+
+fn current_unix_timestamp() -> Result<u64, Box<dyn Error + Send + Sync>> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use alaric_agent::policy::{
+        CommandSpec, FetchRule, Policy, ResourceLimits, Sandbox, StdinMode,
+    };
+    use clap::Parser;
+
+    use super::diff_policies;
+    use crate::Cli;
+
+    fn command(id: &str, timeout_secs: Option<u64>) -> CommandSpec {
+        CommandSpec {
+            id: id.to_string(),
+            program: "/bin/echo".to_string(),
+            fixed_args: Vec::new(),
+            arg_specs: Vec::new(),
+            timeout_secs,
+            max_output_bytes: None,
+            stdin: StdinMode::None,
+            max_input_bytes: None,
+            run_as_user: None,
+            run_as_group: None,
+            supplementary_groups: Vec::new(),
+            limits: ResourceLimits::default(),
+            sandbox: Sandbox::default(),
+            working_dir: None,
+            env: BTreeMap::new(),
+            env_passthrough: Vec::new(),
+            env_specs: Vec::new(),
+        }
+    }
+
+    fn policy(commands: Vec<CommandSpec>) -> Policy {
+        Policy {
+            version: 1,
+            default_timeout_secs: 5,
+            max_output_bytes: 2048,
+            max_input_bytes: None,
+            commands,
+            fetch: Vec::new(),
+            put: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed_entries() {
+        let old = policy(vec![command("echo", None), command("uptime", None)]);
+        let mut new = policy(vec![command("echo", Some(10)), command("df", None)]);
+        new.max_output_bytes = 4096;
+        new.fetch = vec![FetchRule {
+            path: "/var/log/*.log".to_string(),
+            max_bytes: 1024,
+        }];
+
+        assert_eq!(
+            diff_policies(&old, &new),
+            vec![
+                "~ policy max_output_bytes: 2048 -> 4096",
+                "~ command 'echo' timeout_secs: unset -> 10",
+                "- command 'uptime'",
+                "+ command 'df'",
+                "+ fetch '/var/log/*.log'",
+            ]
+        );
+        assert!(diff_policies(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn parses_policy_sign() {
+        let cli = Cli::try_parse_from([
+            "aadmin",
+            "policy",
+            "sign",
+            "policy.json",
+            "--key-id",
+            "control-plane-v1",
+            "--expires-in",
+            "3600",
+            "--serial",
+            "7",
+            "-o",
+            "agent-policy.json",
+        ])
+        .expect("policy sign should parse");
+        assert!(matches!(
+            cli.command,
+            crate::Command::Policy(super::PolicyCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn policy_publish_requires_exactly_one_target() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(
+                ["aadmin", "policy", "publish", "bundle.json"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+
+        assert!(parse(&["--agent", "agent-1"]).is_ok());
+        assert!(parse(&["--group", "web"]).is_ok());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--agent", "agent-1", "--group", "web"]).is_err());
+    }
+}
@@ -0,0 +1,25 @@
+use std::{
+    env,
+    io::{self, Write},
+    process::ExitCode,
+};
+
+use alaric_agent::launch::{EXEC_ENV_VAR, LaunchSpec};
+
+// Matches the shell's status for a command that was found but could not be run.
+const EXEC_FAILED: u8 = 126;
+
+// Started by the agent for commands with run_as credentials: drops to them, then execs the
+// command in place so the agent keeps supervising the same pid.
+fn main() -> ExitCode {
+    let payload = env::var(EXEC_ENV_VAR).ok();
+    let result = LaunchSpec::parse(env::args().skip(1))
+        .and_then(|spec| spec.with_env_payload(payload.as_deref()))
+        .and_then(|spec| spec.exec());
+    let err = match result {
+        Ok(never) => match never {},
+        Err(err) => err,
+    };
+    let _ = writeln!(io::stderr(), "alaric-exec: {}", err);
+    ExitCode::from(EXEC_FAILED)
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use alaric_lib::protocol::ResourceLimit;
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::policy::ResourceLimits;
+
+const DEFAULT_CGROUP_PARENT: &str = "/sys/fs/cgroup/alaric";
+const REMOVE_ATTEMPTS: u32 = 10;
+const REMOVE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+static CGROUP_PARENT: OnceLock<PathBuf> = OnceLock::new();
+static CGROUP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// A transient cgroup v2 holding one command and its descendants.
+#[derive(Debug)]
+pub struct CommandCgroup {
+    path: PathBuf,
+}
+
+// Only takes effect before the first command runs with cgroup limits.
+pub fn set_cgroup_parent(path: PathBuf) {
+    let _ = CGROUP_PARENT.set(path);
+}
+
+fn cgroup_parent() -> &'static Path {
+    CGROUP_PARENT.get_or_init(|| PathBuf::from(DEFAULT_CGROUP_PARENT))
+}
+
+impl CommandCgroup {
+    // `Ok(None)` when the command sets no cgroup limits.
+    pub fn create(limits: &ResourceLimits) -> Result<Option<Self>, io::Error> {
+        if !limits.needs_cgroup() {
+            return Ok(None);
+        }
+        let parent = cgroup_parent();
+        prepare_parent(parent)?;
+
+        let path = parent.join(format!(
+            "cmd-{}-{}",
+            process::id(),
+            CGROUP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&path)?;
+        let cgroup = Self { path };
+        if let Some(bytes) = limits.memory_max_bytes {
+            fs::write(cgroup.path.join("memory.max"), bytes.to_string())?;
+            // Without this the kernel swaps instead of enforcing the limit.
+            if let Err(err) = fs::write(cgroup.path.join("memory.swap.max"), "0") {
+                warn!(
+                    "failed to disable swap for {}: {}",
+                    cgroup.path.display(),
+                    err
+                );
+            }
+        }
+        if let Some(pids) = limits.pids_max {
+            fs::write(cgroup.path.join("pids.max"), pids.to_string())?;
+        }
+        Ok(Some(cgroup))
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The first limit the kernel enforced, read once the command has exited.
+    #[must_use]
+    pub fn exceeded(&self) -> Option<ResourceLimit> {
+        if read_event_count(&self.path.join("memory.events"), "oom_kill") > 0 {
+            return Some(ResourceLimit::Memory);
+        }
+        if read_event_count(&self.path.join("pids.events"), "max") > 0 {
+            return Some(ResourceLimit::Processes);
+        }
+        None
+    }
+
+    // Kills anything the command left behind, then removes the cgroup once it is empty.
+    pub async fn remove(self) {
+        if let Err(err) = fs::write(self.path.join("cgroup.kill"), "1") {
+            warn!("failed to kill cgroup {}: {}", self.path.display(), err);
+        }
+        for _ in 0..REMOVE_ATTEMPTS {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            sleep(REMOVE_RETRY_DELAY).await;
+        }
+        if let Err(err) = fs::remove_dir(&self.path) {
+            warn!("failed to remove cgroup {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+// Creates the parent if needed and delegates the memory and pids controllers to its children.
+fn prepare_parent(parent: &Path) -> Result<(), io::Error> {
+    let mounted = parent
+        .parent()
+        .is_some_and(|root| root.join("cgroup.controllers").is_file());
+    if !mounted {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("no cgroup v2 hierarchy above {}", parent.display()),
+        ));
+    }
+    if !parent.is_dir() {
+        fs::create_dir(parent)?;
+    }
+
+    let subtree_control = parent.join("cgroup.subtree_control");
+    let enabled = fs::read_to_string(&subtree_control)?;
+    let missing = ["memory", "pids"]
+        .into_iter()
+        .filter(|controller| !enabled.split_whitespace().any(|name| name == *controller))
+        .map(|controller| format!("+{}", controller))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        fs::write(&subtree_control, missing.join(" "))?;
+    }
+    Ok(())
+}
+
+fn read_event_count(path: &Path, key: &str) -> u64 {
+    fs::read_to_string(path)
+        .map(|events| parse_event_count(&events, key))
+        .unwrap_or(0)
+}
+
+fn parse_event_count(events: &str, key: &str) -> u64 {
+    events
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(name, _)| *name == key)
+        .and_then(|(_, count)| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_event_count;
+
+    #[test]
+    fn reads_counts_from_event_files() {
+        let memory_events = "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(parse_event_count(memory_events, "oom_kill"), 1);
+        assert_eq!(parse_event_count(memory_events, "oom"), 1);
+        assert_eq!(parse_event_count("max 0\n", "max"), 0);
+        assert_eq!(parse_event_count("", "max"), 0);
+    }
+}
@@ -1,38 +1,102 @@
 use std::{
     collections::BTreeMap,
-    io,
+    env, io,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
     process::{ExitStatus, Stdio},
+    sync::OnceLock,
     time::Duration,
 };
 
 use alaric_lib::protocol::{
-    AgentMessage, CommandId, CommandProtocolError, OutputStream, RejectionCode, RequestId,
-    SecureChannel, send_secure_json,
+    AgentMessage, CancelSignal, CommandId, OutputStream, RejectionCode, RequestId, ResourceLimit,
+};
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{Signal, killpg},
+        wait::{Id, WaitPidFlag, WaitStatus, waitid},
+    },
+    unistd::Pid,
 };
 use regex::Regex;
 use tokio::{
-    io::{AsyncRead, AsyncReadExt, AsyncWrite},
-    process::Command,
-    time::{Instant, sleep},
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::{Child, ChildStdin, Command},
+    signal::unix::{SignalKind, signal},
+    sync::mpsc,
+    task::JoinHandle,
+    time::{Instant, sleep, sleep_until},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    cgroup::CommandCgroup,
+    launch::{
+        Credentials, EXEC_ENV_VAR, EXEC_HELPER_NAME, LaunchSpec, check_sandbox,
+        resolve_credentials, rlimits,
+    },
+    policy::{ArgSpec, CommandSpec, Policy, StdinMode, ValidationRule},
 };
 
-use crate::policy::{ArgSpec, CommandSpec, Policy, ValidationRule};
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+static EXEC_HELPER_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct SessionClosed;
+
+#[derive(Debug)]
+pub enum InputChunk {
+    Data(Vec<u8>),
+    Eof,
+    LimitExceeded,
+}
+
+pub struct RequestControl {
+    pub cancel: mpsc::Receiver<CancelSignal>,
+    pub input: mpsc::UnboundedReceiver<InputChunk>,
+}
+
+#[derive(Debug)]
+enum ExecutorError {
+    Io(io::Error),
+    SessionClosed,
+}
+
+impl From<io::Error> for ExecutorError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<SessionClosed> for ExecutorError {
+    fn from(_: SessionClosed) -> Self {
+        Self::SessionClosed
+    }
+}
 
-pub async fn execute_request<S>(
-    channel: &mut SecureChannel,
-    stream: &mut S,
+struct ProcessOutcome {
+    status: ExitStatus,
+    timed_out: bool,
+    truncated: bool,
+    cancelled: Option<CancelSignal>,
+    forced: bool,
+    input_exceeded: bool,
+}
+
+pub async fn execute_request(
+    outgoing: &mpsc::Sender<AgentMessage>,
     policy: &Policy,
     request_id: RequestId,
     command_id: &CommandId,
     args: &BTreeMap<String, String>,
-) -> Result<(), CommandProtocolError>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-{
+    env: &BTreeMap<String, String>,
+    control: RequestControl,
+) -> Result<(), SessionClosed> {
     let Some(command) = policy.command_by_id(command_id.as_str()) else {
         return send_rejected(
-            channel,
-            stream,
+            outgoing,
             request_id,
             RejectionCode::UnknownCommand,
             format!("unknown command id '{}'", command_id),
@@ -43,23 +107,50 @@ where
     let ordered_args = match validate_and_order_args(command, args) {
         Ok(ordered_args) => ordered_args,
         Err(message) => {
-            return send_rejected(
-                channel,
-                stream,
-                request_id,
-                RejectionCode::InvalidArgs,
-                message,
-            )
-            .await;
+            return send_rejected(outgoing, request_id, RejectionCode::InvalidArgs, message).await;
+        }
+    };
+    if let Err(message) = validate_env(command, env) {
+        return send_rejected(outgoing, request_id, RejectionCode::InvalidArgs, message).await;
+    }
+
+    let credentials = match resolve_credentials(command) {
+        Ok(credentials) => credentials,
+        Err((code, message)) => {
+            return send_rejected(outgoing, request_id, code, message).await;
         }
     };
+    if let Err((code, message)) = check_sandbox(command) {
+        return send_rejected(outgoing, request_id, code, message).await;
+    }
 
-    let mut child = match spawn_child(command, ordered_args) {
+    // Rlimits still apply without a cgroup, so a host without cgroup v2 runs the command anyway.
+    let cgroup = match CommandCgroup::create(&command.limits) {
+        Ok(cgroup) => cgroup,
+        Err(err) => {
+            warn!(
+                "cgroup limits for command '{}' not applied: {}",
+                command.id, err
+            );
+            None
+        }
+    };
+
+    let spawned = spawn_child(
+        command,
+        ordered_args,
+        env,
+        credentials,
+        cgroup.as_ref().map(CommandCgroup::path),
+    );
+    let mut child = match spawned {
         Ok(child) => child,
         Err(err) => {
+            if let Some(cgroup) = cgroup {
+                cgroup.remove().await;
+            }
             return send_rejected(
-                channel,
-                stream,
+                outgoing,
                 request_id,
                 RejectionCode::ExecutionError,
                 format!("failed to spawn command '{}': {}", command.id, err),
@@ -68,84 +159,189 @@ where
         }
     };
 
-    send_secure_json(channel, stream, &AgentMessage::Started { request_id }).await?;
+    send_message(outgoing, AgentMessage::Started { request_id }).await?;
 
+    let stdin_writer = tokio::spawn(write_stdin(
+        child.stdin.take(),
+        command.stdin.clone(),
+        control.input,
+    ));
     let timeout = Duration::from_secs(command.effective_timeout_secs(policy.default_timeout_secs));
     let max_output_bytes = command.effective_max_output_bytes(policy.max_output_bytes);
     let run_outcome = stream_process_output(
-        channel,
-        stream,
+        outgoing,
         request_id,
         &mut child,
         timeout,
         max_output_bytes,
+        control.cancel,
+        stdin_writer,
     )
     .await;
 
+    let cgroup_exceeded = cgroup.as_ref().and_then(CommandCgroup::exceeded);
+    if let Some(cgroup) = cgroup {
+        cgroup.remove().await;
+    }
+
     match run_outcome {
-        Ok((status, timed_out, truncated)) => {
-            let exit_code = status.code().unwrap_or(-1);
-            send_secure_json(
-                channel,
-                stream,
-                &AgentMessage::Completed {
+        Ok(ProcessOutcome {
+            status,
+            cancelled: Some(signal),
+            forced,
+            ..
+        }) => {
+            send_message(
+                outgoing,
+                AgentMessage::Cancelled {
+                    request_id,
+                    signal,
+                    exit_code: status.code().unwrap_or(-1),
+                    forced,
+                },
+            )
+            .await
+        }
+        Ok(outcome) => {
+            let exit_code = outcome.status.code().unwrap_or(-1);
+            let limit_exceeded = cgroup_exceeded.or_else(|| cpu_limit_exceeded(&outcome.status));
+            send_message(
+                outgoing,
+                AgentMessage::Completed {
                     request_id,
                     exit_code,
-                    timed_out,
-                    truncated,
+                    timed_out: outcome.timed_out,
+                    truncated: outcome.truncated,
+                    limit_exceeded,
+                    input_exceeded: outcome.input_exceeded,
                 },
             )
-            .await?;
+            .await
         }
-        Err(CommandProtocolError::Io(_)) => {
-            send_secure_json(
-                channel,
-                stream,
-                &AgentMessage::Completed {
+        Err(ExecutorError::Io(err)) => {
+            warn!("I/O error while running request {}: {}", request_id, err);
+            send_message(
+                outgoing,
+                AgentMessage::Completed {
                     request_id,
                     exit_code: -1,
                     timed_out: false,
                     truncated: false,
+                    limit_exceeded: cgroup_exceeded,
+                    input_exceeded: false,
                 },
             )
-            .await?;
-            return Ok(());
+            .await
         }
-        Err(err) => return Err(err),
+        Err(ExecutorError::SessionClosed) => Err(SessionClosed),
     }
+}
 
-    Ok(())
+// Only takes effect before the first command runs with run_as credentials.
+pub fn set_exec_helper_path(path: PathBuf) {
+    let _ = EXEC_HELPER_PATH.set(path);
+}
+
+fn exec_helper_path() -> Result<PathBuf, io::Error> {
+    match EXEC_HELPER_PATH.get() {
+        Some(path) => Ok(path.clone()),
+        None => Ok(env::current_exe()?.with_file_name(EXEC_HELPER_NAME)),
+    }
+}
+
+// SIGXCPU is only raised by the kernel once RLIMIT_CPU is reached.
+fn cpu_limit_exceeded(status: &ExitStatus) -> Option<ResourceLimit> {
+    (status.signal() == Some(Signal::SIGXCPU as i32)).then_some(ResourceLimit::Cpu)
 }
 
+// Commands with credentials, limits or a sandbox start through the exec helper, which applies
+// them to itself before exec'ing the program.
 fn spawn_child(
     command: &CommandSpec,
     ordered_args: Vec<String>,
-) -> Result<tokio::process::Child, io::Error> {
-    let mut cmd = Command::new(&command.program);
-    cmd.args(&command.fixed_args);
-    cmd.args(ordered_args);
-    cmd.stdin(Stdio::null());
+    client_env: &BTreeMap<String, String>,
+    credentials: Option<Credentials>,
+    cgroup: Option<&Path>,
+) -> Result<Child, io::Error> {
+    let rlimits = rlimits(&command.limits);
+    let sandbox = (!command.sandbox.is_empty()).then(|| command.sandbox.clone());
+    let mut env_vars = Vec::new();
+    if let Ok(path) = env::var("PATH") {
+        env_vars.push(("PATH".to_string(), path));
+    }
+    for name in &command.env_passthrough {
+        if let Ok(value) = env::var(name) {
+            env_vars.push((name.clone(), value));
+        }
+    }
+    env_vars.extend(
+        command
+            .env
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone())),
+    );
+    env_vars.extend(
+        client_env
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone())),
+    );
+    let mut cmd =
+        if credentials.is_some() || cgroup.is_some() || !rlimits.is_empty() || sandbox.is_some() {
+            let spec = LaunchSpec {
+                cgroup: cgroup.map(Path::to_path_buf),
+                rlimits,
+                credentials,
+                sandbox,
+                pid_init: false,
+                working_dir: command.working_dir.as_ref().map(PathBuf::from),
+                env: env_vars,
+                program: command.program.clone(),
+                args: command
+                    .fixed_args
+                    .iter()
+                    .cloned()
+                    .chain(ordered_args)
+                    .collect(),
+            };
+            let mut cmd = Command::new(exec_helper_path()?);
+            cmd.args(spec.to_args());
+            // The helper runs as root, so none of the command's variables reach it before the
+            // final exec.
+            cmd.env_clear();
+            cmd.env(EXEC_ENV_VAR, spec.env_payload());
+            // The helper changes into the working directory once its sandbox mounts are in place.
+            cmd.current_dir("/");
+            cmd
+        } else {
+            let mut cmd = Command::new(&command.program);
+            cmd.args(&command.fixed_args);
+            cmd.args(ordered_args);
+            cmd.env_clear();
+            cmd.envs(env_vars);
+            cmd.current_dir(command.working_dir.as_deref().unwrap_or("/"));
+            cmd
+        };
+    match command.stdin {
+        StdinMode::None => cmd.stdin(Stdio::null()),
+        StdinMode::Stream | StdinMode::Fixed { .. } => cmd.stdin(Stdio::piped()),
+    };
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
-    cmd.env_clear();
-    if let Ok(path) = std::env::var("PATH") {
-        cmd.env("PATH", path);
-    }
-    cmd.current_dir("/");
+    // Leading its own process group lets every signal below reach whatever the command forks.
+    cmd.process_group(0);
+    cmd.kill_on_drop(true);
     cmd.spawn()
 }
 
-async fn stream_process_output<S>(
-    channel: &mut SecureChannel,
-    stream: &mut S,
+async fn stream_process_output(
+    outgoing: &mpsc::Sender<AgentMessage>,
     request_id: RequestId,
-    child: &mut tokio::process::Child,
+    child: &mut Child,
     timeout: Duration,
     max_output_bytes: usize,
-) -> Result<(ExitStatus, bool, bool), CommandProtocolError>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-{
+    mut cancel: mpsc::Receiver<CancelSignal>,
+    mut stdin_writer: JoinHandle<Result<(), StdinLimitExceeded>>,
+) -> Result<ProcessOutcome, ExecutorError> {
     let mut stdout = child
         .stdout
         .take()
@@ -154,6 +350,10 @@ where
         .stderr
         .take()
         .ok_or_else(|| io::Error::other("child stderr was not piped"))?;
+    let mut group = ProcessGroup::of(child);
+    // Registered before the first check so that an exit in between still wakes the loop.
+    let mut child_exits = signal(SignalKind::child())?;
+    let mut exited = group.leader_exited();
 
     let mut stdout_done = false;
     let mut stderr_done = false;
@@ -162,25 +362,32 @@ where
     let mut total_output_bytes = 0usize;
     let mut timed_out = false;
     let mut truncated = false;
+    let mut cancel_open = true;
+    let mut cancelled: Option<CancelSignal> = None;
+    let mut forced = false;
+    let mut kill_deadline = deadline;
+    let mut stdin_done = false;
+    let mut input_exceeded = false;
 
     let mut stdout_buf = [0u8; 1024];
     let mut stderr_buf = [0u8; 1024];
 
-    while !(stdout_done && stderr_done && status.is_some()) {
+    while !(stdout_done && stderr_done && (exited || status.is_some())) {
         let now = Instant::now();
         let sleep_for = deadline.saturating_duration_since(now);
-        let timeout_ready = sleep_for.is_zero() && status.is_none();
+        let timeout_ready = sleep_for.is_zero();
 
+        // The deadline also covers pipes held open by descendants after the command exits.
         if timeout_ready && !timed_out {
             timed_out = true;
-            let _ = child.kill().await;
-            status = Some(child.wait().await?);
+            let exit = kill_tree(child, &mut group).await?;
+            status.get_or_insert(exit);
             continue;
         }
 
         tokio::select! {
-            wait_result = child.wait(), if status.is_none() => {
-                status = Some(wait_result?);
+            _ = child_exits.recv(), if !exited && status.is_none() => {
+                exited = group.leader_exited();
             }
             read_result = stdout.read(&mut stdout_buf), if !stdout_done => {
                 let n = read_result?;
@@ -188,8 +395,7 @@ where
                     stdout_done = true;
                 } else if !timed_out
                     && stream_output(
-                        channel,
-                        stream,
+                        outgoing,
                         request_id,
                         OutputStream::Stdout,
                         &stdout_buf[..n],
@@ -205,8 +411,7 @@ where
                     stderr_done = true;
                 } else if !timed_out
                     && stream_output(
-                        channel,
-                        stream,
+                        outgoing,
                         request_id,
                         OutputStream::Stderr,
                         &stderr_buf[..n],
@@ -216,38 +421,179 @@ where
                         truncated = true;
                     }
             }
-            _ = sleep(sleep_for), if status.is_none() && !timed_out => {
+            _ = sleep(sleep_for), if !timed_out => {
                 timed_out = true;
-                let _ = child.kill().await;
-                status = Some(child.wait().await?);
+                let exit = kill_tree(child, &mut group).await?;
+                status.get_or_insert(exit);
+            }
+            signal = cancel.recv(), if cancel_open && cancelled.is_none() && !exited && status.is_none() => {
+                let Some(signal) = signal else {
+                    cancel_open = false;
+                    continue;
+                };
+                cancelled = Some(signal);
+                if signal == CancelSignal::Kill {
+                    forced = true;
+                    status = Some(kill_tree(child, &mut group).await?);
+                } else {
+                    signal_group(&group, request_id, signal);
+                    kill_deadline = Instant::now() + CANCEL_GRACE_PERIOD;
+                }
+            }
+            write_result = &mut stdin_writer, if !stdin_done => {
+                stdin_done = true;
+                if matches!(write_result, Ok(Err(StdinLimitExceeded))) && !exited && status.is_none() {
+                    input_exceeded = true;
+                    status = Some(kill_tree(child, &mut group).await?);
+                }
+            }
+            _ = sleep_until(kill_deadline), if cancelled.is_some() && !forced && !exited && status.is_none() => {
+                debug!(
+                    "request {} did not exit within the cancel grace period; killing",
+                    request_id
+                );
+                forced = true;
+                status = Some(kill_tree(child, &mut group).await?);
             }
         }
 
         if truncated && status.is_none() {
-            let _ = child.kill().await;
-            status = Some(child.wait().await?);
+            status = Some(kill_tree(child, &mut group).await?);
         }
     }
 
-    Ok((
-        status.expect("status must be set before loop exit"),
+    stdin_writer.abort();
+    // A leader that exited on its own is reaped only now, after the rest of its group is killed.
+    let status = match status {
+        Some(status) => status,
+        None => kill_tree(child, &mut group).await?,
+    };
+
+    Ok(ProcessOutcome {
+        status,
         timed_out,
         truncated,
-    ))
+        cancelled,
+        forced,
+        input_exceeded,
+    })
+}
+
+#[derive(Debug)]
+struct StdinLimitExceeded;
+
+async fn write_stdin(
+    mut stdin: Option<ChildStdin>,
+    mode: StdinMode,
+    mut chunks: mpsc::UnboundedReceiver<InputChunk>,
+) -> Result<(), StdinLimitExceeded> {
+    if let StdinMode::Fixed { data } = &mode
+        && let Some(mut pipe) = stdin.take()
+        && let Err(err) = pipe.write_all(data.as_bytes()).await
+    {
+        debug!("failed to write fixed stdin: {}", err);
+    }
+
+    while let Some(chunk) = chunks.recv().await {
+        match chunk {
+            InputChunk::Data(data) => {
+                let Some(pipe) = stdin.as_mut() else {
+                    continue;
+                };
+                if let Err(err) = pipe.write_all(&data).await {
+                    debug!("child stopped reading stdin: {}", err);
+                    stdin = None;
+                }
+            }
+            InputChunk::Eof => stdin = None,
+            InputChunk::LimitExceeded => return Err(StdinLimitExceeded),
+        }
+    }
+
+    Ok(())
+}
+
+// Each command leads its own process group; dropping this kills whatever is left of it, which
+// covers agent shutdown. The leader is only reaped by `kill_tree`, after the group is killed:
+// until then its pid cannot be reused, so the group id never names an unrelated group.
+struct ProcessGroup {
+    leader: Option<Pid>,
+    reaped: bool,
+}
+
+impl ProcessGroup {
+    fn of(child: &Child) -> Self {
+        Self {
+            leader: child
+                .id()
+                .and_then(|pid| i32::try_from(pid).ok())
+                .map(Pid::from_raw),
+            reaped: false,
+        }
+    }
+
+    fn signal(&self, signal: Signal) -> Result<(), Errno> {
+        match self.leader {
+            Some(leader) if !self.reaped => killpg(leader, signal),
+            _ => Ok(()),
+        }
+    }
+
+    // Checks for an exit without reaping, so the leader's pid stays reserved.
+    fn leader_exited(&self) -> bool {
+        self.leader.is_none_or(|leader| {
+            !matches!(
+                waitid(
+                    Id::Pid(leader),
+                    WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT | WaitPidFlag::WNOHANG,
+                ),
+                Ok(WaitStatus::StillAlive)
+            )
+        })
+    }
 }
 
-async fn stream_output<S>(
-    channel: &mut SecureChannel,
-    stream: &mut S,
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        let _ = self.signal(Signal::SIGKILL);
+    }
+}
+
+async fn kill_tree(child: &mut Child, group: &mut ProcessGroup) -> Result<ExitStatus, io::Error> {
+    if let Err(err) = group.signal(Signal::SIGKILL)
+        && err != Errno::ESRCH
+    {
+        warn!("failed to kill process group: {}", err);
+    }
+    // Still needed if the group could not be signalled.
+    let _ = child.start_kill();
+    let status = child.wait().await?;
+    group.reaped = true;
+    Ok(status)
+}
+
+fn signal_group(group: &ProcessGroup, request_id: RequestId, signal: CancelSignal) {
+    let signal = match signal {
+        CancelSignal::Interrupt => Signal::SIGINT,
+        CancelSignal::Terminate => Signal::SIGTERM,
+        CancelSignal::Kill => Signal::SIGKILL,
+    };
+    if let Err(err) = group.signal(signal) {
+        warn!(
+            "failed to send {} to request {}: {}",
+            signal, request_id, err
+        );
+    }
+}
+
+async fn stream_output(
+    outgoing: &mpsc::Sender<AgentMessage>,
     request_id: RequestId,
     output_stream: OutputStream,
     bytes: &[u8],
     total_output_bytes: &mut usize,
     max_output_bytes: usize,
-) -> Result<bool, CommandProtocolError>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-{
+) -> Result<bool, SessionClosed> {
     if *total_output_bytes >= max_output_bytes {
         return Ok(true);
     }
@@ -259,11 +605,10 @@ where
         return Ok(true);
     }
 
-    let chunk = String::from_utf8_lossy(&bytes[..emit_len]).into_owned();
-    send_secure_json(
-        channel,
-        stream,
-        &AgentMessage::Output {
+    let chunk = bytes[..emit_len].to_vec();
+    send_message(
+        outgoing,
+        AgentMessage::Output {
             request_id,
             stream: output_stream,
             chunk,
@@ -292,7 +637,7 @@ fn validate_and_order_args(
     for arg_spec in &command.arg_specs {
         match args.get(&arg_spec.name) {
             Some(value) => {
-                validate_arg(command, arg_spec, value)?;
+                validate_arg(command, "argument", arg_spec, value)?;
                 ordered_args.push(value.to_string());
             }
             None if arg_spec.required => {
@@ -308,7 +653,41 @@ fn validate_and_order_args(
     Ok(ordered_args)
 }
 
-fn validate_arg(command: &CommandSpec, spec: &ArgSpec, value: &str) -> Result<(), String> {
+// Client env values go through the same rules as args; the policy has already checked that
+// names are unique and well formed.
+fn validate_env(command: &CommandSpec, env: &BTreeMap<String, String>) -> Result<(), String> {
+    for (name, value) in env {
+        let Some(spec) = command.env_spec(name) else {
+            return Err(format!(
+                "environment variable '{}' is not allowed for command '{}'",
+                name, command.id
+            ));
+        };
+        if value.contains('\0') {
+            return Err(format!(
+                "environment variable '{}' must not contain NUL bytes",
+                name
+            ));
+        }
+        validate_arg(command, "environment variable", spec, value)?;
+    }
+    for spec in &command.env_specs {
+        if spec.required && !env.contains_key(&spec.name) {
+            return Err(format!(
+                "missing required environment variable '{}' for command '{}'",
+                spec.name, command.id
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_arg(
+    command: &CommandSpec,
+    kind: &str,
+    spec: &ArgSpec,
+    value: &str,
+) -> Result<(), String> {
     let Some(rule) = &spec.validation else {
         return Ok(());
     };
@@ -318,21 +697,22 @@ fn validate_arg(command: &CommandSpec, spec: &ArgSpec, value: &str) -> Result<()
             let full_match_pattern = format!("^(?:{})$", pattern);
             let regex = Regex::new(&full_match_pattern).map_err(|err| {
                 format!(
-                    "internal policy regex compile error for command '{}' arg '{}': {}",
-                    command.id, spec.name, err
+                    "internal policy regex compile error for command '{}' {} '{}': {}",
+                    command.id, kind, spec.name, err
                 )
             })?;
             if !regex.is_match(value) {
                 return Err(format!(
-                    "argument '{}' value '{}' failed regex validation",
-                    spec.name, value
+                    "{} '{}' value '{}' failed regex validation",
+                    kind, spec.name, value
                 ));
             }
         }
         ValidationRule::Enum { values } => {
             if !values.iter().any(|allowed| allowed == value) {
                 return Err(format!(
-                    "argument '{}' value '{}' is not one of [{}]",
+                    "{} '{}' value '{}' is not one of [{}]",
+                    kind,
                     spec.name,
                     value,
                     values.join(", ")
@@ -344,20 +724,15 @@ fn validate_arg(command: &CommandSpec, spec: &ArgSpec, value: &str) -> Result<()
     Ok(())
 }
 
-async fn send_rejected<S>(
-    channel: &mut SecureChannel,
-    stream: &mut S,
+pub(crate) async fn send_rejected(
+    outgoing: &mpsc::Sender<AgentMessage>,
     request_id: RequestId,
     code: RejectionCode,
     message: String,
-) -> Result<(), CommandProtocolError>
-where
-    S: AsyncRead + AsyncWrite + Unpin,
-{
-    send_secure_json(
-        channel,
-        stream,
-        &AgentMessage::Rejected {
+) -> Result<(), SessionClosed> {
+    send_message(
+        outgoing,
+        AgentMessage::Rejected {
             request_id,
             code,
             message,
@@ -365,3 +740,10 @@ where
     )
     .await
 }
+
+pub(crate) async fn send_message(
+    outgoing: &mpsc::Sender<AgentMessage>,
+    message: AgentMessage,
+) -> Result<(), SessionClosed> {
+    outgoing.send(message).await.map_err(|_| SessionClosed)
+}
@@ -0,0 +1,465 @@
+use std::{
+    fs::Permissions,
+    os::{
+        fd::AsRawFd,
+        unix::fs::{PermissionsExt, chown},
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alaric_lib::protocol::{AgentMessage, CancelSignal, RejectionCode, RequestId};
+use nix::unistd::{Group, User};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+    task,
+};
+use tracing::warn;
+
+use crate::{
+    executor::{InputChunk, RequestControl, SessionClosed, send_message, send_rejected},
+    policy::Policy,
+};
+
+const FETCH_CHUNK_BYTES: usize = 16 * 1024;
+
+static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+struct PutTarget {
+    path: PathBuf,
+    mode: u32,
+    owner: Option<u32>,
+    group: Option<u32>,
+}
+
+enum UploadError {
+    Rejected(RejectionCode, String),
+    Cancelled(CancelSignal),
+    SessionClosed,
+}
+
+pub async fn fetch_file(
+    outgoing: &mpsc::Sender<AgentMessage>,
+    policy: &Policy,
+    request_id: RequestId,
+    path: &str,
+    mut cancel: mpsc::Receiver<CancelSignal>,
+) -> Result<(), SessionClosed> {
+    let (file, size) = match open_for_fetch(policy, path).await {
+        Ok(opened) => opened,
+        Err((code, message)) => return send_rejected(outgoing, request_id, code, message).await,
+    };
+
+    send_message(outgoing, AgentMessage::FetchStarted { request_id, size }).await?;
+
+    // Only the size reported up front is sent, even if the file grows meanwhile.
+    let mut reader = file.take(size);
+    let mut hasher = Sha256::new();
+    let mut sent = 0u64;
+    let mut buf = vec![0u8; FETCH_CHUNK_BYTES];
+    loop {
+        let n = tokio::select! {
+            read_result = reader.read(&mut buf) => match read_result {
+                Ok(n) => n,
+                Err(err) => {
+                    warn!("I/O error while fetching '{}' for request {}: {}", path, request_id, err);
+                    return send_rejected(
+                        outgoing,
+                        request_id,
+                        RejectionCode::ExecutionError,
+                        format!("failed to read '{}': {}", path, err),
+                    )
+                    .await;
+                }
+            },
+            Some(signal) = cancel.recv() => {
+                return send_message(
+                    outgoing,
+                    AgentMessage::Cancelled {
+                        request_id,
+                        signal,
+                        exit_code: -1,
+                        forced: false,
+                    },
+                )
+                .await;
+            }
+        };
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+        sent += n as u64;
+        send_message(
+            outgoing,
+            AgentMessage::FetchChunk {
+                request_id,
+                data: buf[..n].to_vec(),
+            },
+        )
+        .await?;
+    }
+
+    send_message(
+        outgoing,
+        AgentMessage::FetchCompleted {
+            request_id,
+            size: sent,
+            sha256: hex::encode(hasher.finalize()),
+        },
+    )
+    .await
+}
+
+async fn open_for_fetch(
+    policy: &Policy,
+    path: &str,
+) -> Result<(File, u64), (RejectionCode, String)> {
+    let requested = Path::new(path);
+    if !requested.is_absolute() {
+        return Err((
+            RejectionCode::InvalidArgs,
+            format!("fetch path '{}' must be absolute", path),
+        ));
+    }
+    if policy.fetch_rule_for(requested).is_none() {
+        return Err(not_allowed(path));
+    }
+
+    // Match again after resolving symlinks and `..` so links cannot escape the allowed globs.
+    let resolved: PathBuf = fs::canonicalize(requested).await.map_err(|err| {
+        (
+            RejectionCode::ExecutionError,
+            format!("failed to resolve '{}': {}", path, err),
+        )
+    })?;
+    if policy.fetch_rule_for(&resolved).is_none() {
+        return Err(not_allowed(path));
+    }
+
+    // A component may be swapped for a link after the check above. O_NOFOLLOW covers the last
+    // one, and the path the kernel reports for the open file covers the directories.
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NOFOLLOW)
+        .open(&resolved)
+        .await
+        .map_err(|err| {
+            (
+                RejectionCode::ExecutionError,
+                format!("failed to open '{}': {}", path, err),
+            )
+        })?;
+    let opened = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))
+        .await
+        .map_err(|err| {
+            (
+                RejectionCode::ExecutionError,
+                format!("failed to resolve opened '{}': {}", path, err),
+            )
+        })?;
+    if opened != resolved {
+        return Err((
+            RejectionCode::ExecutionError,
+            format!("'{}' changed while it was being opened", path),
+        ));
+    }
+    let Some(rule) = policy.fetch_rule_for(&opened) else {
+        return Err(not_allowed(path));
+    };
+    let metadata = file.metadata().await.map_err(|err| {
+        (
+            RejectionCode::ExecutionError,
+            format!("failed to stat '{}': {}", path, err),
+        )
+    })?;
+    if !metadata.is_file() {
+        return Err((
+            RejectionCode::InvalidArgs,
+            format!("'{}' is not a regular file", path),
+        ));
+    }
+    if metadata.len() > rule.max_bytes {
+        return Err((
+            RejectionCode::OutputLimit,
+            format!(
+                "'{}' is {} bytes, above the {} byte fetch limit",
+                path,
+                metadata.len(),
+                rule.max_bytes
+            ),
+        ));
+    }
+
+    Ok((file, metadata.len()))
+}
+
+fn not_allowed(path: &str) -> (RejectionCode, String) {
+    (
+        RejectionCode::PolicyError,
+        format!("fetching '{}' is not allowed by policy", path),
+    )
+}
+
+pub async fn put_file(
+    outgoing: &mpsc::Sender<AgentMessage>,
+    policy: &Policy,
+    request_id: RequestId,
+    path: &str,
+    size: u64,
+    mode: Option<u32>,
+    control: RequestControl,
+) -> Result<(), SessionClosed> {
+    let target = match prepare_put(policy, path, size, mode).await {
+        Ok(target) => target,
+        Err((code, message)) => return send_rejected(outgoing, request_id, code, message).await,
+    };
+
+    let temp_path = temp_path_for(&target.path);
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp_path)
+        .await
+    {
+        Ok(file) => file,
+        Err(err) => {
+            return send_rejected(
+                outgoing,
+                request_id,
+                RejectionCode::ExecutionError,
+                format!("failed to create temporary file for '{}': {}", path, err),
+            )
+            .await;
+        }
+    };
+
+    let RequestControl { cancel, input } = control;
+    let outcome = match receive_upload(&mut file, size, cancel, input).await {
+        Ok(sha256) => finish_put(file, &temp_path, &target)
+            .await
+            .map(|()| sha256)
+            .map_err(|err| {
+                warn!(
+                    "failed to install upload '{}' for request {}: {}",
+                    path, request_id, err
+                );
+                UploadError::Rejected(
+                    RejectionCode::ExecutionError,
+                    format!("failed to write '{}': {}", path, err),
+                )
+            }),
+        Err(err) => Err(err),
+    };
+    let err = match outcome {
+        Ok(sha256) => {
+            return send_message(
+                outgoing,
+                AgentMessage::PutCompleted {
+                    request_id,
+                    path: path.to_string(),
+                    size,
+                    sha256,
+                },
+            )
+            .await;
+        }
+        Err(err) => err,
+    };
+
+    let _ = fs::remove_file(&temp_path).await;
+    match err {
+        UploadError::Rejected(code, message) => {
+            send_rejected(outgoing, request_id, code, message).await
+        }
+        UploadError::Cancelled(signal) => {
+            send_message(
+                outgoing,
+                AgentMessage::Cancelled {
+                    request_id,
+                    signal,
+                    exit_code: -1,
+                    forced: false,
+                },
+            )
+            .await
+        }
+        UploadError::SessionClosed => Err(SessionClosed),
+    }
+}
+
+async fn prepare_put(
+    policy: &Policy,
+    path: &str,
+    size: u64,
+    mode: Option<u32>,
+) -> Result<PutTarget, (RejectionCode, String)> {
+    let requested = Path::new(path);
+    if !requested.is_absolute() {
+        return Err((
+            RejectionCode::InvalidArgs,
+            format!("put path '{}' must be absolute", path),
+        ));
+    }
+    let Some(rule) = policy.put_rule_for(requested) else {
+        return Err((
+            RejectionCode::PolicyError,
+            format!("writing '{}' is not allowed by policy", path),
+        ));
+    };
+    if size > rule.max_bytes {
+        return Err((
+            RejectionCode::InputLimit,
+            format!(
+                "upload of {} bytes is above the {} byte put limit for '{}'",
+                size, rule.max_bytes, rule.directory
+            ),
+        ));
+    }
+
+    let allowed_mode = rule.effective_mode();
+    let mode = mode.unwrap_or(allowed_mode);
+    if mode & !allowed_mode != 0 {
+        return Err((
+            RejectionCode::PolicyError,
+            format!(
+                "mode {:04o} is not allowed for '{}' (allowed: {:04o})",
+                mode, rule.directory, allowed_mode
+            ),
+        ));
+    }
+
+    // Account lookups go through NSS, which may block on a directory service.
+    let (owner, group) = (rule.owner.clone(), rule.group.clone());
+    let (owner, group) = task::spawn_blocking(move || {
+        Ok::<_, (RejectionCode, String)>((
+            owner.as_deref().map(resolve_user).transpose()?,
+            group.as_deref().map(resolve_group).transpose()?,
+        ))
+    })
+    .await
+    .map_err(|err| {
+        (
+            RejectionCode::ExecutionError,
+            format!("failed to look up put owner: {}", err),
+        )
+    })??;
+    Ok(PutTarget {
+        path: requested.to_path_buf(),
+        mode,
+        owner,
+        group,
+    })
+}
+
+async fn receive_upload(
+    file: &mut File,
+    size: u64,
+    mut cancel: mpsc::Receiver<CancelSignal>,
+    mut input: mpsc::UnboundedReceiver<InputChunk>,
+) -> Result<String, UploadError> {
+    let mut hasher = Sha256::new();
+    let mut received = 0u64;
+    loop {
+        tokio::select! {
+            chunk = input.recv() => match chunk {
+                Some(InputChunk::Data(data)) => {
+                    received += data.len() as u64;
+                    hasher.update(&data);
+                    file.write_all(&data).await.map_err(|err| {
+                        UploadError::Rejected(
+                            RejectionCode::ExecutionError,
+                            format!("failed to write upload: {}", err),
+                        )
+                    })?;
+                }
+                Some(InputChunk::Eof) => {
+                    if received != size {
+                        return Err(UploadError::Rejected(
+                            RejectionCode::InvalidArgs,
+                            format!("upload ended after {} of {} declared bytes", received, size),
+                        ));
+                    }
+                    return Ok(hex::encode(hasher.finalize()));
+                }
+                Some(InputChunk::LimitExceeded) => {
+                    return Err(UploadError::Rejected(
+                        RejectionCode::InputLimit,
+                        format!("upload exceeded the {} declared bytes", size),
+                    ));
+                }
+                None => return Err(UploadError::SessionClosed),
+            },
+            Some(signal) = cancel.recv() => return Err(UploadError::Cancelled(signal)),
+        }
+    }
+}
+
+async fn finish_put(mut file: File, temp_path: &Path, target: &PutTarget) -> io::Result<()> {
+    file.flush().await?;
+    file.sync_all().await?;
+    drop(file);
+
+    if target.owner.is_some() || target.group.is_some() {
+        chown(temp_path, target.owner, target.group)?;
+    }
+    fs::set_permissions(temp_path, Permissions::from_mode(target.mode)).await?;
+    fs::rename(temp_path, &target.path).await?;
+
+    // Persist the rename itself; the data is already on disk either way.
+    if let Some(parent) = target.path.parent()
+        && let Err(err) = File::open(parent).await?.sync_all().await
+    {
+        warn!("failed to sync directory '{}': {}", parent.display(), err);
+    }
+    Ok(())
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let counter = UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}-{}.partial", name, process::id(), counter))
+}
+
+fn resolve_user(name: &str) -> Result<u32, (RejectionCode, String)> {
+    if let Ok(uid) = name.parse::<u32>() {
+        return Ok(uid);
+    }
+    match User::from_name(name) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        Ok(None) => Err((
+            RejectionCode::PolicyError,
+            format!("put owner '{}' does not exist", name),
+        )),
+        Err(err) => Err((
+            RejectionCode::ExecutionError,
+            format!("failed to look up user '{}': {}", name, err),
+        )),
+    }
+}
+
+fn resolve_group(name: &str) -> Result<u32, (RejectionCode, String)> {
+    if let Ok(gid) = name.parse::<u32>() {
+        return Ok(gid);
+    }
+    match Group::from_name(name) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        Ok(None) => Err((
+            RejectionCode::PolicyError,
+            format!("put group '{}' does not exist", name),
+        )),
+        Err(err) => Err((
+            RejectionCode::ExecutionError,
+            format!("failed to look up group '{}': {}", name, err),
+        )),
+    }
+}
@@ -0,0 +1,607 @@
+use std::{
+    convert::Infallible,
+    env,
+    ffi::CString,
+    fmt, fs, io,
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{self, Command},
+};
+
+use alaric_lib::protocol::RejectionCode;
+use nix::{
+    errno::Errno,
+    sys::{
+        resource::{Resource, setrlimit},
+        signal::{SigSet, Signal, raise},
+    },
+    unistd::{Gid, Group, Uid, User, execve, setgid, setgroups, setuid},
+};
+use seccompiler::apply_filter;
+
+use crate::{
+    policy::{CommandSpec, Namespace, ResourceLimits, Sandbox},
+    sandbox::{enter_namespaces, restrict, seccomp_filter},
+};
+
+// File name of the helper binary, installed next to `alaric-agent`.
+pub const EXEC_HELPER_NAME: &str = "alaric-exec";
+
+// The helper runs with only this variable set; it holds the command's environment, which is
+// applied at the final exec.
+pub const EXEC_ENV_VAR: &str = "ALARIC_EXEC_ENV";
+
+// Searched when the command's environment has no PATH, as execvp does.
+const DEFAULT_SEARCH_PATH: &str = "/bin:/usr/bin";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub groups: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rlimit {
+    Cpu,
+    AddressSpace,
+    OpenFiles,
+    Processes,
+}
+
+// What `alaric-exec` applies to itself before replacing itself with `program`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchSpec {
+    pub cgroup: Option<PathBuf>,
+    pub rlimits: Vec<(Rlimit, u64)>,
+    pub credentials: Option<Credentials>,
+    pub sandbox: Option<Sandbox>,
+    // Set on the copy of the helper that runs as init of the command's PID namespace.
+    pub pid_init: bool,
+    pub working_dir: Option<PathBuf>,
+    // Passed through `EXEC_ENV_VAR` rather than the arguments, which other users can read.
+    pub env: Vec<(String, String)>,
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum LaunchError {
+    Usage(String),
+    Cgroup(PathBuf, io::Error),
+    Rlimit(Rlimit, nix::Error),
+    Credentials(&'static str, nix::Error),
+    Sandbox(String),
+    WorkingDir(PathBuf, io::Error),
+    Exec(String, nix::Error),
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::Usage(message) => write!(f, "{}", message),
+            LaunchError::Cgroup(path, source) => {
+                write!(f, "failed to join cgroup '{}': {}", path.display(), source)
+            }
+            LaunchError::Rlimit(limit, source) => {
+                write!(f, "failed to set {} limit: {}", limit.name(), source)
+            }
+            LaunchError::Credentials(step, source) => {
+                write!(f, "failed to {}: {}", step, source)
+            }
+            LaunchError::Sandbox(message) => write!(f, "{}", message),
+            LaunchError::WorkingDir(path, source) => {
+                write!(f, "failed to change into '{}': {}", path.display(), source)
+            }
+            LaunchError::Exec(program, source) => {
+                write!(f, "failed to execute '{}': {}", program, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+impl LaunchSpec {
+    #[must_use]
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cgroup) = &self.cgroup {
+            args.extend(["--cgroup".to_string(), cgroup.display().to_string()]);
+        }
+        for (limit, value) in &self.rlimits {
+            args.extend([
+                "--rlimit".to_string(),
+                format!("{}={}", limit.name(), value),
+            ]);
+        }
+        if let Some(credentials) = &self.credentials {
+            if let Some(uid) = credentials.uid {
+                args.extend(["--uid".to_string(), uid.to_string()]);
+            }
+            if let Some(gid) = credentials.gid {
+                args.extend(["--gid".to_string(), gid.to_string()]);
+            }
+            let groups = credentials
+                .groups
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            args.extend(["--groups".to_string(), groups.join(",")]);
+        }
+        if let Some(sandbox) = &self.sandbox {
+            let profile = serde_json::to_string(sandbox).unwrap_or_default();
+            args.extend(["--sandbox".to_string(), profile]);
+        }
+        if self.pid_init {
+            args.push("--pid-init".to_string());
+        }
+        if let Some(dir) = &self.working_dir {
+            args.extend(["--cwd".to_string(), dir.display().to_string()]);
+        }
+        args.push("--".to_string());
+        args.push(self.program.clone());
+        args.extend(self.args.iter().cloned());
+        args
+    }
+
+    #[must_use]
+    pub fn env_payload(&self) -> String {
+        serde_json::to_string(&self.env).unwrap_or_default()
+    }
+
+    pub fn with_env_payload(mut self, payload: Option<&str>) -> Result<Self, LaunchError> {
+        if let Some(payload) = payload {
+            self.env = serde_json::from_str(payload).map_err(|err| {
+                LaunchError::Usage(format!(
+                    "{} must hold the command environment: {}",
+                    EXEC_ENV_VAR, err
+                ))
+            })?;
+        }
+        Ok(self)
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, LaunchError> {
+        let mut cgroup = None;
+        let mut rlimits = Vec::new();
+        let mut credentials: Option<Credentials> = None;
+        let mut sandbox = None;
+        let mut pid_init = false;
+        let mut working_dir = None;
+        let mut args = args.into_iter();
+        loop {
+            let Some(flag) = args.next() else {
+                return Err(LaunchError::Usage(
+                    "missing '--' before program".to_string(),
+                ));
+            };
+            match flag.as_str() {
+                "--cgroup" => {
+                    let Some(path) = args.next() else {
+                        return Err(LaunchError::Usage("--cgroup expects a path".to_string()));
+                    };
+                    cgroup = Some(PathBuf::from(path));
+                }
+                "--rlimit" => rlimits.push(parse_rlimit(args.next())?),
+                "--uid" => {
+                    credentials.get_or_insert_with(Credentials::default).uid =
+                        Some(parse_id(&flag, args.next())?);
+                }
+                "--gid" => {
+                    credentials.get_or_insert_with(Credentials::default).gid =
+                        Some(parse_id(&flag, args.next())?);
+                }
+                "--groups" => {
+                    let value = args.next().unwrap_or_default();
+                    credentials.get_or_insert_with(Credentials::default).groups = value
+                        .split(',')
+                        .filter(|group| !group.is_empty())
+                        .map(|group| parse_id(&flag, Some(group.to_string())))
+                        .collect::<Result<_, _>>()?;
+                }
+                "--sandbox" => {
+                    let profile = args.next().unwrap_or_default();
+                    sandbox = Some(serde_json::from_str(&profile).map_err(|err| {
+                        LaunchError::Usage(format!("--sandbox expects a sandbox profile: {}", err))
+                    })?);
+                }
+                "--pid-init" => pid_init = true,
+                "--cwd" => {
+                    let Some(path) = args.next() else {
+                        return Err(LaunchError::Usage("--cwd expects a path".to_string()));
+                    };
+                    working_dir = Some(PathBuf::from(path));
+                }
+                "--" => break,
+                other => {
+                    return Err(LaunchError::Usage(format!("unknown argument '{}'", other)));
+                }
+            }
+        }
+
+        let Some(program) = args.next() else {
+            return Err(LaunchError::Usage("missing program".to_string()));
+        };
+        Ok(Self {
+            cgroup,
+            rlimits,
+            credentials,
+            sandbox,
+            pid_init,
+            working_dir,
+            env: Vec::new(),
+            program,
+            args: args.collect(),
+        })
+    }
+
+    // Credentials go after everything that may need the privileges they drop, and within them
+    // the uid goes last for the same reason. The seccomp filter follows them so a denied
+    // syscall cannot break the switch.
+    pub fn exec(&self) -> Result<Infallible, LaunchError> {
+        if let Some(cgroup) = &self.cgroup {
+            // Writing 0 moves the writing process, which is this one.
+            fs::write(cgroup.join("cgroup.procs"), "0")
+                .map_err(|source| LaunchError::Cgroup(cgroup.clone(), source))?;
+        }
+        for (limit, value) in &self.rlimits {
+            let (soft, hard) = match limit {
+                // SIGXCPU at the soft limit identifies the overrun; SIGKILL follows a second
+                // later if the command ignores it.
+                Rlimit::Cpu => (*value, value.saturating_add(1)),
+                Rlimit::AddressSpace | Rlimit::OpenFiles | Rlimit::Processes => (*value, *value),
+            };
+            setrlimit(limit.resource(), soft, hard)
+                .map_err(|source| LaunchError::Rlimit(*limit, source))?;
+        }
+        if let Some(sandbox) = &self.sandbox {
+            if !self.pid_init {
+                enter_namespaces(sandbox).map_err(LaunchError::Sandbox)?;
+                if sandbox.has_namespace(Namespace::Pid) {
+                    return self.run_pid_init();
+                }
+            }
+            restrict(sandbox, self.pid_init).map_err(LaunchError::Sandbox)?;
+        }
+        let seccomp = match &self.sandbox {
+            Some(sandbox) if !sandbox.deny_syscalls.is_empty() => {
+                Some(seccomp_filter(&sandbox.deny_syscalls).map_err(LaunchError::Sandbox)?)
+            }
+            _ => None,
+        };
+        if let Some(credentials) = &self.credentials {
+            let groups = credentials
+                .groups
+                .iter()
+                .map(|gid| Gid::from_raw(*gid))
+                .collect::<Vec<_>>();
+            setgroups(&groups)
+                .map_err(|source| LaunchError::Credentials("set supplementary groups", source))?;
+            if let Some(gid) = credentials.gid {
+                setgid(Gid::from_raw(gid))
+                    .map_err(|source| LaunchError::Credentials("set gid", source))?;
+            }
+            if let Some(uid) = credentials.uid {
+                setuid(Uid::from_raw(uid))
+                    .map_err(|source| LaunchError::Credentials("set uid", source))?;
+            }
+        }
+        // Changed into as the target user, who must be able to reach it.
+        if let Some(dir) = &self.working_dir {
+            env::set_current_dir(dir)
+                .map_err(|source| LaunchError::WorkingDir(dir.clone(), source))?;
+        }
+        if let Some(filter) = &seccomp {
+            // Also sets no_new_privs, which installing a filter without CAP_SYS_ADMIN requires.
+            apply_filter(filter).map_err(|err| {
+                LaunchError::Sandbox(format!("failed to apply seccomp filter: {}", err))
+            })?;
+        }
+
+        let argv = std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| LaunchError::Usage("arguments must not contain NUL bytes".to_string()))?;
+        let envp = self
+            .env
+            .iter()
+            .map(|(name, value)| CString::new(format!("{}={}", name, value)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| {
+                LaunchError::Usage("environment must not contain NUL bytes".to_string())
+            })?;
+        self.exec_program(&argv, &envp)
+    }
+
+    // Like execvp, but the search uses the command's PATH, not the helper's empty environment.
+    fn exec_program(&self, argv: &[CString], envp: &[CString]) -> Result<Infallible, LaunchError> {
+        let exec_error = |source| LaunchError::Exec(self.program.clone(), source);
+        if self.program.contains('/') {
+            return execve(&argv[0], argv, envp).map_err(exec_error);
+        }
+        let search = self
+            .env
+            .iter()
+            .find(|(name, _)| name == "PATH")
+            .map_or(DEFAULT_SEARCH_PATH, |(_, value)| value.as_str());
+        let mut last_error = Errno::ENOENT;
+        for dir in search.split(':').filter(|dir| !dir.is_empty()) {
+            let Ok(candidate) = CString::new(format!("{}/{}", dir, self.program)) else {
+                continue;
+            };
+            match execve(&candidate, argv, envp) {
+                Err(Errno::ENOENT | Errno::ENOTDIR) => {}
+                Err(err) => last_error = err,
+            }
+        }
+        Err(exec_error(last_error))
+    }
+
+    // Only children join a PID namespace created by `unshare`, so the helper starts itself
+    // again as the namespace's init and exits the way that copy does.
+    fn run_pid_init(&self) -> Result<Infallible, LaunchError> {
+        let init = LaunchSpec {
+            // Both are inherited already.
+            cgroup: None,
+            rlimits: Vec::new(),
+            pid_init: true,
+            ..self.clone()
+        };
+        // Cancel signals are meant for the command; this process only waits for it.
+        let mut cancel_signals = SigSet::empty();
+        for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+            cancel_signals.add(signal);
+        }
+        let _ = cancel_signals.thread_block();
+
+        let status = env::current_exe()
+            .and_then(|helper| {
+                Command::new(helper)
+                    .args(init.to_args())
+                    .env_clear()
+                    .env(EXEC_ENV_VAR, init.env_payload())
+                    .status()
+            })
+            .map_err(|err| {
+                LaunchError::Sandbox(format!("failed to start PID namespace init: {}", err))
+            })?;
+        if let Some(signal) = status.signal() {
+            if let Ok(signal) = Signal::try_from(signal) {
+                let mut raised = SigSet::empty();
+                raised.add(signal);
+                let _ = raised.thread_unblock();
+                let _ = raise(signal);
+            }
+            process::exit(128 + signal);
+        }
+        process::exit(status.code().unwrap_or(1))
+    }
+}
+
+impl Rlimit {
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Rlimit::Cpu => "cpu",
+            Rlimit::AddressSpace => "as",
+            Rlimit::OpenFiles => "nofile",
+            Rlimit::Processes => "nproc",
+        }
+    }
+
+    const fn resource(self) -> Resource {
+        match self {
+            Rlimit::Cpu => Resource::RLIMIT_CPU,
+            Rlimit::AddressSpace => Resource::RLIMIT_AS,
+            Rlimit::OpenFiles => Resource::RLIMIT_NOFILE,
+            Rlimit::Processes => Resource::RLIMIT_NPROC,
+        }
+    }
+}
+
+#[must_use]
+pub fn rlimits(limits: &ResourceLimits) -> Vec<(Rlimit, u64)> {
+    [
+        (Rlimit::Cpu, limits.cpu_secs),
+        (Rlimit::AddressSpace, limits.address_space_bytes),
+        (Rlimit::OpenFiles, limits.open_files),
+        (Rlimit::Processes, limits.processes),
+    ]
+    .into_iter()
+    .filter_map(|(limit, value)| value.map(|value| (limit, value)))
+    .collect()
+}
+
+fn parse_rlimit(value: Option<String>) -> Result<(Rlimit, u64), LaunchError> {
+    let usage = || LaunchError::Usage("--rlimit expects NAME=VALUE".to_string());
+    let value = value.ok_or_else(usage)?;
+    let (name, value) = value.split_once('=').ok_or_else(usage)?;
+    let limit = [
+        Rlimit::Cpu,
+        Rlimit::AddressSpace,
+        Rlimit::OpenFiles,
+        Rlimit::Processes,
+    ]
+    .into_iter()
+    .find(|limit| limit.name() == name)
+    .ok_or_else(|| LaunchError::Usage(format!("unknown rlimit '{}'", name)))?;
+    let value = value.parse::<u64>().map_err(|_| usage())?;
+    Ok((limit, value))
+}
+
+fn parse_id(flag: &str, value: Option<String>) -> Result<u32, LaunchError> {
+    value
+        .as_deref()
+        .and_then(|value| value.parse::<u32>().ok())
+        .ok_or_else(|| LaunchError::Usage(format!("{} expects a numeric id", flag)))
+}
+
+// Unsharing namespaces needs CAP_SYS_ADMIN, which in practice means an agent running as root.
+pub fn check_sandbox(command: &CommandSpec) -> Result<(), (RejectionCode, String)> {
+    if command.sandbox.namespaces.is_empty() || Uid::effective().is_root() {
+        return Ok(());
+    }
+    Err((
+        RejectionCode::ExecutionError,
+        format!(
+            "command '{}' sandbox uses namespaces, which requires the agent to run as root",
+            command.id
+        ),
+    ))
+}
+
+// Resolves the command's run-as names against the local user database. `None` means the
+// command runs with the agent's own credentials.
+pub fn resolve_credentials(
+    command: &CommandSpec,
+) -> Result<Option<Credentials>, (RejectionCode, String)> {
+    if !command.drops_privileges() {
+        return Ok(None);
+    }
+    if !Uid::effective().is_root() {
+        return Err((
+            RejectionCode::ExecutionError,
+            format!(
+                "command '{}' sets run_as credentials, which requires the agent to run as root",
+                command.id
+            ),
+        ));
+    }
+
+    let user = command
+        .run_as_user
+        .as_deref()
+        .map(|name| resolve_user(command, name))
+        .transpose()?;
+    let gid = match (&command.run_as_group, &user) {
+        (Some(name), _) => Some(resolve_group(command, name)?),
+        (None, Some((_, primary_gid))) => Some(*primary_gid),
+        (None, None) => None,
+    };
+    let groups = command
+        .supplementary_groups
+        .iter()
+        .map(|name| resolve_group(command, name))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(Credentials {
+        uid: user.map(|(uid, _)| uid),
+        gid,
+        groups,
+    }))
+}
+
+fn resolve_user(command: &CommandSpec, name: &str) -> Result<(u32, u32), (RejectionCode, String)> {
+    let lookup = match name.parse::<u32>() {
+        Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+        Err(_) => User::from_name(name),
+    };
+    match lookup {
+        Ok(Some(user)) => Ok((user.uid.as_raw(), user.gid.as_raw())),
+        // A bare uid without a passwd entry is usable once a group is given explicitly.
+        Ok(None) => match (name.parse::<u32>(), &command.run_as_group) {
+            (Ok(uid), Some(_)) => Ok((uid, 0)),
+            _ => Err((
+                RejectionCode::PolicyError,
+                format!(
+                    "command '{}' run_as_user '{}' does not exist",
+                    command.id, name
+                ),
+            )),
+        },
+        Err(err) => Err((
+            RejectionCode::ExecutionError,
+            format!("failed to look up user '{}': {}", name, err),
+        )),
+    }
+}
+
+fn resolve_group(command: &CommandSpec, name: &str) -> Result<u32, (RejectionCode, String)> {
+    if let Ok(gid) = name.parse::<u32>() {
+        return Ok(gid);
+    }
+    match Group::from_name(name) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        Ok(None) => Err((
+            RejectionCode::PolicyError,
+            format!("command '{}' group '{}' does not exist", command.id, name),
+        )),
+        Err(err) => Err((
+            RejectionCode::ExecutionError,
+            format!("failed to look up group '{}': {}", name, err),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::policy::{Namespace, Sandbox};
+
+    use super::{Credentials, LaunchSpec, Rlimit};
+
+    #[test]
+    fn launch_args_round_trip() {
+        let spec = LaunchSpec {
+            cgroup: Some(PathBuf::from("/sys/fs/cgroup/alaric/cmd-1")),
+            rlimits: vec![(Rlimit::Cpu, 5), (Rlimit::OpenFiles, 64)],
+            credentials: Some(Credentials {
+                uid: Some(65534),
+                gid: Some(65534),
+                groups: vec![4, 100],
+            }),
+            sandbox: Some(Sandbox {
+                namespaces: vec![Namespace::Mount, Namespace::Pid],
+                read_only_root: true,
+                writable_paths: vec!["/var/tmp/my dir".to_string()],
+                deny_syscalls: vec!["ptrace".to_string()],
+                ..Sandbox::default()
+            }),
+            pid_init: true,
+            working_dir: Some(PathBuf::from("/srv/app")),
+            env: Vec::new(),
+            program: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), "--uid 0".to_string()],
+        };
+        let parsed = LaunchSpec::parse(spec.to_args()).expect("launch args should parse");
+        assert_eq!(parsed, spec);
+
+        let limited = LaunchSpec {
+            cgroup: None,
+            credentials: None,
+            sandbox: None,
+            pid_init: false,
+            working_dir: None,
+            ..spec
+        };
+        assert_eq!(
+            LaunchSpec::parse(limited.to_args()).expect("launch args should parse"),
+            limited
+        );
+        let with_env = LaunchSpec {
+            env: vec![
+                ("PATH".to_string(), "/usr/bin:/bin".to_string()),
+                ("APP_MODE".to_string(), "fast=1 --uid 0".to_string()),
+            ],
+            ..limited.clone()
+        };
+        let payload = with_env.env_payload();
+        assert!(!with_env.to_args().concat().contains("APP_MODE"));
+        assert_eq!(
+            LaunchSpec::parse(with_env.to_args())
+                .and_then(|spec| spec.with_env_payload(Some(&payload)))
+                .expect("launch env should parse"),
+            with_env
+        );
+        assert!(
+            LaunchSpec::parse(limited.to_args())
+                .and_then(|spec| spec.with_env_payload(Some("{}")))
+                .is_err()
+        );
+        assert!(LaunchSpec::parse(["--uid".to_string(), "root".to_string()]).is_err());
+        assert!(LaunchSpec::parse(["--rlimit".to_string(), "core=0".to_string()]).is_err());
+    }
+}
@@ -1,3 +1,8 @@
+pub mod cgroup;
 pub mod executor;
+pub mod files;
+pub mod launch;
 pub mod policy;
+pub mod reload;
+pub mod sandbox;
 pub mod session;
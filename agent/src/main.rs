@@ -1,24 +1,46 @@
 use std::error::Error;
 
-use std::{collections::BTreeSet, env, path::Path, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    path::Path,
+    sync::Arc,
+};
 
-use alaric_agent::{policy::Policy, session::run_secure_session};
-use alaric_lib::constants::DEFAULT_SERVER_PORT;
+use alaric_agent::{
+    cgroup::set_cgroup_parent,
+    executor::set_exec_helper_path,
+    policy::{Policy, TrustedPolicyKeys},
+    reload::{PolicyReloader, SharedPolicy},
+    session::{TunnelContext, run_secure_session},
+};
+use alaric_lib::config::{AlaricConfig, RelayConfig, SessionConfig};
 use alaric_lib::protocol::{
-    AgentId, HandshakeProofRequest, HandshakeRequest, HandshakeResponse, IdentityBundle,
-    PeerAttestationPolicy, TrustedIdentityKeys, build_auth_proof_ed25519, read_json_frame,
-    write_json_frame,
+    AgentControlMessage, AgentId, ClientId, CommandId, HandshakeAccepted, HandshakeProofRequest,
+    HandshakeRequest, HandshakeResponse, IdentityBundle, PeerAttestationPolicy,
+    RelayControlMessage, SessionId, TrustedIdentityKeys, build_auth_proof_ed25519,
+    noise_static_keypair_from_hex, read_json_frame, read_json_message, write_json_frame,
 };
 use alaric_lib::security::noise::types::Keypair;
-use tokio::{net::TcpStream, time::sleep};
-use tracing::{error, info};
+use alaric_lib::transport::RelayStream;
+use tokio::{
+    io::split,
+    sync::mpsc,
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+use tracing::{error, info, warn};
 
 mod signal;
 
 const AGENT_TAGS_ENV: &str = "AGENT_TAGS";
+const AGENT_EXEC_HELPER_PATH_ENV: &str = "AGENT_EXEC_HELPER_PATH";
+const AGENT_CGROUP_PARENT_ENV: &str = "AGENT_CGROUP_PARENT";
 const AGENT_IDENTITY_BUNDLE_PATH_ENV: &str = "AGENT_IDENTITY_BUNDLE_PATH";
+const AGENT_NOISE_PRIVATE_KEY_ENV: &str = "AGENT_NOISE_PRIVATE_KEY";
 const AGENT_PEER_ATTESTATION_POLICY_PATH_ENV: &str = "AGENT_PEER_ATTESTATION_POLICY_PATH";
 const AGENT_POLICY_KEYS_PATH_ENV: &str = "AGENT_POLICY_KEYS_PATH";
+const AGENT_POLICY_SOURCE_ENV: &str = "AGENT_POLICY_SOURCE";
 const DEFAULT_AGENT_IDENTITY_BUNDLE_PATH: &str = "./identity-bundle.json";
 const DEFAULT_AGENT_POLICY_KEYS_PATH: &str = "./policy-keys.json";
 
@@ -28,7 +50,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let shutdown = signal::shutdown_signal();
     tokio::pin!(shutdown);
 
-    let addr = format!("127.0.0.1:{}", DEFAULT_SERVER_PORT);
+    let config = AlaricConfig::load()?;
     let agent_id = AgentId::new(env::var("AGENT_ID").unwrap_or_else(|_| "agent-default".into()))?;
     let auth_key_id = env::var("AGENT_AUTH_KEY_ID")
         .map_err(|_| "AGENT_AUTH_KEY_ID must be set for handshake authentication")?;
@@ -36,14 +58,56 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .map_err(|_| "AGENT_AUTH_PRIVATE_KEY must be set for handshake authentication")?;
     let policy_path =
         env::var("AGENT_POLICY_PATH").unwrap_or_else(|_| "./agent-policy.json".to_string());
-    let policy = Policy::load(&policy_path)?;
-    info!("loaded policy from {}", policy_path);
+    let policy_source = load_policy_source()?;
+    let trusted_keys = TrustedPolicyKeys::load_default()?;
+    let (reloader, policy) = match policy_source {
+        PolicySource::File => PolicyReloader::load(policy_path.clone(), trusted_keys)?,
+        PolicySource::Relay => PolicyReloader::load_cached(policy_path.clone(), trusted_keys),
+    };
+    match reloader.expires_at_unix() {
+        Some(expires_at_unix) => info!(
+            "loaded policy from {} (serial={}, expires_at_unix={})",
+            policy_path,
+            reloader.serial().unwrap_or_default(),
+            expires_at_unix
+        ),
+        None => info!(
+            "no usable policy cached at {}; waiting for the relay to deliver one",
+            policy_path
+        ),
+    }
+    let policy_updates = match policy_source {
+        PolicySource::File => None,
+        PolicySource::Relay => Some(reloader.remote_bundles()),
+    };
+    tokio::spawn(reloader.run());
+    if let Ok(path) = env::var(AGENT_EXEC_HELPER_PATH_ENV) {
+        set_exec_helper_path(path.into());
+    }
+    if let Ok(path) = env::var(AGENT_CGROUP_PARENT_ENV) {
+        set_cgroup_parent(path.into());
+    }
     let attestation_policy = load_agent_peer_attestation_policy()?;
     let identity_bundle = load_agent_identity_bundle()?;
+    let noise_private_key = load_noise_private_key(&agent_id, identity_bundle.as_ref())?;
+    let context = Arc::new(AgentContext {
+        relay: config.relay,
+        session: config.session,
+        agent_id,
+        auth_key_id,
+        auth_private_key,
+        noise_private_key,
+        attestation_policy,
+        identity_bundle,
+        policy,
+        policy_updates,
+    });
 
+    // After a connection ends, start with the next relay so a failed one is not retried first.
+    let mut next_endpoint = 0;
     loop {
         let connect_result = tokio::select! {
-            result = TcpStream::connect(&addr) => result,
+            result = context.relay.connect_from(next_endpoint) => result,
             _ = &mut shutdown => {
                 info!("shutdown signal received before connect, exiting");
                 break;
@@ -51,17 +115,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
         };
 
         match connect_result {
-            Ok(stream) => {
+            Ok((stream, endpoint)) => {
+                next_endpoint = endpoint + 1;
                 tokio::select! {
-                    result = connection_loop(
-                        stream,
-                        agent_id.clone(),
-                        &auth_key_id,
-                        &auth_private_key,
-                        &attestation_policy,
-                        &identity_bundle,
-                        &policy,
-                    ) => {
+                    result = connection_loop(stream, endpoint, &context) => {
                         if let Err(err) = result {
                             error!("connection error: {}", err);
                         }
@@ -78,7 +135,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
 
         tokio::select! {
-            _ = sleep(Duration::from_secs(1)) => {}
+            _ = sleep(context.relay.reconnect_delay()) => {}
             _ = &mut shutdown => {
                 info!("shutdown signal received, exiting");
                 break;
@@ -89,87 +146,233 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn connection_loop(
-    mut stream: TcpStream,
+struct AgentContext {
+    relay: RelayConfig,
+    session: SessionConfig,
     agent_id: AgentId,
-    auth_key_id: &str,
-    auth_private_key: &str,
-    attestation_policy: &PeerAttestationPolicy,
-    identity_bundle: &Option<IdentityBundle>,
-    policy: &Policy,
+    auth_key_id: String,
+    auth_private_key: String,
+    // Without a long-term key each tunnel uses a fresh Noise static key.
+    noise_private_key: Option<String>,
+    attestation_policy: PeerAttestationPolicy,
+    identity_bundle: Option<IdentityBundle>,
+    policy: SharedPolicy,
+    // Set when the relay distributes policy; bundles it sends are handed to the reloader.
+    policy_updates: Option<mpsc::Sender<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PolicySource {
+    File,
+    Relay,
+}
+
+async fn connection_loop(
+    mut stream: RelayStream,
+    endpoint: usize,
+    context: &Arc<AgentContext>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    info!("connected to {}", stream.peer_addr()?);
-    let request = agent_handshake_request(agent_id.clone(), policy);
-    write_json_frame(&mut stream, &request).await?;
+    info!(
+        "connected to relay {} ({})",
+        context.relay.endpoints[endpoint],
+        stream.peer_addr()?
+    );
+    // Subscribe before announcing so a reload racing the handshake is not missed.
+    let mut policy = context.policy.clone();
+    let announced = agent_metadata(policy.current().as_deref());
+    let request = agent_handshake_request(context.agent_id.clone(), announced.clone())
+        .with_max_message_bytes(context.session.max_message_bytes);
+    let accepted = authenticate_with_relay(&mut stream, &request, context).await?;
+    info!(
+        "handshake accepted (agent_id={}, session_id={}); waiting for client tunnels",
+        context.agent_id, accepted.session_id
+    );
+    if context.policy_updates.is_some() {
+        write_json_frame(&mut stream, &AgentControlMessage::FetchPolicy).await?;
+    }
 
-    let challenge = match read_json_frame::<_, HandshakeResponse>(&mut stream).await? {
-        HandshakeResponse::Challenge(challenge) => challenge,
-        HandshakeResponse::Rejected(rejected) => {
-            let rejected_code = format!("{:?}", rejected.code);
-            return Err(format!(
-                "handshake rejected for agent {} ({}): {}",
-                agent_id, rejected_code, rejected.message
+    let (mut reader, mut writer) = split(stream);
+    let announce_loop = async {
+        let mut announced = announced;
+        while policy.changed().await {
+            let metadata = agent_metadata(policy.current().as_deref());
+            if metadata == announced {
+                continue;
+            }
+            info!("policy changed; re-announcing capabilities to the relay");
+            write_json_frame(
+                &mut writer,
+                &AgentControlMessage::UpdateMetadata {
+                    metadata: metadata.clone(),
+                },
             )
-            .into());
+            .await?;
+            announced = metadata;
         }
-        HandshakeResponse::Accepted(accepted) => {
-            info!(
-                "handshake accepted (agent_id={}, session_id={})",
-                agent_id, accepted.session_id
-            );
-            run_secure_session(
-                &mut stream,
-                policy,
-                Keypair::default_keypair(),
-                accepted.session_id,
-                &agent_id,
-                auth_key_id,
-                auth_private_key,
-                attestation_policy,
-                identity_bundle.as_ref(),
+        std::future::pending::<Result<(), Box<dyn Error + Send + Sync>>>().await
+    };
+    let tunnel_loop = async {
+        let mut tunnels = JoinSet::new();
+        loop {
+            let message = read_json_message::<_, RelayControlMessage>(
+                &mut reader,
+                accepted.max_message_bytes,
             )
             .await?;
-            return Ok(());
+            while tunnels.try_join_next().is_some() {}
+
+            match message {
+                RelayControlMessage::OpenTunnel {
+                    session_id,
+                    client_id,
+                    allowed_commands,
+                } => {
+                    info!(
+                        "opening tunnel for client {} (session_id={})",
+                        client_id, session_id
+                    );
+                    let context = Arc::clone(context);
+                    tunnels.spawn(async move {
+                        if let Err(err) = serve_tunnel(
+                            &context,
+                            endpoint,
+                            session_id,
+                            client_id,
+                            allowed_commands,
+                        )
+                        .await
+                        {
+                            error!("tunnel error (session_id={}): {}", session_id, err);
+                        }
+                    });
+                }
+                RelayControlMessage::PolicyBundle { bundle } => {
+                    match (&context.policy_updates, bundle) {
+                        (Some(updates), Some(bundle)) => {
+                            if updates.send(bundle).await.is_err() {
+                                warn!("policy reloader stopped; dropping bundle from relay");
+                            }
+                        }
+                        (Some(_), None) => {
+                            warn!("relay has no policy bundle assigned to this agent");
+                        }
+                        (None, _) => {}
+                    }
+                }
+            }
         }
     };
 
-    let proof = build_auth_proof_ed25519(&request, &challenge, auth_key_id, auth_private_key)?;
-    write_json_frame(&mut stream, &HandshakeProofRequest::new(proof)).await?;
+    tokio::select! {
+        result = announce_loop => result,
+        result = tunnel_loop => result,
+    }
+}
+
+// Tunnels must reach the relay holding the control connection; it is the one that paired them.
+async fn serve_tunnel(
+    context: &AgentContext,
+    endpoint: usize,
+    session_id: SessionId,
+    client_id: ClientId,
+    allowed_commands: Option<Vec<CommandId>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut stream = context.relay.connect_endpoint(endpoint).await?;
+    let request = HandshakeRequest::agent_tunnel(context.agent_id.clone(), session_id);
+    let accepted = authenticate_with_relay(&mut stream, &request, context).await?;
+    if accepted.session_id != session_id {
+        return Err(format!(
+            "relay accepted tunnel for session {}, expected {}",
+            accepted.session_id, session_id
+        )
+        .into());
+    }
 
-    let session_id = match read_json_frame::<_, HandshakeResponse>(&mut stream).await? {
-        HandshakeResponse::Accepted(accepted) => {
-            info!(
-                "handshake accepted (agent_id={}, session_id={})",
-                agent_id, accepted.session_id
-            );
-            accepted.session_id
+    let static_keypair = match &context.noise_private_key {
+        Some(private_key) => noise_static_keypair_from_hex(private_key)?,
+        None => Keypair::default_keypair(),
+    };
+    run_secure_session(
+        &mut stream,
+        &context.policy,
+        static_keypair,
+        &context.session,
+        &context.agent_id,
+        &context.auth_key_id,
+        &context.auth_private_key,
+        &TunnelContext {
+            session_id,
+            client_id,
+            allowed_commands: allowed_commands.as_deref(),
+            attestation_policy: &context.attestation_policy,
+            identity_bundle: context.identity_bundle.as_ref(),
+        },
+    )
+    .await?;
+    info!("tunnel closed (session_id={})", session_id);
+    Ok(())
+}
+
+async fn authenticate_with_relay(
+    stream: &mut RelayStream,
+    request: &HandshakeRequest,
+    context: &AgentContext,
+) -> Result<HandshakeAccepted, Box<dyn Error + Send + Sync>> {
+    timeout(
+        context.relay.handshake_timeout(),
+        perform_handshake(stream, request, context),
+    )
+    .await
+    .map_err(|_| "relay handshake timed out")?
+}
+
+async fn perform_handshake(
+    stream: &mut RelayStream,
+    request: &HandshakeRequest,
+    context: &AgentContext,
+) -> Result<HandshakeAccepted, Box<dyn Error + Send + Sync>> {
+    write_json_frame(stream, request).await?;
+
+    let response = match read_json_frame::<_, HandshakeResponse>(stream).await? {
+        HandshakeResponse::Challenge(challenge) => {
+            let proof = build_auth_proof_ed25519(
+                request,
+                &challenge,
+                &context.auth_key_id,
+                &context.auth_private_key,
+            )?;
+            write_json_frame(stream, &HandshakeProofRequest::new(proof)).await?;
+            read_json_frame::<_, HandshakeResponse>(stream).await?
         }
+        other => other,
+    };
+
+    match response {
+        HandshakeResponse::Accepted(accepted) => Ok(accepted),
         HandshakeResponse::Rejected(rejected) => {
             let rejected_code = format!("{:?}", rejected.code);
-            return Err(format!(
+            Err(format!(
                 "handshake rejected for agent {} ({}): {}",
-                agent_id, rejected_code, rejected.message
+                context.agent_id, rejected_code, rejected.message
             )
-            .into());
+            .into())
         }
         HandshakeResponse::Challenge(_) => {
-            return Err("unexpected second handshake challenge from server".into());
+            Err("unexpected second handshake challenge from server".into())
         }
-    };
+    }
+}
 
-    run_secure_session(
-        &mut stream,
-        policy,
-        Keypair::default_keypair(),
-        session_id,
-        &agent_id,
-        auth_key_id,
-        auth_private_key,
-        attestation_policy,
-        identity_bundle.as_ref(),
-    )
-    .await?;
-    Ok(())
+fn load_policy_source() -> Result<PolicySource, Box<dyn Error>> {
+    match env::var(AGENT_POLICY_SOURCE_ENV).ok().as_deref() {
+        None | Some("file") => Ok(PolicySource::File),
+        Some("relay") => Ok(PolicySource::Relay),
+        Some(other) => Err(format!(
+            "invalid {} '{}'; expected 'file' or 'relay'",
+            AGENT_POLICY_SOURCE_ENV, other
+        )
+        .into()),
+    }
 }
 
 fn load_agent_peer_attestation_policy() -> Result<PeerAttestationPolicy, Box<dyn Error>> {
@@ -212,23 +415,71 @@ fn load_agent_identity_bundle() -> Result<Option<IdentityBundle>, Box<dyn Error>
     Ok(Some(identity_bundle))
 }
 
-fn agent_handshake_request(agent_id: AgentId, policy: &Policy) -> HandshakeRequest {
-    let mut request = HandshakeRequest::agent(agent_id);
-    let capabilities = collect_capabilities(policy);
-    let tags = parse_csv_values(env::var(AGENT_TAGS_ENV).ok());
-
-    if let HandshakeRequest::Agent { metadata, .. } = &mut request {
-        if !capabilities.is_empty() {
-            metadata.insert("capabilities".to_string(), capabilities.join(","));
-        }
-        if !tags.is_empty() {
-            metadata.insert("tags".to_string(), tags.join(","));
+fn load_noise_private_key(
+    agent_id: &AgentId,
+    identity_bundle: Option<&IdentityBundle>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let bound_static_key = identity_bundle
+        .and_then(|bundle| bundle.agent_identity_key(agent_id))
+        .and_then(|identity| identity.noise_public_key);
+    let Ok(private_key) = env::var(AGENT_NOISE_PRIVATE_KEY_ENV) else {
+        if bound_static_key.is_some() {
+            return Err(format!(
+                "identity bundle binds a noise static key to agent '{}' but {} is not set",
+                agent_id, AGENT_NOISE_PRIVATE_KEY_ENV
+            )
+            .into());
         }
+        info!(
+            "{} not set; tunnels will use ephemeral noise static keys",
+            AGENT_NOISE_PRIVATE_KEY_ENV
+        );
+        return Ok(None);
+    };
+
+    let public_key = noise_static_keypair_from_hex(&private_key)?
+        .get_public_key()
+        .as_bytes();
+    if bound_static_key.is_some_and(|bound| bound != public_key) {
+        return Err(format!(
+            "{} does not match the noise static key bound to agent '{}' in the identity bundle",
+            AGENT_NOISE_PRIVATE_KEY_ENV, agent_id
+        )
+        .into());
     }
+    info!(
+        "loaded noise static key (public key {})",
+        hex::encode(public_key)
+    );
+    Ok(Some(private_key))
+}
 
+fn agent_handshake_request(
+    agent_id: AgentId,
+    agent_metadata: BTreeMap<String, String>,
+) -> HandshakeRequest {
+    let mut request = HandshakeRequest::agent(agent_id);
+    if let HandshakeRequest::Agent { metadata, .. } = &mut request {
+        metadata.extend(agent_metadata);
+    }
     request
 }
 
+// An expired policy advertises no capabilities, so discovery stops routing to this agent.
+fn agent_metadata(policy: Option<&Policy>) -> BTreeMap<String, String> {
+    let mut metadata = BTreeMap::new();
+    let capabilities = policy.map(collect_capabilities).unwrap_or_default();
+    let tags = parse_csv_values(env::var(AGENT_TAGS_ENV).ok());
+
+    if !capabilities.is_empty() {
+        metadata.insert("capabilities".to_string(), capabilities.join(","));
+    }
+    if !tags.is_empty() {
+        metadata.insert("tags".to_string(), tags.join(","));
+    }
+    metadata
+}
+
 fn collect_capabilities(policy: &Policy) -> Vec<String> {
     let mut capabilities = BTreeSet::new();
     for command in &policy.commands {
@@ -3,26 +3,38 @@ use std::{
     collections::HashSet,
     error::Error,
     fmt, fs, io,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use glob::{MatchOptions, Pattern};
 use hacl_star::ed25519::{self, PublicKey};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::sandbox::seccomp_filter;
+
 const POLICY_VERSION_V1: u16 = 1;
 const POLICY_BUNDLE_VERSION_V1: u16 = 1;
+const POLICY_BUNDLE_VERSION_V2: u16 = 2;
 const POLICY_SIGNATURE_ALGORITHM_ED25519: &str = "ed25519";
 const POLICY_KEYS_PATH_ENV: &str = "AGENT_POLICY_KEYS_PATH";
 const DEFAULT_POLICY_KEYS_PATH: &str = "./policy-keys.json";
+const DEFAULT_MAX_INPUT_BYTES: usize = 1024 * 1024;
+const DEFAULT_PUT_MODE: u32 = 0o644;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Policy {
     pub version: u16,
     pub default_timeout_secs: u64,
     pub max_output_bytes: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_input_bytes: Option<usize>,
     pub commands: Vec<CommandSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fetch: Vec<FetchRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub put: Vec<PutRule>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +47,115 @@ pub struct CommandSpec {
     pub arg_specs: Vec<ArgSpec>,
     pub timeout_secs: Option<u64>,
     pub max_output_bytes: Option<usize>,
+    #[serde(default, skip_serializing_if = "StdinMode::is_none")]
+    pub stdin: StdinMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_input_bytes: Option<usize>,
+    // User and groups the command runs as, by name or numeric id. The group defaults to the
+    // user's primary group, and supplementary groups are dropped unless listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_as_user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_as_group: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub supplementary_groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_empty")]
+    pub limits: ResourceLimits,
+    #[serde(default, skip_serializing_if = "Sandbox::is_empty")]
+    pub sandbox: Sandbox,
+    // Absolute directory the command starts in; `/` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    // The environment holds only `PATH` plus these: fixed values, agent variables passed
+    // through by name, and client-supplied values checked like args. A name may appear once.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_passthrough: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_specs: Vec<ArgSpec>,
+}
+
+// The first four are rlimits on the command's process; `memory_max_bytes` and `pids_max`
+// cap the cgroup it runs in, where cgroup v2 is available.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_space_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_max_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids_max: Option<u64>,
+}
+
+// Isolation `alaric-exec` sets up before the command starts. `read_only_root` needs the mount
+// namespace, and `writable_paths` are the only places left writable under it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sandbox {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<Namespace>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only_root: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub writable_paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landlock: Option<LandlockRules>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_syscalls: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_new_privs: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Namespace {
+    Mount,
+    Pid,
+    Network,
+}
+
+// Paths the command may read (and execute) or also write beneath; everything else is denied.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LandlockRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub read_write: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StdinMode {
+    #[default]
+    None,
+    Stream,
+    Fixed {
+        data: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchRule {
+    pub path: String,
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PutRule {
+    pub directory: String,
+    pub max_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,30 +173,44 @@ pub enum ValidationRule {
     Enum { values: Vec<String> },
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct SignedPolicyBundle {
-    bundle_version: u16,
-    expires_at_unix: u64,
-    policy: Policy,
-    signature: PolicySignature,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPolicyBundle {
+    pub bundle_version: u16,
+    pub expires_at_unix: u64,
+    // Signed from bundle version 2 and must grow with every bundle signed for an agent; agents
+    // refuse to go back to a lower one. Version 1 bundles have none and count as serial 0.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub serial: u64,
+    pub policy: Policy,
+    pub signature: PolicySignature,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct PolicySignature {
-    key_id: String,
-    algorithm: String,
-    value: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicySignature {
+    pub key_id: String,
+    pub algorithm: String,
+    pub value: String,
 }
 
 #[derive(Debug, Serialize)]
 struct PolicySigningPayload<'a> {
     bundle_version: u16,
     expires_at_unix: u64,
+    // Left out for version 1 so that bundles signed before serials existed still verify.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serial: Option<u64>,
     key_id: &'a str,
     algorithm: &'a str,
     policy: &'a Policy,
 }
 
+#[derive(Debug, Clone)]
+pub struct LoadedPolicy {
+    pub policy: Policy,
+    pub expires_at_unix: u64,
+    pub serial: u64,
+}
+
 #[derive(Clone)]
 pub struct TrustedPolicyKeys {
     keys: BTreeMap<String, PublicKey>,
@@ -185,38 +320,35 @@ impl Policy {
         Self::load_with_keys_at(path, trusted_keys, now_unix)
     }
 
+    pub fn load_bundle(
+        path: impl AsRef<Path>,
+        trusted_keys: &TrustedPolicyKeys,
+    ) -> Result<LoadedPolicy, PolicyError> {
+        let now_unix = current_unix_timestamp()?;
+        Self::load_bundle_at(path, trusted_keys, now_unix)
+    }
+
     fn load_with_keys_at(
         path: impl AsRef<Path>,
         trusted_keys: &TrustedPolicyKeys,
         now_unix: u64,
     ) -> Result<Self, PolicyError> {
-        let path = path.as_ref().to_path_buf();
-        let raw = fs::read_to_string(&path).map_err(|source| PolicyError::Io {
-            path: path.clone(),
-            source,
-        })?;
-
-        let bundle: SignedPolicyBundle = match serde_json::from_str(&raw) {
-            Ok(bundle) => bundle,
-            Err(source) => {
-                if serde_json::from_str::<Policy>(&raw).is_ok() {
-                    return Err(PolicyError::Invalid(
-                        "unsigned policy bundles are rejected; expected a signed bundle envelope"
-                            .to_string(),
-                    ));
-                }
-
-                return Err(PolicyError::Parse {
-                    path: path.clone(),
-                    source,
-                });
-            }
-        };
-
-        validate_bundle(&bundle, trusted_keys, now_unix)?;
-        bundle.policy.validate()?;
+        Self::load_bundle_at(path, trusted_keys, now_unix).map(|loaded| loaded.policy)
+    }
 
-        Ok(bundle.policy)
+    fn load_bundle_at(
+        path: impl AsRef<Path>,
+        trusted_keys: &TrustedPolicyKeys,
+        now_unix: u64,
+    ) -> Result<LoadedPolicy, PolicyError> {
+        let bundle = SignedPolicyBundle::load(path)?;
+        bundle.verify_at(trusted_keys, now_unix)?;
+
+        Ok(LoadedPolicy {
+            policy: bundle.policy,
+            expires_at_unix: bundle.expires_at_unix,
+            serial: bundle.serial,
+        })
     }
 
     pub fn validate(&self) -> Result<(), PolicyError> {
@@ -239,6 +371,12 @@ impl Policy {
             ));
         }
 
+        if matches!(self.max_input_bytes, Some(0)) {
+            return Err(PolicyError::Invalid(
+                "max_input_bytes must be greater than 0".to_string(),
+            ));
+        }
+
         if self.commands.is_empty() {
             return Err(PolicyError::Invalid(
                 "commands must include at least one entry".to_string(),
@@ -277,6 +415,27 @@ impl Policy {
                     command.id
                 )));
             }
+            if matches!(command.max_input_bytes, Some(0)) {
+                return Err(PolicyError::Invalid(format!(
+                    "command '{}' max_input_bytes must be greater than 0",
+                    command.id
+                )));
+            }
+            if let StdinMode::Fixed { data } = &command.stdin {
+                let max_input_bytes = command.effective_max_input_bytes(self.max_input_bytes);
+                if data.len() > max_input_bytes {
+                    return Err(PolicyError::Invalid(format!(
+                        "command '{}' fixed stdin is {} bytes, above the {} byte input limit",
+                        command.id,
+                        data.len(),
+                        max_input_bytes
+                    )));
+                }
+            }
+            validate_credentials(command)?;
+            validate_limits(command)?;
+            validate_sandbox(command)?;
+            validate_environment(command)?;
 
             let mut arg_names = HashSet::new();
             for arg in &command.arg_specs {
@@ -294,35 +453,275 @@ impl Policy {
                 }
 
                 if let Some(rule) = &arg.validation {
-                    validate_rule(command, arg, rule)?;
+                    validate_rule(command, "arg", arg, rule)?;
                 }
             }
         }
 
+        for rule in &self.fetch {
+            validate_path_glob("fetch", &rule.path)?;
+            if rule.max_bytes == 0 {
+                return Err(PolicyError::Invalid(format!(
+                    "fetch rule '{}' max_bytes must be greater than 0",
+                    rule.path
+                )));
+            }
+        }
+
+        for rule in &self.put {
+            if !Path::new(&rule.directory).is_absolute() {
+                return Err(PolicyError::Invalid(format!(
+                    "put directory '{}' must be absolute",
+                    rule.directory
+                )));
+            }
+            if rule.max_bytes == 0 {
+                return Err(PolicyError::Invalid(format!(
+                    "put rule '{}' max_bytes must be greater than 0",
+                    rule.directory
+                )));
+            }
+            if let Some(mode) = &rule.mode
+                && parse_file_mode(mode).is_none()
+            {
+                return Err(PolicyError::Invalid(format!(
+                    "put rule '{}' mode '{}' must be octal permission bits no greater than 0777",
+                    rule.directory, mode
+                )));
+            }
+            if matches!(rule.owner.as_deref(), Some(""))
+                || matches!(rule.group.as_deref(), Some(""))
+            {
+                return Err(PolicyError::Invalid(format!(
+                    "put rule '{}' owner and group must not be empty",
+                    rule.directory
+                )));
+            }
+        }
+
         Ok(())
     }
 
+    #[must_use]
+    pub fn fetch_rule_for(&self, path: &Path) -> Option<&FetchRule> {
+        self.fetch
+            .iter()
+            .find(|rule| path_matches_glob(&rule.path, path))
+    }
+
+    // Uploads land directly inside an allowed directory, never in a subdirectory of it.
+    #[must_use]
+    pub fn put_rule_for(&self, path: &Path) -> Option<&PutRule> {
+        let plain = path
+            .components()
+            .all(|component| matches!(component, Component::RootDir | Component::Normal(_)));
+        if !plain || !path.is_absolute() {
+            return None;
+        }
+        let parent = path.parent()?;
+        path.file_name()?;
+        self.put
+            .iter()
+            .find(|rule| Path::new(&rule.directory) == parent)
+    }
+
     #[must_use]
     pub fn command_by_id(&self, id: &str) -> Option<&CommandSpec> {
         self.commands.iter().find(|command| command.id == id)
     }
 }
 
+impl ResourceLimits {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.cpu_secs.is_none()
+            && self.address_space_bytes.is_none()
+            && self.open_files.is_none()
+            && self.processes.is_none()
+            && self.memory_max_bytes.is_none()
+            && self.pids_max.is_none()
+    }
+
+    #[must_use]
+    pub const fn needs_cgroup(&self) -> bool {
+        self.memory_max_bytes.is_some() || self.pids_max.is_some()
+    }
+}
+
+impl Sandbox {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.namespaces.is_empty()
+            && !self.read_only_root
+            && self.writable_paths.is_empty()
+            && self.landlock.is_none()
+            && self.deny_syscalls.is_empty()
+            && !self.no_new_privs
+    }
+
+    #[must_use]
+    pub fn has_namespace(&self, namespace: Namespace) -> bool {
+        self.namespaces.contains(&namespace)
+    }
+}
+
 impl CommandSpec {
     #[must_use]
     pub fn effective_timeout_secs(&self, policy_default: u64) -> u64 {
         self.timeout_secs.unwrap_or(policy_default)
     }
 
+    #[must_use]
+    pub const fn drops_privileges(&self) -> bool {
+        self.run_as_user.is_some()
+            || self.run_as_group.is_some()
+            || !self.supplementary_groups.is_empty()
+    }
+
     #[must_use]
     pub fn effective_max_output_bytes(&self, policy_default: usize) -> usize {
         self.max_output_bytes.unwrap_or(policy_default)
     }
 
+    #[must_use]
+    pub fn effective_max_input_bytes(&self, policy_default: Option<usize>) -> usize {
+        self.max_input_bytes
+            .or(policy_default)
+            .unwrap_or(DEFAULT_MAX_INPUT_BYTES)
+    }
+
+    // Bytes a client may stream to this command; zero unless stdin is `stream`.
+    #[must_use]
+    pub fn streamed_input_limit(&self, policy_default: Option<usize>) -> usize {
+        match self.stdin {
+            StdinMode::Stream => self.effective_max_input_bytes(policy_default),
+            StdinMode::None | StdinMode::Fixed { .. } => 0,
+        }
+    }
+
     #[must_use]
     pub fn arg_spec(&self, name: &str) -> Option<&ArgSpec> {
         self.arg_specs.iter().find(|arg| arg.name == name)
     }
+
+    #[must_use]
+    pub fn env_spec(&self, name: &str) -> Option<&ArgSpec> {
+        self.env_specs.iter().find(|spec| spec.name == name)
+    }
+}
+
+fn unsigned_bundle_error(raw: &str) -> Option<PolicyError> {
+    serde_json::from_str::<Policy>(raw).ok().map(|_| {
+        PolicyError::Invalid(
+            "unsigned policy bundles are rejected; expected a signed bundle envelope".to_string(),
+        )
+    })
+}
+
+impl SignedPolicyBundle {
+    // Signs a policy that passes the same validation the agent applies at load time.
+    pub fn sign(
+        policy: Policy,
+        key_id: &str,
+        secret_key_hex: &str,
+        expires_at_unix: u64,
+        serial: u64,
+    ) -> Result<Self, PolicyError> {
+        policy.validate()?;
+        if key_id.trim().is_empty() {
+            return Err(PolicyError::Invalid(
+                "policy signature key_id must not be empty".to_string(),
+            ));
+        }
+
+        let secret_key =
+            decode_hex_array::<{ ed25519::SECRET_LENGTH }>("policy signing key", secret_key_hex)?;
+        let payload = serde_json::to_vec(&PolicySigningPayload {
+            bundle_version: POLICY_BUNDLE_VERSION_V2,
+            expires_at_unix,
+            serial: Some(serial),
+            key_id,
+            algorithm: POLICY_SIGNATURE_ALGORITHM_ED25519,
+            policy: &policy,
+        })
+        .map_err(|source| {
+            PolicyError::Invalid(format!(
+                "failed to serialize policy signature payload: {}",
+                source
+            ))
+        })?;
+        let signature = ed25519::SecretKey(secret_key).signature(&payload);
+
+        Ok(Self {
+            bundle_version: POLICY_BUNDLE_VERSION_V2,
+            expires_at_unix,
+            serial,
+            policy,
+            signature: PolicySignature {
+                key_id: key_id.to_string(),
+                algorithm: POLICY_SIGNATURE_ALGORITHM_ED25519.to_string(),
+                value: hex::encode(signature.0),
+            },
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PolicyError> {
+        let path = path.as_ref().to_path_buf();
+        let raw = fs::read_to_string(&path).map_err(|source| PolicyError::Io {
+            path: path.clone(),
+            source,
+        })?;
+
+        serde_json::from_str(&raw).map_err(|source| {
+            unsigned_bundle_error(&raw).unwrap_or(PolicyError::Parse { path, source })
+        })
+    }
+
+    pub fn from_json(raw: &str) -> Result<Self, PolicyError> {
+        serde_json::from_str(raw).map_err(|source| {
+            unsigned_bundle_error(raw).unwrap_or_else(|| {
+                PolicyError::Invalid(format!("failed to parse policy bundle: {}", source))
+            })
+        })
+    }
+
+    pub fn verify(&self, trusted_keys: &TrustedPolicyKeys) -> Result<(), PolicyError> {
+        self.verify_at(trusted_keys, current_unix_timestamp()?)
+    }
+
+    fn verify_at(
+        &self,
+        trusted_keys: &TrustedPolicyKeys,
+        now_unix: u64,
+    ) -> Result<(), PolicyError> {
+        validate_bundle(self, trusted_keys, now_unix)?;
+        self.policy.validate()
+    }
+}
+
+impl LoadedPolicy {
+    #[must_use]
+    pub const fn is_expired_at(&self, now_unix: u64) -> bool {
+        self.expires_at_unix <= now_unix
+    }
+}
+
+impl PutRule {
+    // Permission bits applied to uploads unless the client asks for fewer.
+    #[must_use]
+    pub fn effective_mode(&self) -> u32 {
+        self.mode
+            .as_deref()
+            .and_then(parse_file_mode)
+            .unwrap_or(DEFAULT_PUT_MODE)
+    }
+}
+
+impl StdinMode {
+    #[must_use]
+    pub const fn is_none(&self) -> bool {
+        matches!(self, Self::None)
+    }
 }
 
 fn validate_bundle(
@@ -330,12 +729,21 @@ fn validate_bundle(
     trusted_keys: &TrustedPolicyKeys,
     now_unix: u64,
 ) -> Result<(), PolicyError> {
-    if bundle.bundle_version != POLICY_BUNDLE_VERSION_V1 {
-        return Err(PolicyError::Invalid(format!(
-            "unsupported policy bundle version {}; expected {}",
-            bundle.bundle_version, POLICY_BUNDLE_VERSION_V1
-        )));
-    }
+    let signed_serial = match bundle.bundle_version {
+        POLICY_BUNDLE_VERSION_V1 if bundle.serial != 0 => {
+            return Err(PolicyError::Invalid(
+                "policy bundle version 1 cannot carry a serial".to_string(),
+            ));
+        }
+        POLICY_BUNDLE_VERSION_V1 => None,
+        POLICY_BUNDLE_VERSION_V2 => Some(bundle.serial),
+        version => {
+            return Err(PolicyError::Invalid(format!(
+                "unsupported policy bundle version {}; expected {} or {}",
+                version, POLICY_BUNDLE_VERSION_V1, POLICY_BUNDLE_VERSION_V2
+            )));
+        }
+    };
 
     if bundle.expires_at_unix <= now_unix {
         return Err(PolicyError::Invalid(format!(
@@ -370,6 +778,7 @@ fn validate_bundle(
     let payload = serde_json::to_vec(&PolicySigningPayload {
         bundle_version: bundle.bundle_version,
         expires_at_unix: bundle.expires_at_unix,
+        serial: signed_serial,
         key_id: &bundle.signature.key_id,
         algorithm: &bundle.signature.algorithm,
         policy: &bundle.policy,
@@ -391,6 +800,10 @@ fn validate_bundle(
     Ok(())
 }
 
+const fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
 fn decode_hex_array<const N: usize>(field: &str, value: &str) -> Result<[u8; N], PolicyError> {
     let bytes = hex::decode(value).map_err(|source| {
         PolicyError::Invalid(format!("{} is not valid hex: {}", field, source))
@@ -410,7 +823,7 @@ fn decode_hex_array<const N: usize>(field: &str, value: &str) -> Result<[u8; N],
     Ok(out)
 }
 
-fn current_unix_timestamp() -> Result<u64, PolicyError> {
+pub(crate) fn current_unix_timestamp() -> Result<u64, PolicyError> {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map(|duration| duration.as_secs())
@@ -419,8 +832,264 @@ fn current_unix_timestamp() -> Result<u64, PolicyError> {
         })
 }
 
+fn validate_path_glob(section: &str, pattern: &str) -> Result<(), PolicyError> {
+    if !pattern.starts_with('/') {
+        return Err(PolicyError::Invalid(format!(
+            "{} path '{}' must be absolute",
+            section, pattern
+        )));
+    }
+    Pattern::new(pattern).map_err(|err| {
+        PolicyError::Invalid(format!(
+            "{} path '{}' is not a valid glob: {}",
+            section, pattern, err
+        ))
+    })?;
+    Ok(())
+}
+
+#[must_use]
+pub fn parse_file_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|bits| *bits <= 0o777)
+}
+
+// Names are checked here; they are resolved against the agent's user database at spawn time.
+fn validate_credentials(command: &CommandSpec) -> Result<(), PolicyError> {
+    let names = command
+        .run_as_user
+        .iter()
+        .map(|name| ("run_as_user", name))
+        .chain(
+            command
+                .run_as_group
+                .iter()
+                .map(|name| ("run_as_group", name)),
+        )
+        .chain(
+            command
+                .supplementary_groups
+                .iter()
+                .map(|name| ("supplementary_groups", name)),
+        );
+    for (field, name) in names {
+        if !is_account_name(name) {
+            return Err(PolicyError::Invalid(format!(
+                "command '{}' {} '{}' must be a user or group name or a numeric id",
+                command.id, field, name
+            )));
+        }
+    }
+
+    if !command.supplementary_groups.is_empty()
+        && command.run_as_user.is_none()
+        && command.run_as_group.is_none()
+    {
+        return Err(PolicyError::Invalid(format!(
+            "command '{}' supplementary_groups requires run_as_user or run_as_group",
+            command.id
+        )));
+    }
+    let mut groups = HashSet::new();
+    for group in &command.supplementary_groups {
+        if !groups.insert(group.as_str()) {
+            return Err(PolicyError::Invalid(format!(
+                "command '{}' lists supplementary group '{}' more than once",
+                command.id, group
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_limits(command: &CommandSpec) -> Result<(), PolicyError> {
+    let limits = &command.limits;
+    for (field, value) in [
+        ("cpu_secs", limits.cpu_secs),
+        ("address_space_bytes", limits.address_space_bytes),
+        ("open_files", limits.open_files),
+        ("processes", limits.processes),
+        ("memory_max_bytes", limits.memory_max_bytes),
+        ("pids_max", limits.pids_max),
+    ] {
+        if value == Some(0) {
+            return Err(PolicyError::Invalid(format!(
+                "command '{}' limits.{} must be greater than 0",
+                command.id, field
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_sandbox(command: &CommandSpec) -> Result<(), PolicyError> {
+    let sandbox = &command.sandbox;
+    let invalid = |message: String| {
+        PolicyError::Invalid(format!("command '{}' sandbox.{}", command.id, message))
+    };
+
+    let mut namespaces = HashSet::new();
+    for namespace in &sandbox.namespaces {
+        if !namespaces.insert(namespace) {
+            return Err(invalid(format!(
+                "namespaces lists {:?} more than once",
+                namespace
+            )));
+        }
+    }
+    if sandbox.read_only_root && !sandbox.has_namespace(Namespace::Mount) {
+        return Err(invalid(
+            "read_only_root requires the mount namespace".to_string(),
+        ));
+    }
+    if !sandbox.writable_paths.is_empty() && !sandbox.read_only_root {
+        return Err(invalid(
+            "writable_paths requires read_only_root".to_string(),
+        ));
+    }
+    validate_sandbox_paths("writable_paths", &sandbox.writable_paths).map_err(invalid)?;
+
+    if let Some(rules) = &sandbox.landlock {
+        if rules.read.is_empty() && rules.read_write.is_empty() {
+            return Err(invalid(
+                "landlock must list at least one read or read_write path".to_string(),
+            ));
+        }
+        validate_sandbox_paths("landlock.read", &rules.read).map_err(invalid)?;
+        validate_sandbox_paths("landlock.read_write", &rules.read_write).map_err(invalid)?;
+    }
+
+    let mut syscalls = HashSet::new();
+    for syscall in &sandbox.deny_syscalls {
+        if !syscalls.insert(syscall.as_str()) {
+            return Err(invalid(format!(
+                "deny_syscalls lists '{}' more than once",
+                syscall
+            )));
+        }
+    }
+    // Compiling the filter checks the names against this host's syscall table.
+    if !sandbox.deny_syscalls.is_empty() {
+        seccomp_filter(&sandbox.deny_syscalls)
+            .map_err(|err| invalid(format!("deny_syscalls is invalid: {}", err)))?;
+    }
+    Ok(())
+}
+
+fn validate_sandbox_paths(field: &str, paths: &[String]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for path in paths {
+        if !is_normal_absolute_path(path) {
+            return Err(format!(
+                "{} path '{}' must be absolute without '.' or '..'",
+                field, path
+            ));
+        }
+        if !seen.insert(path.as_str()) {
+            return Err(format!("{} lists '{}' more than once", field, path));
+        }
+    }
+    Ok(())
+}
+
+fn validate_environment(command: &CommandSpec) -> Result<(), PolicyError> {
+    if let Some(dir) = &command.working_dir
+        && !is_normal_absolute_path(dir)
+    {
+        return Err(PolicyError::Invalid(format!(
+            "command '{}' working_dir '{}' must be absolute without '.' or '..'",
+            command.id, dir
+        )));
+    }
+
+    let names = command
+        .env
+        .keys()
+        .map(|name| ("env", name))
+        .chain(
+            command
+                .env_passthrough
+                .iter()
+                .map(|name| ("env_passthrough", name)),
+        )
+        .chain(
+            command
+                .env_specs
+                .iter()
+                .map(|spec| ("env_specs", &spec.name)),
+        );
+    let mut seen = HashSet::new();
+    for (field, name) in names {
+        if !is_env_name(name) {
+            return Err(PolicyError::Invalid(format!(
+                "command '{}' {} name '{}' must be letters, digits and '_', not starting with a digit",
+                command.id, field, name
+            )));
+        }
+        // The agent supplies PATH, and loader variables would reach the root exec helper.
+        if name == "PATH" || name.starts_with("LD_") {
+            return Err(PolicyError::Invalid(format!(
+                "command '{}' {} must not set '{}'",
+                command.id, field, name
+            )));
+        }
+        if !seen.insert(name.as_str()) {
+            return Err(PolicyError::Invalid(format!(
+                "command '{}' sets environment variable '{}' more than once",
+                command.id, name
+            )));
+        }
+    }
+    if let Some((name, _)) = command.env.iter().find(|(_, value)| value.contains('\0')) {
+        return Err(PolicyError::Invalid(format!(
+            "command '{}' env '{}' must not contain NUL bytes",
+            command.id, name
+        )));
+    }
+
+    for spec in &command.env_specs {
+        if let Some(rule) = &spec.validation {
+            validate_rule(command, "env", spec, rule)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_normal_absolute_path(path: &str) -> bool {
+    path.starts_with('/')
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
+}
+
+fn is_env_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_account_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn path_matches_glob(pattern: &str, path: &Path) -> bool {
+    let options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_path_with(path, options))
+}
+
 fn validate_rule(
     command: &CommandSpec,
+    kind: &str,
     arg: &ArgSpec,
     rule: &ValidationRule,
 ) -> Result<(), PolicyError> {
@@ -429,23 +1098,23 @@ fn validate_rule(
             let wrapped_pattern = format!("^(?:{})$", pattern);
             Regex::new(&wrapped_pattern).map_err(|err| {
                 PolicyError::Invalid(format!(
-                    "command '{}' arg '{}' has invalid regex '{}': {}",
-                    command.id, arg.name, pattern, err
+                    "command '{}' {} '{}' has invalid regex '{}': {}",
+                    command.id, kind, arg.name, pattern, err
                 ))
             })?;
         }
         ValidationRule::Enum { values } => {
             if values.is_empty() {
                 return Err(PolicyError::Invalid(format!(
-                    "command '{}' arg '{}' enum must include at least one value",
-                    command.id, arg.name
+                    "command '{}' {} '{}' enum must include at least one value",
+                    command.id, kind, arg.name
                 )));
             }
 
             if values.iter().any(|value| value.is_empty()) {
                 return Err(PolicyError::Invalid(format!(
-                    "command '{}' arg '{}' enum contains an empty value",
-                    command.id, arg.name
+                    "command '{}' {} '{}' enum contains an empty value",
+                    command.id, kind, arg.name
                 )));
             }
 
@@ -453,8 +1122,8 @@ fn validate_rule(
             for value in values {
                 if !seen.insert(value) {
                     return Err(PolicyError::Invalid(format!(
-                        "command '{}' arg '{}' enum contains duplicate value '{}'",
-                        command.id, arg.name, value
+                        "command '{}' {} '{}' enum contains duplicate value '{}'",
+                        command.id, kind, arg.name, value
                     )));
                 }
             }
@@ -465,7 +1134,7 @@ fn validate_rule(
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::{
         collections::BTreeMap,
         fs,
@@ -476,8 +1145,9 @@ mod tests {
     use serde_json::json;
 
     use super::{
-        ArgSpec, CommandSpec, POLICY_SIGNATURE_ALGORITHM_ED25519, Policy, PolicyError,
-        PolicySigningPayload, TrustedPolicyKeys, ValidationRule,
+        ArgSpec, CommandSpec, FetchRule, LandlockRules, Namespace,
+        POLICY_SIGNATURE_ALGORITHM_ED25519, Policy, PolicyError, PolicySigningPayload, PutRule,
+        ResourceLimits, Sandbox, SignedPolicyBundle, StdinMode, TrustedPolicyKeys, ValidationRule,
     };
 
     const TEST_KEY_ID: &str = "control-plane-v1";
@@ -487,8 +1157,9 @@ mod tests {
         0x7f, 0x60,
     ];
     const NOW_UNIX: u64 = 1_800_000_000;
+    const TEST_SERIAL: u64 = 1;
 
-    fn temp_file_path(label: &str) -> std::path::PathBuf {
+    pub(crate) fn temp_file_path(label: &str) -> std::path::PathBuf {
         let nanos = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("system time after unix epoch")
@@ -496,17 +1167,20 @@ mod tests {
         std::env::temp_dir().join(format!("alaric-policy-{}-{}.json", label, nanos))
     }
 
-    fn write_temp_file(contents: &str, label: &str) -> std::path::PathBuf {
+    pub(crate) fn write_temp_file(contents: &str, label: &str) -> std::path::PathBuf {
         let path = temp_file_path(label);
         fs::write(&path, contents).expect("write policy fixture");
         path
     }
 
-    fn test_policy() -> Policy {
+    pub(crate) fn test_policy() -> Policy {
         let policy = Policy {
             version: 1,
             default_timeout_secs: 5,
             max_output_bytes: 2048,
+            max_input_bytes: None,
+            fetch: Vec::new(),
+            put: Vec::new(),
             commands: vec![CommandSpec {
                 id: "echo".to_string(),
                 program: "/bin/echo".to_string(),
@@ -520,13 +1194,24 @@ mod tests {
                 }],
                 timeout_secs: None,
                 max_output_bytes: None,
+                stdin: StdinMode::None,
+                max_input_bytes: None,
+                run_as_user: None,
+                run_as_group: None,
+                supplementary_groups: Vec::new(),
+                limits: ResourceLimits::default(),
+                sandbox: Sandbox::default(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                env_passthrough: Vec::new(),
+                env_specs: Vec::new(),
             }],
         };
         policy.validate().expect("fixture policy should validate");
         policy
     }
 
-    fn trusted_keys() -> TrustedPolicyKeys {
+    pub(crate) fn trusted_keys() -> TrustedPolicyKeys {
         let public_key = ed25519::SecretKey(TEST_SECRET_KEY).get_public();
         let trusted = json!({
             TEST_KEY_ID: hex::encode(public_key.0)
@@ -534,10 +1219,17 @@ mod tests {
         TrustedPolicyKeys::from_json_map(&trusted.to_string()).expect("trusted keys should parse")
     }
 
-    fn sign_bundle(policy: &Policy, key_id: &str, expires_at_unix: u64, algorithm: &str) -> String {
+    fn sign_bundle(
+        policy: &Policy,
+        key_id: &str,
+        expires_at_unix: u64,
+        serial: u64,
+        algorithm: &str,
+    ) -> String {
         let payload = serde_json::to_vec(&PolicySigningPayload {
-            bundle_version: 1,
+            bundle_version: 2,
             expires_at_unix,
+            serial: Some(serial),
             key_id,
             algorithm,
             policy,
@@ -552,12 +1244,14 @@ mod tests {
         key_id: &str,
         bundle_version: u16,
         expires_at_unix: u64,
+        serial: u64,
         algorithm: &str,
         signature_hex: &str,
     ) -> String {
         json!({
             "bundle_version": bundle_version,
             "expires_at_unix": expires_at_unix,
+            "serial": serial,
             "policy": policy,
             "signature": {
                 "key_id": key_id,
@@ -568,6 +1262,72 @@ mod tests {
         .to_string()
     }
 
+    pub(crate) fn signed_bundle(policy: &Policy, expires_at_unix: u64, serial: u64) -> String {
+        let signature = sign_bundle(
+            policy,
+            TEST_KEY_ID,
+            expires_at_unix,
+            serial,
+            POLICY_SIGNATURE_ALGORITHM_ED25519,
+        );
+        signed_bundle_json(
+            policy,
+            TEST_KEY_ID,
+            2,
+            expires_at_unix,
+            serial,
+            POLICY_SIGNATURE_ALGORITHM_ED25519,
+            &signature,
+        )
+    }
+
+    // A bundle as signed before serials existed: version 1, no serial in payload or envelope.
+    pub(crate) fn signed_v1_bundle(policy: &Policy, expires_at_unix: u64) -> String {
+        let payload = serde_json::to_vec(&PolicySigningPayload {
+            bundle_version: 1,
+            expires_at_unix,
+            serial: None,
+            key_id: TEST_KEY_ID,
+            algorithm: POLICY_SIGNATURE_ALGORITHM_ED25519,
+            policy,
+        })
+        .expect("payload should serialize");
+        let signature = ed25519::SecretKey(TEST_SECRET_KEY).signature(&payload);
+        json!({
+            "bundle_version": 1,
+            "expires_at_unix": expires_at_unix,
+            "policy": policy,
+            "signature": {
+                "key_id": TEST_KEY_ID,
+                "algorithm": POLICY_SIGNATURE_ALGORITHM_ED25519,
+                "value": hex::encode(signature.0)
+            }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn loads_bundle_signed_without_serial() {
+        let bundle = signed_v1_bundle(&test_policy(), NOW_UNIX + 300);
+        let path = write_temp_file(&bundle, "signed-v1");
+        let loaded = Policy::load_bundle_at(&path, &trusted_keys(), NOW_UNIX)
+            .expect("version 1 bundle should load");
+        assert_eq!(loaded.serial, 0);
+        assert_eq!(loaded.policy.commands[0].id, "echo");
+
+        // A serial added to a version 1 bundle is not covered by its signature.
+        let with_serial = bundle.replacen(
+            "\"bundle_version\":1,",
+            "\"bundle_version\":1,\"serial\":9,",
+            1,
+        );
+        assert_ne!(with_serial, bundle);
+        fs::write(&path, with_serial).expect("write bundle");
+        assert!(Policy::load_bundle_at(&path, &trusted_keys(), NOW_UNIX).is_err());
+
+        let _ = fs::remove_file(path);
+    }
+
     #[test]
     fn loads_valid_signed_bundle() {
         let policy = test_policy();
@@ -576,13 +1336,15 @@ mod tests {
             &policy,
             TEST_KEY_ID,
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
         );
         let bundle = signed_bundle_json(
             &policy,
             TEST_KEY_ID,
-            1,
+            2,
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
             &signature,
         );
@@ -596,6 +1358,39 @@ mod tests {
         let _ = fs::remove_file(path);
     }
 
+    #[test]
+    fn signed_bundle_round_trips_through_load() {
+        let bundle = SignedPolicyBundle::sign(
+            test_policy(),
+            TEST_KEY_ID,
+            &hex::encode(TEST_SECRET_KEY),
+            NOW_UNIX + 300,
+            TEST_SERIAL,
+        )
+        .expect("valid policy should sign");
+        let encoded = serde_json::to_string(&bundle).expect("bundle should serialize");
+        let path = write_temp_file(&encoded, "signed-round-trip");
+
+        let loaded = Policy::load_with_keys_at(&path, &trusted_keys(), NOW_UNIX)
+            .expect("signed bundle should load");
+        assert_eq!(loaded.commands[0].id, "echo");
+
+        let mut invalid = test_policy();
+        invalid.default_timeout_secs = 0;
+        assert!(
+            SignedPolicyBundle::sign(
+                invalid,
+                TEST_KEY_ID,
+                &hex::encode(TEST_SECRET_KEY),
+                NOW_UNIX + 300,
+                TEST_SERIAL
+            )
+            .is_err()
+        );
+
+        let _ = fs::remove_file(path);
+    }
+
     #[test]
     fn rejects_unsigned_bundle() {
         let trusted_keys = trusted_keys();
@@ -623,13 +1418,15 @@ mod tests {
             &policy,
             "unknown-key",
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
         );
         let bundle = signed_bundle_json(
             &policy,
             "unknown-key",
-            1,
+            2,
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
             &signature,
         );
@@ -650,14 +1447,16 @@ mod tests {
             &policy,
             TEST_KEY_ID,
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
         );
         signature.replace_range(..2, "00");
         let bundle = signed_bundle_json(
             &policy,
             TEST_KEY_ID,
-            1,
+            2,
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
             &signature,
         );
@@ -678,13 +1477,15 @@ mod tests {
             &policy,
             TEST_KEY_ID,
             NOW_UNIX - 1,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
         );
         let bundle = signed_bundle_json(
             &policy,
             TEST_KEY_ID,
-            1,
+            2,
             NOW_UNIX - 1,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
             &signature,
         );
@@ -705,13 +1506,15 @@ mod tests {
             &policy,
             TEST_KEY_ID,
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
         );
         let bundle = signed_bundle_json(
             &policy,
             TEST_KEY_ID,
-            2,
+            3,
             NOW_UNIX + 300,
+            TEST_SERIAL,
             POLICY_SIGNATURE_ALGORITHM_ED25519,
             &signature,
         );
@@ -724,6 +1527,221 @@ mod tests {
         let _ = fs::remove_file(path);
     }
 
+    #[test]
+    fn rejects_fixed_stdin_above_input_limit() {
+        let mut policy = test_policy();
+        policy.max_input_bytes = Some(4);
+        policy.commands[0].stdin = StdinMode::Fixed {
+            data: "too long".to_string(),
+        };
+
+        let err = policy
+            .validate()
+            .expect_err("oversized fixed stdin should fail");
+        assert!(matches!(err, PolicyError::Invalid(_)));
+    }
+
+    #[test]
+    fn validates_run_as_credentials() {
+        let mut policy = test_policy();
+        policy.commands[0].run_as_user = Some("nobody".to_string());
+        policy.commands[0].supplementary_groups = vec!["adm".to_string(), "1001".to_string()];
+        policy.validate().expect("credentials should validate");
+
+        policy.commands[0].run_as_user = Some("-nobody".to_string());
+        assert!(matches!(policy.validate(), Err(PolicyError::Invalid(_))));
+
+        policy.commands[0].run_as_user = None;
+        assert!(matches!(policy.validate(), Err(PolicyError::Invalid(_))));
+
+        policy.commands[0].run_as_group = Some("nogroup".to_string());
+        policy.commands[0].supplementary_groups = vec!["adm".to_string(), "adm".to_string()];
+        assert!(matches!(policy.validate(), Err(PolicyError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_zero_resource_limit() {
+        let mut policy = test_policy();
+        policy.commands[0].limits.open_files = Some(64);
+        policy.validate().expect("open_files limit should validate");
+
+        policy.commands[0].limits.memory_max_bytes = Some(0);
+        assert!(matches!(policy.validate(), Err(PolicyError::Invalid(_))));
+    }
+
+    #[test]
+    fn validates_sandbox_profile() {
+        let mut policy = test_policy();
+        policy.commands[0].sandbox = Sandbox {
+            namespaces: vec![Namespace::Mount, Namespace::Network],
+            read_only_root: true,
+            writable_paths: vec!["/tmp".to_string()],
+            landlock: Some(LandlockRules {
+                read: vec!["/usr".to_string()],
+                read_write: vec!["/tmp".to_string()],
+            }),
+            deny_syscalls: vec!["ptrace".to_string()],
+            no_new_privs: true,
+        };
+        policy.validate().expect("sandbox profile should validate");
+
+        let invalid = [
+            Sandbox {
+                read_only_root: true,
+                ..Sandbox::default()
+            },
+            Sandbox {
+                namespaces: vec![Namespace::Pid, Namespace::Pid],
+                ..Sandbox::default()
+            },
+            Sandbox {
+                namespaces: vec![Namespace::Mount],
+                read_only_root: true,
+                writable_paths: vec!["/srv/../etc".to_string()],
+                ..Sandbox::default()
+            },
+            Sandbox {
+                landlock: Some(LandlockRules::default()),
+                ..Sandbox::default()
+            },
+            Sandbox {
+                deny_syscalls: vec!["not_a_syscall".to_string()],
+                ..Sandbox::default()
+            },
+        ];
+        for sandbox in invalid {
+            policy.commands[0].sandbox = sandbox;
+            assert!(matches!(policy.validate(), Err(PolicyError::Invalid(_))));
+        }
+    }
+
+    #[test]
+    fn validates_command_environment() {
+        let mut policy = test_policy();
+        let command = &mut policy.commands[0];
+        command.working_dir = Some("/srv/app".to_string());
+        command.env = BTreeMap::from([("LANG".to_string(), "C.UTF-8".to_string())]);
+        command.env_passthrough = vec!["HOME".to_string()];
+        command.env_specs = vec![ArgSpec {
+            name: "APP_MODE".to_string(),
+            required: false,
+            validation: Some(ValidationRule::Enum {
+                values: vec!["fast".to_string(), "safe".to_string()],
+            }),
+        }];
+        policy.validate().expect("environment should validate");
+
+        let mut relative_dir = policy.clone();
+        relative_dir.commands[0].working_dir = Some("srv/app".to_string());
+        assert!(matches!(
+            relative_dir.validate(),
+            Err(PolicyError::Invalid(_))
+        ));
+
+        let mut bad_name = policy.clone();
+        bad_name.commands[0].env_passthrough = vec!["1PATH".to_string()];
+        assert!(matches!(bad_name.validate(), Err(PolicyError::Invalid(_))));
+
+        let mut duplicate = policy.clone();
+        duplicate.commands[0].env_passthrough = vec!["LANG".to_string()];
+        assert!(matches!(duplicate.validate(), Err(PolicyError::Invalid(_))));
+
+        for name in ["PATH", "LD_PRELOAD", "LD_LIBRARY_PATH", "LD_AUDIT"] {
+            let mut loader = policy.clone();
+            loader.commands[0].env_specs[0].name = name.to_string();
+            assert!(matches!(loader.validate(), Err(PolicyError::Invalid(_))));
+
+            let mut fixed = policy.clone();
+            fixed.commands[0]
+                .env
+                .insert(name.to_string(), "/tmp".to_string());
+            assert!(matches!(fixed.validate(), Err(PolicyError::Invalid(_))));
+        }
+
+        let mut bad_rule = policy;
+        bad_rule.commands[0].env_specs[0].validation =
+            Some(ValidationRule::Enum { values: Vec::new() });
+        assert!(matches!(bad_rule.validate(), Err(PolicyError::Invalid(_))));
+    }
+
+    #[test]
+    fn fetch_glob_does_not_cross_directories() {
+        let mut policy = test_policy();
+        policy.fetch = vec![FetchRule {
+            path: "/var/log/*.log".to_string(),
+            max_bytes: 1024,
+        }];
+        policy.validate().expect("fetch rule should validate");
+
+        assert!(
+            policy
+                .fetch_rule_for(std::path::Path::new("/var/log/syslog.log"))
+                .is_some()
+        );
+        assert!(
+            policy
+                .fetch_rule_for(std::path::Path::new("/var/log/nested/app.log"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn rejects_relative_fetch_glob() {
+        let mut policy = test_policy();
+        policy.fetch = vec![FetchRule {
+            path: "logs/*.log".to_string(),
+            max_bytes: 1024,
+        }];
+
+        let err = policy
+            .validate()
+            .expect_err("relative fetch glob should fail");
+        assert!(matches!(err, PolicyError::Invalid(_)));
+    }
+
+    #[test]
+    fn put_rule_matches_direct_children_only() {
+        let mut policy = test_policy();
+        policy.put = vec![PutRule {
+            directory: "/srv/uploads".to_string(),
+            max_bytes: 1024,
+            mode: Some("0640".to_string()),
+            owner: None,
+            group: None,
+        }];
+        policy.validate().expect("put rule should validate");
+
+        let rule = policy
+            .put_rule_for(std::path::Path::new("/srv/uploads/app.conf"))
+            .expect("direct child should match");
+        assert_eq!(rule.effective_mode(), 0o640);
+        for path in [
+            "/srv/uploads/nested/app.conf",
+            "/srv/uploads/../etc/passwd",
+            "/srv/uploads",
+        ] {
+            assert!(
+                policy.put_rule_for(std::path::Path::new(path)).is_none(),
+                "{path} should not match"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_put_mode_with_special_bits() {
+        let mut policy = test_policy();
+        policy.put = vec![PutRule {
+            directory: "/srv/uploads".to_string(),
+            max_bytes: 1024,
+            mode: Some("4755".to_string()),
+            owner: None,
+            group: None,
+        }];
+
+        let err = policy.validate().expect_err("setuid put mode should fail");
+        assert!(matches!(err, PolicyError::Invalid(_)));
+    }
+
     #[test]
     fn trusted_keys_rejects_invalid_key_length() {
         let raw = serde_json::to_string(&BTreeMap::from([(TEST_KEY_ID, "00".repeat(31))]))
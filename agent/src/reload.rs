@@ -0,0 +1,528 @@
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{mpsc, watch},
+    time::{MissedTickBehavior, interval},
+};
+use tracing::{info, warn};
+
+use crate::policy::{
+    LoadedPolicy, Policy, PolicyError, SignedPolicyBundle, TrustedPolicyKeys,
+    current_unix_timestamp,
+};
+
+const POLICY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const REMOTE_BUNDLE_QUEUE_CAPACITY: usize = 4;
+
+// The policy sessions should serve right now; `None` once the bundle has expired
+// or before the relay has delivered one.
+#[derive(Clone)]
+pub struct SharedPolicy {
+    current: watch::Receiver<Option<Arc<Policy>>>,
+}
+
+impl SharedPolicy {
+    #[must_use]
+    pub fn current(&self) -> Option<Arc<Policy>> {
+        self.current.borrow().clone()
+    }
+
+    // Resolves when the policy is swapped; returns false once no reloader is left.
+    pub async fn changed(&mut self) -> bool {
+        self.current.changed().await.is_ok()
+    }
+}
+
+impl From<Policy> for SharedPolicy {
+    fn from(policy: Policy) -> Self {
+        let (_, current) = watch::channel(Some(Arc::new(policy)));
+        Self { current }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    inode: u64,
+}
+
+pub struct PolicyReloader {
+    path: PathBuf,
+    trusted_keys: TrustedPolicyKeys,
+    loaded: Option<LoadedPolicy>,
+    // Serial of the newest bundle installed; kept when the cached bundle no longer loads.
+    serial: Option<u64>,
+    stamp: Option<FileStamp>,
+    current: watch::Sender<Option<Arc<Policy>>>,
+    remote_tx: mpsc::Sender<String>,
+    remote_rx: mpsc::Receiver<String>,
+}
+
+impl PolicyReloader {
+    pub fn load(
+        path: impl Into<PathBuf>,
+        trusted_keys: TrustedPolicyKeys,
+    ) -> Result<(Self, SharedPolicy), PolicyError> {
+        let path = path.into();
+        let stamp = file_stamp(&path);
+        let loaded = Policy::load_bundle(&path, &trusted_keys)?;
+        let serial = loaded.serial;
+        Ok(Self::new(
+            path,
+            trusted_keys,
+            Some(loaded),
+            Some(serial),
+            stamp,
+        ))
+    }
+
+    // For relay-distributed policy: the file is only a cache of the last bundle the
+    // relay delivered, so a missing or unusable cache starts the agent without a policy.
+    #[must_use]
+    pub fn load_cached(
+        path: impl Into<PathBuf>,
+        trusted_keys: TrustedPolicyKeys,
+    ) -> (Self, SharedPolicy) {
+        let path = path.into();
+        let stamp = file_stamp(&path);
+        let (loaded, serial) = if stamp.is_some() {
+            match Policy::load_bundle(&path, &trusted_keys) {
+                Ok(loaded) => {
+                    let serial = loaded.serial;
+                    (Some(loaded), Some(serial))
+                }
+                Err(err) => {
+                    warn!(
+                        "ignoring cached policy bundle '{}': {}",
+                        path.display(),
+                        err
+                    );
+                    // The cache was verified before it was written, so an expired bundle
+                    // still bounds which serials the relay may install.
+                    let serial = SignedPolicyBundle::load(&path)
+                        .ok()
+                        .map(|bundle| bundle.serial);
+                    (None, serial)
+                }
+            }
+        } else {
+            (None, None)
+        };
+        Self::new(path, trusted_keys, loaded, serial, stamp)
+    }
+
+    fn new(
+        path: PathBuf,
+        trusted_keys: TrustedPolicyKeys,
+        loaded: Option<LoadedPolicy>,
+        serial: Option<u64>,
+        stamp: Option<FileStamp>,
+    ) -> (Self, SharedPolicy) {
+        let (current, receiver) = watch::channel(
+            loaded
+                .as_ref()
+                .map(|loaded| Arc::new(loaded.policy.clone())),
+        );
+        let (remote_tx, remote_rx) = mpsc::channel(REMOTE_BUNDLE_QUEUE_CAPACITY);
+        let reloader = Self {
+            path,
+            trusted_keys,
+            loaded,
+            serial,
+            stamp,
+            current,
+            remote_tx,
+            remote_rx,
+        };
+        (reloader, SharedPolicy { current: receiver })
+    }
+
+    #[must_use]
+    pub fn expires_at_unix(&self) -> Option<u64> {
+        self.loaded.as_ref().map(|loaded| loaded.expires_at_unix)
+    }
+
+    #[must_use]
+    pub const fn serial(&self) -> Option<u64> {
+        self.serial
+    }
+
+    // Bundles sent here are installed by `run` once they verify.
+    #[must_use]
+    pub fn remote_bundles(&self) -> mpsc::Sender<String> {
+        self.remote_tx.clone()
+    }
+
+    // Verifies the bundle on disk and swaps it in; the served policy is untouched on error.
+    // The same serial is accepted again so that SIGHUP can re-read an unchanged file.
+    pub fn reload(&mut self) -> Result<(), PolicyError> {
+        self.stamp = file_stamp(&self.path);
+        let loaded = Policy::load_bundle(&self.path, &self.trusted_keys)?;
+        if let Some(current) = self.serial
+            && loaded.serial < current
+        {
+            return Err(PolicyError::Invalid(format!(
+                "policy bundle serial {} is older than the installed serial {}",
+                loaded.serial, current
+            )));
+        }
+        self.swap(loaded);
+        Ok(())
+    }
+
+    // Verifies a bundle delivered by the relay, caches it at the policy path, and swaps it
+    // in. Returns false if it matches the cached bundle. Anything else must carry a higher
+    // serial than the installed bundle, so the relay cannot replay an older one. Version 1
+    // bundles have no serial to compare and are only taken until a serial is installed.
+    pub fn install(&mut self, raw: &str) -> Result<bool, PolicyError> {
+        let bundle = SignedPolicyBundle::from_json(raw)?;
+        bundle.verify(&self.trusted_keys)?;
+        if self.loaded.is_some() && fs::read_to_string(&self.path).is_ok_and(|cached| cached == raw)
+        {
+            return Ok(false);
+        }
+        if let Some(current) = self.serial
+            && current > 0
+            && bundle.serial <= current
+        {
+            return Err(PolicyError::Invalid(format!(
+                "policy bundle serial {} is not newer than the installed serial {}",
+                bundle.serial, current
+            )));
+        }
+
+        write_cache(&self.path, raw).map_err(|source| PolicyError::Io {
+            path: self.path.clone(),
+            source,
+        })?;
+        self.stamp = file_stamp(&self.path);
+        self.swap(LoadedPolicy {
+            policy: bundle.policy,
+            expires_at_unix: bundle.expires_at_unix,
+            serial: bundle.serial,
+        });
+        Ok(true)
+    }
+
+    fn swap(&mut self, loaded: LoadedPolicy) {
+        self.current
+            .send_replace(Some(Arc::new(loaded.policy.clone())));
+        self.serial = Some(loaded.serial);
+        self.loaded = Some(loaded);
+    }
+
+    // Stops serving the bundle once it expires; returns true if that happened on this call.
+    pub fn check_expiry(&mut self) -> bool {
+        match current_unix_timestamp() {
+            Ok(now_unix) => self.check_expiry_at(now_unix),
+            Err(err) => {
+                warn!("failed to check policy expiry: {}", err);
+                false
+            }
+        }
+    }
+
+    fn check_expiry_at(&mut self, now_unix: u64) -> bool {
+        let expired = self
+            .loaded
+            .as_ref()
+            .is_some_and(|loaded| loaded.is_expired_at(now_unix));
+        if !expired || self.current.borrow().is_none() {
+            return false;
+        }
+        self.current.send_replace(None);
+        true
+    }
+
+    pub async fn run(mut self) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(err) => {
+                warn!("failed to install SIGHUP handler: {}", err);
+                None
+            }
+        };
+        let mut poll = interval(POLICY_POLL_INTERVAL);
+        poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let reason = tokio::select! {
+                Some(()) = async {
+                    match hangup.as_mut() {
+                        Some(hangup) => hangup.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => "SIGHUP",
+                Some(raw) = self.remote_rx.recv() => {
+                    match self.install(&raw) {
+                        Ok(true) => info!(
+                            "installed policy bundle from relay into {} (serial={}, expires_at_unix={})",
+                            self.path.display(),
+                            self.serial().unwrap_or_default(),
+                            self.expires_at_unix().unwrap_or_default()
+                        ),
+                        Ok(false) => {}
+                        Err(err) => warn!(
+                            "rejected policy bundle from relay; keeping the current policy: {}",
+                            err
+                        ),
+                    }
+                    continue;
+                }
+                _ = poll.tick() => {
+                    if file_stamp(&self.path) == self.stamp {
+                        if self.check_expiry() {
+                            warn!(
+                                "policy bundle '{}' expired (expires_at_unix={}); refusing requests until it is replaced",
+                                self.path.display(),
+                                self.expires_at_unix().unwrap_or_default()
+                            );
+                        }
+                        continue;
+                    }
+                    "file change"
+                }
+            };
+
+            match self.reload() {
+                Ok(()) => info!(
+                    "reloaded policy from {} after {} (serial={}, expires_at_unix={})",
+                    self.path.display(),
+                    reason,
+                    self.serial().unwrap_or_default(),
+                    self.expires_at_unix().unwrap_or_default()
+                ),
+                Err(err) => warn!(
+                    "failed to reload policy from {} after {}; keeping the current policy: {}",
+                    self.path.display(),
+                    reason,
+                    err
+                ),
+            }
+        }
+    }
+}
+
+// Written beside the target and renamed over it so the poll never sees a partial file.
+fn write_cache(path: &Path, contents: &str) -> io::Result<()> {
+    let mut partial_name = OsString::from(".");
+    partial_name.push(path.file_name().unwrap_or_default());
+    partial_name.push(".partial");
+    let partial_path = path.with_file_name(partial_name);
+
+    let result =
+        write_synced(&partial_path, contents).and_then(|()| fs::rename(&partial_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+    result
+}
+
+fn write_synced(path: &Path, contents: &str) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()
+}
+
+fn file_stamp(path: &Path) -> Option<FileStamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some(FileStamp {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+        inode: metadata.ino(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::PolicyReloader;
+    use crate::policy::{
+        current_unix_timestamp,
+        tests::{
+            signed_bundle, signed_v1_bundle, temp_file_path, test_policy, trusted_keys,
+            write_temp_file,
+        },
+    };
+
+    #[test]
+    fn keeps_current_policy_when_reload_fails() {
+        let expires_at_unix = current_unix_timestamp().expect("clock") + 300;
+        let path = write_temp_file(&signed_bundle(&test_policy(), expires_at_unix, 1), "reload");
+        let (mut reloader, shared) =
+            PolicyReloader::load(&path, trusted_keys()).expect("initial bundle should load");
+
+        let mut tampered = test_policy();
+        tampered.commands[0].id = "tampered".to_string();
+        let forged = signed_bundle(&test_policy(), expires_at_unix, 1)
+            .replace("\"id\":\"echo\"", "\"id\":\"tampered\"");
+        fs::write(&path, forged).expect("write forged bundle");
+        assert!(reloader.reload().is_err());
+        let current = shared.current().expect("policy should still be served");
+        assert!(current.command_by_id("echo").is_some());
+
+        fs::write(&path, signed_bundle(&tampered, expires_at_unix, 2)).expect("write new bundle");
+        reloader.reload().expect("re-signed bundle should load");
+        let current = shared.current().expect("policy should be served");
+        assert!(current.command_by_id("tampered").is_some());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn stops_serving_expired_bundle() {
+        let expires_at_unix = current_unix_timestamp().expect("clock") + 300;
+        let path = write_temp_file(&signed_bundle(&test_policy(), expires_at_unix, 1), "expiry");
+        let (mut reloader, shared) =
+            PolicyReloader::load(&path, trusted_keys()).expect("bundle should load");
+
+        assert!(!reloader.check_expiry_at(expires_at_unix - 1));
+        assert!(shared.current().is_some());
+        assert!(reloader.check_expiry_at(expires_at_unix));
+        assert!(shared.current().is_none());
+        assert!(!reloader.check_expiry_at(expires_at_unix + 1));
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn installs_relay_bundle_only_after_verification() {
+        let expires_at_unix = current_unix_timestamp().expect("clock") + 300;
+        let path = temp_file_path("relay");
+        let (mut reloader, shared) = PolicyReloader::load_cached(&path, trusted_keys());
+        assert!(shared.current().is_none());
+
+        let forged = signed_bundle(&test_policy(), expires_at_unix, 1)
+            .replace("\"id\":\"echo\"", "\"id\":\"tampered\"");
+        assert!(reloader.install(&forged).is_err());
+        assert!(shared.current().is_none());
+        assert!(!path.exists());
+
+        let bundle = signed_bundle(&test_policy(), expires_at_unix, 1);
+        assert!(
+            reloader
+                .install(&bundle)
+                .expect("signed bundle should install")
+        );
+        assert!(shared.current().is_some());
+        assert_eq!(fs::read_to_string(&path).expect("cached bundle"), bundle);
+        assert!(!reloader.install(&bundle).expect("same bundle is accepted"));
+
+        let (reloader, shared) = PolicyReloader::load_cached(&path, trusted_keys());
+        assert_eq!(reloader.expires_at_unix(), Some(expires_at_unix));
+        assert!(shared.current().is_some());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn rejects_replayed_relay_bundle() {
+        let expires_at_unix = current_unix_timestamp().expect("clock") + 300;
+        let path = temp_file_path("replay");
+        let (mut reloader, shared) = PolicyReloader::load_cached(&path, trusted_keys());
+
+        let mut older = test_policy();
+        older.commands[0].id = "older".to_string();
+        let newer = signed_bundle(&test_policy(), expires_at_unix, 2);
+        assert!(
+            reloader
+                .install(&newer)
+                .expect("signed bundle should install")
+        );
+        assert!(
+            reloader
+                .install(&signed_bundle(&older, expires_at_unix, 1))
+                .is_err()
+        );
+        assert!(
+            reloader
+                .install(&signed_bundle(&older, expires_at_unix, 2))
+                .is_err()
+        );
+        assert!(!reloader.install(&newer).expect("same bundle is accepted"));
+        let current = shared.current().expect("policy should be served");
+        assert!(current.command_by_id("echo").is_some());
+        assert_eq!(fs::read_to_string(&path).expect("cached bundle"), newer);
+
+        fs::write(&path, signed_bundle(&older, expires_at_unix, 1)).expect("write older bundle");
+        assert!(reloader.reload().is_err());
+        assert!(
+            shared
+                .current()
+                .is_some_and(|policy| policy.command_by_id("echo").is_some())
+        );
+
+        // The serial outlives the cached bundle's expiry across restarts.
+        fs::write(
+            &path,
+            signed_bundle(&test_policy(), expires_at_unix - 600, 3),
+        )
+        .expect("write expired bundle");
+        let (mut reloader, shared) = PolicyReloader::load_cached(&path, trusted_keys());
+        assert!(shared.current().is_none());
+        assert_eq!(reloader.serial(), Some(3));
+        assert!(reloader.install(&newer).is_err());
+        assert!(shared.current().is_none());
+        assert!(
+            reloader
+                .install(&signed_bundle(&older, expires_at_unix, 4))
+                .expect("newer bundle should install")
+        );
+        assert!(
+            shared
+                .current()
+                .is_some_and(|policy| policy.command_by_id("older").is_some())
+        );
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn replaces_unserialed_bundle_until_a_serial_is_installed() {
+        let expires_at_unix = current_unix_timestamp().expect("clock") + 300;
+        let path = temp_file_path("v1");
+        let (mut reloader, shared) = PolicyReloader::load_cached(&path, trusted_keys());
+
+        let mut other = test_policy();
+        other.commands[0].id = "other".to_string();
+        assert!(
+            reloader
+                .install(&signed_v1_bundle(&test_policy(), expires_at_unix))
+                .expect("version 1 bundle should install")
+        );
+        assert_eq!(reloader.serial(), Some(0));
+        assert!(
+            reloader
+                .install(&signed_v1_bundle(&other, expires_at_unix))
+                .expect("version 1 bundle should replace another")
+        );
+        assert!(
+            reloader
+                .install(&signed_bundle(&test_policy(), expires_at_unix, 1))
+                .expect("serial bundle should install")
+        );
+        assert!(
+            reloader
+                .install(&signed_v1_bundle(&other, expires_at_unix))
+                .is_err()
+        );
+        assert!(
+            shared
+                .current()
+                .is_some_and(|policy| policy.command_by_id("echo").is_some())
+        );
+
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{env, fs};
+
+use landlock::{
+    ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreated, RulesetCreatedAttr, RulesetStatus,
+    path_beneath_rules,
+};
+use nix::{
+    errno::Errno,
+    mount::{MsFlags, mount},
+    sched::{CloneFlags, unshare},
+    sys::prctl::set_no_new_privs,
+};
+use seccompiler::{BpfProgram, TargetArch, compile_from_json};
+use serde_json::json;
+
+use crate::policy::{LandlockRules, Namespace, Sandbox};
+
+const SECCOMP_FILTER_NAME: &str = "command";
+const LANDLOCK_ABI: ABI = ABI::V5;
+
+// Builds a filter that fails the listed syscalls with EPERM and allows everything else. Names
+// are resolved for the architecture this process runs on.
+pub fn seccomp_filter(deny_syscalls: &[String]) -> Result<BpfProgram, String> {
+    let arch = TargetArch::try_from(env::consts::ARCH).map_err(|err| err.to_string())?;
+    let rules = deny_syscalls
+        .iter()
+        .map(|syscall| json!({ "syscall": syscall }))
+        .collect::<Vec<_>>();
+    let filters = json!({
+        SECCOMP_FILTER_NAME: {
+            "mismatch_action": "allow",
+            "match_action": { "errno": Errno::EPERM as u32 },
+            "filter": rules,
+        }
+    });
+    let mut programs =
+        compile_from_json(filters.to_string().as_bytes(), arch).map_err(|err| err.to_string())?;
+    programs
+        .remove(SECCOMP_FILTER_NAME)
+        .ok_or_else(|| "seccomp filter was not compiled".to_string())
+}
+
+pub(crate) fn enter_namespaces(sandbox: &Sandbox) -> Result<(), String> {
+    let flags = sandbox
+        .namespaces
+        .iter()
+        .fold(CloneFlags::empty(), |flags, namespace| {
+            flags
+                | match namespace {
+                    Namespace::Mount => CloneFlags::CLONE_NEWNS,
+                    Namespace::Pid => CloneFlags::CLONE_NEWPID,
+                    Namespace::Network => CloneFlags::CLONE_NEWNET,
+                }
+        });
+    unshare(flags).map_err(|err| format!("failed to unshare namespaces: {}", err))
+}
+
+// Applies everything but seccomp, which goes last so the credential switch is not filtered.
+// `pid_init` is set when this process is init of the command's new PID namespace.
+pub(crate) fn restrict(sandbox: &Sandbox, pid_init: bool) -> Result<(), String> {
+    if sandbox.has_namespace(Namespace::Mount) {
+        prepare_mounts(sandbox, pid_init)?;
+    }
+    if sandbox.no_new_privs {
+        set_no_new_privs().map_err(|err| format!("failed to set no_new_privs: {}", err))?;
+    }
+    if let Some(rules) = &sandbox.landlock {
+        restrict_paths(rules)?;
+    }
+    Ok(())
+}
+
+fn prepare_mounts(sandbox: &Sandbox, mount_proc: bool) -> Result<(), String> {
+    // Keeps the mounts below from propagating back into the agent's namespace.
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+    .map_err(|err| format!("failed to make mounts private: {}", err))?;
+
+    if mount_proc {
+        mount(
+            Some("proc"),
+            "/proc",
+            Some("proc"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            None::<&str>,
+        )
+        .map_err(|err| format!("failed to mount /proc: {}", err))?;
+    }
+
+    if !sandbox.read_only_root {
+        return Ok(());
+    }
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+        .map_err(|err| format!("failed to read mount table: {}", err))?;
+    // A read-only remount only covers one mount, so every mount below the root is remounted.
+    for (mount_point, flags) in parse_mountinfo(&mountinfo) {
+        remount(&mount_point, flags | MsFlags::MS_RDONLY)?;
+    }
+    for path in &sandbox.writable_paths {
+        mount(
+            Some(path.as_str()),
+            path.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        )
+        .map_err(|err| format!("failed to bind writable path '{}': {}", path, err))?;
+        remount(path, MsFlags::MS_NOSUID | MsFlags::MS_NODEV)?;
+    }
+    Ok(())
+}
+
+fn remount(mount_point: &str, flags: MsFlags) -> Result<(), String> {
+    match mount(
+        None::<&str>,
+        mount_point,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | flags,
+        None::<&str>,
+    ) {
+        // Mounts under a path that has since been mounted over can no longer be reached.
+        Ok(()) | Err(Errno::ENOENT) => Ok(()),
+        Err(err) => Err(format!("failed to remount '{}': {}", mount_point, err)),
+    }
+}
+
+// Mount points and the per-mount flags a bind remount has to keep.
+fn parse_mountinfo(mountinfo: &str) -> Vec<(String, MsFlags)> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ').skip(4);
+            let mount_point = unescape_mount_path(fields.next()?);
+            let flags = fields
+                .next()?
+                .split(',')
+                .fold(MsFlags::empty(), |flags, option| {
+                    flags
+                        | match option {
+                            "nosuid" => MsFlags::MS_NOSUID,
+                            "nodev" => MsFlags::MS_NODEV,
+                            "noexec" => MsFlags::MS_NOEXEC,
+                            "noatime" => MsFlags::MS_NOATIME,
+                            "nodiratime" => MsFlags::MS_NODIRATIME,
+                            "relatime" => MsFlags::MS_RELATIME,
+                            _ => MsFlags::empty(),
+                        }
+                });
+            Some((mount_point, flags))
+        })
+        .collect()
+}
+
+// The kernel writes space, tab, newline and backslash in mount paths as octal escapes.
+fn unescape_mount_path(raw: &str) -> String {
+    let mut path = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(index) = rest.find('\\') {
+        path.push_str(&rest[..index]);
+        let escape = rest.get(index + 1..index + 4);
+        match escape.and_then(|digits| u8::from_str_radix(digits, 8).ok()) {
+            Some(byte) => {
+                path.push(char::from(byte));
+                rest = &rest[index + 4..];
+            }
+            None => {
+                path.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    path.push_str(rest);
+    path
+}
+
+// Paths that do not exist on this host are skipped rather than failing the command.
+fn restrict_paths(rules: &LandlockRules) -> Result<(), String> {
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(LANDLOCK_ABI))
+        .and_then(Ruleset::create)
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                &rules.read,
+                AccessFs::from_read(LANDLOCK_ABI),
+            ))
+        })
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                &rules.read_write,
+                AccessFs::from_all(LANDLOCK_ABI),
+            ))
+        })
+        .and_then(RulesetCreated::restrict_self)
+        .map_err(|err| format!("failed to apply Landlock rules: {}", err))?;
+    if status.ruleset == RulesetStatus::NotEnforced {
+        return Err("Landlock is not supported by this kernel".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::mount::MsFlags;
+
+    use super::{parse_mountinfo, seccomp_filter};
+
+    #[test]
+    fn parses_mount_points_and_flags() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 0:31 / /mnt/backup\\040disk rw,nosuid,nodev,noexec shared:9 - tmpfs tmpfs rw
+";
+        assert_eq!(
+            parse_mountinfo(mountinfo),
+            vec![
+                ("/".to_string(), MsFlags::MS_RELATIME),
+                (
+                    "/mnt/backup disk".to_string(),
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn seccomp_filter_rejects_unknown_syscalls() {
+        assert!(seccomp_filter(&["ptrace".to_string(), "mount".to_string()]).is_ok());
+        assert!(seccomp_filter(&["not_a_syscall".to_string()]).is_err());
+    }
+}
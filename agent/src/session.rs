@@ -1,22 +1,40 @@
-use std::{error::Error, fmt};
+use std::{collections::HashMap, error::Error, fmt, path::Path, sync::Arc};
 
 use alaric_lib::{
+    config::SessionConfig,
     protocol::{
-        AgentId, ClientMessage, CommandProtocolError, IdentityBundle, PeerAttestationError,
-        PeerAttestationInit, PeerAttestationMode, PeerAttestationPolicy, PeerAttestationResult,
-        Role, SecureChannel, SecureChannelError, SessionId, build_peer_attestation_proof,
-        recv_secure_json, send_secure_json, verify_peer_attestation_proof,
+        AgentId, AgentMessage, CancelSignal, ClientId, ClientMessage, CommandId,
+        CommandProtocolError, IdentityBundle, PeerAttestationError, PeerAttestationInit,
+        PeerAttestationMode, PeerAttestationPolicy, PeerAttestationResult, ProtocolError,
+        RejectionCode, RequestId, Role, SecureChannel, SecureChannelError, SessionId,
+        build_peer_attestation_proof, recv_secure_json, recv_secure_json_split, send_secure_json,
+        send_secure_json_split, verify_peer_attestation_proof,
     },
     security::noise::types::Keypair,
 };
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite},
+    sync::mpsc,
+    task::{AbortHandle, JoinSet},
+};
+use tracing::debug;
+
+use crate::{
+    executor::{InputChunk, RequestControl, execute_request},
+    files::{fetch_file, put_file},
+    policy::Policy,
+    reload::SharedPolicy,
+};
 
-use crate::{executor::execute_request, policy::Policy};
+const MAX_CONCURRENT_REQUESTS: usize = 32;
+const OUTGOING_QUEUE_CAPACITY: usize = 64;
+const CANCEL_QUEUE_CAPACITY: usize = 2;
 
 #[derive(Debug)]
 pub enum SessionError {
     Protocol(CommandProtocolError),
     Attestation(String),
+    Closed,
 }
 
 impl fmt::Display for SessionError {
@@ -24,12 +42,20 @@ impl fmt::Display for SessionError {
         match self {
             SessionError::Protocol(err) => write!(f, "protocol error: {}", err),
             SessionError::Attestation(message) => write!(f, "attestation error: {}", message),
+            SessionError::Closed => f.write_str("session closed while messages were pending"),
         }
     }
 }
 
 impl Error for SessionError {}
 
+struct InFlightRequest {
+    handle: AbortHandle,
+    cancel: mpsc::Sender<CancelSignal>,
+    input: Option<mpsc::UnboundedSender<InputChunk>>,
+    input_remaining: usize,
+}
+
 impl From<CommandProtocolError> for SessionError {
     fn from(value: CommandProtocolError) -> Self {
         Self::Protocol(value)
@@ -48,57 +74,386 @@ impl From<PeerAttestationError> for SessionError {
     }
 }
 
+// Inputs the relay supplied when it paired this tunnel, along with how the client is verified.
+pub struct TunnelContext<'a> {
+    pub session_id: SessionId,
+    pub client_id: ClientId,
+    pub allowed_commands: Option<&'a [CommandId]>,
+    pub attestation_policy: &'a PeerAttestationPolicy,
+    pub identity_bundle: Option<&'a IdentityBundle>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run_secure_session<S>(
     stream: &mut S,
-    policy: &Policy,
+    policy: &SharedPolicy,
     static_keypair: Keypair,
-    session_id: SessionId,
+    session_config: &SessionConfig,
     agent_id: &AgentId,
     auth_key_id: &str,
     auth_private_key: &str,
-    attestation_policy: &PeerAttestationPolicy,
-    identity_bundle: Option<&IdentityBundle>,
+    tunnel: &TunnelContext<'_>,
 ) -> Result<(), SessionError>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    let mut secure = SecureChannel::handshake_xx_responder(stream, static_keypair).await?;
+    let mut secure = SecureChannel::handshake_xx_responder(
+        stream,
+        static_keypair,
+        session_config.max_message_bytes,
+    )
+    .await?
+    .with_rekey_policy(session_config.rekey_policy());
     perform_peer_attestation(
         &mut secure,
         stream,
-        &session_id,
+        tunnel,
         agent_id,
         auth_key_id,
         auth_private_key,
-        attestation_policy,
-        identity_bundle,
     )
     .await?;
 
-    let request = recv_secure_json::<_, ClientMessage>(&mut secure, stream).await?;
+    serve_requests(secure, stream, policy, tunnel.allowed_commands).await
+}
 
-    match request {
-        ClientMessage::Execute {
-            request_id,
-            command_id,
-            args,
-        } => {
-            execute_request(&mut secure, stream, policy, request_id, &command_id, &args).await?;
+async fn serve_requests<S>(
+    secure: SecureChannel,
+    stream: &mut S,
+    policy: &SharedPolicy,
+    allowed_commands: Option<&[CommandId]>,
+) -> Result<(), SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut receiver, mut sender) = secure.into_split();
+    let (mut reader, mut writer) = io::split(stream);
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<AgentMessage>(OUTGOING_QUEUE_CAPACITY);
+
+    let write_loop = async {
+        while let Some(message) = outgoing_rx.recv().await {
+            send_secure_json_split(&mut sender, &mut writer, &message).await?;
         }
-    }
+        Ok::<(), SessionError>(())
+    };
 
+    let read_loop = async move {
+        let mut executions = JoinSet::new();
+        let mut in_flight: HashMap<RequestId, InFlightRequest> = HashMap::new();
+
+        loop {
+            let message = match recv_secure_json_split::<_, ClientMessage>(
+                &mut receiver,
+                &mut reader,
+            )
+            .await
+            {
+                Ok(message) => message,
+                Err(err) if is_clean_eof(&err) => {
+                    debug!("client closed the session without sending close");
+                    break;
+                }
+                Err(err) => return Err(SessionError::from(err)),
+            };
+
+            while executions.try_join_next().is_some() {}
+            in_flight.retain(|_, request| !request.handle.is_finished());
+
+            match message {
+                ClientMessage::Execute {
+                    request_id,
+                    command_id,
+                    args,
+                    env,
+                } => {
+                    if let Err((code, message)) = check_grant(allowed_commands, Some(&command_id)) {
+                        send_session_rejection(&outgoing_tx, request_id, code, message).await?;
+                        continue;
+                    }
+                    let policy = match admit_request(&in_flight, request_id, policy) {
+                        Ok(policy) => policy,
+                        Err((code, message)) => {
+                            send_session_rejection(&outgoing_tx, request_id, code, message).await?;
+                            continue;
+                        }
+                    };
+
+                    let input_remaining = policy
+                        .command_by_id(command_id.as_str())
+                        .map_or(0, |command| {
+                            command.streamed_input_limit(policy.max_input_bytes)
+                        });
+                    let outgoing = outgoing_tx.clone();
+                    let (cancel_tx, cancel_rx) = mpsc::channel(CANCEL_QUEUE_CAPACITY);
+                    let (stdin_tx, stdin_rx) = mpsc::unbounded_channel();
+                    let control = RequestControl {
+                        cancel: cancel_rx,
+                        input: stdin_rx,
+                    };
+                    let handle = executions.spawn(async move {
+                        if execute_request(
+                            &outgoing,
+                            &policy,
+                            request_id,
+                            &command_id,
+                            &args,
+                            &env,
+                            control,
+                        )
+                        .await
+                        .is_err()
+                        {
+                            debug!("session closed while request {} was running", request_id);
+                        }
+                    });
+                    in_flight.insert(
+                        request_id,
+                        InFlightRequest {
+                            handle,
+                            cancel: cancel_tx,
+                            input: Some(stdin_tx),
+                            input_remaining,
+                        },
+                    );
+                }
+                ClientMessage::Fetch { request_id, path } => {
+                    if let Err((code, message)) = check_grant(allowed_commands, None) {
+                        send_session_rejection(&outgoing_tx, request_id, code, message).await?;
+                        continue;
+                    }
+                    let policy = match admit_request(&in_flight, request_id, policy) {
+                        Ok(policy) => policy,
+                        Err((code, message)) => {
+                            send_session_rejection(&outgoing_tx, request_id, code, message).await?;
+                            continue;
+                        }
+                    };
+
+                    let outgoing = outgoing_tx.clone();
+                    let (cancel_tx, cancel_rx) = mpsc::channel(CANCEL_QUEUE_CAPACITY);
+                    let handle = executions.spawn(async move {
+                        if fetch_file(&outgoing, &policy, request_id, &path, cancel_rx)
+                            .await
+                            .is_err()
+                        {
+                            debug!("session closed while request {} was running", request_id);
+                        }
+                    });
+                    in_flight.insert(
+                        request_id,
+                        InFlightRequest {
+                            handle,
+                            cancel: cancel_tx,
+                            input: None,
+                            input_remaining: 0,
+                        },
+                    );
+                }
+                ClientMessage::Put {
+                    request_id,
+                    path,
+                    size,
+                    mode,
+                } => {
+                    if let Err((code, message)) = check_grant(allowed_commands, None) {
+                        send_session_rejection(&outgoing_tx, request_id, code, message).await?;
+                        continue;
+                    }
+                    let policy = match admit_request(&in_flight, request_id, policy) {
+                        Ok(policy) => policy,
+                        Err((code, message)) => {
+                            send_session_rejection(&outgoing_tx, request_id, code, message).await?;
+                            continue;
+                        }
+                    };
+
+                    // Budget the declared size, capped by the matching rule, so a client
+                    // cannot queue more than the policy would ever accept.
+                    let input_remaining = policy
+                        .put_rule_for(Path::new(&path))
+                        .map_or(0, |rule| size.min(rule.max_bytes))
+                        .try_into()
+                        .unwrap_or(usize::MAX);
+                    let outgoing = outgoing_tx.clone();
+                    let (cancel_tx, cancel_rx) = mpsc::channel(CANCEL_QUEUE_CAPACITY);
+                    let (input_tx, input_rx) = mpsc::unbounded_channel();
+                    let control = RequestControl {
+                        cancel: cancel_rx,
+                        input: input_rx,
+                    };
+                    let handle = executions.spawn(async move {
+                        if put_file(&outgoing, &policy, request_id, &path, size, mode, control)
+                            .await
+                            .is_err()
+                        {
+                            debug!("session closed while request {} was running", request_id);
+                        }
+                    });
+                    in_flight.insert(
+                        request_id,
+                        InFlightRequest {
+                            handle,
+                            cancel: cancel_tx,
+                            input: Some(input_tx),
+                            input_remaining,
+                        },
+                    );
+                }
+                ClientMessage::PutChunk {
+                    request_id,
+                    data,
+                    eof,
+                } => {
+                    let Some(request) = in_flight.get_mut(&request_id) else {
+                        debug!("ignoring upload data for unknown request {}", request_id);
+                        continue;
+                    };
+                    forward_input(request, data, eof);
+                }
+                ClientMessage::Stdin {
+                    request_id,
+                    data,
+                    eof,
+                } => {
+                    let Some(request) = in_flight.get_mut(&request_id) else {
+                        debug!("ignoring stdin for unknown request {}", request_id);
+                        continue;
+                    };
+                    forward_input(request, data, eof);
+                }
+                ClientMessage::Cancel { request_id, signal } => match in_flight.get(&request_id) {
+                    Some(request) => {
+                        if request.cancel.try_send(signal).is_err() {
+                            debug!("request {} is already being cancelled", request_id);
+                        }
+                    }
+                    None => {
+                        debug!("ignoring cancel for unknown request {}", request_id);
+                    }
+                },
+                ClientMessage::Close => break,
+            }
+        }
+
+        // Closing the input channels lets uploads still waiting for data abort.
+        drop(in_flight);
+        while executions.join_next().await.is_some() {}
+        Ok::<(), SessionError>(())
+    };
+
+    tokio::try_join!(read_loop, write_loop)?;
     Ok(())
 }
 
+// The budget is charged here so buffered input never outgrows the policy cap,
+// however slowly the process or upload drains it.
+fn forward_input(request: &mut InFlightRequest, data: Vec<u8>, eof: bool) {
+    let Some(input) = &request.input else {
+        debug!("ignoring input for a request that does not take any");
+        return;
+    };
+    let chunk = if data.len() > request.input_remaining {
+        request.input_remaining = 0;
+        Some(InputChunk::LimitExceeded)
+    } else if data.is_empty() {
+        None
+    } else {
+        request.input_remaining -= data.len();
+        Some(InputChunk::Data(data))
+    };
+
+    // A send error means the execution already finished; late input is dropped.
+    if let Some(chunk) = chunk {
+        let _ = input.send(chunk);
+    }
+    if eof {
+        let _ = input.send(InputChunk::Eof);
+    }
+}
+
+// The relay narrows a tunnel to the commands named in the client's grant. File transfers
+// are not commands, so they need a grant that covers everything.
+fn check_grant(
+    allowed_commands: Option<&[CommandId]>,
+    command_id: Option<&CommandId>,
+) -> Result<(), (RejectionCode, String)> {
+    let Some(allowed_commands) = allowed_commands else {
+        return Ok(());
+    };
+    match command_id {
+        Some(command_id) if allowed_commands.contains(command_id) => Ok(()),
+        Some(command_id) => Err((
+            RejectionCode::Forbidden,
+            format!("client is not granted command '{}'", command_id),
+        )),
+        None => Err((
+            RejectionCode::Forbidden,
+            "file transfer requires a grant for all commands".to_string(),
+        )),
+    }
+}
+
+// Each request runs against the policy current at admission, so a reload never
+// changes the rules under a request that is already running.
+fn admit_request(
+    in_flight: &HashMap<RequestId, InFlightRequest>,
+    request_id: RequestId,
+    policy: &SharedPolicy,
+) -> Result<Arc<Policy>, (RejectionCode, String)> {
+    if in_flight.contains_key(&request_id) {
+        return Err((
+            RejectionCode::ExecutionError,
+            format!("request id {} is already in use", request_id),
+        ));
+    }
+    if in_flight.len() >= MAX_CONCURRENT_REQUESTS {
+        return Err((
+            RejectionCode::ExecutionError,
+            format!(
+                "session already has {} requests in flight",
+                MAX_CONCURRENT_REQUESTS
+            ),
+        ));
+    }
+    policy.current().ok_or_else(|| {
+        (
+            RejectionCode::PolicyError,
+            "agent has no unexpired policy bundle".to_string(),
+        )
+    })
+}
+
+async fn send_session_rejection(
+    outgoing: &mpsc::Sender<AgentMessage>,
+    request_id: RequestId,
+    code: RejectionCode,
+    message: String,
+) -> Result<(), SessionError> {
+    outgoing
+        .send(AgentMessage::Rejected {
+            request_id,
+            code,
+            message,
+        })
+        .await
+        .map_err(|_| SessionError::Closed)
+}
+
+fn is_clean_eof(err: &CommandProtocolError) -> bool {
+    matches!(
+        err,
+        CommandProtocolError::SecureChannel(SecureChannelError::Protocol(ProtocolError::Io(io_err)))
+            if io_err.kind() == std::io::ErrorKind::UnexpectedEof
+    )
+}
+
 async fn perform_peer_attestation<S>(
     secure: &mut SecureChannel,
     stream: &mut S,
-    session_id: &SessionId,
+    tunnel: &TunnelContext<'_>,
     agent_id: &AgentId,
     auth_key_id: &str,
     auth_private_key: &str,
-    attestation_policy: &PeerAttestationPolicy,
-    identity_bundle: Option<&IdentityBundle>,
 ) -> Result<(), SessionError>
 where
     S: AsyncRead + AsyncWrite + Unpin,
@@ -121,7 +476,53 @@ where
         return Err(SessionError::Attestation(message));
     }
 
-    let mode = attestation_policy.resolve(&init.client_id, agent_id);
+    // The relay authenticated the client that opened this tunnel; the id it claims here must match.
+    if init.client_id != tunnel.client_id {
+        let message = format!(
+            "client attestation init id '{}' does not match relay client id '{}'",
+            init.client_id, tunnel.client_id
+        );
+        send_secure_json(
+            secure,
+            stream,
+            &PeerAttestationResult::rejected(PeerAttestationMode::Required, message.clone()),
+        )
+        .await?;
+        return Err(SessionError::Attestation(message));
+    }
+
+    let mode = tunnel
+        .attestation_policy
+        .resolve(&tunnel.client_id, agent_id);
+    // A Noise static key bound in the identity bundle is enforced regardless of attestation mode.
+    let static_key_error = tunnel.identity_bundle.and_then(|bundle| {
+        match bundle
+            .client_identity_key(&tunnel.client_id)
+            .and_then(|identity| identity.noise_public_key)
+        {
+            Some(bound_static_key) if !secure.remote_static_matches(&bound_static_key) => {
+                Some(format!(
+                    "client '{}' did not present the noise static key bound in the identity bundle",
+                    tunnel.client_id
+                ))
+            }
+            None if bundle.binds_client_noise_keys() => Some(format!(
+                "identity bundle binds noise static keys but none for client '{}'",
+                tunnel.client_id
+            )),
+            _ => None,
+        }
+    });
+    if let Some(message) = static_key_error {
+        send_secure_json(
+            secure,
+            stream,
+            &PeerAttestationResult::rejected(mode, message.clone()),
+        )
+        .await?;
+        return Err(SessionError::Attestation(message));
+    }
+
     if mode == PeerAttestationMode::Disabled {
         send_secure_json(secure, stream, &PeerAttestationResult::accepted(mode, None)).await?;
         return Ok(());
@@ -173,7 +574,7 @@ where
         return Err(SessionError::Attestation(message));
     }
 
-    let Some(identity_bundle) = identity_bundle else {
+    let Some(identity_bundle) = tunnel.identity_bundle else {
         if mode.requires_attestation() {
             let message =
                 "peer attestation is required but no identity bundle is loaded".to_string();
@@ -210,7 +611,7 @@ where
     };
     let verified = verify_peer_attestation_proof(
         &client_proof,
-        session_id,
+        &tunnel.session_id,
         handshake_hash,
         &client_proof.client_id,
         agent_id,
@@ -233,7 +634,7 @@ where
     }
 
     let agent_proof = build_peer_attestation_proof(
-        session_id,
+        &tunnel.session_id,
         handshake_hash,
         &client_proof.client_id,
         agent_id,
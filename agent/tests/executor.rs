@@ -0,0 +1,339 @@
+use std::{collections::BTreeMap, error::Error, fs, path::PathBuf, time::Duration};
+
+use alaric_agent::{
+    executor::{RequestControl, execute_request, set_exec_helper_path},
+    policy::Policy,
+};
+use alaric_lib::protocol::{
+    AgentMessage, CommandId, OutputStream, RejectionCode, RequestId, ResourceLimit,
+};
+use nix::unistd::Uid;
+use serde_json::{Value, json};
+use tokio::{sync::mpsc, time::sleep};
+
+struct RunResult {
+    stdout: String,
+    exit_code: Option<i32>,
+    timed_out: bool,
+    truncated: bool,
+    limit_exceeded: Option<ResourceLimit>,
+    rejected: Option<RejectionCode>,
+}
+
+async fn run_command(command: Value) -> Result<RunResult, Box<dyn Error>> {
+    run_command_with_env(command, BTreeMap::new()).await
+}
+
+async fn run_command_with_env(
+    command: Value,
+    env: BTreeMap<String, String>,
+) -> Result<RunResult, Box<dyn Error>> {
+    set_exec_helper_path(PathBuf::from(env!("CARGO_BIN_EXE_alaric-exec")));
+    let command_id = CommandId::new(command["id"].as_str().unwrap_or_default())?;
+    let policy: Policy = serde_json::from_value(json!({
+        "version": 1,
+        "default_timeout_secs": 10,
+        "max_output_bytes": 4096,
+        "commands": [command]
+    }))?;
+    policy.validate()?;
+
+    let (outgoing, mut messages) = mpsc::channel(16);
+    let (_cancel_tx, cancel) = mpsc::channel(1);
+    let (_input_tx, input) = mpsc::unbounded_channel();
+    execute_request(
+        &outgoing,
+        &policy,
+        RequestId(1),
+        &command_id,
+        &BTreeMap::new(),
+        &env,
+        RequestControl { cancel, input },
+    )
+    .await
+    .map_err(|_| "session closed")?;
+    drop(outgoing);
+
+    let mut stdout = Vec::new();
+    let mut result = RunResult {
+        stdout: String::new(),
+        exit_code: None,
+        timed_out: false,
+        truncated: false,
+        limit_exceeded: None,
+        rejected: None,
+    };
+    while let Some(message) = messages.recv().await {
+        match message {
+            AgentMessage::Output {
+                stream: OutputStream::Stdout,
+                chunk,
+                ..
+            } => stdout.extend(chunk),
+            AgentMessage::Completed {
+                exit_code,
+                timed_out,
+                truncated,
+                limit_exceeded,
+                ..
+            } => {
+                result.exit_code = Some(exit_code);
+                result.timed_out = timed_out;
+                result.truncated = truncated;
+                result.limit_exceeded = limit_exceeded;
+            }
+            AgentMessage::Rejected { code, .. } => result.rejected = Some(code),
+            _ => {}
+        }
+    }
+    result.stdout = String::from_utf8(stdout)?;
+    Ok(result)
+}
+
+// Killed descendants are reparented to init, so a zombie counts as gone until it is reaped.
+async fn wait_for_exit(pid: &str) -> bool {
+    for _ in 0..40 {
+        let exited = fs::read_to_string(format!("/proc/{pid}/stat")).map_or(true, |stat| {
+            stat.rsplit_once(')')
+                .is_some_and(|(_, rest)| rest.trim_start().starts_with('Z'))
+        });
+        if exited {
+            return true;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn runs_command_with_dropped_privileges() -> Result<(), Box<dyn Error>> {
+    // Switching users needs root; there is nothing to check otherwise.
+    if !Uid::effective().is_root() {
+        return Ok(());
+    }
+
+    let result = run_command(json!({
+        "id": "whoami",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "id -u; id -g; id -G"],
+        "timeout_secs": null,
+        "max_output_bytes": null,
+        "run_as_user": "65534",
+        "run_as_group": "65534",
+        "supplementary_groups": ["100"]
+    }))
+    .await?;
+    assert_eq!(result.exit_code, Some(0));
+    assert_eq!(result.stdout, "65534\n65534\n65534 100\n");
+    Ok(())
+}
+
+#[tokio::test]
+async fn applies_rlimits_and_reports_cpu_overrun() -> Result<(), Box<dyn Error>> {
+    let result = run_command(json!({
+        "id": "ulimit",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "ulimit -n"],
+        "timeout_secs": null,
+        "max_output_bytes": null,
+        "limits": { "open_files": 64 }
+    }))
+    .await?;
+    assert_eq!(result.stdout, "64\n");
+    assert_eq!(result.limit_exceeded, None);
+
+    let result = run_command(json!({
+        "id": "spin",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "while :; do :; done"],
+        "timeout_secs": null,
+        "max_output_bytes": null,
+        "limits": { "cpu_secs": 1 }
+    }))
+    .await?;
+    assert_eq!(result.exit_code, Some(-1));
+    assert_eq!(result.limit_exceeded, Some(ResourceLimit::Cpu));
+    Ok(())
+}
+
+#[tokio::test]
+async fn kills_forked_descendants_on_timeout_and_truncation() -> Result<(), Box<dyn Error>> {
+    let result = run_command(json!({
+        "id": "fork_and_hang",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "sleep 30 & echo $!; sleep 30"],
+        "timeout_secs": 1,
+        "max_output_bytes": null
+    }))
+    .await?;
+    assert!(result.timed_out);
+    let grandchild = result
+        .stdout
+        .lines()
+        .next()
+        .ok_or("missing grandchild pid")?;
+    assert!(
+        wait_for_exit(grandchild).await,
+        "pid {grandchild} outlived the timeout"
+    );
+
+    let result = run_command(json!({
+        "id": "fork_and_flood",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "sleep 30 & echo $!; yes"],
+        "timeout_secs": null,
+        "max_output_bytes": 1024
+    }))
+    .await?;
+    assert!(result.truncated);
+    assert!(!result.timed_out);
+    let grandchild = result
+        .stdout
+        .lines()
+        .next()
+        .ok_or("missing grandchild pid")?;
+    assert!(
+        wait_for_exit(grandchild).await,
+        "pid {grandchild} outlived the truncation"
+    );
+
+    // Backgrounded pipe holders used to keep the request open after the command exited.
+    let result = run_command(json!({
+        "id": "fork_and_exit",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "sleep 30 & echo $!"],
+        "timeout_secs": 1,
+        "max_output_bytes": null
+    }))
+    .await?;
+    assert_eq!(result.exit_code, Some(0));
+    let grandchild = result
+        .stdout
+        .lines()
+        .next()
+        .ok_or("missing grandchild pid")?;
+    assert!(
+        wait_for_exit(grandchild).await,
+        "pid {grandchild} outlived the command"
+    );
+
+    // Without the pipes nothing holds the request open; the group is killed before the
+    // command is reaped.
+    let result = run_command(json!({
+        "id": "detach_and_exit",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "sleep 30 >/dev/null 2>&1 & echo $!"],
+        "timeout_secs": 30,
+        "max_output_bytes": null
+    }))
+    .await?;
+    assert_eq!(result.exit_code, Some(0));
+    assert!(!result.timed_out);
+    let grandchild = result
+        .stdout
+        .lines()
+        .next()
+        .ok_or("missing grandchild pid")?;
+    assert!(
+        wait_for_exit(grandchild).await,
+        "pid {grandchild} outlived the command"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn runs_command_inside_sandbox() -> Result<(), Box<dyn Error>> {
+    // Namespaces and mounts need root.
+    if !Uid::effective().is_root() {
+        return Ok(());
+    }
+
+    let result = run_command(json!({
+        "id": "sandboxed",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "echo $$; \
+            touch /etc/alaric-sandbox 2>/dev/null || echo read-only; \
+            touch /tmp/alaric-sandbox && rm /tmp/alaric-sandbox && echo writable; \
+            grep -c : /proc/net/dev; \
+            grep NoNewPrivs /proc/self/status | cut -f2; \
+            mkdir /tmp/alaric-sandbox 2>/dev/null || echo denied"],
+        "timeout_secs": null,
+        "max_output_bytes": null,
+        "sandbox": {
+            "namespaces": ["mount", "pid", "network"],
+            "read_only_root": true,
+            "writable_paths": ["/tmp"],
+            "deny_syscalls": ["mkdir", "mkdirat"],
+            "no_new_privs": true
+        }
+    }))
+    .await?;
+    assert_eq!(result.exit_code, Some(0));
+    // PID 1 of its own namespace, only loopback, and mkdir filtered out.
+    assert_eq!(result.stdout, "1\nread-only\nwritable\n1\n1\ndenied\n");
+
+    let result = run_command(json!({
+        "id": "landlocked",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "cat /etc/hostname 2>/dev/null || echo denied; \
+            echo written > /tmp/alaric-landlock && cat /tmp/alaric-landlock; \
+            rm /tmp/alaric-landlock"],
+        "timeout_secs": null,
+        "max_output_bytes": null,
+        "sandbox": {
+            "landlock": {
+                "read": ["/bin", "/usr", "/lib", "/lib64"],
+                "read_write": ["/tmp", "/dev/null"]
+            }
+        }
+    }))
+    .await?;
+    assert_eq!(result.exit_code, Some(0));
+    assert_eq!(result.stdout, "denied\nwritten\n");
+    Ok(())
+}
+
+#[tokio::test]
+async fn sets_working_dir_and_environment() -> Result<(), Box<dyn Error>> {
+    let mut command = json!({
+        "id": "show_env",
+        "program": "/bin/sh",
+        "fixed_args": ["-c", "pwd; echo \"$LANG|$GREETING|$HOME|${SECRET-unset}\""],
+        "timeout_secs": null,
+        "max_output_bytes": null,
+        "working_dir": "/tmp",
+        "env": { "LANG": "C.UTF-8" },
+        "env_passthrough": ["HOME"],
+        "env_specs": [{
+            "name": "GREETING",
+            "required": true,
+            "validation": { "type": "enum", "values": ["hello", "hi"] }
+        }]
+    });
+    let greeting = BTreeMap::from([("GREETING".to_string(), "hello".to_string())]);
+    let home = std::env::var("HOME").unwrap_or_default();
+    let expected = format!("/tmp\nC.UTF-8|hello|{home}|unset\n");
+
+    let result = run_command_with_env(command.clone(), greeting.clone()).await?;
+    assert_eq!(result.exit_code, Some(0));
+    assert_eq!(result.stdout, expected);
+
+    // Through the exec helper the working directory is entered after the limits are applied.
+    command["limits"] = json!({ "open_files": 64 });
+    let result = run_command_with_env(command.clone(), greeting).await?;
+    assert_eq!(result.stdout, expected);
+
+    for env in [
+        BTreeMap::from([("GREETING".to_string(), "bye".to_string())]),
+        BTreeMap::from([
+            ("GREETING".to_string(), "hi".to_string()),
+            ("SECRET".to_string(), "x".to_string()),
+        ]),
+        BTreeMap::new(),
+    ] {
+        let result = run_command_with_env(command.clone(), env).await?;
+        assert_eq!(result.rejected, Some(RejectionCode::InvalidArgs));
+        assert_eq!(result.exit_code, None);
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+use alaric_lib::protocol::{
+    AgentId, AgentMessage, CancelSignal, ClientMessage, CommandId, RequestId,
+    recv_secure_json_split, send_secure_json_split,
+};
+use clap::Args;
+use serde::Deserialize;
+use tokio::io::split;
+
+use crate::{
+    DynError,
+    output::{OutputMode, TargetOutput},
+    run, session,
+};
+
+// The agent rejects requests beyond this many in flight on one session.
+const MAX_BATCH_PARALLEL: usize = 32;
+
+#[derive(Args, Debug)]
+pub(super) struct BatchCommand {
+    #[arg(long = "target", value_name = "AGENT_ID")]
+    target: String,
+
+    // JSON array of `{"command_id", "args", "env"}` objects, all run over one session.
+    #[arg(long = "file", value_name = "PATH")]
+    file: PathBuf,
+
+    #[arg(long = "parallel", value_name = "N", default_value = "8")]
+    parallel: NonZeroUsize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchEntry {
+    command_id: CommandId,
+    #[serde(default)]
+    args: BTreeMap<String, String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+pub(super) async fn run(auth: &session::ClientAuth, command: BatchCommand) -> Result<(), DynError> {
+    let target_agent_id = AgentId::new(command.target.clone()).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid --target '{}': {err}", command.target),
+        )
+    })?;
+    if command.parallel.get() > MAX_BATCH_PARALLEL {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--parallel may be at most {MAX_BATCH_PARALLEL}"),
+        )
+        .into());
+    }
+    let entries = load_batch(&command.file)?;
+    // Request ids start at 1 and follow the order of the file.
+    let labels = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| format!("{}#{}", entry.command_id, index + 1))
+        .collect::<Vec<_>>();
+
+    let attestation_policy = run::load_attestation_policy()?;
+    let identity_bundle = run::load_identity()?;
+    let (mut stream, secure) = run::open_secure_session(
+        auth,
+        &target_agent_id,
+        &attestation_policy,
+        identity_bundle.as_ref(),
+    )
+    .await?;
+    let (mut receiver, mut sender) = secure.into_split();
+    let (mut reader, mut writer) = split(&mut stream);
+
+    let mut pending = entries.iter().zip(&labels).enumerate();
+    let mut in_flight = HashMap::new();
+    let mut failed = Vec::new();
+    let mut not_started = entries.len();
+    let mut stop_scheduling = false;
+    let mut next_cancel_signal = Some(CancelSignal::Terminate);
+    let mut interrupted = Box::pin(tokio::signal::ctrl_c());
+
+    loop {
+        while !stop_scheduling && in_flight.len() < command.parallel.get() {
+            let Some((index, (entry, label))) = pending.next() else {
+                break;
+            };
+            let request_id = RequestId(index as u64 + 1);
+            send_secure_json_split(
+                &mut sender,
+                &mut writer,
+                &ClientMessage::Execute {
+                    request_id,
+                    command_id: entry.command_id.clone(),
+                    args: entry.args.clone(),
+                    env: entry.env.clone(),
+                },
+            )
+            .await?;
+            not_started -= 1;
+            in_flight.insert(
+                request_id,
+                (
+                    &entry.command_id,
+                    TargetOutput::new(label, OutputMode::Prefixed),
+                ),
+            );
+        }
+        if in_flight.is_empty() {
+            break;
+        }
+
+        let message = tokio::select! {
+            message = recv_secure_json_split::<_, AgentMessage>(&mut receiver, &mut reader) => message?,
+            signal_result = &mut interrupted, if next_cancel_signal.is_some() => {
+                signal_result?;
+                stop_scheduling = true;
+                let Some(signal) = next_cancel_signal else {
+                    continue;
+                };
+                next_cancel_signal = match signal {
+                    CancelSignal::Kill => None,
+                    _ => Some(CancelSignal::Kill),
+                };
+                interrupted.set(tokio::signal::ctrl_c());
+                for request_id in in_flight.keys() {
+                    println!("cancelling request {} (signal={:?})", request_id, signal);
+                    send_secure_json_split(
+                        &mut sender,
+                        &mut writer,
+                        &ClientMessage::Cancel {
+                            request_id: *request_id,
+                            signal,
+                        },
+                    )
+                    .await?;
+                }
+                continue;
+            }
+        };
+
+        let (request_id, failure) = match message {
+            AgentMessage::Started { request_id } => {
+                if let Some((command_id, _)) = in_flight.get(&request_id) {
+                    println!(
+                        "command '{}' started (request_id={})",
+                        command_id, request_id
+                    );
+                }
+                continue;
+            }
+            AgentMessage::Output {
+                request_id,
+                stream,
+                chunk,
+            } => {
+                if let Some((_, output)) = in_flight.get_mut(&request_id) {
+                    output.write(stream, &chunk)?;
+                }
+                continue;
+            }
+            AgentMessage::Completed {
+                request_id,
+                exit_code,
+                timed_out,
+                truncated,
+                limit_exceeded,
+                input_exceeded,
+            } => (
+                request_id,
+                run::completion_failure_message(
+                    exit_code,
+                    timed_out,
+                    truncated,
+                    limit_exceeded,
+                    input_exceeded,
+                ),
+            ),
+            AgentMessage::Cancelled {
+                request_id, signal, ..
+            } => (request_id, Some(format!("cancelled (signal={signal:?})"))),
+            AgentMessage::Rejected {
+                request_id,
+                code,
+                message,
+            } => (request_id, Some(format!("rejected ({code:?}): {message}"))),
+            _ => continue,
+        };
+        let Some((command_id, mut output)) = in_flight.remove(&request_id) else {
+            continue;
+        };
+        output.finish()?;
+        match failure {
+            Some(failure) => {
+                println!(
+                    "command '{}' failed (request_id={}): {}",
+                    command_id, request_id, failure
+                );
+                failed.push(format!("{}#{}", command_id, request_id));
+            }
+            None => println!(
+                "command '{}' completed (request_id={})",
+                command_id, request_id
+            ),
+        }
+    }
+
+    send_secure_json_split(&mut sender, &mut writer, &ClientMessage::Close).await?;
+
+    let mut summary = Vec::new();
+    if !failed.is_empty() {
+        summary.push(format!(
+            "{} of {} request(s) failed: {}",
+            failed.len(),
+            entries.len(),
+            failed.join(", ")
+        ));
+    }
+    if not_started > 0 {
+        summary.push(format!("{} request(s) not started", not_started));
+    }
+    if summary.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::other(summary.join("; ")).into())
+}
+
+fn load_batch(path: &Path) -> Result<Vec<BatchEntry>, DynError> {
+    let raw = std::fs::read_to_string(path)?;
+    parse_batch(&raw).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid batch file '{}': {err}", path.display()),
+        )
+        .into()
+    })
+}
+
+fn parse_batch(raw: &str) -> Result<Vec<BatchEntry>, String> {
+    let entries = serde_json::from_str::<Vec<BatchEntry>>(raw).map_err(|err| err.to_string())?;
+    if entries.is_empty() {
+        return Err("batch has no commands".to_string());
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_batch;
+
+    #[test]
+    fn parses_batch_entries() {
+        let entries = parse_batch(
+            r#"[
+                {"command_id": "uptime"},
+                {"command_id": "echo_text", "args": {"text": "hi"}, "env": {"LANG": "C"}}
+            ]"#,
+        )
+        .expect("batch should parse");
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command_id.as_str(), "uptime");
+        assert!(entries[0].args.is_empty());
+        assert_eq!(entries[1].args.get("text").map(String::as_str), Some("hi"));
+        assert_eq!(entries[1].env.get("LANG").map(String::as_str), Some("C"));
+    }
+
+    #[test]
+    fn rejects_empty_or_malformed_batch() {
+        assert!(parse_batch("[]").is_err());
+        assert!(parse_batch(r#"[{"command_id": "bad id"}]"#).is_err());
+        assert!(parse_batch(r#"[{"command_id": "uptime", "argz": {}}]"#).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+
+use alaric_lib::protocol::{
+    AgentId, AgentMessage, ClientMessage, RequestId, SecureChannel, recv_secure_json,
+    send_secure_json,
+};
+use alaric_lib::transport::RelayStream;
+use clap::Args;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+
+use crate::{DynError, run, session};
+
+#[derive(Args, Debug)]
+pub(super) struct FetchCommand {
+    #[arg(long = "target", value_name = "AGENT_ID")]
+    target: String,
+
+    #[arg(long = "path", value_name = "REMOTE_PATH")]
+    path: String,
+
+    #[arg(long = "output", short = 'o', value_name = "LOCAL_PATH")]
+    output: PathBuf,
+}
+
+pub(super) async fn run(auth: &session::ClientAuth, command: FetchCommand) -> Result<(), DynError> {
+    let target_agent_id = AgentId::new(command.target.clone()).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid --target '{}': {err}", command.target),
+        )
+    })?;
+    let attestation_policy = run::load_attestation_policy()?;
+    let identity_bundle = run::load_identity()?;
+    let (mut stream, mut secure) = run::open_secure_session(
+        auth,
+        &target_agent_id,
+        &attestation_policy,
+        identity_bundle.as_ref(),
+    )
+    .await?;
+
+    let request_id = RequestId(1);
+    send_secure_json(
+        &mut secure,
+        &mut stream,
+        &ClientMessage::Fetch {
+            request_id,
+            path: command.path.clone(),
+        },
+    )
+    .await?;
+
+    let partial_path = partial_path(&command.output);
+    let mut file = File::create(&partial_path).await?;
+    let outcome = receive_file(&mut secure, &mut stream, request_id, &mut file).await;
+    send_secure_json(&mut secure, &mut stream, &ClientMessage::Close).await?;
+
+    let (size, sha256) = match outcome {
+        Ok(summary) => summary,
+        Err(err) => {
+            drop(file);
+            let _ = fs::remove_file(&partial_path).await;
+            return Err(err);
+        }
+    };
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&partial_path, &command.output).await?;
+
+    println!(
+        "fetched '{}' from target '{}' to '{}' ({} bytes, sha256={})",
+        command.path,
+        target_agent_id,
+        command.output.display(),
+        size,
+        sha256
+    );
+    Ok(())
+}
+
+async fn receive_file(
+    secure: &mut SecureChannel,
+    stream: &mut RelayStream,
+    request_id: RequestId,
+    file: &mut File,
+) -> Result<(u64, String), DynError> {
+    let mut hasher = Sha256::new();
+    let mut received = 0u64;
+
+    loop {
+        let message = recv_secure_json::<_, AgentMessage>(secure, stream).await?;
+
+        match message {
+            AgentMessage::FetchStarted {
+                request_id: message_request_id,
+                size,
+            } if message_request_id == request_id => {
+                println!("receiving {} bytes", size);
+            }
+            AgentMessage::FetchChunk {
+                request_id: message_request_id,
+                data,
+            } if message_request_id == request_id => {
+                hasher.update(&data);
+                received += data.len() as u64;
+                file.write_all(&data).await?;
+            }
+            AgentMessage::FetchCompleted {
+                request_id: message_request_id,
+                size,
+                sha256,
+            } if message_request_id == request_id => {
+                let local_sha256 = hex::encode(hasher.finalize());
+                if size != received || !sha256.eq_ignore_ascii_case(&local_sha256) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "fetched file failed verification (agent: {} bytes, sha256={}; local: {} bytes, sha256={})",
+                            size, sha256, received, local_sha256
+                        ),
+                    )
+                    .into());
+                }
+                return Ok((received, local_sha256));
+            }
+            AgentMessage::Rejected {
+                request_id: message_request_id,
+                code,
+                message,
+            } if message_request_id == request_id => {
+                return Err(io::Error::other(format!(
+                    "fetch rejected (request_id={}, code={:?}): {}",
+                    request_id, code, message
+                ))
+                .into());
+            }
+            AgentMessage::Cancelled {
+                request_id: message_request_id,
+                ..
+            } if message_request_id == request_id => {
+                return Err(io::Error::other(format!(
+                    "fetch cancelled (request_id={})",
+                    request_id
+                ))
+                .into());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output
+        .file_name()
+        .map(OsString::from)
+        .unwrap_or_else(|| OsString::from("fetch"));
+    name.push(".partial");
+    output.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::partial_path;
+
+    #[test]
+    fn partial_path_sits_next_to_output() {
+        assert_eq!(
+            partial_path(Path::new("/tmp/out/app.log")),
+            Path::new("/tmp/out/app.log.partial")
+        );
+    }
+}
@@ -1,12 +1,64 @@
+use std::io;
+
+use alaric_lib::protocol::{AgentGroupId, AgentPresenceStatus, DiscoveryQuery};
 use clap::Args;
 
-use crate::{DynError, session};
+use crate::{
+    DynError,
+    report::{OutputFormat, write_agent_list},
+    session,
+};
 
 #[derive(Args, Debug)]
-pub(super) struct ListAgentsCommand;
+pub(super) struct ListAgentsCommand {
+    #[arg(long = "output", value_enum, default_value_t)]
+    output: OutputFormat,
+
+    #[arg(long = "status", value_name = "online|offline", value_parser = parse_status)]
+    status: Option<AgentPresenceStatus>,
+
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+
+    #[arg(long = "capability", value_name = "COMMAND_ID")]
+    capabilities: Vec<String>,
+
+    #[arg(long = "name-prefix", value_name = "PREFIX")]
+    name_prefix: Option<String>,
+
+    #[arg(long = "group", value_name = "GROUP_ID")]
+    group: Option<String>,
 
-pub(super) async fn run(auth: &session::ClientAuth, _: ListAgentsCommand) -> Result<(), DynError> {
-    let response = session::fetch_discovery(auth).await?;
+    #[arg(long = "page-size", value_name = "N")]
+    page_size: Option<u32>,
+}
+
+pub(super) async fn run(
+    auth: &session::ClientAuth,
+    command: ListAgentsCommand,
+) -> Result<(), DynError> {
+    let group = match command.group {
+        Some(group) => Some(AgentGroupId::new(group.clone()).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid --group '{group}': {err}"),
+            )
+        })?),
+        None => None,
+    };
+    let query = DiscoveryQuery {
+        status: command.status,
+        tags: command.tags,
+        capabilities: command.capabilities,
+        name_prefix: command.name_prefix,
+        group,
+        page_size: command.page_size,
+        cursor: None,
+    };
+    let response = session::fetch_discovery(auth, query).await?;
+    if command.output != OutputFormat::Text {
+        return Ok(write_agent_list(command.output, &response)?);
+    }
 
     if response.agents.is_empty() {
         println!("no agents discovered");
@@ -47,3 +99,13 @@ pub(super) async fn run(auth: &session::ClientAuth, _: ListAgentsCommand) -> Res
 
     Ok(())
 }
+
+fn parse_status(raw: &str) -> Result<AgentPresenceStatus, String> {
+    match raw {
+        "online" => Ok(AgentPresenceStatus::Online),
+        "offline" => Ok(AgentPresenceStatus::Offline),
+        _ => Err(format!(
+            "invalid status '{raw}'; expected online or offline"
+        )),
+    }
+}
@@ -4,8 +4,14 @@ use clap::{Parser, Subcommand};
 
 use crate::run::run_cmd;
 
+mod batch;
+mod fetch;
 mod list_agents;
+mod output;
+mod put;
+mod report;
 mod run;
+mod selector;
 mod session;
 
 type DynError = Box<dyn Error + Send + Sync>;
@@ -22,6 +28,12 @@ enum Command {
     ListAgents(list_agents::ListAgentsCommand),
     #[command(arg_required_else_help = true)]
     Run(run::RunCommand),
+    #[command(arg_required_else_help = true)]
+    Batch(batch::BatchCommand),
+    #[command(arg_required_else_help = true)]
+    Fetch(fetch::FetchCommand),
+    #[command(arg_required_else_help = true)]
+    Put(put::PutCommand),
 }
 
 #[tokio::main]
@@ -32,6 +44,9 @@ async fn main() -> Result<(), DynError> {
     match cli.command {
         Command::ListAgents(command) => list_agents::run(&auth, command).await?,
         Command::Run(command) => run_cmd(&auth, command).await?,
+        Command::Batch(command) => batch::run(&auth, command).await?,
+        Command::Fetch(command) => fetch::run(&auth, command).await?,
+        Command::Put(command) => put::run(&auth, command).await?,
     }
 
     Ok(())
@@ -49,10 +64,31 @@ mod tests {
             .expect("list-agents should parse");
         assert!(matches!(
             cli.command,
-            crate::Command::ListAgents(super::list_agents::ListAgentsCommand)
+            crate::Command::ListAgents(super::list_agents::ListAgentsCommand { .. })
         ));
     }
 
+    #[test]
+    fn parses_machine_readable_output() {
+        for args in [
+            vec!["alaric-client", "list-agents", "--output", "jsonl"],
+            vec![
+                "alaric-client",
+                "run",
+                "--command-id",
+                "echo_text",
+                "--target",
+                "agent-default",
+                "--output",
+                "json",
+            ],
+        ] {
+            Cli::try_parse_from(args).expect("machine-readable output should parse");
+        }
+
+        assert!(Cli::try_parse_from(["alaric-client", "list-agents", "--output", "yaml"]).is_err());
+    }
+
     #[test]
     fn parses_run() {
         let cli = Cli::try_parse_from([
@@ -72,4 +108,97 @@ mod tests {
             crate::Command::Run(super::run::RunCommand { .. })
         ));
     }
+
+    #[test]
+    fn parses_parallel_run() {
+        let cli = Cli::try_parse_from([
+            "alaric-client",
+            "run",
+            "--command-id",
+            "echo_text",
+            "--group",
+            "ca-west-prod01",
+            "--parallel",
+            "16",
+            "--max-failures",
+            "3",
+            "--buffer-output",
+        ])
+        .expect("parallel run should parse");
+        assert!(matches!(cli.command, crate::Command::Run(_)));
+
+        assert!(
+            Cli::try_parse_from([
+                "alaric-client",
+                "run",
+                "--command-id",
+                "echo_text",
+                "--parallel",
+                "0",
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn parses_batch() {
+        let cli = Cli::try_parse_from([
+            "alaric-client",
+            "batch",
+            "--target",
+            "agent-default",
+            "--file",
+            "commands.json",
+            "--parallel",
+            "4",
+        ])
+        .expect("batch should parse");
+
+        assert!(matches!(
+            cli.command,
+            crate::Command::Batch(super::batch::BatchCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn parses_fetch() {
+        let cli = Cli::try_parse_from([
+            "alaric-client",
+            "fetch",
+            "--target",
+            "agent-default",
+            "--path",
+            "/var/log/syslog",
+            "--output",
+            "syslog",
+        ])
+        .expect("fetch should parse");
+
+        assert!(matches!(
+            cli.command,
+            crate::Command::Fetch(super::fetch::FetchCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn parses_put() {
+        let cli = Cli::try_parse_from([
+            "alaric-client",
+            "put",
+            "--target",
+            "agent-default",
+            "--input",
+            "app.conf",
+            "--path",
+            "/srv/uploads/app.conf",
+            "--mode",
+            "0640",
+        ])
+        .expect("put should parse");
+
+        assert!(matches!(
+            cli.command,
+            crate::Command::Put(super::put::PutCommand { .. })
+        ));
+    }
 }
@@ -0,0 +1,148 @@
+use std::io::{self, Write};
+
+use alaric_lib::protocol::OutputStream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum OutputMode {
+    // A single target writes straight through.
+    Raw,
+    // Complete lines are written as they arrive, tagged with the target and stream.
+    Prefixed,
+    // Everything is held until the target finishes, then written as one block.
+    Buffered,
+}
+
+pub(super) struct TargetOutput<'a> {
+    // Names the target (or request) in prefixes and buffered headers.
+    label: &'a str,
+    mode: OutputMode,
+    pending_stdout: Vec<u8>,
+    pending_stderr: Vec<u8>,
+    buffered: Vec<(OutputStream, Vec<u8>)>,
+}
+
+impl<'a> TargetOutput<'a> {
+    pub(super) const fn new(label: &'a str, mode: OutputMode) -> Self {
+        Self {
+            label,
+            mode,
+            pending_stdout: Vec::new(),
+            pending_stderr: Vec::new(),
+            buffered: Vec::new(),
+        }
+    }
+
+    pub(super) fn write(&mut self, stream: OutputStream, chunk: &[u8]) -> Result<(), io::Error> {
+        match self.mode {
+            OutputMode::Raw => write_stream(stream, chunk),
+            OutputMode::Prefixed => {
+                let prefix = line_prefix(self.label, stream);
+                let pending = match stream {
+                    OutputStream::Stdout => &mut self.pending_stdout,
+                    OutputStream::Stderr => &mut self.pending_stderr,
+                };
+                let lines = take_prefixed_lines(&prefix, pending, chunk);
+                if lines.is_empty() {
+                    return Ok(());
+                }
+                write_stream(stream, &lines)
+            }
+            OutputMode::Buffered => {
+                match self.buffered.last_mut() {
+                    Some((last_stream, data)) if *last_stream == stream => {
+                        data.extend_from_slice(chunk);
+                    }
+                    _ => self.buffered.push((stream, chunk.to_vec())),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Writes whatever is still held back: unterminated lines, or the whole buffered block.
+    pub(super) fn finish(&mut self) -> Result<(), io::Error> {
+        match self.mode {
+            OutputMode::Raw => Ok(()),
+            OutputMode::Prefixed => {
+                for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+                    let pending = match stream {
+                        OutputStream::Stdout => &mut self.pending_stdout,
+                        OutputStream::Stderr => &mut self.pending_stderr,
+                    };
+                    if pending.is_empty() {
+                        continue;
+                    }
+                    let mut tail = std::mem::take(pending);
+                    tail.push(b'\n');
+                    let prefix = line_prefix(self.label, stream);
+                    write_stream(
+                        stream,
+                        &take_prefixed_lines(&prefix, &mut Vec::new(), &tail),
+                    )?;
+                }
+                Ok(())
+            }
+            OutputMode::Buffered => {
+                if self.buffered.is_empty() {
+                    return Ok(());
+                }
+                println!("output from target '{}':", self.label);
+                for (stream, data) in self.buffered.drain(..) {
+                    write_stream(stream, &data)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn line_prefix(label: &str, stream: OutputStream) -> String {
+    let stream_label = match stream {
+        OutputStream::Stdout => "stdout",
+        OutputStream::Stderr => "stderr",
+    };
+    format!("[{label}:{stream_label}] ")
+}
+
+// Returns the complete lines of `pending` + `chunk`, each prefixed, and keeps the unterminated
+// tail in `pending` so lines from concurrent targets never interleave mid-line.
+fn take_prefixed_lines(prefix: &str, pending: &mut Vec<u8>, chunk: &[u8]) -> Vec<u8> {
+    pending.extend_from_slice(chunk);
+    let Some(last_newline) = pending.iter().rposition(|byte| *byte == b'\n') else {
+        return Vec::new();
+    };
+
+    let tail = pending.split_off(last_newline + 1);
+    let complete = std::mem::replace(pending, tail);
+    let mut lines = Vec::with_capacity(complete.len());
+    for line in complete.split_inclusive(|byte| *byte == b'\n') {
+        lines.extend_from_slice(prefix.as_bytes());
+        lines.extend_from_slice(line);
+    }
+    lines
+}
+
+fn write_stream(stream: OutputStream, data: &[u8]) -> Result<(), io::Error> {
+    let mut out: Box<dyn Write> = match stream {
+        OutputStream::Stdout => Box::new(io::stdout().lock()),
+        OutputStream::Stderr => Box::new(io::stderr().lock()),
+    };
+    out.write_all(data)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::take_prefixed_lines;
+
+    #[test]
+    fn prefixes_only_complete_lines() {
+        let mut pending = Vec::new();
+        assert!(take_prefixed_lines("[a] ", &mut pending, b"par").is_empty());
+        assert_eq!(
+            take_prefixed_lines("[a] ", &mut pending, b"tial\nsecond\nthi"),
+            b"[a] partial\n[a] second\n".to_vec()
+        );
+        assert_eq!(pending, b"thi".to_vec());
+    }
+}
@@ -0,0 +1,207 @@
+use std::{io, path::PathBuf};
+
+use alaric_lib::protocol::{
+    AgentId, AgentMessage, ClientMessage, RequestId, SecureChannel, recv_secure_json,
+    send_secure_json,
+};
+use alaric_lib::transport::RelayStream;
+use clap::Args;
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::{DynError, run, session};
+
+const PUT_CHUNK_BYTES: usize = 16 * 1024;
+
+#[derive(Args, Debug)]
+pub(super) struct PutCommand {
+    #[arg(long = "target", value_name = "AGENT_ID")]
+    target: String,
+
+    #[arg(long = "input", short = 'i', value_name = "LOCAL_PATH")]
+    input: PathBuf,
+
+    #[arg(long = "path", value_name = "REMOTE_PATH")]
+    path: String,
+
+    #[arg(long = "mode", value_name = "MODE", value_parser = parse_mode)]
+    mode: Option<u32>,
+}
+
+pub(super) async fn run(auth: &session::ClientAuth, command: PutCommand) -> Result<(), DynError> {
+    let target_agent_id = AgentId::new(command.target.clone()).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid --target '{}': {err}", command.target),
+        )
+    })?;
+    let mut file = File::open(&command.input).await.map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("failed to open '{}': {err}", command.input.display()),
+        )
+    })?;
+    let size = file.metadata().await?.len();
+
+    let attestation_policy = run::load_attestation_policy()?;
+    let identity_bundle = run::load_identity()?;
+    let (mut stream, mut secure) = run::open_secure_session(
+        auth,
+        &target_agent_id,
+        &attestation_policy,
+        identity_bundle.as_ref(),
+    )
+    .await?;
+
+    let request_id = RequestId(1);
+    send_secure_json(
+        &mut secure,
+        &mut stream,
+        &ClientMessage::Put {
+            request_id,
+            path: command.path.clone(),
+            size,
+            mode: command.mode,
+        },
+    )
+    .await?;
+
+    let local_sha256 = send_file(&mut secure, &mut stream, request_id, &mut file, size).await?;
+    let outcome = receive_confirmation(&mut secure, &mut stream, request_id).await;
+    send_secure_json(&mut secure, &mut stream, &ClientMessage::Close).await?;
+
+    let (remote_size, remote_sha256) = outcome?;
+    if remote_size != size || !remote_sha256.eq_ignore_ascii_case(&local_sha256) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "uploaded file failed verification (agent: {} bytes, sha256={}; local: {} bytes, sha256={})",
+                remote_size, remote_sha256, size, local_sha256
+            ),
+        )
+        .into());
+    }
+
+    println!(
+        "uploaded '{}' to '{}' on target '{}' ({} bytes, sha256={})",
+        command.input.display(),
+        command.path,
+        target_agent_id,
+        size,
+        local_sha256
+    );
+    Ok(())
+}
+
+// Sends exactly the size announced in the put request, even if the file grows meanwhile.
+async fn send_file(
+    secure: &mut SecureChannel,
+    stream: &mut RelayStream,
+    request_id: RequestId,
+    file: &mut File,
+    size: u64,
+) -> Result<String, DynError> {
+    let mut reader = file.take(size);
+    let mut hasher = Sha256::new();
+    let mut sent = 0u64;
+    let mut buf = vec![0u8; PUT_CHUNK_BYTES];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        sent += n as u64;
+        send_secure_json(
+            secure,
+            stream,
+            &ClientMessage::PutChunk {
+                request_id,
+                data: buf[..n].to_vec(),
+                eof: false,
+            },
+        )
+        .await?;
+    }
+    if sent != size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "local file shrank to {} of {} bytes while uploading",
+                sent, size
+            ),
+        )
+        .into());
+    }
+
+    send_secure_json(
+        secure,
+        stream,
+        &ClientMessage::PutChunk {
+            request_id,
+            data: Vec::new(),
+            eof: true,
+        },
+    )
+    .await?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn receive_confirmation(
+    secure: &mut SecureChannel,
+    stream: &mut RelayStream,
+    request_id: RequestId,
+) -> Result<(u64, String), DynError> {
+    loop {
+        let message = recv_secure_json::<_, AgentMessage>(secure, stream).await?;
+
+        match message {
+            AgentMessage::PutCompleted {
+                request_id: message_request_id,
+                size,
+                sha256,
+                ..
+            } if message_request_id == request_id => return Ok((size, sha256)),
+            AgentMessage::Rejected {
+                request_id: message_request_id,
+                code,
+                message,
+            } if message_request_id == request_id => {
+                return Err(io::Error::other(format!(
+                    "put rejected (request_id={}, code={:?}): {}",
+                    request_id, code, message
+                ))
+                .into());
+            }
+            AgentMessage::Cancelled {
+                request_id: message_request_id,
+                ..
+            } if message_request_id == request_id => {
+                return Err(
+                    io::Error::other(format!("put cancelled (request_id={})", request_id)).into(),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("'{}' is not an octal mode between 0000 and 0777", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_mode;
+
+    #[test]
+    fn parses_octal_modes() {
+        assert_eq!(parse_mode("0640"), Ok(0o640));
+        assert_eq!(parse_mode("755"), Ok(0o755));
+        assert!(parse_mode("4755").is_err());
+        assert!(parse_mode("rw-r--r--").is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+};
+
+use alaric_lib::protocol::{
+    AgentDiscoveryEntry, AgentGroupDiscoveryEntry, AgentId, CancelSignal, CommandId,
+    ListAgentsResponse, OutputStream, RejectionCode, ResourceLimit,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::ValueEnum;
+use serde::{Serialize, Serializer};
+
+// Bumped whenever a field is removed or changes meaning; new fields may be added freely.
+pub(super) const REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(super) enum OutputFormat {
+    #[default]
+    Text,
+    // One document written once everything has finished.
+    Json,
+    // One object per line, written as events happen.
+    Jsonl,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(super) enum RunEvent<'a> {
+    // A selected agent left out before anything ran on it.
+    Skipped {
+        target: &'a AgentId,
+        reason: String,
+    },
+    Started {
+        target: &'a AgentId,
+        command_id: &'a CommandId,
+    },
+    Output {
+        target: &'a AgentId,
+        stream: OutputStream,
+        #[serde(serialize_with = "serialize_base64")]
+        data: &'a [u8],
+    },
+    CancelRequested {
+        target: &'a AgentId,
+        signal: CancelSignal,
+    },
+    Completed {
+        target: &'a AgentId,
+        exit_code: i32,
+        timed_out: bool,
+        truncated: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit_exceeded: Option<ResourceLimit>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        input_exceeded: bool,
+    },
+    Cancelled {
+        target: &'a AgentId,
+        signal: CancelSignal,
+        exit_code: i32,
+        forced: bool,
+    },
+    Rejected {
+        target: &'a AgentId,
+        code: RejectionCode,
+        message: &'a str,
+    },
+    // The target did not finish successfully, for any reason including the ones above.
+    Failed {
+        target: &'a AgentId,
+        error: String,
+    },
+    Summary(RunSummary<'a>),
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct RunSummary<'a> {
+    pub(super) schema_version: u32,
+    pub(super) command_id: &'a CommandId,
+    pub(super) succeeded: Vec<&'a AgentId>,
+    pub(super) failed: Vec<&'a AgentId>,
+    pub(super) aborted: Vec<&'a AgentId>,
+    pub(super) not_started: Vec<&'a AgentId>,
+}
+
+#[derive(Debug, Serialize)]
+struct RunReport<'a> {
+    schema_version: u32,
+    events: Vec<serde_json::Value>,
+    summary: RunSummary<'a>,
+}
+
+// Targets run concurrently on one task, so events are collected behind a `RefCell`.
+pub(super) struct RunReporter {
+    format: OutputFormat,
+    command_id: CommandId,
+    events: RefCell<Vec<serde_json::Value>>,
+}
+
+impl RunReporter {
+    pub(super) const fn new(format: OutputFormat, command_id: CommandId) -> Self {
+        Self {
+            format,
+            command_id,
+            events: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub(super) const fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    pub(super) fn emit(&self, event: &RunEvent<'_>) -> Result<(), io::Error> {
+        match self.format {
+            OutputFormat::Text => {
+                print_text_event(&self.command_id, event);
+                Ok(())
+            }
+            OutputFormat::Json => {
+                self.events.borrow_mut().push(serde_json::to_value(event)?);
+                Ok(())
+            }
+            OutputFormat::Jsonl => write_json_line(event),
+        }
+    }
+
+    pub(super) fn finish(self, summary: RunSummary<'_>) -> Result<(), io::Error> {
+        match self.format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::Json => write_json_document(&RunReport {
+                schema_version: REPORT_SCHEMA_VERSION,
+                events: self.events.into_inner(),
+                summary,
+            }),
+            OutputFormat::Jsonl => write_json_line(&RunEvent::Summary(summary)),
+        }
+    }
+}
+
+fn print_text_event(command_id: &CommandId, event: &RunEvent<'_>) {
+    match event {
+        RunEvent::Skipped { target, reason } => {
+            eprintln!("skipping agent '{}': {}", target, reason);
+        }
+        RunEvent::Started { target, .. } => {
+            println!("command '{}' started for target '{}'", command_id, target);
+        }
+        RunEvent::CancelRequested { target, signal } => {
+            println!(
+                "cancelling command '{}' for target '{}' (signal={:?})",
+                command_id, target, signal
+            );
+        }
+        RunEvent::Completed {
+            target,
+            exit_code,
+            timed_out,
+            truncated,
+            limit_exceeded,
+            input_exceeded,
+        } => {
+            let mut limit = limit_exceeded
+                .map(|limit| format!(", limit_exceeded={limit:?}"))
+                .unwrap_or_default();
+            if *input_exceeded {
+                limit.push_str(", input_exceeded=true");
+            }
+            println!(
+                "command '{}' completed for target '{}' (exit_code={}, timed_out={}, truncated={}{})",
+                command_id, target, exit_code, timed_out, truncated, limit,
+            );
+        }
+        RunEvent::Cancelled {
+            target,
+            signal,
+            exit_code,
+            forced,
+        } => {
+            println!(
+                "command '{}' cancelled for target '{}' (signal={:?}, exit_code={}, forced={})",
+                command_id, target, signal, exit_code, forced,
+            );
+        }
+        RunEvent::Failed { target, error } => {
+            println!("target '{}' failed: {error}", target);
+        }
+        // Output goes through `TargetOutput`, and rejections surface through `Failed`.
+        RunEvent::Output { .. } | RunEvent::Rejected { .. } | RunEvent::Summary(_) => {}
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AgentListReport<'a> {
+    schema_version: u32,
+    generated_at_unix: u64,
+    agents: &'a [AgentDiscoveryEntry],
+    groups: &'a [AgentGroupDiscoveryEntry],
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AgentListRecord<'a> {
+    Agent(&'a AgentDiscoveryEntry),
+    Group(&'a AgentGroupDiscoveryEntry),
+}
+
+// Writes the discovery response for `json`/`jsonl`; text output stays in `list_agents`.
+pub(super) fn write_agent_list(
+    format: OutputFormat,
+    response: &ListAgentsResponse,
+) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Text => Ok(()),
+        OutputFormat::Json => write_json_document(&AgentListReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            generated_at_unix: response.generated_at_unix,
+            agents: &response.agents,
+            groups: &response.groups,
+        }),
+        OutputFormat::Jsonl => {
+            for agent in &response.agents {
+                write_json_line(&AgentListRecord::Agent(agent))?;
+            }
+            for group in &response.groups {
+                write_json_line(&AgentListRecord::Group(group))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn write_json_line<T: Serialize>(value: &T) -> Result<(), io::Error> {
+    let mut out = io::stdout().lock();
+    serde_json::to_writer(&mut out, value)?;
+    out.write_all(b"\n")?;
+    out.flush()
+}
+
+fn write_json_document<T: Serialize>(value: &T) -> Result<(), io::Error> {
+    let mut out = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut out, value)?;
+    out.write_all(b"\n")?;
+    out.flush()
+}
+
+fn serialize_base64<S>(bytes: &&[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&STANDARD.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use alaric_lib::protocol::{
+        AgentDiscoveryEntry, AgentId, AgentPresenceStatus, CommandId, OutputStream,
+    };
+    use serde_json::json;
+
+    use super::{AgentListRecord, RunEvent, RunSummary};
+
+    #[test]
+    fn run_events_have_stable_shape() {
+        let target = AgentId::new("agent-default").expect("valid agent id");
+        let command_id = CommandId::new("echo_text").expect("valid command id");
+
+        let output = RunEvent::Output {
+            target: &target,
+            stream: OutputStream::Stderr,
+            data: b"hi\n",
+        };
+        assert_eq!(
+            serde_json::to_value(&output).expect("output event should serialize"),
+            json!({
+                "event": "output",
+                "target": "agent-default",
+                "stream": "stderr",
+                "data": "aGkK",
+            })
+        );
+
+        let skipped = RunEvent::Skipped {
+            target: &target,
+            reason: "it does not advertise command 'echo_text'".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&skipped).expect("skipped event should serialize"),
+            json!({
+                "event": "skipped",
+                "target": "agent-default",
+                "reason": "it does not advertise command 'echo_text'",
+            })
+        );
+
+        let summary = RunEvent::Summary(RunSummary {
+            schema_version: 1,
+            command_id: &command_id,
+            succeeded: vec![&target],
+            failed: Vec::new(),
+            aborted: Vec::new(),
+            not_started: Vec::new(),
+        });
+        assert_eq!(
+            serde_json::to_value(&summary).expect("summary should serialize"),
+            json!({
+                "event": "summary",
+                "schema_version": 1,
+                "command_id": "echo_text",
+                "succeeded": ["agent-default"],
+                "failed": [],
+                "aborted": [],
+                "not_started": [],
+            })
+        );
+    }
+
+    #[test]
+    fn agent_records_are_tagged_by_type() {
+        let agent = AgentDiscoveryEntry {
+            agent_id: AgentId::new("agent-default").expect("valid agent id"),
+            display_name: None,
+            capabilities: vec!["uptime".to_string()],
+            tags: Vec::new(),
+            status: AgentPresenceStatus::Online,
+            status_age_secs: 4,
+        };
+        assert_eq!(
+            serde_json::to_value(AgentListRecord::Agent(&agent))
+                .expect("agent record should serialize"),
+            json!({
+                "type": "agent",
+                "agent_id": "agent-default",
+                "display_name": null,
+                "capabilities": ["uptime"],
+                "tags": [],
+                "status": "online",
+                "status_age_secs": 4,
+            })
+        );
+    }
+}
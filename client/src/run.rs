@@ -1,30 +1,41 @@
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
-    env,
-    io::{self, Write},
+    env, io,
+    num::NonZeroUsize,
     path::Path,
 };
 
 use alaric_lib::{
     protocol::{
-        AgentGroupId, AgentId, AgentMessage, ClientId, ClientMessage, CommandId, HandshakeRequest,
-        IdentityBundle, OutputStream, PeerAttestationInit, PeerAttestationMode,
-        PeerAttestationPolicy, PeerAttestationResult, RequestId, Role, SecureChannel, SessionId,
-        TrustedIdentityKeys, build_peer_attestation_proof, recv_secure_json, send_secure_json,
+        AgentGroupId, AgentId, AgentMessage, CancelSignal, ClientId, ClientMessage, CommandId,
+        DiscoveryQuery, HandshakeRequest, IdentityBundle, PeerAttestationInit, PeerAttestationMode,
+        PeerAttestationPolicy, PeerAttestationResult, RequestId, ResourceLimit, Role,
+        SecureChannel, SecureReceiver, SessionId, TrustedIdentityKeys,
+        build_peer_attestation_proof, noise_static_keypair_from_hex, recv_secure_json,
+        recv_secure_json_split, send_secure_json, send_secure_json_split,
         verify_peer_attestation_proof,
     },
     security::noise::types::Keypair,
+    transport::RelayStream,
 };
 use clap::Args;
-use tokio::net::TcpStream;
-
-use crate::{DynError, session};
+use futures_util::{StreamExt, stream::FuturesUnordered};
+use tokio::io::{AsyncRead, AsyncReadExt, split};
+
+use crate::{
+    DynError,
+    output::{OutputMode, TargetOutput},
+    report::{OutputFormat, REPORT_SCHEMA_VERSION, RunEvent, RunReporter, RunSummary},
+    selector::{Selector, parse_selector},
+    session,
+};
 
 const CLIENT_IDENTITY_BUNDLE_PATH_ENV: &str = "CLIENT_IDENTITY_BUNDLE_PATH";
 const CLIENT_PEER_ATTESTATION_POLICY_PATH_ENV: &str = "CLIENT_PEER_ATTESTATION_POLICY_PATH";
 const CLIENT_TRUSTED_KEYS_PATH_ENV: &str = "CLIENT_TRUSTED_KEYS_PATH";
 const DEFAULT_CLIENT_IDENTITY_BUNDLE_PATH: &str = "./identity-bundle.json";
 const DEFAULT_CLIENT_TRUSTED_KEYS_PATH: &str = "./policy-keys.json";
+const STDIN_CHUNK_BYTES: usize = 8 * 1024;
 
 #[derive(Args, Debug)]
 pub(super) struct RunCommand {
@@ -34,11 +45,59 @@ pub(super) struct RunCommand {
     #[arg(long = "arg", value_name = "NAME=VALUE", value_parser = parse_named_arg)]
     args: Vec<(String, String)>,
 
+    #[arg(long = "env", value_name = "NAME=VALUE", value_parser = parse_env_var)]
+    env: Vec<(String, String)>,
+
     #[arg(long = "target", value_name = "AGENT_ID")]
     targets: Vec<String>,
 
     #[arg(long = "group", value_name = "GROUP_ID")]
     groups: Vec<String>,
+
+    #[arg(long = "select", value_name = "SELECTOR", value_parser = parse_selector)]
+    selectors: Vec<Selector>,
+
+    #[arg(long = "stdin")]
+    stdin: bool,
+
+    #[arg(long = "parallel", value_name = "N", default_value = "1")]
+    parallel: NonZeroUsize,
+
+    #[arg(long = "fail-fast")]
+    fail_fast: bool,
+
+    #[arg(long = "max-failures", value_name = "N")]
+    max_failures: Option<usize>,
+
+    #[arg(long = "buffer-output")]
+    buffer_output: bool,
+
+    #[arg(long = "output", value_enum, default_value_t)]
+    output: OutputFormat,
+}
+
+// Decides when to stop starting new targets; targets already running are left to finish unless
+// `fail_fast` is set.
+#[derive(Debug, Clone, Copy)]
+struct FailurePolicy {
+    fail_fast: bool,
+    max_failures: Option<usize>,
+}
+
+impl FailurePolicy {
+    const fn stop_scheduling(self, failures: usize) -> bool {
+        match self.max_failures {
+            _ if self.fail_fast => failures > 0,
+            Some(max_failures) => failures > max_failures,
+            None => false,
+        }
+    }
+}
+
+enum StdinSource {
+    None,
+    Stream,
+    Buffered(Vec<u8>),
 }
 
 pub(super) async fn run_cmd(
@@ -47,153 +106,442 @@ pub(super) async fn run_cmd(
 ) -> Result<(), DynError> {
     let command_id = resolve_command_id(command.command_id)?;
     let args = command.args.into_iter().collect::<BTreeMap<_, _>>();
+    let env = command.env.into_iter().collect::<BTreeMap<_, _>>();
 
     let attestation_policy = load_attestation_policy()?;
     let identity_bundle = load_identity()?;
-    let targets = resolve_targets(&command.targets, &command.groups, auth).await?;
+    let reporter = RunReporter::new(command.output, command_id.clone());
+    let targets = resolve_targets(
+        &command.targets,
+        &command.groups,
+        &command.selectors,
+        &command_id,
+        auth,
+        &reporter,
+    )
+    .await?;
 
     let multi_target = targets.len() > 1;
+    let stdin = match (command.stdin, multi_target) {
+        (false, _) => StdinSource::None,
+        (true, false) => StdinSource::Stream,
+        (true, true) => {
+            let mut buffered = Vec::new();
+            tokio::io::stdin().read_to_end(&mut buffered).await?;
+            StdinSource::Buffered(buffered)
+        }
+    };
+    let output_mode = match (multi_target, command.buffer_output) {
+        (false, _) => OutputMode::Raw,
+        (true, false) => OutputMode::Prefixed,
+        (true, true) => OutputMode::Buffered,
+    };
+    let failure_policy = FailurePolicy {
+        fail_fast: command.fail_fast,
+        max_failures: command.max_failures,
+    };
+
+    let mut pending = targets.iter();
+    let mut running = FuturesUnordered::new();
+    let mut in_flight = Vec::new();
+    let mut succeeded_targets = Vec::new();
     let mut failed_targets = Vec::new();
+    let mut aborted_targets = Vec::new();
+    let mut stop_scheduling = false;
+    let interrupted = tokio::signal::ctrl_c();
+    tokio::pin!(interrupted);
 
-    for target in targets {
-        if multi_target {
-            println!("target '{}'", target);
+    loop {
+        while !stop_scheduling && running.len() < command.parallel.get() {
+            let Some(target) = pending.next() else {
+                break;
+            };
+            if multi_target && reporter.format() == OutputFormat::Text {
+                println!("target '{}'", target);
+            }
+            in_flight.push(target);
+            let (command_id, args, env, stdin) = (&command_id, &args, &env, &stdin);
+            let (attestation_policy, identity_bundle) = (&attestation_policy, &identity_bundle);
+            let reporter = &reporter;
+            running.push(async move {
+                let mut output = TargetOutput::new(target.as_str(), output_mode);
+                let outcome = run_for_target(
+                    auth,
+                    target,
+                    command_id,
+                    args,
+                    env,
+                    stdin,
+                    attestation_policy,
+                    identity_bundle.as_ref(),
+                    &mut output,
+                    reporter,
+                )
+                .await;
+                let flushed = output.finish();
+                (target, outcome.and(flushed.map_err(DynError::from)))
+            });
         }
 
-        let outcome = run_for_target(
-            auth,
-            &target,
-            &command_id,
-            &args,
-            &attestation_policy,
-            identity_bundle.as_ref(),
-            multi_target,
-        )
-        .await;
+        let finished = tokio::select! {
+            finished = running.next() => finished,
+            // Running targets get the signal too and cancel their commands.
+            signal_result = &mut interrupted, if !stop_scheduling => {
+                signal_result?;
+                stop_scheduling = true;
+                continue;
+            }
+        };
+        let Some((target, outcome)) = finished else {
+            break;
+        };
 
-        if let Err(err) = outcome {
-            println!("target '{}' failed: {err}", target);
-            failed_targets.push(target.to_string());
+        in_flight.retain(|running| *running != target);
+        match outcome {
+            Ok(()) => succeeded_targets.push(target),
+            Err(err) => {
+                reporter.emit(&RunEvent::Failed {
+                    target,
+                    error: err.to_string(),
+                })?;
+                failed_targets.push(target);
+            }
+        }
+        if !stop_scheduling && failure_policy.stop_scheduling(failed_targets.len()) {
+            stop_scheduling = true;
+            if failure_policy.fail_fast {
+                // Dropping a target's future closes its tunnel, and the agent kills the command.
+                aborted_targets.append(&mut in_flight);
+                break;
+            }
         }
     }
+    drop(running);
 
-    if failed_targets.is_empty() {
-        Ok(())
-    } else {
-        Err(io::Error::other(format!(
+    let not_started = pending.collect::<Vec<_>>();
+    let mut summary = Vec::new();
+    if !failed_targets.is_empty() {
+        summary.push(format!(
             "command failed for {} target(s): {}",
             failed_targets.len(),
-            failed_targets.join(", ")
-        ))
-        .into())
+            failed_targets
+                .iter()
+                .map(|target| target.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    if !aborted_targets.is_empty() {
+        summary.push(format!(
+            "{} running target(s) aborted",
+            aborted_targets.len()
+        ));
+    }
+    if !not_started.is_empty() {
+        summary.push(format!("{} target(s) not started", not_started.len()));
+    }
+
+    reporter.finish(RunSummary {
+        schema_version: REPORT_SCHEMA_VERSION,
+        command_id: &command_id,
+        succeeded: succeeded_targets,
+        failed: failed_targets,
+        aborted: aborted_targets,
+        not_started,
+    })?;
+
+    if summary.is_empty() {
+        return Ok(());
     }
+    Err(io::Error::other(summary.join("; ")).into())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_for_target(
     auth: &session::ClientAuth,
     target_agent_id: &AgentId,
     command_id: &CommandId,
     args: &BTreeMap<String, String>,
+    env: &BTreeMap<String, String>,
+    stdin: &StdinSource,
     attestation_policy: &PeerAttestationPolicy,
     identity_bundle: Option<&IdentityBundle>,
-    with_target_prefix: bool,
+    output: &mut TargetOutput<'_>,
+    reporter: &RunReporter,
 ) -> Result<(), DynError> {
-    let request = HandshakeRequest::client(auth.client_id.clone(), target_agent_id.clone());
-    let mut connection = session::connect_authenticated(&request, auth).await?;
-    let mut secure =
-        SecureChannel::handshake_xx_initiator(&mut connection.stream, Keypair::default_keypair())
-            .await?;
-
-    perform_peer_attestation(
-        &mut secure,
-        &mut connection.stream,
-        &connection.session_id,
-        &auth.client_id,
-        target_agent_id,
-        &auth.auth_key_id,
-        &auth.auth_private_key,
-        attestation_policy,
-        identity_bundle,
-    )
-    .await?;
+    let (mut stream, mut secure) =
+        open_secure_session(auth, target_agent_id, attestation_policy, identity_bundle).await?;
 
     let request_id = RequestId(1);
     send_secure_json(
         &mut secure,
-        &mut connection.stream,
+        &mut stream,
         &ClientMessage::Execute {
             request_id,
             command_id: command_id.clone(),
             args: args.clone(),
+            env: env.clone(),
         },
     )
     .await?;
 
+    let (mut receiver, mut sender) = secure.into_split();
+    let (mut reader, mut writer) = split(&mut stream);
+
+    if let StdinSource::Buffered(data) = stdin {
+        for chunk in data.chunks(STDIN_CHUNK_BYTES) {
+            send_secure_json_split(
+                &mut sender,
+                &mut writer,
+                &ClientMessage::Stdin {
+                    request_id,
+                    data: chunk.to_vec(),
+                    eof: false,
+                },
+            )
+            .await?;
+        }
+        send_secure_json_split(
+            &mut sender,
+            &mut writer,
+            &ClientMessage::Stdin {
+                request_id,
+                data: Vec::new(),
+                eof: true,
+            },
+        )
+        .await?;
+    }
+    let mut local_stdin = tokio::io::stdin();
+    let mut stdin_open = matches!(stdin, StdinSource::Stream);
+    let mut stdin_buf = [0u8; STDIN_CHUNK_BYTES];
+
+    let receive = receive_outcome(
+        &mut receiver,
+        &mut reader,
+        request_id,
+        command_id,
+        target_agent_id,
+        output,
+        reporter,
+    );
+    tokio::pin!(receive);
+
+    let mut next_cancel_signal = Some(CancelSignal::Terminate);
+    let mut interrupted = Box::pin(tokio::signal::ctrl_c());
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut receive => break outcome?,
+            signal_result = &mut interrupted, if next_cancel_signal.is_some() => {
+                signal_result?;
+                let Some(signal) = next_cancel_signal else {
+                    continue;
+                };
+                next_cancel_signal = match signal {
+                    CancelSignal::Kill => None,
+                    _ => Some(CancelSignal::Kill),
+                };
+                interrupted.set(tokio::signal::ctrl_c());
+                reporter.emit(&RunEvent::CancelRequested {
+                    target: target_agent_id,
+                    signal,
+                })?;
+                send_secure_json_split(
+                    &mut sender,
+                    &mut writer,
+                    &ClientMessage::Cancel { request_id, signal },
+                )
+                .await?;
+            }
+            read_result = local_stdin.read(&mut stdin_buf), if stdin_open => {
+                let n = read_result?;
+                let eof = n == 0;
+                stdin_open = !eof;
+                let data = stdin_buf[..n].to_vec();
+                send_secure_json_split(
+                    &mut sender,
+                    &mut writer,
+                    &ClientMessage::Stdin { request_id, data, eof },
+                )
+                .await?;
+            }
+        }
+    };
+
+    send_secure_json_split(&mut sender, &mut writer, &ClientMessage::Close).await?;
+    Ok(outcome?)
+}
+
+async fn receive_outcome<S>(
+    receiver: &mut SecureReceiver,
+    reader: &mut S,
+    request_id: RequestId,
+    command_id: &CommandId,
+    target_agent_id: &AgentId,
+    output: &mut TargetOutput<'_>,
+    reporter: &RunReporter,
+) -> Result<Result<(), io::Error>, DynError>
+where
+    S: AsyncRead + Unpin,
+{
     loop {
-        let message =
-            recv_secure_json::<_, AgentMessage>(&mut secure, &mut connection.stream).await?;
+        let message = recv_secure_json_split::<_, AgentMessage>(receiver, reader).await?;
 
         match message {
             AgentMessage::Started {
                 request_id: message_request_id,
             } if message_request_id == request_id => {
-                println!(
-                    "command '{}' started for target '{}'",
-                    command_id, target_agent_id
-                );
+                reporter.emit(&RunEvent::Started {
+                    target: target_agent_id,
+                    command_id,
+                })?;
             }
             AgentMessage::Output {
                 request_id: message_request_id,
                 stream,
                 chunk,
             } if message_request_id == request_id => {
-                print_output(target_agent_id, stream, &chunk, with_target_prefix)?;
+                if reporter.format() == OutputFormat::Text {
+                    output.write(stream, &chunk)?;
+                } else {
+                    reporter.emit(&RunEvent::Output {
+                        target: target_agent_id,
+                        stream,
+                        data: &chunk,
+                    })?;
+                }
             }
             AgentMessage::Completed {
                 request_id: message_request_id,
                 exit_code,
                 timed_out,
                 truncated,
+                limit_exceeded,
+                input_exceeded,
             } if message_request_id == request_id => {
-                println!(
-                    "command '{}' completed for target '{}' (exit_code={}, timed_out={}, truncated={})",
-                    command_id, target_agent_id, exit_code, timed_out, truncated,
+                reporter.emit(&RunEvent::Completed {
+                    target: target_agent_id,
+                    exit_code,
+                    timed_out,
+                    truncated,
+                    limit_exceeded,
+                    input_exceeded,
+                })?;
+
+                return Ok(
+                    match completion_failure_message(
+                        exit_code,
+                        timed_out,
+                        truncated,
+                        limit_exceeded,
+                        input_exceeded,
+                    ) {
+                        Some(failure_message) => Err(io::Error::other(format!(
+                            "command failed (request_id={}): {}",
+                            request_id, failure_message
+                        ))),
+                        None => Ok(()),
+                    },
                 );
-
-                if let Some(failure_message) =
-                    completion_failure_message(exit_code, timed_out, truncated)
-                {
-                    return Err(io::Error::other(format!(
-                        "command failed (request_id={}): {}",
-                        request_id, failure_message
-                    ))
-                    .into());
-                }
-
-                break;
+            }
+            AgentMessage::Cancelled {
+                request_id: message_request_id,
+                signal,
+                exit_code,
+                forced,
+            } if message_request_id == request_id => {
+                reporter.emit(&RunEvent::Cancelled {
+                    target: target_agent_id,
+                    signal,
+                    exit_code,
+                    forced,
+                })?;
+
+                return Ok(Err(io::Error::other(format!(
+                    "command cancelled (request_id={})",
+                    request_id
+                ))));
             }
             AgentMessage::Rejected {
                 request_id: message_request_id,
                 code,
                 message,
             } if message_request_id == request_id => {
-                return Err(io::Error::other(format!(
+                reporter.emit(&RunEvent::Rejected {
+                    target: target_agent_id,
+                    code,
+                    message: &message,
+                })?;
+                return Ok(Err(io::Error::other(format!(
                     "command rejected (request_id={}, code={:?}): {}",
                     request_id, code, message
-                ))
-                .into());
+                ))));
             }
             _ => {}
         }
     }
+}
 
-    Ok(())
+pub(super) async fn open_secure_session(
+    auth: &session::ClientAuth,
+    target_agent_id: &AgentId,
+    attestation_policy: &PeerAttestationPolicy,
+    identity_bundle: Option<&IdentityBundle>,
+) -> Result<(RelayStream, SecureChannel), DynError> {
+    let request = HandshakeRequest::client(auth.client_id.clone(), target_agent_id.clone());
+    let mut connection = session::connect_authenticated(&request, auth).await?;
+    let static_keypair = match &auth.noise_private_key {
+        Some(private_key) => noise_static_keypair_from_hex(private_key)?,
+        None => Keypair::default_keypair(),
+    };
+    let mut secure = SecureChannel::handshake_xx_initiator(
+        &mut connection.stream,
+        static_keypair,
+        auth.session.max_message_bytes,
+    )
+    .await?
+    .with_rekey_policy(auth.session.rekey_policy());
+
+    // A Noise static key bound in the identity bundle is enforced regardless of attestation mode.
+    let bound_static_key = identity_bundle
+        .and_then(|bundle| bundle.agent_identity_key(target_agent_id))
+        .and_then(|identity| identity.noise_public_key);
+    if let Some(bound_static_key) = bound_static_key
+        && !secure.remote_static_matches(&bound_static_key)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "agent '{}' did not present the noise static key bound in the identity bundle",
+                target_agent_id
+            ),
+        )
+        .into());
+    }
+
+    perform_peer_attestation(
+        &mut secure,
+        &mut connection.stream,
+        &connection.session_id,
+        &auth.client_id,
+        target_agent_id,
+        &auth.auth_key_id,
+        &auth.auth_private_key,
+        attestation_policy,
+        identity_bundle,
+    )
+    .await?;
+
+    Ok((connection.stream, secure))
 }
 
 async fn resolve_targets(
     explicit_targets: &[String],
     groups: &[String],
+    selectors: &[Selector],
+    command_id: &CommandId,
     auth: &session::ClientAuth,
+    reporter: &RunReporter,
 ) -> Result<Vec<AgentId>, DynError> {
     let mut seen_targets = BTreeSet::new();
     let mut targets = Vec::new();
@@ -211,12 +559,19 @@ async fn resolve_targets(
         }
     }
 
-    if !groups.is_empty() {
-        let discovery = session::fetch_discovery(auth).await?;
+    let discovery = if groups.is_empty() && selectors.is_empty() {
+        None
+    } else {
+        Some(session::fetch_discovery(auth, DiscoveryQuery::default()).await?)
+    };
+
+    if let Some(discovery) = &discovery
+        && !groups.is_empty()
+    {
         let group_members = discovery
             .groups
-            .into_iter()
-            .map(|group| (group.group_id.as_str().to_string(), group.members))
+            .iter()
+            .map(|group| (group.group_id.as_str(), &group.members))
             .collect::<HashMap<_, _>>();
 
         for group in groups {
@@ -235,7 +590,7 @@ async fn resolve_targets(
                 .into());
             };
 
-            for member in members {
+            for member in *members {
                 if seen_targets.insert(member.as_str().to_string()) {
                     targets.push(member.clone());
                 }
@@ -243,6 +598,44 @@ async fn resolve_targets(
         }
     }
 
+    if let Some(discovery) = &discovery
+        && !selectors.is_empty()
+    {
+        let mut selected = 0;
+        for agent in &discovery.agents {
+            if !selectors.iter().any(|selector| selector.matches(agent)) {
+                continue;
+            }
+            selected += 1;
+            if !agent
+                .capabilities
+                .iter()
+                .any(|capability| capability == command_id.as_str())
+            {
+                reporter.emit(&RunEvent::Skipped {
+                    target: &agent.agent_id,
+                    reason: format!("it does not advertise command '{}'", command_id),
+                })?;
+                continue;
+            }
+            if seen_targets.insert(agent.agent_id.as_str().to_string()) {
+                targets.push(agent.agent_id.clone());
+            }
+        }
+        if selected == 0 {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "no agents matched --select").into(),
+            );
+        }
+        if targets.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no agent matched by --select advertises command '{command_id}'"),
+            )
+            .into());
+        }
+    }
+
     if targets.is_empty() {
         let fallback_target = AgentId::new(
             env::var("TARGET_AGENT_ID").unwrap_or_else(|_| "agent-default".to_string()),
@@ -285,9 +678,21 @@ fn parse_named_arg(raw: &str) -> Result<(String, String), String> {
     Ok((name.to_string(), value.to_string()))
 }
 
-fn load_attestation_policy() -> Result<PeerAttestationPolicy, DynError> {
+fn parse_env_var(raw: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = raw.split_once('=') else {
+        return Err(format!("invalid --env value '{raw}'; expected NAME=VALUE"));
+    };
+
+    if name.is_empty() {
+        return Err("environment variable name must not be empty".to_string());
+    }
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+pub(super) fn load_attestation_policy() -> Result<PeerAttestationPolicy, DynError> {
     let Some(path) = env::var(CLIENT_PEER_ATTESTATION_POLICY_PATH_ENV).ok() else {
-        println!(
+        eprintln!(
             "{} not set; using default peer attestation policy",
             CLIENT_PEER_ATTESTATION_POLICY_PATH_ENV
         );
@@ -295,18 +700,18 @@ fn load_attestation_policy() -> Result<PeerAttestationPolicy, DynError> {
     };
 
     let policy = PeerAttestationPolicy::load_from_path(&path)?;
-    println!("loaded peer attestation policy from {path}");
+    eprintln!("loaded peer attestation policy from {path}");
     Ok(policy)
 }
 
-fn load_identity() -> Result<Option<IdentityBundle>, DynError> {
+pub(super) fn load_identity() -> Result<Option<IdentityBundle>, DynError> {
     let configured_identity_bundle_path = env::var(CLIENT_IDENTITY_BUNDLE_PATH_ENV).ok();
     let identity_bundle_path = configured_identity_bundle_path
         .clone()
         .unwrap_or_else(|| DEFAULT_CLIENT_IDENTITY_BUNDLE_PATH.to_string());
 
     if configured_identity_bundle_path.is_none() && !Path::new(&identity_bundle_path).exists() {
-        println!(
+        eprintln!(
             "identity bundle '{}' not found; peer attestation may fall back based on policy",
             identity_bundle_path
         );
@@ -318,7 +723,7 @@ fn load_identity() -> Result<Option<IdentityBundle>, DynError> {
     let trusted_keys = TrustedIdentityKeys::load_from_path(&trusted_keys_path)?;
 
     let identity_bundle = IdentityBundle::load_from_path(&identity_bundle_path, &trusted_keys)?;
-    println!(
+    eprintln!(
         "loaded client identity bundle from {} (expires_at_unix={})",
         identity_bundle_path,
         identity_bundle.expires_at_unix()
@@ -330,7 +735,7 @@ fn load_identity() -> Result<Option<IdentityBundle>, DynError> {
 #[allow(clippy::too_many_arguments)]
 async fn perform_peer_attestation(
     secure: &mut SecureChannel,
-    stream: &mut TcpStream,
+    stream: &mut RelayStream,
     session_id: &SessionId,
     client_id: &ClientId,
     target_agent_id: &AgentId,
@@ -447,55 +852,13 @@ async fn perform_peer_attestation(
     Ok(())
 }
 
-fn print_output(
-    target_agent_id: &AgentId,
-    stream: OutputStream,
-    chunk: &str,
-    with_target_prefix: bool,
-) -> Result<(), io::Error> {
-    if !with_target_prefix {
-        match stream {
-            OutputStream::Stdout => {
-                print!("{chunk}");
-                io::stdout().flush()?;
-            }
-            OutputStream::Stderr => {
-                eprint!("{chunk}");
-                io::stderr().flush()?;
-            }
-        }
-        return Ok(());
-    }
-
-    let stream_label = match stream {
-        OutputStream::Stdout => "stdout",
-        OutputStream::Stderr => "stderr",
-    };
-
-    for segment in chunk.split_inclusive('\n') {
-        match stream {
-            OutputStream::Stdout => {
-                print!("[{target_agent_id}:{stream_label}] {segment}");
-                io::stdout().flush()?;
-            }
-            OutputStream::Stderr => {
-                eprint!("[{target_agent_id}:{stream_label}] {segment}");
-                io::stderr().flush()?;
-            }
-        }
-    }
-
-    if !chunk.ends_with('\n') {
-        match stream {
-            OutputStream::Stdout => io::stdout().flush()?,
-            OutputStream::Stderr => io::stderr().flush()?,
-        }
-    }
-
-    Ok(())
-}
-
-fn completion_failure_message(exit_code: i32, timed_out: bool, truncated: bool) -> Option<String> {
+pub(super) fn completion_failure_message(
+    exit_code: i32,
+    timed_out: bool,
+    truncated: bool,
+    limit_exceeded: Option<ResourceLimit>,
+    input_exceeded: bool,
+) -> Option<String> {
     let mut reasons = Vec::new();
 
     if exit_code != 0 {
@@ -507,6 +870,12 @@ fn completion_failure_message(exit_code: i32, timed_out: bool, truncated: bool)
     if truncated {
         reasons.push("truncated=true".to_string());
     }
+    if let Some(limit) = limit_exceeded {
+        reasons.push(format!("limit_exceeded={limit:?}"));
+    }
+    if input_exceeded {
+        reasons.push("input_exceeded=true".to_string());
+    }
 
     if reasons.is_empty() {
         None
@@ -517,7 +886,9 @@ fn completion_failure_message(exit_code: i32, timed_out: bool, truncated: bool)
 
 #[cfg(test)]
 mod tests {
-    use super::{completion_failure_message, parse_named_arg};
+    use alaric_lib::protocol::ResourceLimit;
+
+    use super::{FailurePolicy, completion_failure_message, parse_env_var, parse_named_arg};
 
     #[test]
     fn parses_arg_pair() {
@@ -532,16 +903,60 @@ mod tests {
         assert!(parse_named_arg("=hello").is_err());
     }
 
+    #[test]
+    fn parses_env_var() {
+        assert_eq!(
+            parse_env_var("LANG=C.UTF-8").expect("env var should parse"),
+            ("LANG".to_string(), "C.UTF-8".to_string())
+        );
+        assert!(parse_env_var("=value").is_err());
+        assert!(parse_env_var("LANG").is_err());
+    }
+
+    #[test]
+    fn failure_policy_stops_scheduling_past_threshold() {
+        let fail_fast = FailurePolicy {
+            fail_fast: true,
+            max_failures: Some(5),
+        };
+        assert!(!fail_fast.stop_scheduling(0));
+        assert!(fail_fast.stop_scheduling(1));
+
+        let threshold = FailurePolicy {
+            fail_fast: false,
+            max_failures: Some(2),
+        };
+        assert!(!threshold.stop_scheduling(2));
+        assert!(threshold.stop_scheduling(3));
+
+        let unbounded = FailurePolicy {
+            fail_fast: false,
+            max_failures: None,
+        };
+        assert!(!unbounded.stop_scheduling(100));
+    }
+
     #[test]
     fn completion_success_has_no_failure_message() {
-        assert_eq!(completion_failure_message(0, false, false), None);
+        assert_eq!(
+            completion_failure_message(0, false, false, None, false),
+            None
+        );
     }
 
     #[test]
     fn completion_failure_lists_reasons() {
         assert_eq!(
-            completion_failure_message(2, true, true),
+            completion_failure_message(2, true, true, None, false),
             Some("exit_code=2, timed_out=true, truncated=true".to_string())
         );
+        assert_eq!(
+            completion_failure_message(0, false, false, Some(ResourceLimit::Memory), false),
+            Some("limit_exceeded=Memory".to_string())
+        );
+        assert_eq!(
+            completion_failure_message(-1, false, false, None, true),
+            Some("exit_code=-1, input_exceeded=true".to_string())
+        );
     }
 }
@@ -0,0 +1,121 @@
+use alaric_lib::protocol::{AgentDiscoveryEntry, AgentPresenceStatus};
+
+// A conjunction of terms, written as `tag=prod,region=ca-west and capability=restart_nginx`.
+// Terms are separated by `,` or ` and `; every term must hold for an agent to be selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Selector {
+    terms: Vec<SelectorTerm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SelectorTerm {
+    Tag(String),
+    Capability(String),
+    Status(AgentPresenceStatus),
+}
+
+impl Selector {
+    pub(super) fn matches(&self, agent: &AgentDiscoveryEntry) -> bool {
+        self.terms.iter().all(|term| match term {
+            SelectorTerm::Tag(tag) => agent.tags.iter().any(|candidate| candidate == tag),
+            SelectorTerm::Capability(capability) => agent
+                .capabilities
+                .iter()
+                .any(|candidate| candidate == capability),
+            SelectorTerm::Status(status) => agent.status == *status,
+        })
+    }
+}
+
+pub(super) fn parse_selector(raw: &str) -> Result<Selector, String> {
+    let mut terms = Vec::new();
+    for clause in raw.split(" and ") {
+        for term in clause.split(',') {
+            terms.push(parse_term(term.trim())?);
+        }
+    }
+    Ok(Selector { terms })
+}
+
+// Keys other than `tag`, `capability` and `status` match a tag of the same `key=value` form,
+// so `region=ca-west` selects agents tagged `region=ca-west`.
+fn parse_term(term: &str) -> Result<SelectorTerm, String> {
+    let Some((key, value)) = term.split_once('=') else {
+        return Err(format!(
+            "invalid selector term '{term}'; expected KEY=VALUE"
+        ));
+    };
+    let (key, value) = (key.trim(), value.trim());
+    if key.is_empty() || value.is_empty() {
+        return Err(format!(
+            "invalid selector term '{term}'; key and value must not be empty"
+        ));
+    }
+
+    match key {
+        "tag" => Ok(SelectorTerm::Tag(value.to_string())),
+        "capability" => Ok(SelectorTerm::Capability(value.to_string())),
+        "status" => match value {
+            "online" => Ok(SelectorTerm::Status(AgentPresenceStatus::Online)),
+            "offline" => Ok(SelectorTerm::Status(AgentPresenceStatus::Offline)),
+            _ => Err(format!(
+                "invalid status '{value}' in selector; expected online or offline"
+            )),
+        },
+        _ => Ok(SelectorTerm::Tag(format!("{key}={value}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alaric_lib::protocol::{AgentDiscoveryEntry, AgentId, AgentPresenceStatus};
+
+    use super::parse_selector;
+
+    fn agent(
+        tags: &[&str],
+        capabilities: &[&str],
+        status: AgentPresenceStatus,
+    ) -> AgentDiscoveryEntry {
+        AgentDiscoveryEntry {
+            agent_id: AgentId::new("agent-default").expect("valid agent id"),
+            display_name: None,
+            capabilities: capabilities.iter().map(ToString::to_string).collect(),
+            tags: tags.iter().map(ToString::to_string).collect(),
+            status,
+            status_age_secs: 0,
+        }
+    }
+
+    #[test]
+    fn matches_tags_capabilities_and_status() {
+        let selector = parse_selector(
+            "tag=prod,region=ca-west and capability=restart_nginx and status=online",
+        )
+        .expect("selector should parse");
+
+        assert!(selector.matches(&agent(
+            &["prod", "region=ca-west"],
+            &["restart_nginx", "uptime"],
+            AgentPresenceStatus::Online,
+        )));
+        assert!(!selector.matches(&agent(
+            &["prod", "region=ca-west"],
+            &["restart_nginx"],
+            AgentPresenceStatus::Offline,
+        )));
+        assert!(!selector.matches(&agent(
+            &["prod"],
+            &["restart_nginx"],
+            AgentPresenceStatus::Online,
+        )));
+    }
+
+    #[test]
+    fn rejects_malformed_terms() {
+        assert!(parse_selector("prod").is_err());
+        assert!(parse_selector("tag=").is_err());
+        assert!(parse_selector("status=sleeping").is_err());
+        assert!(parse_selector("tag=prod,,status=online").is_err());
+    }
+}
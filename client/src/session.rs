@@ -1,13 +1,16 @@
-use std::{env, io};
+use std::{collections::HashSet, env, io};
 
 use alaric_lib::{
-    constants::DEFAULT_SERVER_PORT,
+    config::{AlaricConfig, RelayConfig, SessionConfig},
     protocol::{
-        ClientId, HandshakeProofRequest, HandshakeRequest, HandshakeResponse, ListAgentsResponse,
-        SessionId, build_auth_proof_ed25519, read_json_frame, write_json_frame,
+        ClientId, DiscoveryPageRequest, DiscoveryQuery, HandshakeErrorCode, HandshakeProofRequest,
+        HandshakeRequest, HandshakeResponse, ListAgentsResponse, SessionId,
+        build_auth_proof_ed25519, noise_static_keypair_from_hex, read_json_frame,
+        read_json_message, write_json_frame, write_json_message,
     },
+    transport::RelayStream,
 };
-use tokio::net::TcpStream;
+use tokio::time::timeout;
 
 use crate::DynError;
 
@@ -16,12 +19,17 @@ pub(super) struct ClientAuth {
     pub(super) client_id: ClientId,
     pub(super) auth_key_id: String,
     pub(super) auth_private_key: String,
+    // Without a long-term key each session uses a fresh Noise static key.
+    pub(super) noise_private_key: Option<String>,
+    pub(super) relay: RelayConfig,
+    pub(super) session: SessionConfig,
 }
 
 #[derive(Debug)]
 pub(super) struct AuthenticatedConnection {
-    pub(super) stream: TcpStream,
+    pub(super) stream: RelayStream,
     pub(super) session_id: SessionId,
+    pub(super) max_message_bytes: usize,
 }
 
 impl ClientAuth {
@@ -45,32 +53,156 @@ impl ClientAuth {
             "CLIENT_AUTH_PRIVATE_KEY must be set for handshake authentication",
         )?;
 
+        let noise_private_key = env::var("CLIENT_NOISE_PRIVATE_KEY").ok();
+        if let Some(private_key) = &noise_private_key {
+            noise_static_keypair_from_hex(private_key)?;
+        }
+
+        let config = AlaricConfig::load()?;
         Ok(Self {
             client_id,
             auth_key_id,
             auth_private_key,
+            noise_private_key,
+            relay: config.relay,
+            session: config.session,
         })
     }
 }
 
-pub(super) async fn fetch_discovery(auth: &ClientAuth) -> Result<ListAgentsResponse, DynError> {
-    let request = HandshakeRequest::client_discovery(auth.client_id.clone());
+// A relay still offering pages after this many is treated as broken.
+const MAX_DISCOVERY_PAGES: usize = 10_000;
+
+// Follows `next_cursor` on the same connection until the last page and returns every page's
+// agents together.
+pub(super) async fn fetch_discovery(
+    auth: &ClientAuth,
+    query: DiscoveryQuery,
+) -> Result<ListAgentsResponse, DynError> {
+    let request = HandshakeRequest::client_discovery(auth.client_id.clone(), query)
+        .with_max_message_bytes(auth.session.max_message_bytes);
     let mut connection = connect_authenticated(&request, auth).await?;
-    let response = read_json_frame::<_, ListAgentsResponse>(&mut connection.stream).await?;
+    let mut response = read_json_message::<_, ListAgentsResponse>(
+        &mut connection.stream,
+        connection.max_message_bytes,
+    )
+    .await?;
+
+    let mut seen_cursors = HashSet::new();
+    let mut next_cursor = response.next_cursor.take();
+    while let Some(cursor) = next_cursor {
+        if !seen_cursors.insert(cursor.clone()) {
+            return Err(
+                io::Error::other(format!("relay repeated discovery cursor '{cursor}'")).into(),
+            );
+        }
+        if seen_cursors.len() >= MAX_DISCOVERY_PAGES {
+            return Err(io::Error::other(format!(
+                "discovery did not finish within {MAX_DISCOVERY_PAGES} pages"
+            ))
+            .into());
+        }
+        write_json_message(
+            &mut connection.stream,
+            &DiscoveryPageRequest { cursor },
+            connection.max_message_bytes,
+        )
+        .await?;
+        let page = read_json_message::<_, ListAgentsResponse>(
+            &mut connection.stream,
+            connection.max_message_bytes,
+        )
+        .await?;
+        response.agents.extend(page.agents);
+        next_cursor = page.next_cursor;
+    }
     Ok(response)
 }
 
+enum AttemptError {
+    // The relay is unreachable, or the target agent is not connected to it.
+    Retry(DynError),
+    Fatal(DynError),
+}
+
+// Tries each relay endpoint in order until one accepts the handshake.
 pub(super) async fn connect_authenticated(
     request: &HandshakeRequest,
     auth: &ClientAuth,
 ) -> Result<AuthenticatedConnection, DynError> {
-    let addr = format!("127.0.0.1:{DEFAULT_SERVER_PORT}");
-    let mut stream = TcpStream::connect(addr).await?;
+    let mut last_error = None;
+    for (index, endpoint) in auth.relay.endpoints.iter().enumerate() {
+        match connect_endpoint_authenticated(index, request, auth).await {
+            Ok(connection) => return Ok(connection),
+            Err(AttemptError::Retry(err)) => {
+                last_error = Some(format!("relay {endpoint}: {err}"));
+            }
+            Err(AttemptError::Fatal(err)) => return Err(err),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotConnected,
+        last_error.unwrap_or_else(|| "no relay endpoints configured".to_string()),
+    )
+    .into())
+}
+
+async fn connect_endpoint_authenticated(
+    endpoint: usize,
+    request: &HandshakeRequest,
+    auth: &ClientAuth,
+) -> Result<AuthenticatedConnection, AttemptError> {
+    let mut stream = auth
+        .relay
+        .connect_endpoint(endpoint)
+        .await
+        .map_err(|err| AttemptError::Retry(err.into()))?;
+    let final_response = timeout(
+        auth.relay.handshake_timeout(),
+        perform_handshake(&mut stream, request, auth),
+    )
+    .await
+    .map_err(|_| AttemptError::Retry("handshake timed out".into()))?
+    .map_err(AttemptError::Retry)?;
+
+    match final_response {
+        HandshakeResponse::Accepted(accepted) => Ok(AuthenticatedConnection {
+            stream,
+            session_id: accepted.session_id,
+            max_message_bytes: accepted.max_message_bytes,
+        }),
+        HandshakeResponse::Rejected(rejected) => {
+            let err = io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "handshake rejected ({}): {:?}: {}",
+                    request_context(request),
+                    rejected.code,
+                    rejected.message
+                ),
+            )
+            .into();
+            if rejected.code == HandshakeErrorCode::AgentUnavailable {
+                Err(AttemptError::Retry(err))
+            } else {
+                Err(AttemptError::Fatal(err))
+            }
+        }
+        HandshakeResponse::Challenge(_) => Err(AttemptError::Fatal(
+            io::Error::other("unexpected second handshake challenge from server").into(),
+        )),
+    }
+}
 
-    write_json_frame(&mut stream, request).await?;
+async fn perform_handshake(
+    stream: &mut RelayStream,
+    request: &HandshakeRequest,
+    auth: &ClientAuth,
+) -> Result<HandshakeResponse, DynError> {
+    write_json_frame(stream, request).await?;
 
-    let initial = read_json_frame::<_, HandshakeResponse>(&mut stream).await?;
-    let final_response = match initial {
+    let initial = read_json_frame::<_, HandshakeResponse>(stream).await?;
+    match initial {
         HandshakeResponse::Challenge(challenge) => {
             let proof = build_auth_proof_ed25519(
                 request,
@@ -78,30 +210,10 @@ pub(super) async fn connect_authenticated(
                 &auth.auth_key_id,
                 &auth.auth_private_key,
             )?;
-            write_json_frame(&mut stream, &HandshakeProofRequest::new(proof)).await?;
-            read_json_frame::<_, HandshakeResponse>(&mut stream).await?
-        }
-        other => other,
-    };
-
-    match final_response {
-        HandshakeResponse::Accepted(accepted) => Ok(AuthenticatedConnection {
-            stream,
-            session_id: accepted.session_id,
-        }),
-        HandshakeResponse::Rejected(rejected) => Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            format!(
-                "handshake rejected ({}): {:?}: {}",
-                request_context(request),
-                rejected.code,
-                rejected.message
-            ),
-        )
-        .into()),
-        HandshakeResponse::Challenge(_) => {
-            Err(io::Error::other("unexpected second handshake challenge from server").into())
+            write_json_frame(stream, &HandshakeProofRequest::new(proof)).await?;
+            Ok(read_json_frame::<_, HandshakeResponse>(stream).await?)
         }
+        other => Ok(other),
     }
 }
 
@@ -116,5 +228,6 @@ fn request_context(request: &HandshakeRequest) -> String {
         } => format!("target={target_agent_id}"),
         HandshakeRequest::ClientDiscovery { .. } => "mode=discovery".to_string(),
         HandshakeRequest::Agent { .. } => "mode=agent".to_string(),
+        HandshakeRequest::AgentTunnel { .. } => "mode=agent_tunnel".to_string(),
     }
 }
@@ -1,6 +1,6 @@
 use std::{collections::BTreeMap, env, error::Error, fs};
 
-use hacl_star::ed25519;
+use hacl_star::{curve25519, ed25519};
 use rand::random;
 use serde::Serialize;
 
@@ -22,6 +22,7 @@ struct AuthConfigFile {
 struct AuthConfigIdentity {
     key_id: String,
     public_key: String,
+    noise_public_key: String,
 }
 
 fn generate_keypair_hex() -> (String, String) {
@@ -30,6 +31,12 @@ fn generate_keypair_hex() -> (String, String) {
     (hex::encode(secret_key), hex::encode(public_key.0))
 }
 
+fn generate_noise_keypair_hex() -> (String, String) {
+    let secret_key = random::<[u8; 32]>();
+    let public_key = curve25519::SecretKey(secret_key).get_public();
+    (hex::encode(secret_key), hex::encode(public_key.0))
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let output_path = env::args()
         .nth(1)
@@ -37,6 +44,8 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let (agent_private_key, agent_public_key) = generate_keypair_hex();
     let (client_private_key, client_public_key) = generate_keypair_hex();
+    let (agent_noise_private_key, agent_noise_public_key) = generate_noise_keypair_hex();
+    let (client_noise_private_key, client_noise_public_key) = generate_noise_keypair_hex();
 
     let config = AuthConfigFile {
         version: AUTH_CONFIG_VERSION_V2,
@@ -45,6 +54,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             AuthConfigIdentity {
                 key_id: DEFAULT_AGENT_KEY_ID.to_string(),
                 public_key: agent_public_key,
+                noise_public_key: agent_noise_public_key,
             },
         )]),
         clients: BTreeMap::from([(
@@ -52,6 +62,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             AuthConfigIdentity {
                 key_id: DEFAULT_CLIENT_KEY_ID.to_string(),
                 public_key: client_public_key,
+                noise_public_key: client_noise_public_key,
             },
         )]),
     };
@@ -66,8 +77,10 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     println!("export AGENT_AUTH_KEY_ID={DEFAULT_AGENT_KEY_ID}");
     println!("export AGENT_AUTH_PRIVATE_KEY={agent_private_key}");
+    println!("export AGENT_NOISE_PRIVATE_KEY={agent_noise_private_key}");
     println!("export CLIENT_AUTH_KEY_ID={DEFAULT_CLIENT_KEY_ID}");
     println!("export CLIENT_AUTH_PRIVATE_KEY={client_private_key}");
+    println!("export CLIENT_NOISE_PRIVATE_KEY={client_noise_private_key}");
 
     Ok(())
 }
@@ -29,6 +29,8 @@ struct AuthConfigFile {
 struct AuthConfigIdentity {
     key_id: String,
     public_key: String,
+    #[serde(default)]
+    noise_public_key: Option<String>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -70,6 +72,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 IdentityPrincipal {
                     key_id: identity.key_id,
                     public_key: identity.public_key,
+                    noise_public_key: identity.noise_public_key,
                 },
             )
         })
@@ -83,6 +86,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 IdentityPrincipal {
                     key_id: identity.key_id,
                     public_key: identity.public_key,
+                    noise_public_key: identity.noise_public_key,
                 },
             )
         })
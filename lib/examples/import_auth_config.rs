@@ -5,7 +5,8 @@ use alaric_lib::database::{
     principals::{KeyAlgorithm, PrincipalKind},
 };
 use serde::Deserialize;
-use sqlx::types::Uuid;
+use serde_json::json;
+use sqlx::types::{Json, Uuid};
 
 #[derive(Debug, Deserialize)]
 struct AuthConfigFile {
@@ -20,6 +21,8 @@ struct AuthConfigFile {
 struct AuthConfigIdentity {
     key_id: String,
     public_key: String,
+    #[serde(default)]
+    noise_public_key: Option<String>,
 }
 
 #[tokio::main]
@@ -52,6 +55,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             &external_id,
             &identity.key_id,
             &identity.public_key,
+            identity.noise_public_key.as_deref(),
         )
         .await?;
     }
@@ -62,6 +66,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             &external_id,
             &identity.key_id,
             &identity.public_key,
+            identity.noise_public_key.as_deref(),
         )
         .await?;
     }
@@ -77,20 +82,37 @@ async fn upsert_identity(
     external_id: &str,
     key_id: &str,
     public_key_hex: &str,
+    noise_public_key_hex: Option<&str>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let public_key = hex::decode(public_key_hex)?;
+    // principal_keys only holds Ed25519 keys, so the Noise static key goes in the principal metadata.
+    let metadata = match noise_public_key_hex {
+        Some(noise_public_key_hex) => {
+            if hex::decode(noise_public_key_hex)?.len() != 32 {
+                return Err(format!(
+                    "noise_public_key for '{}' must be 32 bytes of hex",
+                    external_id
+                )
+                .into());
+            }
+            json!({ "noise_public_key": noise_public_key_hex })
+        }
+        None => json!({}),
+    };
 
     let principal_id: Uuid = sqlx::query_scalar(
         r#"
         INSERT INTO principals (kind, external_id, metadata)
-        VALUES ($1, $2, '{}'::jsonb)
+        VALUES ($1, $2, $3::jsonb)
         ON CONFLICT (kind, external_id) DO UPDATE
-        SET disabled_at = NULL
+        SET disabled_at = NULL,
+            metadata = (principals.metadata - 'noise_public_key') || EXCLUDED.metadata
         RETURNING id
         "#,
     )
     .bind(kind)
     .bind(external_id)
+    .bind(Json(metadata))
     .fetch_one(database.pool())
     .await?;
 
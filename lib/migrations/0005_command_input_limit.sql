@@ -0,0 +1 @@
+ALTER TYPE command_rejection_code ADD VALUE IF NOT EXISTS 'input_limit';
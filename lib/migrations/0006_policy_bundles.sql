@@ -0,0 +1,45 @@
+CREATE TABLE policy_bundles (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    agent_principal_id UUID REFERENCES principals(id) ON DELETE CASCADE,
+    group_id UUID REFERENCES agent_groups(id) ON DELETE CASCADE,
+    bundle TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((agent_principal_id IS NULL) <> (group_id IS NULL))
+);
+
+CREATE UNIQUE INDEX idx_policy_bundles_agent_principal_id
+    ON policy_bundles(agent_principal_id)
+    WHERE agent_principal_id IS NOT NULL;
+
+CREATE UNIQUE INDEX idx_policy_bundles_group_id
+    ON policy_bundles(group_id)
+    WHERE group_id IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION notify_policy_changed()
+RETURNS trigger
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    PERFORM pg_notify('alaric_policy_changed', TG_TABLE_NAME || ':' || TG_OP);
+    IF TG_OP = 'DELETE' THEN
+        RETURN OLD;
+    END IF;
+    RETURN NEW;
+END;
+$$;
+
+DROP TRIGGER IF EXISTS trg_policy_bundles_policy_changed ON policy_bundles;
+CREATE TRIGGER trg_policy_bundles_policy_changed
+AFTER INSERT OR UPDATE OR DELETE
+ON policy_bundles
+FOR EACH ROW
+EXECUTE FUNCTION notify_policy_changed();
+
+-- Group membership decides which group bundle an agent resolves to.
+DROP TRIGGER IF EXISTS trg_agent_group_members_policy_changed ON agent_group_members;
+CREATE TRIGGER trg_agent_group_members_policy_changed
+AFTER INSERT OR UPDATE OR DELETE
+ON agent_group_members
+FOR EACH ROW
+EXECUTE FUNCTION notify_policy_changed();
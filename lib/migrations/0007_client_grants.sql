@@ -0,0 +1,42 @@
+ALTER TYPE handshake_rejection_code ADD VALUE IF NOT EXISTS 'forbidden';
+ALTER TYPE command_rejection_code ADD VALUE IF NOT EXISTS 'forbidden';
+
+CREATE TABLE client_groups (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    external_id TEXT NOT NULL UNIQUE,
+    display_name TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (char_length(external_id) BETWEEN 3 AND 64),
+    CHECK (external_id ~ '^[A-Za-z0-9._-]+$')
+);
+
+CREATE TABLE client_group_members (
+    group_id UUID NOT NULL REFERENCES client_groups(id) ON DELETE CASCADE,
+    client_principal_id UUID NOT NULL REFERENCES principals(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (group_id, client_principal_id)
+);
+
+CREATE INDEX idx_client_group_members_client_principal_id
+    ON client_group_members(client_principal_id);
+
+-- Lets a client or client group reach an agent or agent group. An empty command_ids
+-- array allows every command, fetch and put in the agent's policy.
+CREATE TABLE client_grants (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    client_principal_id UUID REFERENCES principals(id) ON DELETE CASCADE,
+    client_group_id UUID REFERENCES client_groups(id) ON DELETE CASCADE,
+    agent_principal_id UUID REFERENCES principals(id) ON DELETE CASCADE,
+    agent_group_id UUID REFERENCES agent_groups(id) ON DELETE CASCADE,
+    command_ids TEXT[] NOT NULL DEFAULT ARRAY[]::text[],
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((client_principal_id IS NULL) <> (client_group_id IS NULL)),
+    CHECK ((agent_principal_id IS NULL) <> (agent_group_id IS NULL))
+);
+
+CREATE UNIQUE INDEX idx_client_grants_subject_target
+    ON client_grants(
+        COALESCE(client_principal_id, client_group_id),
+        COALESCE(agent_principal_id, agent_group_id)
+    );
@@ -0,0 +1,533 @@
+use std::{
+    env,
+    error::Error,
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::{
+    constants::DEFAULT_SERVER_PORT,
+    protocol::{DEFAULT_MAX_MESSAGE_BYTES, MAX_FRAME_BYTES, RekeyPolicy},
+    transport::{RelayStream, RelayTlsConnector, validate_pin},
+};
+
+pub const CONFIG_PATH_ENV: &str = "ALARIC_CONFIG";
+pub const DEFAULT_CONFIG_PATH: &str = "./alaric.toml";
+
+const LISTEN_ADDR_ENV: &str = "ALARIC_LISTEN_ADDR";
+const TUNNEL_OPEN_TIMEOUT_ENV: &str = "ALARIC_TUNNEL_OPEN_TIMEOUT_SECS";
+const PRESENCE_HEARTBEAT_INTERVAL_ENV: &str = "ALARIC_PRESENCE_HEARTBEAT_INTERVAL_SECS";
+const RELAY_ENDPOINTS_ENV: &str = "ALARIC_RELAY_ENDPOINTS";
+const CONNECT_TIMEOUT_ENV: &str = "ALARIC_CONNECT_TIMEOUT_SECS";
+const HANDSHAKE_TIMEOUT_ENV: &str = "ALARIC_HANDSHAKE_TIMEOUT_SECS";
+const RECONNECT_DELAY_ENV: &str = "ALARIC_RECONNECT_DELAY_SECS";
+const TLS_CERT_PATH_ENV: &str = "ALARIC_TLS_CERT_PATH";
+const TLS_KEY_PATH_ENV: &str = "ALARIC_TLS_KEY_PATH";
+const PINNED_CERT_SHA256_ENV: &str = "ALARIC_RELAY_PINNED_CERT_SHA256";
+const REKEY_AFTER_MESSAGES_ENV: &str = "ALARIC_REKEY_AFTER_MESSAGES";
+const REKEY_AFTER_BYTES_ENV: &str = "ALARIC_REKEY_AFTER_BYTES";
+const MAX_MESSAGE_BYTES_ENV: &str = "ALARIC_MAX_MESSAGE_BYTES";
+const SERVER_MAX_MESSAGE_BYTES_ENV: &str = "ALARIC_SERVER_MAX_MESSAGE_BYTES";
+const ENFORCE_CLIENT_GRANTS_ENV: &str = "ALARIC_ENFORCE_CLIENT_GRANTS";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(
+                    f,
+                    "failed to read config file '{}': {}",
+                    path.display(),
+                    source
+                )
+            }
+            ConfigError::Parse { path, source } => {
+                write!(
+                    f,
+                    "failed to parse config file '{}': {}",
+                    path.display(),
+                    source
+                )
+            }
+            ConfigError::Invalid(message) => write!(f, "invalid config: {}", message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlaricConfig {
+    pub server: ServerConfig,
+    pub relay: RelayConfig,
+    pub session: SessionConfig,
+}
+
+// Settings for the relay process itself.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen_addr: SocketAddr,
+    pub tunnel_open_timeout_secs: u64,
+    pub presence_heartbeat_interval_secs: u64,
+    // Upper bound on relay messages sent to agents and clients, whatever they request.
+    pub max_message_bytes: usize,
+    pub tls: Option<ServerTlsConfig>,
+    // When set, clients need a grant in `client_grants` to open a session with an agent.
+    pub enforce_client_grants: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: SocketAddr::from(([0, 0, 0, 0], DEFAULT_SERVER_PORT)),
+            tunnel_open_timeout_secs: 10,
+            presence_heartbeat_interval_secs: 10,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            tls: None,
+            enforce_client_grants: false,
+        }
+    }
+}
+
+// PEM certificate chain and private key served by the relay listener.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerTlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl ServerConfig {
+    #[must_use]
+    pub const fn tunnel_open_timeout(&self) -> Duration {
+        Duration::from_secs(self.tunnel_open_timeout_secs)
+    }
+
+    #[must_use]
+    pub const fn presence_heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.presence_heartbeat_interval_secs)
+    }
+}
+
+// End-to-end session settings shared by agents and clients.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub rekey_after_messages: u64,
+    pub rekey_after_bytes: u64,
+    // Largest message this peer reassembles; advertised to the relay and the remote peer.
+    pub max_message_bytes: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        let rekey_policy = RekeyPolicy::default();
+        Self {
+            rekey_after_messages: rekey_policy.after_messages,
+            rekey_after_bytes: rekey_policy.after_bytes,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+        }
+    }
+}
+
+impl SessionConfig {
+    #[must_use]
+    pub const fn rekey_policy(&self) -> RekeyPolicy {
+        RekeyPolicy {
+            after_messages: self.rekey_after_messages,
+            after_bytes: self.rekey_after_bytes,
+        }
+    }
+}
+
+// How agents and clients reach the relay. Endpoints are tried in order; TLS is used when
+// at least one certificate pin is configured.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    pub endpoints: Vec<String>,
+    pub connect_timeout_secs: u64,
+    pub handshake_timeout_secs: u64,
+    pub reconnect_delay_secs: u64,
+    pub pinned_cert_sha256: Vec<String>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![format!("127.0.0.1:{}", DEFAULT_SERVER_PORT)],
+            connect_timeout_secs: 5,
+            handshake_timeout_secs: 10,
+            reconnect_delay_secs: 1,
+            pinned_cert_sha256: Vec::new(),
+        }
+    }
+}
+
+impl RelayConfig {
+    #[must_use]
+    pub const fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    #[must_use]
+    pub const fn handshake_timeout(&self) -> Duration {
+        Duration::from_secs(self.handshake_timeout_secs)
+    }
+
+    #[must_use]
+    pub const fn reconnect_delay(&self) -> Duration {
+        Duration::from_secs(self.reconnect_delay_secs)
+    }
+
+    // Connects to the first reachable endpoint, starting at `start`, and returns its index.
+    pub async fn connect_from(&self, start: usize) -> io::Result<(RelayStream, usize)> {
+        let mut last_error = None;
+        for offset in 0..self.endpoints.len() {
+            let index = (start + offset) % self.endpoints.len();
+            match self.connect_endpoint(index).await {
+                Ok(stream) => return Ok((stream, index)),
+                Err(err) => {
+                    last_error = Some(io::Error::new(
+                        err.kind(),
+                        format!("relay {}: {}", self.endpoints[index], err),
+                    ))
+                }
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no relay endpoints")))
+    }
+
+    pub async fn connect(&self) -> io::Result<(RelayStream, usize)> {
+        self.connect_from(0).await
+    }
+
+    pub async fn connect_endpoint(&self, index: usize) -> io::Result<RelayStream> {
+        let endpoint = self.endpoints.get(index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no relay endpoint at index {}", index),
+            )
+        })?;
+        let stream = timeout(self.connect_timeout(), TcpStream::connect(endpoint))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))??;
+        if self.pinned_cert_sha256.is_empty() {
+            return Ok(stream.into());
+        }
+
+        let connector = RelayTlsConnector::new(&self.pinned_cert_sha256)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+        timeout(
+            self.handshake_timeout(),
+            connector.connect(stream, endpoint),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))?
+    }
+}
+
+impl AlaricConfig {
+    // Reads `ALARIC_CONFIG` (or `./alaric.toml` if present), then applies env overrides.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = match env::var(CONFIG_PATH_ENV) {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()),
+        };
+        Self::load_with(path.as_deref(), |name| env::var(name).ok())
+    }
+
+    pub fn load_with(
+        path: Option<&Path>,
+        env_var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let mut config = match path {
+            Some(path) => Self::load_from_path(path)?,
+            None => Self::default(),
+        };
+        config.apply_env(env_var)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load_from_path(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref().to_path_buf();
+        let raw = fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        toml::from_str(&raw).map_err(|source| ConfigError::Parse { path, source })
+    }
+
+    fn apply_env(&mut self, env_var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(raw) = env_var(LISTEN_ADDR_ENV) {
+            self.server.listen_addr = parse_env(LISTEN_ADDR_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(TUNNEL_OPEN_TIMEOUT_ENV) {
+            self.server.tunnel_open_timeout_secs = parse_env(TUNNEL_OPEN_TIMEOUT_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(PRESENCE_HEARTBEAT_INTERVAL_ENV) {
+            self.server.presence_heartbeat_interval_secs =
+                parse_env(PRESENCE_HEARTBEAT_INTERVAL_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(RELAY_ENDPOINTS_ENV) {
+            self.relay.endpoints = parse_list(&raw);
+        }
+        if let Some(raw) = env_var(CONNECT_TIMEOUT_ENV) {
+            self.relay.connect_timeout_secs = parse_env(CONNECT_TIMEOUT_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(HANDSHAKE_TIMEOUT_ENV) {
+            self.relay.handshake_timeout_secs = parse_env(HANDSHAKE_TIMEOUT_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(RECONNECT_DELAY_ENV) {
+            self.relay.reconnect_delay_secs = parse_env(RECONNECT_DELAY_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(REKEY_AFTER_MESSAGES_ENV) {
+            self.session.rekey_after_messages = parse_env(REKEY_AFTER_MESSAGES_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(REKEY_AFTER_BYTES_ENV) {
+            self.session.rekey_after_bytes = parse_env(REKEY_AFTER_BYTES_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(MAX_MESSAGE_BYTES_ENV) {
+            self.session.max_message_bytes = parse_env(MAX_MESSAGE_BYTES_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(SERVER_MAX_MESSAGE_BYTES_ENV) {
+            self.server.max_message_bytes = parse_env(SERVER_MAX_MESSAGE_BYTES_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(ENFORCE_CLIENT_GRANTS_ENV) {
+            self.server.enforce_client_grants = parse_env(ENFORCE_CLIENT_GRANTS_ENV, &raw)?;
+        }
+        if let Some(raw) = env_var(PINNED_CERT_SHA256_ENV) {
+            self.relay.pinned_cert_sha256 = parse_list(&raw);
+        }
+        match (env_var(TLS_CERT_PATH_ENV), env_var(TLS_KEY_PATH_ENV)) {
+            (None, None) => {}
+            (cert_path, key_path) => {
+                let current = self.server.tls.take();
+                let cert_path = cert_path
+                    .map(PathBuf::from)
+                    .or_else(|| current.as_ref().map(|tls| tls.cert_path.clone()));
+                let key_path = key_path
+                    .map(PathBuf::from)
+                    .or_else(|| current.as_ref().map(|tls| tls.key_path.clone()));
+                let (Some(cert_path), Some(key_path)) = (cert_path, key_path) else {
+                    return Err(ConfigError::Invalid(format!(
+                        "{} and {} must be set together",
+                        TLS_CERT_PATH_ENV, TLS_KEY_PATH_ENV
+                    )));
+                };
+                self.server.tls = Some(ServerTlsConfig {
+                    cert_path,
+                    key_path,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.relay.endpoints.is_empty() {
+            return Err(ConfigError::Invalid(
+                "relay.endpoints must list at least one relay".to_string(),
+            ));
+        }
+        for endpoint in &self.relay.endpoints {
+            let port = endpoint
+                .rsplit_once(':')
+                .and_then(|(host, port)| (!host.is_empty()).then_some(port));
+            if port.and_then(|port| port.parse::<u16>().ok()).is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "relay endpoint '{}' must be host:port",
+                    endpoint
+                )));
+            }
+        }
+
+        for pin in &self.relay.pinned_cert_sha256 {
+            validate_pin(pin).map_err(|err| ConfigError::Invalid(err.to_string()))?;
+        }
+
+        for (name, value) in [
+            (
+                "server.tunnel_open_timeout_secs",
+                self.server.tunnel_open_timeout_secs,
+            ),
+            (
+                "server.presence_heartbeat_interval_secs",
+                self.server.presence_heartbeat_interval_secs,
+            ),
+            (
+                "relay.connect_timeout_secs",
+                self.relay.connect_timeout_secs,
+            ),
+            (
+                "relay.handshake_timeout_secs",
+                self.relay.handshake_timeout_secs,
+            ),
+            (
+                "session.rekey_after_messages",
+                self.session.rekey_after_messages,
+            ),
+            ("session.rekey_after_bytes", self.session.rekey_after_bytes),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "{} must be greater than 0",
+                    name
+                )));
+            }
+        }
+
+        for (name, value) in [
+            ("server.max_message_bytes", self.server.max_message_bytes),
+            ("session.max_message_bytes", self.session.max_message_bytes),
+        ] {
+            if value < MAX_FRAME_BYTES {
+                return Err(ConfigError::Invalid(format!(
+                    "{} must be at least {}",
+                    name, MAX_FRAME_BYTES
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_env<T>(name: &str, raw: &str) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    raw.trim()
+        .parse()
+        .map_err(|err| ConfigError::Invalid(format!("invalid {} '{}': {}", name, raw, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::SocketAddr};
+
+    use tokio::net::TcpListener;
+
+    use super::{AlaricConfig, ConfigError, RelayConfig};
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn parses_file_and_fills_defaults() {
+        let config: AlaricConfig = toml::from_str(
+            r#"
+            [server]
+            listen_addr = "127.0.0.1:9000"
+
+            [relay]
+            endpoints = ["relay-a.example:7443", "relay-b.example:7443"]
+            connect_timeout_secs = 2
+            "#,
+        )
+        .expect("config should parse");
+
+        assert_eq!(
+            config.server.listen_addr,
+            "127.0.0.1:9000".parse::<SocketAddr>().expect("addr")
+        );
+        assert_eq!(config.server.tunnel_open_timeout_secs, 10);
+        assert_eq!(config.relay.endpoints.len(), 2);
+        assert_eq!(config.relay.connect_timeout_secs, 2);
+        assert_eq!(config.relay.reconnect_delay_secs, 1);
+        config.validate().expect("config should validate");
+    }
+
+    #[test]
+    fn env_overrides_take_precedence() {
+        let config = AlaricConfig::load_with(
+            None,
+            env(&[
+                ("ALARIC_RELAY_ENDPOINTS", "relay-a:7443, relay-b:7443,"),
+                ("ALARIC_LISTEN_ADDR", "[::]:7000"),
+                ("ALARIC_HANDSHAKE_TIMEOUT_SECS", "3"),
+            ]),
+        )
+        .expect("config should load");
+
+        assert_eq!(config.relay.endpoints, vec!["relay-a:7443", "relay-b:7443"]);
+        assert_eq!(config.server.listen_addr.port(), 7000);
+        assert_eq!(config.relay.handshake_timeout_secs, 3);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for vars in [
+            [("ALARIC_RELAY_ENDPOINTS", ",")],
+            [("ALARIC_RELAY_ENDPOINTS", "relay-without-port")],
+            [("ALARIC_CONNECT_TIMEOUT_SECS", "0")],
+            [("ALARIC_RECONNECT_DELAY_SECS", "soon")],
+            [("ALARIC_REKEY_AFTER_MESSAGES", "0")],
+            [("ALARIC_MAX_MESSAGE_BYTES", "1024")],
+            [("ALARIC_RELAY_PINNED_CERT_SHA256", "not-a-digest")],
+            [("ALARIC_TLS_CERT_PATH", "/etc/alaric/relay.pem")],
+        ] {
+            let result = AlaricConfig::load_with(None, env(&vars));
+            assert!(
+                matches!(result, Err(ConfigError::Invalid(_))),
+                "{:?} should be rejected",
+                vars
+            );
+        }
+        assert!(toml::from_str::<AlaricConfig>("[relay]\nendpoint = []\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn connect_fails_over_to_next_endpoint() {
+        let unused = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let unused_addr = unused.local_addr().expect("addr");
+        drop(unused);
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+
+        let relay = RelayConfig {
+            endpoints: vec![
+                unused_addr.to_string(),
+                listener.local_addr().expect("addr").to_string(),
+            ],
+            ..RelayConfig::default()
+        };
+        let (_, index) = relay.connect().await.expect("second endpoint is reachable");
+        assert_eq!(index, 1);
+    }
+}
@@ -1,2 +1,3 @@
 pub const DEFAULT_SERVER_PORT: u16 = 7443;
 pub const AUTH_CONFIG_NOTIFY_CHANNEL: &str = "alaric_auth_config_changed";
+pub const POLICY_NOTIFY_CHANNEL: &str = "alaric_policy_changed";
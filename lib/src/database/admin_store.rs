@@ -17,7 +17,9 @@ use crate::{
         Database,
         principals::{KeyAlgorithm, PrincipalKind},
     },
-    protocol::{AgentGroupId, AgentId, ClientId, PeerAttestationMode, decode_ed25519_public_key},
+    protocol::{
+        AgentGroupId, AgentId, ClientId, CommandId, PeerAttestationMode, decode_ed25519_public_key,
+    },
 };
 
 #[derive(Debug)]
@@ -28,6 +30,7 @@ pub enum AdminStoreError {
     UnknownAgentGroupMembers(Vec<String>),
     InvalidKeyId,
     InvalidPublicKey(String),
+    InvalidCommandId(String),
 }
 
 impl fmt::Display for AdminStoreError {
@@ -49,6 +52,9 @@ impl fmt::Display for AdminStoreError {
             AdminStoreError::InvalidPublicKey(message) => {
                 write!(f, "invalid Ed25519 public key: {}", message)
             }
+            AdminStoreError::InvalidCommandId(message) => {
+                write!(f, "invalid command id: {}", message)
+            }
         }
     }
 }
@@ -154,6 +160,65 @@ pub enum GroupDeleteOutcome {
     NotFound,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyTarget<'a> {
+    Agent(&'a str),
+    Group(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyPublishOutcome {
+    Published,
+    Replaced,
+    TargetNotFound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyUnpublishOutcome {
+    Removed,
+    NotPublished,
+    TargetNotFound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientGroupAddOutcome {
+    Added,
+    AlreadyMember,
+    GroupNotFound,
+    ClientNotFound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientGroupRemoveOutcome {
+    Removed,
+    NotMember,
+    GroupNotFound,
+    ClientNotFound,
+}
+
+// Who a grant applies to; the agent side reuses `PolicyTarget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantSubject<'a> {
+    Client(&'a str),
+    ClientGroup(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantSetOutcome {
+    Granted,
+    Replaced,
+    SubjectNotFound,
+    TargetNotFound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantRevokeOutcome {
+    Revoked,
+    NotGranted,
+    SubjectNotFound,
+    TargetNotFound,
+}
+
 #[derive(Debug, Clone, FromRow)]
 pub struct PrincipalListEntry {
     pub kind: PrincipalKind,
@@ -174,6 +239,25 @@ pub struct AgentGroupListEntry {
     pub member_agent_ids: Vec<String>,
 }
 
+#[derive(Debug, Clone, FromRow)]
+pub struct ClientGroupListEntry {
+    pub external_id: String,
+    pub display_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub member_client_ids: Vec<String>,
+}
+
+// Exactly one of each pair is set. An empty `command_ids` grants every command.
+#[derive(Debug, Clone, FromRow)]
+pub struct ClientGrantListEntry {
+    pub client_id: Option<String>,
+    pub client_group_id: Option<String>,
+    pub agent_id: Option<String>,
+    pub agent_group_id: Option<String>,
+    pub command_ids: Vec<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
 #[derive(Debug, FromRow)]
 struct PrincipalStateRow {
     id: Uuid,
@@ -979,6 +1063,488 @@ impl Database {
 
         Ok(groups)
     }
+
+    pub async fn admin_publish_policy_bundle(
+        &self,
+        target: PolicyTarget<'_>,
+        bundle: &str,
+    ) -> Result<PolicyPublishOutcome, AdminStoreError> {
+        let mut tx = self.pool().begin().await?;
+        let Some(target_ids) = find_policy_target(&mut tx, target).await? else {
+            tx.commit().await?;
+            return Ok(PolicyPublishOutcome::TargetNotFound);
+        };
+
+        let replaced = sqlx::query(
+            r#"
+            UPDATE policy_bundles
+            SET bundle = $3,
+                updated_at = NOW()
+            WHERE agent_principal_id IS NOT DISTINCT FROM $1
+              AND group_id IS NOT DISTINCT FROM $2
+            "#,
+        )
+        .bind(target_ids.agent_principal_id)
+        .bind(target_ids.group_id)
+        .bind(bundle)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            != 0;
+
+        if !replaced {
+            sqlx::query(
+                r#"
+                INSERT INTO policy_bundles (agent_principal_id, group_id, bundle)
+                VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(target_ids.agent_principal_id)
+            .bind(target_ids.group_id)
+            .bind(bundle)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        if replaced {
+            Ok(PolicyPublishOutcome::Replaced)
+        } else {
+            Ok(PolicyPublishOutcome::Published)
+        }
+    }
+
+    pub async fn admin_unpublish_policy_bundle(
+        &self,
+        target: PolicyTarget<'_>,
+    ) -> Result<PolicyUnpublishOutcome, AdminStoreError> {
+        let mut tx = self.pool().begin().await?;
+        let Some(target_ids) = find_policy_target(&mut tx, target).await? else {
+            tx.commit().await?;
+            return Ok(PolicyUnpublishOutcome::TargetNotFound);
+        };
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM policy_bundles
+            WHERE agent_principal_id IS NOT DISTINCT FROM $1
+              AND group_id IS NOT DISTINCT FROM $2
+            "#,
+        )
+        .bind(target_ids.agent_principal_id)
+        .bind(target_ids.group_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        if result.rows_affected() == 0 {
+            Ok(PolicyUnpublishOutcome::NotPublished)
+        } else {
+            Ok(PolicyUnpublishOutcome::Removed)
+        }
+    }
+}
+
+impl Database {
+    pub async fn admin_create_client_group(
+        &self,
+        group_id: &str,
+        display_name: Option<&str>,
+    ) -> Result<GroupCreateOutcome, AdminStoreError> {
+        validate_group_id(group_id)?;
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO client_groups (external_id, display_name)
+            VALUES ($1, $2)
+            ON CONFLICT (external_id) DO NOTHING
+            "#,
+        )
+        .bind(group_id)
+        .bind(display_name)
+        .execute(self.pool())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            Ok(GroupCreateOutcome::AlreadyExists)
+        } else {
+            Ok(GroupCreateOutcome::Created)
+        }
+    }
+
+    pub async fn admin_delete_client_group(
+        &self,
+        group_id: &str,
+    ) -> Result<GroupDeleteOutcome, AdminStoreError> {
+        validate_group_id(group_id)?;
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM client_groups
+            WHERE external_id = $1
+            "#,
+        )
+        .bind(group_id)
+        .execute(self.pool())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            Ok(GroupDeleteOutcome::NotFound)
+        } else {
+            Ok(GroupDeleteOutcome::Deleted)
+        }
+    }
+
+    pub async fn admin_add_client_to_group(
+        &self,
+        group_id: &str,
+        client_id: &str,
+    ) -> Result<ClientGroupAddOutcome, AdminStoreError> {
+        validate_group_id(group_id)?;
+        validate_principal_id(PrincipalKind::Client, client_id)?;
+
+        let mut tx = self.pool().begin().await?;
+        let Some(group_id) = find_client_group_id(&mut *tx, group_id).await? else {
+            tx.commit().await?;
+            return Ok(ClientGroupAddOutcome::GroupNotFound);
+        };
+        let Some(client) = find_principal_state(&mut *tx, PrincipalKind::Client, client_id).await?
+        else {
+            tx.commit().await?;
+            return Ok(ClientGroupAddOutcome::ClientNotFound);
+        };
+        if client.disabled_at.is_some() {
+            tx.commit().await?;
+            return Ok(ClientGroupAddOutcome::ClientNotFound);
+        }
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO client_group_members (group_id, client_principal_id)
+            VALUES ($1, $2)
+            ON CONFLICT (group_id, client_principal_id) DO NOTHING
+            "#,
+        )
+        .bind(group_id)
+        .bind(client.id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        if result.rows_affected() == 0 {
+            Ok(ClientGroupAddOutcome::AlreadyMember)
+        } else {
+            Ok(ClientGroupAddOutcome::Added)
+        }
+    }
+
+    pub async fn admin_remove_client_from_group(
+        &self,
+        group_id: &str,
+        client_id: &str,
+    ) -> Result<ClientGroupRemoveOutcome, AdminStoreError> {
+        validate_group_id(group_id)?;
+        validate_principal_id(PrincipalKind::Client, client_id)?;
+
+        let mut tx = self.pool().begin().await?;
+        let Some(group_id) = find_client_group_id(&mut *tx, group_id).await? else {
+            tx.commit().await?;
+            return Ok(ClientGroupRemoveOutcome::GroupNotFound);
+        };
+        let Some(client_principal_id) =
+            find_principal_id(&mut *tx, PrincipalKind::Client, client_id).await?
+        else {
+            tx.commit().await?;
+            return Ok(ClientGroupRemoveOutcome::ClientNotFound);
+        };
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM client_group_members
+            WHERE group_id = $1
+              AND client_principal_id = $2
+            "#,
+        )
+        .bind(group_id)
+        .bind(client_principal_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        if result.rows_affected() == 0 {
+            Ok(ClientGroupRemoveOutcome::NotMember)
+        } else {
+            Ok(ClientGroupRemoveOutcome::Removed)
+        }
+    }
+
+    pub async fn admin_list_client_groups(
+        &self,
+    ) -> Result<Vec<ClientGroupListEntry>, AdminStoreError> {
+        let groups = sqlx::query_as::<_, ClientGroupListEntry>(
+            r#"
+            SELECT
+                g.external_id,
+                g.display_name,
+                g.created_at,
+                COALESCE(
+                    ARRAY_AGG(p.external_id ORDER BY p.external_id)
+                        FILTER (WHERE p.external_id IS NOT NULL),
+                    ARRAY[]::text[]
+                ) AS member_client_ids
+            FROM client_groups AS g
+            LEFT JOIN client_group_members AS gm
+                ON gm.group_id = g.id
+            LEFT JOIN principals AS p
+                ON p.id = gm.client_principal_id
+               AND p.kind = 'client'
+               AND p.disabled_at IS NULL
+            GROUP BY g.id, g.external_id, g.display_name, g.created_at
+            ORDER BY g.external_id
+            "#,
+        )
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(groups)
+    }
+
+    // Replaces the command list of an existing grant for the same subject and target.
+    pub async fn admin_set_client_grant(
+        &self,
+        subject: GrantSubject<'_>,
+        target: PolicyTarget<'_>,
+        command_ids: &[String],
+    ) -> Result<GrantSetOutcome, AdminStoreError> {
+        let command_ids = validate_command_ids(command_ids)?;
+
+        let mut tx = self.pool().begin().await?;
+        let Some(subject_ids) = find_grant_subject(&mut tx, subject).await? else {
+            tx.commit().await?;
+            return Ok(GrantSetOutcome::SubjectNotFound);
+        };
+        let Some(target_ids) = find_policy_target(&mut tx, target).await? else {
+            tx.commit().await?;
+            return Ok(GrantSetOutcome::TargetNotFound);
+        };
+
+        let replaced = sqlx::query(
+            r#"
+            UPDATE client_grants
+            SET command_ids = $5,
+                updated_at = NOW()
+            WHERE client_principal_id IS NOT DISTINCT FROM $1
+              AND client_group_id IS NOT DISTINCT FROM $2
+              AND agent_principal_id IS NOT DISTINCT FROM $3
+              AND agent_group_id IS NOT DISTINCT FROM $4
+            "#,
+        )
+        .bind(subject_ids.client_principal_id)
+        .bind(subject_ids.client_group_id)
+        .bind(target_ids.agent_principal_id)
+        .bind(target_ids.group_id)
+        .bind(&command_ids)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            != 0;
+
+        if !replaced {
+            sqlx::query(
+                r#"
+                INSERT INTO client_grants (
+                    client_principal_id,
+                    client_group_id,
+                    agent_principal_id,
+                    agent_group_id,
+                    command_ids
+                )
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+            )
+            .bind(subject_ids.client_principal_id)
+            .bind(subject_ids.client_group_id)
+            .bind(target_ids.agent_principal_id)
+            .bind(target_ids.group_id)
+            .bind(&command_ids)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        if replaced {
+            Ok(GrantSetOutcome::Replaced)
+        } else {
+            Ok(GrantSetOutcome::Granted)
+        }
+    }
+
+    pub async fn admin_revoke_client_grant(
+        &self,
+        subject: GrantSubject<'_>,
+        target: PolicyTarget<'_>,
+    ) -> Result<GrantRevokeOutcome, AdminStoreError> {
+        let mut tx = self.pool().begin().await?;
+        let Some(subject_ids) = find_grant_subject(&mut tx, subject).await? else {
+            tx.commit().await?;
+            return Ok(GrantRevokeOutcome::SubjectNotFound);
+        };
+        let Some(target_ids) = find_policy_target(&mut tx, target).await? else {
+            tx.commit().await?;
+            return Ok(GrantRevokeOutcome::TargetNotFound);
+        };
+
+        let result = sqlx::query(
+            r#"
+            DELETE FROM client_grants
+            WHERE client_principal_id IS NOT DISTINCT FROM $1
+              AND client_group_id IS NOT DISTINCT FROM $2
+              AND agent_principal_id IS NOT DISTINCT FROM $3
+              AND agent_group_id IS NOT DISTINCT FROM $4
+            "#,
+        )
+        .bind(subject_ids.client_principal_id)
+        .bind(subject_ids.client_group_id)
+        .bind(target_ids.agent_principal_id)
+        .bind(target_ids.group_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        if result.rows_affected() == 0 {
+            Ok(GrantRevokeOutcome::NotGranted)
+        } else {
+            Ok(GrantRevokeOutcome::Revoked)
+        }
+    }
+
+    pub async fn admin_list_client_grants(
+        &self,
+    ) -> Result<Vec<ClientGrantListEntry>, AdminStoreError> {
+        let grants = sqlx::query_as::<_, ClientGrantListEntry>(
+            r#"
+            SELECT
+                client.external_id AS client_id,
+                client_group.external_id AS client_group_id,
+                agent.external_id AS agent_id,
+                agent_group.external_id AS agent_group_id,
+                cg.command_ids,
+                cg.updated_at
+            FROM client_grants AS cg
+            LEFT JOIN principals AS client
+                ON client.id = cg.client_principal_id
+            LEFT JOIN client_groups AS client_group
+                ON client_group.id = cg.client_group_id
+            LEFT JOIN principals AS agent
+                ON agent.id = cg.agent_principal_id
+            LEFT JOIN agent_groups AS agent_group
+                ON agent_group.id = cg.agent_group_id
+            ORDER BY
+                COALESCE(client.external_id, client_group.external_id),
+                COALESCE(agent.external_id, agent_group.external_id)
+            "#,
+        )
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(grants)
+    }
+}
+
+// Exactly one of the ids is set, matching the client_grants check constraint.
+struct GrantSubjectIds {
+    client_principal_id: Option<Uuid>,
+    client_group_id: Option<Uuid>,
+}
+
+async fn find_grant_subject(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subject: GrantSubject<'_>,
+) -> Result<Option<GrantSubjectIds>, AdminStoreError> {
+    match subject {
+        GrantSubject::Client(client_id) => {
+            validate_principal_id(PrincipalKind::Client, client_id)?;
+            Ok(
+                find_principal_id(&mut **tx, PrincipalKind::Client, client_id)
+                    .await?
+                    .map(|id| GrantSubjectIds {
+                        client_principal_id: Some(id),
+                        client_group_id: None,
+                    }),
+            )
+        }
+        GrantSubject::ClientGroup(group_id) => {
+            validate_group_id(group_id)?;
+            Ok(find_client_group_id(&mut **tx, group_id)
+                .await?
+                .map(|id| GrantSubjectIds {
+                    client_principal_id: None,
+                    client_group_id: Some(id),
+                }))
+        }
+    }
+}
+
+fn validate_command_ids(command_ids: &[String]) -> Result<Vec<String>, AdminStoreError> {
+    let mut validated = BTreeSet::new();
+    for command_id in command_ids {
+        CommandId::new(command_id.as_str())
+            .map_err(|err| AdminStoreError::InvalidCommandId(err.to_string()))?;
+        validated.insert(command_id.clone());
+    }
+    Ok(validated.into_iter().collect())
+}
+
+async fn find_client_group_id<'a, E>(
+    executor: E,
+    external_id: &str,
+) -> Result<Option<Uuid>, sqlx::Error>
+where
+    E: Executor<'a, Database = sqlx::Postgres>,
+{
+    sqlx::query_scalar::<_, Uuid>(
+        r#"
+        SELECT id
+        FROM client_groups
+        WHERE external_id = $1
+        LIMIT 1
+        "#,
+    )
+    .bind(external_id)
+    .fetch_optional(executor)
+    .await
+}
+
+// Exactly one of the ids is set, matching the policy_bundles check constraint.
+struct PolicyTargetIds {
+    agent_principal_id: Option<Uuid>,
+    group_id: Option<Uuid>,
+}
+
+async fn find_policy_target(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    target: PolicyTarget<'_>,
+) -> Result<Option<PolicyTargetIds>, AdminStoreError> {
+    match target {
+        PolicyTarget::Agent(agent_id) => {
+            validate_principal_id(PrincipalKind::Agent, agent_id)?;
+            Ok(find_principal_id(&mut **tx, PrincipalKind::Agent, agent_id)
+                .await?
+                .map(|id| PolicyTargetIds {
+                    agent_principal_id: Some(id),
+                    group_id: None,
+                }))
+        }
+        PolicyTarget::Group(group_id) => {
+            validate_group_id(group_id)?;
+            Ok(find_agent_group_state(&mut **tx, group_id)
+                .await?
+                .map(|group| PolicyTargetIds {
+                    agent_principal_id: None,
+                    group_id: Some(group.id),
+                }))
+        }
+    }
 }
 
 fn validate_principal_id(kind: PrincipalKind, external_id: &str) -> Result<(), AdminStoreError> {
@@ -2,7 +2,10 @@ use std::{error::Error, fmt};
 
 use sqlx::postgres::PgListener;
 
-use crate::{constants::AUTH_CONFIG_NOTIFY_CHANNEL, database::Database};
+use crate::{
+    constants::{AUTH_CONFIG_NOTIFY_CHANNEL, POLICY_NOTIFY_CHANNEL},
+    database::Database,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AuthConfigNotification {
@@ -38,7 +41,10 @@ pub struct AuthConfigListener {
 impl AuthConfigListener {
     pub async fn connect(database: &Database) -> Result<Self, AuthConfigListenerError> {
         let mut listener = PgListener::connect_with(database.pool()).await?;
-        listener.listen(AUTH_CONFIG_NOTIFY_CHANNEL).await?;
+        // Policy assignments ride along so the relay needs a single listener connection.
+        listener
+            .listen_all([AUTH_CONFIG_NOTIFY_CHANNEL, POLICY_NOTIFY_CHANNEL])
+            .await?;
         Ok(Self { listener })
     }
 
@@ -26,6 +26,8 @@ pub enum CommandRejectionCode {
     ExecutionError,
     Timeout,
     OutputLimit,
+    InputLimit,
+    Forbidden,
 }
 
 impl From<RejectionCode> for CommandRejectionCode {
@@ -37,6 +39,8 @@ impl From<RejectionCode> for CommandRejectionCode {
             RejectionCode::ExecutionError => Self::ExecutionError,
             RejectionCode::Timeout => Self::Timeout,
             RejectionCode::OutputLimit => Self::OutputLimit,
+            RejectionCode::InputLimit => Self::InputLimit,
+            RejectionCode::Forbidden => Self::Forbidden,
         }
     }
 }
@@ -7,13 +7,15 @@ mod server_store;
 pub mod sessions;
 
 pub use admin_store::{
-    AdminStoreError, AgentGroupListEntry, AttestationSetOutcome, GroupAddOutcome,
-    GroupCreateOutcome, GroupDeleteOutcome, GroupMoveOutcome, GroupRemoveOutcome,
-    GroupSetNameOutcome, GroupUpsertOutcome, KeyAddOutcome, KeyRevokeOutcome, KeyRotateOutcome,
+    AdminStoreError, AgentGroupListEntry, AttestationSetOutcome, ClientGrantListEntry,
+    ClientGroupAddOutcome, ClientGroupListEntry, ClientGroupRemoveOutcome, GrantRevokeOutcome,
+    GrantSetOutcome, GrantSubject, GroupAddOutcome, GroupCreateOutcome, GroupDeleteOutcome,
+    GroupMoveOutcome, GroupRemoveOutcome, GroupSetNameOutcome, GroupUpsertOutcome, KeyAddOutcome,
+    KeyRevokeOutcome, KeyRotateOutcome, PolicyPublishOutcome, PolicyTarget, PolicyUnpublishOutcome,
     PrincipalAddOutcome, PrincipalDisableOutcome, PrincipalListEntry,
 };
 pub use auth_listener::{AuthConfigListener, AuthConfigListenerError, AuthConfigNotification};
-pub use server_store::{ActivePrincipalKey, PruneLogsResult, ServerStoreError};
+pub use server_store::{ActivePrincipalKey, ClientGrant, PruneLogsResult, ServerStoreError};
 
 use std::{env, error::Error, fmt, io, time::Duration};
 
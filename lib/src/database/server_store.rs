@@ -19,7 +19,7 @@ use crate::{
     },
     protocol::{
         AgentDiscoveryEntry, AgentGroupDiscoveryEntry, AgentGroupId, AgentId, AgentPresenceStatus,
-        ClientId, HandshakeErrorCode, HandshakeRequest, SessionId,
+        ClientId, DiscoveryQuery, HandshakeErrorCode, HandshakeRequest, SessionId,
     },
 };
 
@@ -38,6 +38,13 @@ pub struct PruneLogsResult {
     pub session_logs_deleted: i64,
 }
 
+// What a client may do on one agent, combined from every grant that matches the pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientGrant {
+    AllCommands,
+    Commands(BTreeSet<String>),
+}
+
 #[derive(Debug)]
 pub enum ServerStoreError {
     Sqlx(sqlx::Error),
@@ -197,7 +204,10 @@ impl Database {
             Some(HandshakeRequest::ClientDiscovery { client_id, .. }) => {
                 (Some(client_id.as_str().to_string()), None, None)
             }
-            Some(HandshakeRequest::Agent { agent_id, .. }) => (
+            Some(
+                HandshakeRequest::Agent { agent_id, .. }
+                | HandshakeRequest::AgentTunnel { agent_id, .. },
+            ) => (
                 None,
                 Some(agent_id.as_str().to_string()),
                 Some(agent_id.as_str().to_string()),
@@ -363,35 +373,52 @@ impl Database {
         session_id: SessionId,
         client_id: &ClientId,
         target_agent_id: &AgentId,
-        peer: SocketAddr,
+        client_peer: SocketAddr,
+        agent_peer: SocketAddr,
     ) -> Result<(), ServerStoreError> {
         let client_principal_id =
             resolve_principal_id(self, PrincipalKind::Client, Some(client_id.as_str())).await?;
         let target_agent_principal_id =
             resolve_principal_id(self, PrincipalKind::Agent, Some(target_agent_id.as_str()))
                 .await?;
-        let (client_peer_ip, client_peer_port) = split_peer_addr(peer);
+        let (client_peer_ip, client_peer_port) = split_peer_addr(client_peer);
+        let (agent_peer_ip, agent_peer_port) = split_peer_addr(agent_peer);
 
-        let _ = sqlx::query_as!(
-            SessionIdRow,
+        sqlx::query(
             r#"
-            UPDATE session_log
-            SET client_principal_id = $2,
-                target_agent_principal_id = $3,
-                paired_agent_principal_id = $3,
-                client_peer_ip = $4::inet,
-                client_peer_port = $5,
+            INSERT INTO session_log (
+                session_id,
+                client_principal_id,
+                target_agent_principal_id,
+                paired_agent_principal_id,
+                outcome,
+                client_peer_ip,
+                client_peer_port,
+                agent_peer_ip,
+                agent_peer_port,
+                opened_at,
+                paired_at
+            )
+            VALUES ($1, $2, $3, $3, 'accepted', $4::inet, $5, $6::inet, $7, NOW(), NOW())
+            ON CONFLICT (session_id) DO UPDATE
+            SET client_principal_id = EXCLUDED.client_principal_id,
+                target_agent_principal_id = EXCLUDED.target_agent_principal_id,
+                paired_agent_principal_id = EXCLUDED.paired_agent_principal_id,
+                client_peer_ip = EXCLUDED.client_peer_ip,
+                client_peer_port = EXCLUDED.client_peer_port,
+                agent_peer_ip = EXCLUDED.agent_peer_ip,
+                agent_peer_port = EXCLUDED.agent_peer_port,
                 paired_at = NOW()
-            WHERE session_id = $1
-            RETURNING session_id
             "#,
-            session_id.as_uuid(),
-            client_principal_id,
-            target_agent_principal_id,
-            client_peer_ip,
-            client_peer_port,
         )
-        .fetch_one(self.pool())
+        .bind(session_id.as_uuid())
+        .bind(client_principal_id)
+        .bind(target_agent_principal_id)
+        .bind(client_peer_ip)
+        .bind(client_peer_port)
+        .bind(agent_peer_ip)
+        .bind(agent_peer_port)
+        .execute(self.pool())
         .await?;
         Ok(())
     }
@@ -489,8 +516,122 @@ impl Database {
         Ok(())
     }
 
+    pub async fn record_agent_metadata(
+        &self,
+        session_id: SessionId,
+        agent_id: &AgentId,
+        presence_metadata: &Value,
+    ) -> Result<(), ServerStoreError> {
+        let Some(agent_principal_id) =
+            resolve_principal_id(self, PrincipalKind::Agent, Some(agent_id.as_str())).await?
+        else {
+            return Ok(());
+        };
+
+        sqlx::query(
+            r#"
+            UPDATE agent_presence
+            SET metadata = $3::jsonb,
+                last_seen_at = NOW()
+            WHERE principal_id = $1
+              AND connected_session_id = $2
+            "#,
+        )
+        .bind(agent_principal_id)
+        .bind(session_id.as_uuid())
+        .bind(Json(sanitize_presence_metadata(presence_metadata)))
+        .execute(self.pool())
+        .await?;
+        Ok(())
+    }
+
+    // An agent-specific bundle wins over group bundles; among groups the lowest group id wins.
+    pub async fn resolve_agent_policy_bundle(
+        &self,
+        agent_id: &AgentId,
+    ) -> Result<Option<String>, ServerStoreError> {
+        let Some(agent_principal_id) =
+            resolve_principal_id(self, PrincipalKind::Agent, Some(agent_id.as_str())).await?
+        else {
+            return Ok(None);
+        };
+
+        let bundle = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT pb.bundle
+            FROM policy_bundles AS pb
+            LEFT JOIN agent_groups AS g
+                ON g.id = pb.group_id
+            WHERE pb.agent_principal_id = $1
+               OR pb.group_id IN (
+                   SELECT gm.group_id
+                   FROM agent_group_members AS gm
+                   WHERE gm.agent_principal_id = $1
+               )
+            ORDER BY pb.agent_principal_id IS NULL, g.external_id
+            LIMIT 1
+            "#,
+        )
+        .bind(agent_principal_id)
+        .fetch_optional(self.pool())
+        .await?;
+        Ok(bundle)
+    }
+
+    // Grants match the client directly or through a client group, and the agent directly or
+    // through an agent group. `None` means no grant covers the pair.
+    pub async fn resolve_client_grant(
+        &self,
+        client_id: &ClientId,
+        agent_id: &AgentId,
+    ) -> Result<Option<ClientGrant>, ServerStoreError> {
+        let Some(client_principal_id) =
+            resolve_principal_id(self, PrincipalKind::Client, Some(client_id.as_str())).await?
+        else {
+            return Ok(None);
+        };
+        let Some(agent_principal_id) =
+            resolve_principal_id(self, PrincipalKind::Agent, Some(agent_id.as_str())).await?
+        else {
+            return Ok(None);
+        };
+
+        let grants = sqlx::query_scalar::<_, Vec<String>>(
+            r#"
+            SELECT cg.command_ids
+            FROM client_grants AS cg
+            WHERE (
+                    cg.client_principal_id = $1
+                    OR cg.client_group_id IN (
+                        SELECT cgm.group_id
+                        FROM client_group_members AS cgm
+                        WHERE cgm.client_principal_id = $1
+                    )
+                )
+              AND (
+                    cg.agent_principal_id = $2
+                    OR cg.agent_group_id IN (
+                        SELECT agm.group_id
+                        FROM agent_group_members AS agm
+                        WHERE agm.agent_principal_id = $2
+                    )
+                )
+            "#,
+        )
+        .bind(client_principal_id)
+        .bind(agent_principal_id)
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(combine_client_grants(grants))
+    }
+
+    // Returns at most `limit` agents ordered by id, starting after `after`.
     pub async fn list_discoverable_agents(
         &self,
+        query: &DiscoveryQuery,
+        after: Option<&AgentId>,
+        limit: i64,
     ) -> Result<Vec<AgentDiscoveryEntry>, ServerStoreError> {
         let now = Utc::now();
         let rows = sqlx::query_as::<_, AgentDiscoveryRow>(
@@ -508,9 +649,44 @@ impl Database {
                 ON ap.principal_id = p.id
             WHERE p.kind = 'agent'
               AND p.disabled_at IS NULL
+              AND ($1::text IS NULL OR p.external_id > $1)
+              AND ($2::text IS NULL OR starts_with(p.external_id, $2))
+              AND COALESCE(ap.metadata -> 'tags', '[]'::jsonb) ?& $3::text[]
+              AND COALESCE(ap.metadata -> 'capabilities', '[]'::jsonb) ?& $4::text[]
+              AND (
+                  $5::text IS NULL
+                  OR EXISTS (
+                      SELECT 1
+                      FROM agent_group_members AS gm
+                      JOIN agent_groups AS g
+                          ON g.id = gm.group_id
+                      WHERE gm.agent_principal_id = p.id
+                        AND g.external_id = $5
+                  )
+              )
+              AND (
+                  $6::boolean IS NULL
+                  OR COALESCE(
+                      ap.connected_session_id IS NOT NULL AND ap.lease_expires_at >= $7,
+                      false
+                  ) = $6
+              )
             ORDER BY p.external_id
+            LIMIT $8
             "#,
         )
+        .bind(after.map(AgentId::as_str))
+        .bind(query.name_prefix.as_deref())
+        .bind(&query.tags)
+        .bind(&query.capabilities)
+        .bind(query.group.as_ref().map(AgentGroupId::as_str))
+        .bind(
+            query
+                .status
+                .map(|status| status == AgentPresenceStatus::Online),
+        )
+        .bind(now)
+        .bind(limit)
         .fetch_all(self.pool())
         .await?;
 
@@ -558,6 +734,7 @@ impl Database {
 
     pub async fn list_discoverable_agent_groups(
         &self,
+        group: Option<&AgentGroupId>,
     ) -> Result<Vec<AgentGroupDiscoveryEntry>, ServerStoreError> {
         let rows = sqlx::query_as::<_, AgentGroupDiscoveryRow>(
             r#"
@@ -576,10 +753,12 @@ impl Database {
                 ON p.id = gm.agent_principal_id
                AND p.kind = 'agent'
                AND p.disabled_at IS NULL
+            WHERE $1::text IS NULL OR g.external_id = $1
             GROUP BY g.id, g.external_id, g.display_name
             ORDER BY g.external_id
             "#,
         )
+        .bind(group.map(AgentGroupId::as_str))
         .fetch_all(self.pool())
         .await?;
 
@@ -937,3 +1116,16 @@ async fn resolve_principal_id(
 
     Ok(row.map(|row| row.id))
 }
+
+fn combine_client_grants(grants: Vec<Vec<String>>) -> Option<ClientGrant> {
+    let mut combined: Option<BTreeSet<String>> = None;
+    for command_ids in grants {
+        if command_ids.is_empty() {
+            return Some(ClientGrant::AllCommands);
+        }
+        combined
+            .get_or_insert_with(BTreeSet::new)
+            .extend(command_ids);
+    }
+    combined.map(ClientGrant::Commands)
+}
@@ -26,6 +26,7 @@ pub enum HandshakeRejectionCode {
     AgentIdInUse,
     AgentUnavailable,
     Unauthorized,
+    Forbidden,
     InternalError,
 }
 
@@ -37,6 +38,7 @@ impl From<HandshakeErrorCode> for HandshakeRejectionCode {
             HandshakeErrorCode::AgentIdInUse => Self::AgentIdInUse,
             HandshakeErrorCode::AgentUnavailable => Self::AgentUnavailable,
             HandshakeErrorCode::Unauthorized => Self::Unauthorized,
+            HandshakeErrorCode::Forbidden => Self::Forbidden,
             HandshakeErrorCode::InternalError => Self::InternalError,
         }
     }
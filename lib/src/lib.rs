@@ -1,4 +1,6 @@
+pub mod config;
 pub mod constants;
 pub mod database;
 pub mod protocol;
 pub mod security;
+pub mod transport;
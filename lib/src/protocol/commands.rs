@@ -3,7 +3,7 @@ use std::{collections::BTreeMap, error::Error, fmt, io};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use super::{SecureChannel, SecureChannelError};
+use super::{SecureChannel, SecureChannelError, SecureReceiver, SecureSender};
 
 const MIN_COMMAND_ID_LEN: usize = 1;
 const MAX_COMMAND_ID_LEN: usize = 128;
@@ -104,7 +104,50 @@ pub enum ClientMessage {
         request_id: RequestId,
         command_id: CommandId,
         args: BTreeMap<String, String>,
+        // Environment variables the command's policy lets the client set.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        env: BTreeMap<String, String>,
     },
+    Fetch {
+        request_id: RequestId,
+        path: String,
+    },
+    Put {
+        request_id: RequestId,
+        path: String,
+        size: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+    },
+    PutChunk {
+        request_id: RequestId,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+        #[serde(default)]
+        eof: bool,
+    },
+    Stdin {
+        request_id: RequestId,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+        #[serde(default)]
+        eof: bool,
+    },
+    Cancel {
+        request_id: RequestId,
+        #[serde(default)]
+        signal: CancelSignal,
+    },
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelSignal {
+    Interrupt,
+    #[default]
+    Terminate,
+    Kill,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -114,6 +157,15 @@ pub enum OutputStream {
     Stderr,
 }
 
+// A per-command resource limit that ended or constrained the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceLimit {
+    Cpu,
+    Memory,
+    Processes,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RejectionCode {
@@ -123,6 +175,8 @@ pub enum RejectionCode {
     ExecutionError,
     Timeout,
     OutputLimit,
+    InputLimit,
+    Forbidden,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -134,13 +188,45 @@ pub enum AgentMessage {
     Output {
         request_id: RequestId,
         stream: OutputStream,
-        chunk: String,
+        #[serde(with = "base64_bytes")]
+        chunk: Vec<u8>,
     },
     Completed {
         request_id: RequestId,
         exit_code: i32,
         timed_out: bool,
         truncated: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit_exceeded: Option<ResourceLimit>,
+        // The client sent more stdin than the command accepts, so it was killed.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        input_exceeded: bool,
+    },
+    FetchStarted {
+        request_id: RequestId,
+        size: u64,
+    },
+    FetchChunk {
+        request_id: RequestId,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    FetchCompleted {
+        request_id: RequestId,
+        size: u64,
+        sha256: String,
+    },
+    PutCompleted {
+        request_id: RequestId,
+        path: String,
+        size: u64,
+        sha256: String,
+    },
+    Cancelled {
+        request_id: RequestId,
+        signal: CancelSignal,
+        exit_code: i32,
+        forced: bool,
     },
     Rejected {
         request_id: RequestId,
@@ -149,6 +235,26 @@ pub enum AgentMessage {
     },
 }
 
+mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug)]
 pub enum CommandProtocolError {
     SecureChannel(SecureChannelError),
@@ -213,11 +319,41 @@ where
     Ok(message)
 }
 
+pub async fn send_secure_json_split<S, T>(
+    sender: &mut SecureSender,
+    stream: &mut S,
+    message: &T,
+) -> Result<(), CommandProtocolError>
+where
+    S: AsyncWrite + Unpin,
+    T: Serialize + ?Sized,
+{
+    let payload = serde_json::to_vec(message)?;
+    sender.send(stream, &payload).await?;
+    Ok(())
+}
+
+pub async fn recv_secure_json_split<S, T>(
+    receiver: &mut SecureReceiver,
+    stream: &mut S,
+) -> Result<T, CommandProtocolError>
+where
+    S: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let payload = receiver.recv(stream).await?;
+    let message = serde_json::from_slice(&payload)?;
+    Ok(message)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
-    use super::{AgentMessage, ClientMessage, CommandId, OutputStream, RejectionCode, RequestId};
+    use super::{
+        AgentMessage, CancelSignal, ClientMessage, CommandId, OutputStream, RejectionCode,
+        RequestId,
+    };
 
     #[test]
     fn client_message_round_trip() {
@@ -227,6 +363,7 @@ mod tests {
             request_id: RequestId(42),
             command_id: CommandId::new("list_dir").expect("valid command id"),
             args,
+            env: BTreeMap::from([("LANG".to_string(), "C.UTF-8".to_string())]),
         };
 
         let encoded = serde_json::to_vec(&original).expect("serialize client message");
@@ -236,12 +373,47 @@ mod tests {
         assert_eq!(decoded, original);
     }
 
+    #[test]
+    fn close_message_wire_format() {
+        let encoded = serde_json::to_string(&ClientMessage::Close).expect("serialize close");
+        assert_eq!(encoded, r#"{"type":"close"}"#);
+    }
+
+    #[test]
+    fn cancel_signal_defaults_to_terminate() {
+        let decoded: ClientMessage = serde_json::from_str(r#"{"type":"cancel","request_id":3}"#)
+            .expect("deserialize cancel message");
+        assert_eq!(
+            decoded,
+            ClientMessage::Cancel {
+                request_id: RequestId(3),
+                signal: CancelSignal::Terminate,
+            }
+        );
+    }
+
+    #[test]
+    fn put_mode_is_optional() {
+        let decoded: ClientMessage =
+            serde_json::from_str(r#"{"type":"put","request_id":5,"path":"/srv/a","size":3}"#)
+                .expect("deserialize put message");
+        assert_eq!(
+            decoded,
+            ClientMessage::Put {
+                request_id: RequestId(5),
+                path: "/srv/a".to_string(),
+                size: 3,
+                mode: None,
+            }
+        );
+    }
+
     #[test]
     fn agent_message_round_trip() {
         let original = AgentMessage::Output {
             request_id: RequestId(7),
             stream: OutputStream::Stdout,
-            chunk: "hello".to_string(),
+            chunk: vec![0x1f, 0x8b, 0xff, b'\n'],
         };
 
         let encoded = serde_json::to_vec(&original).expect("serialize agent message");
@@ -251,6 +423,20 @@ mod tests {
         assert_eq!(decoded, original);
     }
 
+    #[test]
+    fn output_chunk_is_base64_on_the_wire() {
+        let encoded = serde_json::to_string(&AgentMessage::Output {
+            request_id: RequestId(1),
+            stream: OutputStream::Stderr,
+            chunk: vec![0xff, 0x00],
+        })
+        .expect("serialize output message");
+        assert_eq!(
+            encoded,
+            r#"{"type":"output","request_id":1,"stream":"stderr","chunk":"/wA="}"#
+        );
+    }
+
     #[test]
     fn rejection_code_wire_value_stability() {
         let encoded =
@@ -1,7 +1,11 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use serde::{Deserialize, Serialize};
 
 use super::{AgentGroupId, AgentId, PROTOCOL_VERSION};
 
+pub const DEFAULT_DISCOVERY_PAGE_SIZE: u32 = 500;
+pub const MAX_DISCOVERY_PAGE_SIZE: u32 = 1000;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum AgentPresenceStatus {
@@ -36,13 +40,64 @@ pub struct AgentGroupDiscoveryEntry {
     pub members: Vec<AgentId>,
 }
 
+// Sent with a discovery handshake. Every filter that is set must match; `tags` and
+// `capabilities` require all listed values.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveryQuery {
+    #[serde(default)]
+    pub status: Option<AgentPresenceStatus>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub name_prefix: Option<String>,
+    #[serde(default)]
+    pub group: Option<AgentGroupId>,
+    #[serde(default)]
+    pub page_size: Option<u32>,
+    // The `next_cursor` of the previous page; absent for the first page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+impl DiscoveryQuery {
+    #[must_use]
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+            .unwrap_or(DEFAULT_DISCOVERY_PAGE_SIZE)
+            .clamp(1, MAX_DISCOVERY_PAGE_SIZE)
+    }
+}
+
+// Cursors are opaque to clients; they carry the last agent id of the page they follow.
+#[must_use]
+pub fn encode_discovery_cursor(last_agent_id: &AgentId) -> String {
+    URL_SAFE_NO_PAD.encode(last_agent_id.as_str())
+}
+
+#[must_use]
+pub fn decode_discovery_cursor(cursor: &str) -> Option<AgentId> {
+    let decoded = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    AgentId::new(String::from_utf8(decoded).ok()?).ok()
+}
+
+// Asks for the page after a response's `next_cursor` on the same discovery connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveryPageRequest {
+    pub cursor: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ListAgentsResponse {
     pub protocol_version: u16,
     pub generated_at_unix: u64,
     pub agents: Vec<AgentDiscoveryEntry>,
+    // Groups are only sent with the first page.
     #[serde(default)]
     pub groups: Vec<AgentGroupDiscoveryEntry>,
+    #[serde(default)]
+    pub next_cursor: Option<String>,
 }
 
 impl ListAgentsResponse {
@@ -51,12 +106,44 @@ impl ListAgentsResponse {
         generated_at_unix: u64,
         agents: Vec<AgentDiscoveryEntry>,
         groups: Vec<AgentGroupDiscoveryEntry>,
+        next_cursor: Option<String>,
     ) -> Self {
         Self {
             protocol_version: PROTOCOL_VERSION,
             generated_at_unix,
             agents,
             groups,
+            next_cursor,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AgentId, DiscoveryQuery, MAX_DISCOVERY_PAGE_SIZE, decode_discovery_cursor,
+        encode_discovery_cursor,
+    };
+
+    #[test]
+    fn cursor_round_trips_and_rejects_garbage() {
+        let agent_id = AgentId::new("agent-0042").expect("valid agent id");
+        let cursor = encode_discovery_cursor(&agent_id);
+        assert_eq!(decode_discovery_cursor(&cursor), Some(agent_id));
+        assert_eq!(decode_discovery_cursor("not a cursor!"), None);
+    }
+
+    #[test]
+    fn page_size_is_clamped() {
+        let query = DiscoveryQuery {
+            page_size: Some(1_000_000),
+            ..DiscoveryQuery::default()
+        };
+        assert_eq!(query.page_size(), MAX_DISCOVERY_PAGE_SIZE);
+        let query = DiscoveryQuery {
+            page_size: Some(0),
+            ..DiscoveryQuery::default()
+        };
+        assert_eq!(query.page_size(), 1);
+    }
+}
@@ -4,12 +4,18 @@ use serde::{Serialize, de::DeserializeOwned};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub const MAX_FRAME_BYTES: usize = 64 * 1024;
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+// Set in the length prefix of every fragment except the last one of a message. Plain frame
+// readers see it as an oversized frame, so fragmented messages never parse as single frames.
+const FRAGMENT_CONTINUES: u32 = 1 << 31;
 
 #[derive(Debug)]
 pub enum ProtocolError {
     Io(io::Error),
     Json(serde_json::Error),
     FrameTooLarge(usize),
+    MessageTooLarge { size: usize, max: usize },
 }
 
 impl fmt::Display for ProtocolError {
@@ -22,6 +28,11 @@ impl fmt::Display for ProtocolError {
                 "frame is {} bytes, above configured maximum {}",
                 size, MAX_FRAME_BYTES
             ),
+            ProtocolError::MessageTooLarge { size, max } => write!(
+                f,
+                "message is at least {} bytes, above negotiated maximum {}",
+                size, max
+            ),
         }
     }
 }
@@ -79,3 +90,156 @@ where
     let payload = read_bytes_frame(reader).await?;
     serde_json::from_slice::<T>(&payload).map_err(ProtocolError::Json)
 }
+
+// Writes a JSON message of up to `max_message_bytes`, split across as many frames as needed.
+pub async fn write_json_message<W, T>(
+    writer: &mut W,
+    message: &T,
+    max_message_bytes: usize,
+) -> Result<(), ProtocolError>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize + ?Sized,
+{
+    let payload = serde_json::to_vec(message).map_err(ProtocolError::Json)?;
+    if payload.len() > max_message_bytes {
+        return Err(ProtocolError::MessageTooLarge {
+            size: payload.len(),
+            max: max_message_bytes,
+        });
+    }
+
+    let mut fragments = payload.chunks(MAX_FRAME_BYTES).peekable();
+    while let Some(fragment) = fragments.next() {
+        let mut len = fragment.len() as u32;
+        if fragments.peek().is_some() {
+            len |= FRAGMENT_CONTINUES;
+        }
+        writer.write_u32(len).await?;
+        writer.write_all(fragment).await?;
+    }
+    if payload.is_empty() {
+        writer.write_u32(0).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+// Reassembles a message written by `write_json_message`, buffering at most `max_message_bytes`.
+pub async fn read_json_message<R, T>(
+    reader: &mut R,
+    max_message_bytes: usize,
+) -> Result<T, ProtocolError>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let mut payload = Vec::new();
+    loop {
+        let prefix = reader.read_u32().await?;
+        let len = (prefix & !FRAGMENT_CONTINUES) as usize;
+        if len > MAX_FRAME_BYTES {
+            return Err(ProtocolError::FrameTooLarge(len));
+        }
+        let size = payload.len() + len;
+        if size > max_message_bytes {
+            return Err(ProtocolError::MessageTooLarge {
+                size,
+                max: max_message_bytes,
+            });
+        }
+
+        let start = payload.len();
+        payload.resize(size, 0);
+        reader.read_exact(&mut payload[start..]).await?;
+        if prefix & FRAGMENT_CONTINUES == 0 {
+            break;
+        }
+    }
+    serde_json::from_slice::<T>(&payload).map_err(ProtocolError::Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::{
+        MAX_FRAME_BYTES, ProtocolError, read_json_frame, read_json_message, write_json_message,
+    };
+    use crate::protocol::{
+        AgentDiscoveryEntry, AgentId, AgentPresenceStatus, DEFAULT_MAX_MESSAGE_BYTES,
+        ListAgentsResponse,
+    };
+
+    fn list_agents_response(count: usize) -> ListAgentsResponse {
+        let agents = (0..count)
+            .map(|index| AgentDiscoveryEntry {
+                agent_id: AgentId::new(format!("agent-{index:05}")).expect("valid agent id"),
+                display_name: Some(format!("Agent {index}")),
+                capabilities: vec!["uptime".to_string(), "disk_usage".to_string()],
+                tags: vec!["prod".to_string()],
+                status: AgentPresenceStatus::Online,
+                status_age_secs: 3,
+            })
+            .collect();
+        ListAgentsResponse::new(1_700_000_000, agents, Vec::new(), None)
+    }
+
+    #[tokio::test]
+    async fn list_agents_response_for_many_agents_spans_frames() {
+        let response = list_agents_response(5_000);
+        let (mut writer, mut reader) = duplex(64 * 1024);
+
+        let (written, read) = tokio::join!(
+            write_json_message(&mut writer, &response, DEFAULT_MAX_MESSAGE_BYTES),
+            read_json_message::<_, ListAgentsResponse>(&mut reader, DEFAULT_MAX_MESSAGE_BYTES),
+        );
+        written.expect("write fragmented response");
+        assert_eq!(read.expect("reassemble response"), response);
+        assert!(serde_json::to_vec(&response).expect("serialize").len() > MAX_FRAME_BYTES);
+    }
+
+    #[tokio::test]
+    async fn reassembly_stops_at_memory_cap() {
+        let response = list_agents_response(5_000);
+        let (mut writer, mut reader) = duplex(1024 * 1024);
+        write_json_message(&mut writer, &response, DEFAULT_MAX_MESSAGE_BYTES)
+            .await
+            .expect("write fragmented response");
+
+        let result = read_json_message::<_, ListAgentsResponse>(&mut reader, 128 * 1024).await;
+        assert!(matches!(
+            result,
+            Err(ProtocolError::MessageTooLarge { max, .. }) if max == 128 * 1024
+        ));
+        assert!(matches!(
+            write_json_message(&mut writer, &response, 128 * 1024).await,
+            Err(ProtocolError::MessageTooLarge { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn small_messages_stay_single_frames() {
+        let response = list_agents_response(3);
+        let (mut writer, mut reader) = duplex(64 * 1024);
+        write_json_message(&mut writer, &response, DEFAULT_MAX_MESSAGE_BYTES)
+            .await
+            .expect("write response");
+        assert_eq!(
+            read_json_frame::<_, ListAgentsResponse>(&mut reader)
+                .await
+                .expect("plain frame reader accepts single-frame messages"),
+            response
+        );
+
+        let large = list_agents_response(5_000);
+        let (mut writer, mut reader) = duplex(1024 * 1024);
+        write_json_message(&mut writer, &large, DEFAULT_MAX_MESSAGE_BYTES)
+            .await
+            .expect("write fragmented response");
+        assert!(matches!(
+            read_json_frame::<_, ListAgentsResponse>(&mut reader).await,
+            Err(ProtocolError::FrameTooLarge(_))
+        ));
+    }
+}
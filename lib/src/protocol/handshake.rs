@@ -3,9 +3,13 @@ use std::{collections::BTreeMap, error::Error, fmt};
 use hacl_star::ed25519;
 use serde::{Deserialize, Serialize};
 
-use super::ids::{AgentId, ClientId, SessionId};
+use super::{
+    discovery::DiscoveryQuery,
+    framing::{DEFAULT_MAX_MESSAGE_BYTES, MAX_FRAME_BYTES},
+    ids::{AgentId, ClientId, SessionId},
+};
 
-pub const PROTOCOL_VERSION: u16 = 1;
+pub const PROTOCOL_VERSION: u16 = 16;
 pub const AUTH_METHOD_ED25519_CHALLENGE_V1: &str = "ed25519_challenge_v1";
 const AUTH_SIGNING_CONTEXT_V1: &str = "alaric-handshake-auth-v1";
 
@@ -39,6 +43,9 @@ pub enum HandshakeRequest {
         protocol_version: u16,
         agent_id: AgentId,
         metadata: BTreeMap<String, String>,
+        // Largest relay message, such as a policy bundle, the peer is willing to reassemble.
+        #[serde(default = "single_frame_message_bytes")]
+        max_message_bytes: usize,
     },
     Client {
         protocol_version: u16,
@@ -50,6 +57,15 @@ pub enum HandshakeRequest {
         protocol_version: u16,
         client_id: ClientId,
         metadata: BTreeMap<String, String>,
+        #[serde(default = "single_frame_message_bytes")]
+        max_message_bytes: usize,
+        #[serde(default)]
+        query: DiscoveryQuery,
+    },
+    AgentTunnel {
+        protocol_version: u16,
+        agent_id: AgentId,
+        session_id: SessionId,
     },
 }
 
@@ -60,6 +76,7 @@ impl HandshakeRequest {
             protocol_version: PROTOCOL_VERSION,
             agent_id,
             metadata: BTreeMap::new(),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
         }
     }
 
@@ -74,11 +91,22 @@ impl HandshakeRequest {
     }
 
     #[must_use]
-    pub const fn client_discovery(client_id: ClientId) -> Self {
+    pub const fn client_discovery(client_id: ClientId, query: DiscoveryQuery) -> Self {
         Self::ClientDiscovery {
             protocol_version: PROTOCOL_VERSION,
             client_id,
             metadata: BTreeMap::new(),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            query,
+        }
+    }
+
+    #[must_use]
+    pub const fn agent_tunnel(agent_id: AgentId, session_id: SessionId) -> Self {
+        Self::AgentTunnel {
+            protocol_version: PROTOCOL_VERSION,
+            agent_id,
+            session_id,
         }
     }
 
@@ -94,6 +122,41 @@ impl HandshakeRequest {
             HandshakeRequest::ClientDiscovery {
                 protocol_version, ..
             } => *protocol_version,
+            HandshakeRequest::AgentTunnel {
+                protocol_version, ..
+            } => *protocol_version,
+        }
+    }
+
+    // Not const: the compiler cannot prove `self` is never dropped here.
+    #[allow(clippy::missing_const_for_fn)]
+    #[must_use]
+    pub fn with_max_message_bytes(mut self, value: usize) -> Self {
+        match &mut self {
+            HandshakeRequest::Agent {
+                max_message_bytes, ..
+            }
+            | HandshakeRequest::ClientDiscovery {
+                max_message_bytes, ..
+            } => *max_message_bytes = value,
+            HandshakeRequest::Client { .. } | HandshakeRequest::AgentTunnel { .. } => {}
+        }
+        self
+    }
+
+    // Tunnel connections only carry Noise frames, which never span more than one relay frame.
+    #[must_use]
+    pub const fn max_message_bytes(&self) -> usize {
+        match self {
+            HandshakeRequest::Agent {
+                max_message_bytes, ..
+            }
+            | HandshakeRequest::ClientDiscovery {
+                max_message_bytes, ..
+            } => *max_message_bytes,
+            HandshakeRequest::Client { .. } | HandshakeRequest::AgentTunnel { .. } => {
+                MAX_FRAME_BYTES
+            }
         }
     }
 
@@ -103,6 +166,7 @@ impl HandshakeRequest {
             HandshakeRequest::Agent { .. } => Role::Agent,
             HandshakeRequest::Client { .. } => Role::Client,
             HandshakeRequest::ClientDiscovery { .. } => Role::Client,
+            HandshakeRequest::AgentTunnel { .. } => Role::Agent,
         }
     }
 }
@@ -167,6 +231,7 @@ pub enum HandshakeErrorCode {
     AgentIdInUse,
     AgentUnavailable,
     Unauthorized,
+    Forbidden,
     InternalError,
 }
 
@@ -174,6 +239,13 @@ pub enum HandshakeErrorCode {
 pub struct HandshakeAccepted {
     pub protocol_version: u16,
     pub session_id: SessionId,
+    // The smaller of the requested and relay limits; relay messages on this connection fit it.
+    #[serde(default = "single_frame_message_bytes")]
+    pub max_message_bytes: usize,
+}
+
+const fn single_frame_message_bytes() -> usize {
+    MAX_FRAME_BYTES
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -261,6 +333,10 @@ enum AuthPrincipal<'a> {
     ClientDiscovery {
         client_id: &'a ClientId,
     },
+    AgentTunnel {
+        agent_id: &'a AgentId,
+        session_id: &'a SessionId,
+    },
 }
 
 #[derive(Debug, Serialize)]
@@ -354,6 +430,14 @@ fn signing_payload(
         HandshakeRequest::ClientDiscovery { client_id, .. } => {
             AuthPrincipal::ClientDiscovery { client_id }
         }
+        HandshakeRequest::AgentTunnel {
+            agent_id,
+            session_id,
+            ..
+        } => AuthPrincipal::AgentTunnel {
+            agent_id,
+            session_id,
+        },
     };
 
     serde_json::to_vec(&AuthSigningPayload {
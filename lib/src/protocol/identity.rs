@@ -10,6 +10,10 @@ use hacl_star::ed25519::{self, PublicKey};
 use serde::{Deserialize, Serialize};
 
 use super::{AgentId, ClientId};
+use crate::security::noise::{
+    consts::DHLEN,
+    types::{self as noise, Keypair},
+};
 
 const IDENTITY_BUNDLE_SIGNING_CONTEXT_V1: &str = "alaric-identity-bundle-v1";
 pub const IDENTITY_BUNDLE_VERSION_V1: u16 = 1;
@@ -19,6 +23,9 @@ pub const IDENTITY_BUNDLE_SIGNATURE_ALGORITHM_ED25519: &str = "ed25519";
 pub struct IdentityPrincipal {
     pub key_id: String,
     pub public_key: String,
+    // Long-term Curve25519 key the principal uses as its Noise XX static key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise_public_key: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -54,6 +61,7 @@ struct IdentityBundleSigningPayload<'a> {
 pub struct IdentityPublicKey {
     pub key_id: String,
     pub public_key: [u8; ed25519::PUBLIC_LENGTH],
+    pub noise_public_key: Option<[u8; DHLEN]>,
 }
 
 #[derive(Debug, Clone)]
@@ -242,6 +250,14 @@ impl IdentityBundle {
         self.clients.get(client_id)
     }
 
+    // Once any client has a bound Noise key, clients without one cannot be told apart by key.
+    #[must_use]
+    pub fn binds_client_noise_keys(&self) -> bool {
+        self.clients
+            .values()
+            .any(|identity| identity.noise_public_key.is_some())
+    }
+
     #[must_use]
     pub const fn expires_at_unix(&self) -> u64 {
         self.expires_at_unix
@@ -370,9 +386,35 @@ fn parse_principal_identity_key(
         &format!("{} public key", field_prefix),
         &identity.public_key,
     )?;
+    let noise_public_key = identity
+        .noise_public_key
+        .as_deref()
+        .map(|encoded| {
+            parse_noise_public_key(&format!("{} noise public key", field_prefix), encoded)
+        })
+        .transpose()?;
     Ok(IdentityPublicKey {
         key_id: identity.key_id.clone(),
         public_key,
+        noise_public_key,
+    })
+}
+
+fn parse_noise_public_key(field: &str, encoded: &str) -> Result<[u8; DHLEN], IdentityBundleError> {
+    let key = decode_hex_array::<DHLEN>(field, encoded)?;
+    noise::PublicKey::from_bytes(key)
+        .map_err(|err| IdentityBundleError::Invalid(format!("{} is invalid: {}", field, err)))?;
+    Ok(key)
+}
+
+// Loads a principal's long-term Noise static keypair from its hex-encoded private key.
+pub fn noise_static_keypair_from_hex(
+    private_key_hex: &str,
+) -> Result<Keypair, IdentityBundleError> {
+    let private_key =
+        decode_hex_array::<DHLEN>("noise static private key", private_key_hex.trim())?;
+    Keypair::from_private_key(noise::PrivateKey::from_bytes(private_key)).map_err(|err| {
+        IdentityBundleError::Invalid(format!("invalid noise static private key: {}", err))
     })
 }
 
@@ -421,7 +463,8 @@ mod tests {
     use serde_json::json;
 
     use super::{
-        IdentityBundle, IdentityPrincipal, TrustedIdentityKeys, sign_identity_bundle_ed25519,
+        IdentityBundle, IdentityPrincipal, TrustedIdentityKeys, noise_static_keypair_from_hex,
+        sign_identity_bundle_ed25519,
     };
 
     const SIGNING_KEY_ID: &str = "control-plane-v1";
@@ -452,6 +495,7 @@ mod tests {
                 key_id: "agent-default-v1".to_string(),
                 public_key: "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
                     .to_string(),
+                noise_public_key: None,
             },
         )])
     }
@@ -463,6 +507,7 @@ mod tests {
                 key_id: "client-local-v1".to_string(),
                 public_key: "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c"
                     .to_string(),
+                noise_public_key: None,
             },
         )])
     }
@@ -538,4 +583,46 @@ mod tests {
             .expect_err("expired bundle should fail");
         assert!(err.to_string().contains("expired"));
     }
+
+    #[test]
+    fn binds_signed_noise_static_keys() {
+        let trusted_keys = trusted_keys();
+        let noise_keypair = noise_static_keypair_from_hex(
+            "a8abababababababababababababababababababababababababababababab6b",
+        )
+        .expect("noise private key should parse");
+        let noise_public_key = noise_keypair.get_public_key().as_bytes();
+        let mut agents = sample_agents();
+        for identity in agents.values_mut() {
+            identity.noise_public_key = Some(hex::encode(noise_public_key));
+        }
+
+        let mut signed = sign_identity_bundle_ed25519(
+            now_unix() + 300,
+            agents,
+            sample_clients(),
+            SIGNING_KEY_ID,
+            &hex::encode(SIGNING_SECRET_KEY),
+        )
+        .expect("bundle signing should succeed");
+        let signed_json = serde_json::to_string(&signed).expect("signed bundle should serialize");
+        let bundle = IdentityBundle::from_signed_json(&signed_json, &trusted_keys)
+            .expect("signed bundle should verify");
+        let agent_id = super::AgentId::new("agent-default").expect("agent id should be valid");
+        assert_eq!(
+            bundle
+                .agent_identity_key(&agent_id)
+                .and_then(|identity| identity.noise_public_key),
+            Some(noise_public_key)
+        );
+        assert!(!bundle.binds_client_noise_keys());
+
+        for identity in signed.agents.values_mut() {
+            identity.noise_public_key = Some(hex::encode([9_u8; 32]));
+        }
+        let signed_json = serde_json::to_string(&signed).expect("signed bundle should serialize");
+        let err = IdentityBundle::from_signed_json(&signed_json, &trusted_keys)
+            .expect_err("swapped noise key should break the signature");
+        assert!(err.to_string().contains("signature verification failed"));
+    }
 }
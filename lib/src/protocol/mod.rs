@@ -6,6 +6,7 @@ mod handshake;
 mod identity;
 mod ids;
 mod peer_attestation;
+mod relay;
 mod secure;
 
 pub use attestation_policy::{
@@ -13,15 +14,18 @@ pub use attestation_policy::{
     PeerAttestationPolicyError, PrincipalAttestationModes,
 };
 pub use commands::{
-    AgentMessage, ClientMessage, CommandId, CommandIdError, CommandProtocolError, OutputStream,
-    RejectionCode, RequestId, recv_secure_json, send_secure_json,
+    AgentMessage, CancelSignal, ClientMessage, CommandId, CommandIdError, CommandProtocolError,
+    OutputStream, RejectionCode, RequestId, ResourceLimit, recv_secure_json,
+    recv_secure_json_split, send_secure_json, send_secure_json_split,
 };
 pub use discovery::{
-    AgentDiscoveryEntry, AgentGroupDiscoveryEntry, AgentPresenceStatus, ListAgentsResponse,
+    AgentDiscoveryEntry, AgentGroupDiscoveryEntry, AgentPresenceStatus,
+    DEFAULT_DISCOVERY_PAGE_SIZE, DiscoveryPageRequest, DiscoveryQuery, ListAgentsResponse,
+    MAX_DISCOVERY_PAGE_SIZE, decode_discovery_cursor, encode_discovery_cursor,
 };
 pub use framing::{
-    MAX_FRAME_BYTES, ProtocolError, read_bytes_frame, read_json_frame, write_bytes_frame,
-    write_json_frame,
+    DEFAULT_MAX_MESSAGE_BYTES, MAX_FRAME_BYTES, ProtocolError, read_bytes_frame, read_json_frame,
+    read_json_message, write_bytes_frame, write_json_frame, write_json_message,
 };
 pub use handshake::{
     AUTH_METHOD_ED25519_CHALLENGE_V1, AuthCryptoError, AuthProof, HandshakeAccepted,
@@ -32,7 +36,8 @@ pub use handshake::{
 pub use identity::{
     IDENTITY_BUNDLE_SIGNATURE_ALGORITHM_ED25519, IDENTITY_BUNDLE_VERSION_V1, IdentityBundle,
     IdentityBundleError, IdentityBundleSignature, IdentityPrincipal, IdentityPublicKey,
-    SignedIdentityBundle, TrustedIdentityKeys, sign_identity_bundle_ed25519,
+    SignedIdentityBundle, TrustedIdentityKeys, noise_static_keypair_from_hex,
+    sign_identity_bundle_ed25519,
 };
 pub use ids::{AgentGroupId, AgentId, ClientId, IdError, SessionId};
 pub use peer_attestation::{
@@ -40,14 +45,15 @@ pub use peer_attestation::{
     PeerAttestationInit, PeerAttestationProof, PeerAttestationResult, build_peer_attestation_proof,
     verify_peer_attestation_proof,
 };
+pub use relay::{AgentControlMessage, RelayControlMessage};
 pub use secure::{
     NOISE_HANDSHAKE_MSG_A_LEN, NOISE_HANDSHAKE_MSG_B_LEN, NOISE_HANDSHAKE_MSG_C_LEN,
-    NOISE_PROLOGUE, SecureChannel, SecureChannelError,
+    NOISE_PROLOGUE, RekeyPolicy, SecureChannel, SecureChannelError, SecureReceiver, SecureSender,
 };
 
 #[cfg(test)]
 mod tests {
-    use super::{AgentGroupId, AgentId, ClientId, HandshakeRequest};
+    use super::{AgentGroupId, AgentId, ClientId, DiscoveryQuery, HandshakeRequest, SessionId};
 
     #[test]
     fn agent_id_validation_rejects_invalid_chars() {
@@ -74,7 +80,14 @@ mod tests {
     #[test]
     fn client_discovery_helpers_set_expected_role() {
         let client_id = ClientId::new("client-main").expect("valid client id");
-        let request = HandshakeRequest::client_discovery(client_id);
+        let request = HandshakeRequest::client_discovery(client_id, DiscoveryQuery::default());
         assert_eq!(request.role().as_str(), "client");
     }
+
+    #[test]
+    fn agent_tunnel_helpers_set_expected_role() {
+        let agent_id = AgentId::new("agent-main").expect("valid agent id");
+        let request = HandshakeRequest::agent_tunnel(agent_id, SessionId::new_random());
+        assert_eq!(request.role().as_str(), "agent");
+    }
 }
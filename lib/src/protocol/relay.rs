@@ -0,0 +1,30 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ClientId, CommandId, SessionId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayControlMessage {
+    OpenTunnel {
+        session_id: SessionId,
+        client_id: ClientId,
+        // Set when the client's grant names specific commands; absent means no restriction.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        allowed_commands: Option<Vec<CommandId>>,
+    },
+    // A signed policy bundle, verbatim as published; agents verify it themselves.
+    PolicyBundle {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bundle: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentControlMessage {
+    UpdateMetadata { metadata: BTreeMap<String, String> },
+    // Asks for the assigned policy bundle and subscribes to later assignments.
+    FetchPolicy,
+}
@@ -1,9 +1,14 @@
-use std::{error::Error, fmt};
+use std::{
+    error::Error,
+    fmt, mem,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
 
+use constant_time_eq::constant_time_eq;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::security::noise::{
-    consts::{DHLEN, MAC_LENGTH},
+    consts::{DHLEN, HASHLEN, MAC_LENGTH, MAX_MESSAGE},
     error::NoiseError,
     noisesession::NoiseSession,
     types::Keypair,
@@ -11,10 +16,21 @@ use crate::security::noise::{
 
 use super::{MAX_FRAME_BYTES, ProtocolError, read_bytes_frame, write_bytes_frame};
 
+// Messages b and c carry the sender's maximum message size as their encrypted payload.
+const MAX_MESSAGE_FIELD_LEN: usize = 4;
+
 pub const NOISE_PROLOGUE: &[u8] = b"alaric/noise-xx-v1";
 pub const NOISE_HANDSHAKE_MSG_A_LEN: usize = DHLEN + MAC_LENGTH;
-pub const NOISE_HANDSHAKE_MSG_B_LEN: usize = (2 * DHLEN) + (2 * MAC_LENGTH);
-pub const NOISE_HANDSHAKE_MSG_C_LEN: usize = DHLEN + (2 * MAC_LENGTH);
+pub const NOISE_HANDSHAKE_MSG_B_LEN: usize = (2 * DHLEN) + (2 * MAC_LENGTH) + MAX_MESSAGE_FIELD_LEN;
+pub const NOISE_HANDSHAKE_MSG_C_LEN: usize = DHLEN + (2 * MAC_LENGTH) + MAX_MESSAGE_FIELD_LEN;
+
+// Every transport plaintext starts with a kind byte. A rekey frame is sealed under the old key
+// and tells the peer to rekey its incoming cipherstate before opening the next frame. Messages
+// too large for one frame are sent as fragment frames followed by a final data frame.
+const FRAME_KIND_DATA: u8 = 0;
+const FRAME_KIND_REKEY: u8 = 1;
+const FRAME_KIND_FRAGMENT: u8 = 2;
+const FRAME_KIND_LEN: usize = 1;
 
 #[derive(Debug)]
 pub enum SecureChannelError {
@@ -25,8 +41,12 @@ pub enum SecureChannelError {
         expected: usize,
         got: usize,
     },
-    TransportMessageTooLarge(usize),
+    TransportMessageTooLarge {
+        size: usize,
+        max: usize,
+    },
     TransportFrameTooSmall(usize),
+    UnknownFrameKind(u8),
     HandshakeIncomplete,
 }
 
@@ -44,15 +64,17 @@ impl fmt::Display for SecureChannelError {
                 "invalid Noise XX message length at {}: expected {} bytes, got {}",
                 step, expected, got
             ),
-            SecureChannelError::TransportMessageTooLarge(len) => write!(
+            SecureChannelError::TransportMessageTooLarge { size, max } => write!(
                 f,
-                "transport message is {} bytes before MAC, above configured maximum {}",
-                len,
-                MAX_FRAME_BYTES.saturating_sub(MAC_LENGTH)
+                "transport message is at least {} bytes, above negotiated maximum {}",
+                size, max
             ),
             SecureChannelError::TransportFrameTooSmall(len) => {
                 write!(f, "received transport frame too small for MAC: {}", len)
             }
+            SecureChannelError::UnknownFrameKind(kind) => {
+                write!(f, "received transport frame of unknown kind {}", kind)
+            }
             SecureChannelError::HandshakeIncomplete => {
                 f.write_str("noise handshake completed without entering transport mode")
             }
@@ -74,14 +96,50 @@ impl From<NoiseError> for SecureChannelError {
     }
 }
 
-pub struct SecureChannel {
+// Each side rekeys the direction it sends on once either limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    pub after_messages: u64,
+    pub after_bytes: u64,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            after_messages: 1 << 20,
+            after_bytes: 1 << 30,
+        }
+    }
+}
+
+struct TransportState {
     session: NoiseSession,
+    rekey_policy: RekeyPolicy,
+    sent_messages: u64,
+    sent_bytes: u64,
+    // The smaller of both peers' advertised limits; applies to both directions.
+    max_message_bytes: usize,
+    reassembly: Vec<u8>,
+}
+
+impl TransportState {
+    const fn rekey_due(&self) -> bool {
+        self.sent_messages >= self.rekey_policy.after_messages
+            || self.sent_bytes >= self.rekey_policy.after_bytes
+    }
+}
+
+pub struct SecureChannel {
+    transport: TransportState,
+    remote_static_public_key: [u8; DHLEN],
+    handshake_hash: [u8; HASHLEN],
 }
 
 impl SecureChannel {
     pub async fn handshake_xx_initiator<S>(
         stream: &mut S,
         static_keypair: Keypair,
+        max_message_bytes: usize,
     ) -> Result<Self, SecureChannelError>
     where
         S: AsyncRead + AsyncWrite + Unpin,
@@ -95,17 +153,23 @@ impl SecureChannel {
         let mut msg_b = read_bytes_frame(stream).await?;
         validate_handshake_len("message_b", &msg_b, NOISE_HANDSHAKE_MSG_B_LEN)?;
         session.recv_message(&mut msg_b)?;
+        let remote_max_message_bytes = read_max_message_bytes(&mut msg_b);
 
         let mut msg_c = vec![0u8; NOISE_HANDSHAKE_MSG_C_LEN];
+        write_max_message_bytes(&mut msg_c, max_message_bytes);
         session.send_message(&mut msg_c)?;
         write_bytes_frame(stream, &msg_c).await?;
 
-        Self::from_transport_session(session)
+        Self::from_transport_session(
+            session,
+            negotiate_max_message_bytes(max_message_bytes, remote_max_message_bytes),
+        )
     }
 
     pub async fn handshake_xx_responder<S>(
         stream: &mut S,
         static_keypair: Keypair,
+        max_message_bytes: usize,
     ) -> Result<Self, SecureChannelError>
     where
         S: AsyncRead + AsyncWrite + Unpin,
@@ -117,14 +181,25 @@ impl SecureChannel {
         session.recv_message(&mut msg_a)?;
 
         let mut msg_b = vec![0u8; NOISE_HANDSHAKE_MSG_B_LEN];
+        write_max_message_bytes(&mut msg_b, max_message_bytes);
         session.send_message(&mut msg_b)?;
         write_bytes_frame(stream, &msg_b).await?;
 
         let mut msg_c = read_bytes_frame(stream).await?;
         validate_handshake_len("message_c", &msg_c, NOISE_HANDSHAKE_MSG_C_LEN)?;
         session.recv_message(&mut msg_c)?;
+        let remote_max_message_bytes = read_max_message_bytes(&mut msg_c);
 
-        Self::from_transport_session(session)
+        Self::from_transport_session(
+            session,
+            negotiate_max_message_bytes(max_message_bytes, remote_max_message_bytes),
+        )
+    }
+
+    #[must_use]
+    pub const fn with_rekey_policy(mut self, rekey_policy: RekeyPolicy) -> Self {
+        self.transport.rekey_policy = rekey_policy;
+        self
     }
 
     pub async fn send<S>(
@@ -135,50 +210,273 @@ impl SecureChannel {
     where
         S: AsyncWrite + Unpin,
     {
-        let frame_len = plaintext.len().checked_add(MAC_LENGTH).ok_or(
-            SecureChannelError::TransportMessageTooLarge(plaintext.len()),
-        )?;
+        let frames = seal_transport_frames(&mut self.transport, plaintext)?;
+        write_transport_frames(stream, frames).await
+    }
 
-        if frame_len > MAX_FRAME_BYTES {
-            return Err(SecureChannelError::TransportMessageTooLarge(
-                plaintext.len(),
-            ));
+    pub async fn recv<S>(&mut self, stream: &mut S) -> Result<Vec<u8>, SecureChannelError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        loop {
+            let frame = read_bytes_frame(stream).await?;
+            if let Some(plaintext) = open_transport_frame(&mut self.transport, frame)? {
+                return Ok(plaintext);
+            }
         }
+    }
+
+    #[must_use]
+    pub fn into_split(self) -> (SecureReceiver, SecureSender) {
+        let transport = Arc::new(Mutex::new(self.transport));
+        (
+            SecureReceiver {
+                transport: Arc::clone(&transport),
+            },
+            SecureSender { transport },
+        )
+    }
+
+    #[must_use]
+    pub const fn max_message_bytes(&self) -> usize {
+        self.transport.max_message_bytes
+    }
+
+    #[must_use]
+    pub const fn remote_static_public_key(&self) -> [u8; DHLEN] {
+        self.remote_static_public_key
+    }
+
+    #[must_use]
+    pub fn remote_static_matches(&self, expected: &[u8; DHLEN]) -> bool {
+        constant_time_eq(&self.remote_static_public_key, expected)
+    }
+
+    #[must_use]
+    pub const fn handshake_hash(&self) -> [u8; HASHLEN] {
+        self.handshake_hash
+    }
+
+    fn from_transport_session(
+        session: NoiseSession,
+        max_message_bytes: usize,
+    ) -> Result<Self, SecureChannelError> {
+        let Some(remote_static) = session.get_remote_static_public_key() else {
+            return Err(SecureChannelError::HandshakeIncomplete);
+        };
+        let Some(handshake_hash) = session.get_handshake_hash() else {
+            return Err(SecureChannelError::HandshakeIncomplete);
+        };
+
+        Ok(Self {
+            transport: TransportState {
+                session,
+                rekey_policy: RekeyPolicy::default(),
+                sent_messages: 0,
+                sent_bytes: 0,
+                max_message_bytes,
+                reassembly: Vec::new(),
+            },
+            remote_static_public_key: remote_static.as_bytes(),
+            handshake_hash,
+        })
+    }
+}
+
+pub struct SecureSender {
+    transport: Arc<Mutex<TransportState>>,
+}
 
-        let mut in_out = vec![0u8; frame_len];
-        in_out[..plaintext.len()].copy_from_slice(plaintext);
-        self.session.send_message(&mut in_out)?;
-        write_bytes_frame(stream, &in_out).await?;
-        Ok(())
+impl SecureSender {
+    pub async fn send<S>(
+        &mut self,
+        stream: &mut S,
+        plaintext: &[u8],
+    ) -> Result<(), SecureChannelError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let frames = seal_transport_frames(&mut lock_transport(&self.transport), plaintext)?;
+        write_transport_frames(stream, frames).await
     }
+}
+
+pub struct SecureReceiver {
+    transport: Arc<Mutex<TransportState>>,
+}
 
+impl SecureReceiver {
     pub async fn recv<S>(&mut self, stream: &mut S) -> Result<Vec<u8>, SecureChannelError>
     where
         S: AsyncRead + Unpin,
     {
-        let mut in_out = read_bytes_frame(stream).await?;
-        if in_out.len() < MAC_LENGTH {
-            return Err(SecureChannelError::TransportFrameTooSmall(in_out.len()));
+        loop {
+            let frame = read_bytes_frame(stream).await?;
+            if let Some(plaintext) =
+                open_transport_frame(&mut lock_transport(&self.transport), frame)?
+            {
+                return Ok(plaintext);
+            }
         }
-        self.session.recv_message(&mut in_out)?;
-        in_out.truncate(in_out.len() - MAC_LENGTH);
-        Ok(in_out)
     }
+}
 
-    #[must_use]
-    pub const fn handshake_hash(&self) -> [u8; 32] {
-        self.session
-            .get_handshake_hash()
-            .expect("handshake hash should always be available in transport mode")
+fn lock_transport(transport: &Mutex<TransportState>) -> MutexGuard<'_, TransportState> {
+    transport.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Noise caps a transport message one byte below the relay frame limit.
+const fn max_transport_plaintext() -> usize {
+    let max_sealed = if MAX_MESSAGE < MAX_FRAME_BYTES {
+        MAX_MESSAGE
+    } else {
+        MAX_FRAME_BYTES
+    };
+    max_sealed.saturating_sub(MAC_LENGTH + FRAME_KIND_LEN)
+}
+
+// A peer can never push the limit below what fits in a single frame.
+fn negotiate_max_message_bytes(local: usize, remote: usize) -> usize {
+    local.min(remote).max(max_transport_plaintext())
+}
+
+// The handshake payload sits just before the trailing MAC of messages b and c.
+fn max_message_field(message: &mut [u8]) -> &mut [u8] {
+    let end = message.len() - MAC_LENGTH;
+    &mut message[end - MAX_MESSAGE_FIELD_LEN..end]
+}
+
+fn write_max_message_bytes(message: &mut [u8], max_message_bytes: usize) {
+    let value = u32::try_from(max_message_bytes).unwrap_or(u32::MAX);
+    max_message_field(message).copy_from_slice(&value.to_be_bytes());
+}
+
+fn read_max_message_bytes(message: &mut [u8]) -> usize {
+    let mut value = [0u8; MAX_MESSAGE_FIELD_LEN];
+    value.copy_from_slice(max_message_field(message));
+    usize::try_from(u32::from_be_bytes(value)).unwrap_or(usize::MAX)
+}
+
+// Seals the message as one data frame, or as fragments ending in a data frame, with a rekey
+// frame ahead of any fragment once the outgoing direction is due for one. The size check comes
+// first so a rejected message never leaves the peers' keys out of step.
+fn seal_transport_frames(
+    transport: &mut TransportState,
+    plaintext: &[u8],
+) -> Result<Vec<Vec<u8>>, SecureChannelError> {
+    if plaintext.len() > transport.max_message_bytes {
+        return Err(SecureChannelError::TransportMessageTooLarge {
+            size: plaintext.len(),
+            max: transport.max_message_bytes,
+        });
     }
 
-    const fn from_transport_session(session: NoiseSession) -> Result<Self, SecureChannelError> {
-        if !session.is_transport() {
-            return Err(SecureChannelError::HandshakeIncomplete);
+    let fragment_len = max_transport_plaintext();
+    let fragment_count = plaintext.len().div_ceil(fragment_len).max(1);
+    let mut frames = Vec::with_capacity(fragment_count + 1);
+    for index in 0..fragment_count {
+        if transport.rekey_due() {
+            frames.push(seal_transport_message(
+                &mut transport.session,
+                FRAME_KIND_REKEY,
+                &[],
+            )?);
+            transport.session.rekey_outgoing_cipherstate();
+            transport.sent_messages = 0;
+            transport.sent_bytes = 0;
+        }
+
+        let start = index * fragment_len;
+        let end = plaintext.len().min(start + fragment_len);
+        let kind = if end < plaintext.len() {
+            FRAME_KIND_FRAGMENT
+        } else {
+            FRAME_KIND_DATA
+        };
+        let frame = seal_transport_message(&mut transport.session, kind, &plaintext[start..end])?;
+        transport.sent_messages = transport.sent_messages.saturating_add(1);
+        transport.sent_bytes = transport
+            .sent_bytes
+            .saturating_add(u64::try_from(frame.len()).unwrap_or(u64::MAX));
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+async fn write_transport_frames<S>(
+    stream: &mut S,
+    frames: Vec<Vec<u8>>,
+) -> Result<(), SecureChannelError>
+where
+    S: AsyncWrite + Unpin,
+{
+    for frame in frames {
+        write_bytes_frame(stream, &frame).await?;
+    }
+    Ok(())
+}
+
+// Returns `None` for a rekey frame, after switching the incoming cipherstate to the new key,
+// and for a fragment, which is buffered until the final data frame completes the message.
+fn open_transport_frame(
+    transport: &mut TransportState,
+    frame: Vec<u8>,
+) -> Result<Option<Vec<u8>>, SecureChannelError> {
+    let mut plaintext = open_transport_message(&mut transport.session, frame)?;
+    match plaintext.first().copied() {
+        Some(kind @ (FRAME_KIND_DATA | FRAME_KIND_FRAGMENT)) => {
+            let size = transport.reassembly.len() + plaintext.len() - FRAME_KIND_LEN;
+            if size > transport.max_message_bytes {
+                return Err(SecureChannelError::TransportMessageTooLarge {
+                    size,
+                    max: transport.max_message_bytes,
+                });
+            }
+            if kind == FRAME_KIND_DATA && transport.reassembly.is_empty() {
+                plaintext.drain(..FRAME_KIND_LEN);
+                return Ok(Some(plaintext));
+            }
+
+            transport
+                .reassembly
+                .extend_from_slice(&plaintext[FRAME_KIND_LEN..]);
+            if kind == FRAME_KIND_FRAGMENT {
+                return Ok(None);
+            }
+            Ok(Some(mem::take(&mut transport.reassembly)))
+        }
+        Some(FRAME_KIND_REKEY) if plaintext.len() == FRAME_KIND_LEN => {
+            transport.session.rekey_incoming_cipherstate();
+            Ok(None)
         }
+        Some(kind) => Err(SecureChannelError::UnknownFrameKind(kind)),
+        None => Err(SecureChannelError::TransportFrameTooSmall(MAC_LENGTH)),
+    }
+}
+
+fn seal_transport_message(
+    session: &mut NoiseSession,
+    kind: u8,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, SecureChannelError> {
+    let body_len = FRAME_KIND_LEN + plaintext.len();
+    let mut in_out = vec![0u8; body_len + MAC_LENGTH];
+    in_out[0] = kind;
+    in_out[FRAME_KIND_LEN..body_len].copy_from_slice(plaintext);
+    session.send_message(&mut in_out)?;
+    Ok(in_out)
+}
 
-        Ok(Self { session })
+fn open_transport_message(
+    session: &mut NoiseSession,
+    mut in_out: Vec<u8>,
+) -> Result<Vec<u8>, SecureChannelError> {
+    if in_out.len() < MAC_LENGTH {
+        return Err(SecureChannelError::TransportFrameTooSmall(in_out.len()));
     }
+    session.recv_message(&mut in_out)?;
+    in_out.truncate(in_out.len() - MAC_LENGTH);
+    Ok(in_out)
 }
 
 const fn validate_handshake_len(
@@ -195,3 +493,229 @@ const fn validate_handshake_len(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{DuplexStream, duplex, split};
+
+    use super::{RekeyPolicy, SecureChannel, SecureChannelError, max_transport_plaintext};
+    use crate::{protocol::DEFAULT_MAX_MESSAGE_BYTES, security::noise::types::Keypair};
+
+    async fn channel_pair(
+        rekey_policy: RekeyPolicy,
+    ) -> ((SecureChannel, DuplexStream), (SecureChannel, DuplexStream)) {
+        channel_pair_with_limits(
+            rekey_policy,
+            DEFAULT_MAX_MESSAGE_BYTES,
+            DEFAULT_MAX_MESSAGE_BYTES,
+        )
+        .await
+    }
+
+    async fn channel_pair_with_limits(
+        rekey_policy: RekeyPolicy,
+        initiator_max_message_bytes: usize,
+        responder_max_message_bytes: usize,
+    ) -> ((SecureChannel, DuplexStream), (SecureChannel, DuplexStream)) {
+        let (mut initiator_stream, mut responder_stream) = duplex(256 * 1024);
+        let (initiator, responder) = tokio::join!(
+            SecureChannel::handshake_xx_initiator(
+                &mut initiator_stream,
+                Keypair::default_keypair(),
+                initiator_max_message_bytes,
+            ),
+            SecureChannel::handshake_xx_responder(
+                &mut responder_stream,
+                Keypair::default_keypair(),
+                responder_max_message_bytes,
+            ),
+        );
+        (
+            (
+                initiator
+                    .expect("initiator handshake")
+                    .with_rekey_policy(rekey_policy),
+                initiator_stream,
+            ),
+            (
+                responder
+                    .expect("responder handshake")
+                    .with_rekey_policy(rekey_policy),
+                responder_stream,
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn rekeys_in_step_across_many_messages() {
+        let rekey_policy = RekeyPolicy {
+            after_messages: 2,
+            after_bytes: u64::MAX,
+        };
+        let ((mut client, mut client_stream), (mut agent, mut agent_stream)) =
+            channel_pair(rekey_policy).await;
+
+        for round in 0..500_usize {
+            let request = vec![u8::try_from(round % 251).unwrap_or_default(); round % 97];
+            client
+                .send(&mut client_stream, &request)
+                .await
+                .expect("client send");
+            assert_eq!(
+                agent.recv(&mut agent_stream).await.expect("agent recv"),
+                request
+            );
+
+            if round % 3 == 0 {
+                let response = format!("response-{round}").into_bytes();
+                agent
+                    .send(&mut agent_stream, &response)
+                    .await
+                    .expect("agent send");
+                assert_eq!(
+                    client.recv(&mut client_stream).await.expect("client recv"),
+                    response
+                );
+            }
+        }
+        assert!(client.transport.sent_messages <= rekey_policy.after_messages);
+        assert!(agent.transport.sent_messages <= rekey_policy.after_messages);
+    }
+
+    #[tokio::test]
+    async fn split_halves_rekey_on_byte_volume() {
+        let rekey_policy = RekeyPolicy {
+            after_messages: u64::MAX,
+            after_bytes: 4096,
+        };
+        let ((client, client_stream), (agent, agent_stream)) = channel_pair(rekey_policy).await;
+        let (mut client_rx, mut client_tx) = client.into_split();
+        let (mut agent_rx, mut agent_tx) = agent.into_split();
+        let (mut client_reader, mut client_writer) = split(client_stream);
+        let (mut agent_reader, mut agent_writer) = split(agent_stream);
+        let payload = |index: usize| vec![u8::try_from(index % 256).unwrap_or_default(); 1500];
+
+        let client_send = async {
+            for index in 0..300 {
+                client_tx
+                    .send(&mut client_writer, &payload(index))
+                    .await
+                    .expect("client send");
+            }
+        };
+        let agent_send = async {
+            for index in 0..300 {
+                agent_tx
+                    .send(&mut agent_writer, &payload(index))
+                    .await
+                    .expect("agent send");
+            }
+        };
+        let client_recv = async {
+            for index in 0..300 {
+                let received = client_rx
+                    .recv(&mut client_reader)
+                    .await
+                    .expect("client recv");
+                assert_eq!(received, payload(index));
+            }
+        };
+        let agent_recv = async {
+            for index in 0..300 {
+                let received = agent_rx.recv(&mut agent_reader).await.expect("agent recv");
+                assert_eq!(received, payload(index));
+            }
+        };
+        tokio::join!(client_send, agent_send, client_recv, agent_recv);
+    }
+
+    #[tokio::test]
+    async fn oversized_message_does_not_desync_rekeying() {
+        let rekey_policy = RekeyPolicy {
+            after_messages: 1,
+            after_bytes: u64::MAX,
+        };
+        let ((mut client, mut client_stream), (mut agent, mut agent_stream)) =
+            channel_pair(rekey_policy).await;
+
+        client
+            .send(&mut client_stream, b"first")
+            .await
+            .expect("client send");
+        let oversized = vec![0_u8; client.max_message_bytes() + 1];
+        assert!(matches!(
+            client.send(&mut client_stream, &oversized).await,
+            Err(SecureChannelError::TransportMessageTooLarge { .. })
+        ));
+        client
+            .send(&mut client_stream, b"second")
+            .await
+            .expect("client send");
+
+        assert_eq!(agent.recv(&mut agent_stream).await.expect("recv"), b"first");
+        assert_eq!(
+            agent.recv(&mut agent_stream).await.expect("recv"),
+            b"second"
+        );
+    }
+
+    #[tokio::test]
+    async fn fragments_messages_above_frame_limit() {
+        let rekey_policy = RekeyPolicy {
+            after_messages: 3,
+            after_bytes: u64::MAX,
+        };
+        let ((mut client, mut client_stream), (mut agent, mut agent_stream)) =
+            channel_pair(rekey_policy).await;
+        let large = (0..(1 << 20) + 17)
+            .map(|index: usize| u8::try_from(index % 253).unwrap_or_default())
+            .collect::<Vec<_>>();
+        let exact = vec![7_u8; 2 * max_transport_plaintext()];
+
+        let send = async {
+            for message in [&large, &exact, &b"after".to_vec()] {
+                client
+                    .send(&mut client_stream, message)
+                    .await
+                    .expect("client send");
+            }
+        };
+        let recv = async {
+            assert_eq!(agent.recv(&mut agent_stream).await.expect("recv"), large);
+            assert_eq!(agent.recv(&mut agent_stream).await.expect("recv"), exact);
+            assert_eq!(agent.recv(&mut agent_stream).await.expect("recv"), b"after");
+        };
+        tokio::join!(send, recv);
+    }
+
+    #[tokio::test]
+    async fn negotiates_smaller_limit_and_caps_reassembly() {
+        let limit = 128 * 1024;
+        let ((mut client, mut client_stream), (mut agent, mut agent_stream)) =
+            channel_pair_with_limits(RekeyPolicy::default(), DEFAULT_MAX_MESSAGE_BYTES, limit)
+                .await;
+        assert_eq!(client.max_message_bytes(), limit);
+        assert_eq!(agent.max_message_bytes(), limit);
+
+        let oversized = vec![1_u8; limit + 1];
+        assert!(matches!(
+            client.send(&mut client_stream, &oversized).await,
+            Err(SecureChannelError::TransportMessageTooLarge { .. })
+        ));
+
+        // A peer ignoring the negotiated limit is cut off before the buffer grows past it.
+        client.transport.max_message_bytes = DEFAULT_MAX_MESSAGE_BYTES;
+        let send = async {
+            let _ = client.send(&mut client_stream, &oversized).await;
+        };
+        let recv = async {
+            assert!(matches!(
+                agent.recv(&mut agent_stream).await,
+                Err(SecureChannelError::TransportMessageTooLarge { size, max })
+                    if size > limit && max == limit
+            ));
+            assert!(agent.transport.reassembly.len() <= limit);
+        };
+        tokio::join!(send, recv);
+    }
+}
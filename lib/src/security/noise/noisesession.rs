@@ -59,7 +59,25 @@ impl NoiseSession {
 
     /// Calls the [Rekey](https://noiseprotocol.org/noise.html#rekey) method for `cs2`
     pub fn rekey_remote_cipherstate(&mut self) {
-        self.cs1.rekey()
+        self.cs2.rekey()
+    }
+
+    /// Rekeys the cipherstate this party encrypts with: `cs1` for the initiator, `cs2` for the responder.
+    pub fn rekey_outgoing_cipherstate(&mut self) {
+        if self.i {
+            self.cs1.rekey()
+        } else {
+            self.cs2.rekey()
+        }
+    }
+
+    /// Rekeys the cipherstate this party decrypts with: `cs2` for the initiator, `cs1` for the responder.
+    pub fn rekey_incoming_cipherstate(&mut self) {
+        if self.i {
+            self.cs2.rekey()
+        } else {
+            self.cs1.rekey()
+        }
     }
 
     /// `NoiseSession` destructor function.
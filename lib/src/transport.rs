@@ -0,0 +1,363 @@
+use std::{
+    error::Error,
+    fmt, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use rustls::{
+    ClientConfig, DigitallySignedStruct, ServerConfig, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{WebPkiSupportedAlgorithms, ring},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+
+// A connection to or from the relay, with or without TLS.
+#[derive(Debug)]
+pub enum RelayStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl RelayStream {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp().peer_addr()
+    }
+
+    #[must_use]
+    pub const fn is_tls(&self) -> bool {
+        matches!(self, RelayStream::Tls(_))
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            RelayStream::Plain(stream) => stream,
+            RelayStream::Tls(stream) => stream.get_ref().0,
+        }
+    }
+}
+
+impl From<TcpStream> for RelayStream {
+    fn from(stream: TcpStream) -> Self {
+        RelayStream::Plain(stream)
+    }
+}
+
+impl AsyncRead for RelayStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            RelayStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            RelayStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for RelayStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            RelayStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            RelayStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            RelayStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            RelayStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            RelayStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            RelayStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TlsSetupError {
+    Read {
+        path: PathBuf,
+        source: rustls::pki_types::pem::Error,
+    },
+    Tls(rustls::Error),
+    InvalidPin(String),
+}
+
+impl fmt::Display for TlsSetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsSetupError::Read { path, source } => {
+                write!(f, "failed to read '{}': {}", path.display(), source)
+            }
+            TlsSetupError::Tls(source) => write!(f, "invalid TLS configuration: {}", source),
+            TlsSetupError::InvalidPin(message) => {
+                write!(f, "invalid certificate pin: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for TlsSetupError {}
+
+impl From<rustls::Error> for TlsSetupError {
+    fn from(value: rustls::Error) -> Self {
+        Self::Tls(value)
+    }
+}
+
+#[derive(Clone)]
+pub struct RelayTlsAcceptor {
+    acceptor: TlsAcceptor,
+}
+
+impl RelayTlsAcceptor {
+    pub fn from_pem_files(
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self, TlsSetupError> {
+        let cert_path = cert_path.as_ref();
+        let key_path = key_path.as_ref();
+        let cert_chain = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|source| TlsSetupError::Read {
+                path: cert_path.to_path_buf(),
+                source,
+            })?;
+        let key = PrivateKeyDer::from_pem_file(key_path).map_err(|source| TlsSetupError::Read {
+            path: key_path.to_path_buf(),
+            source,
+        })?;
+        Self::new(cert_chain, key)
+    }
+
+    pub fn new(
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self, TlsSetupError> {
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<RelayStream> {
+        let stream = self.acceptor.accept(stream).await?;
+        Ok(RelayStream::Tls(Box::new(TlsStream::Server(stream))))
+    }
+}
+
+// Trusts the relay only if its leaf certificate hashes to one of the pins; CA chains and
+// host names are not consulted, so self-signed relay certificates work.
+#[derive(Clone)]
+pub struct RelayTlsConnector {
+    connector: TlsConnector,
+}
+
+impl RelayTlsConnector {
+    pub fn new(pinned_cert_sha256: &[String]) -> Result<Self, TlsSetupError> {
+        if pinned_cert_sha256.is_empty() {
+            return Err(TlsSetupError::InvalidPin(
+                "at least one pinned certificate sha256 is required".to_string(),
+            ));
+        }
+        let pins = pinned_cert_sha256
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let provider = Arc::new(ring::default_provider());
+        let verifier = PinnedCertVerifier {
+            pins,
+            algorithms: provider.signature_verification_algorithms,
+        };
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    pub async fn connect(&self, stream: TcpStream, endpoint: &str) -> io::Result<RelayStream> {
+        let stream = self
+            .connector
+            .connect(server_name(endpoint)?, stream)
+            .await?;
+        Ok(RelayStream::Tls(Box::new(TlsStream::Client(stream))))
+    }
+}
+
+#[must_use]
+pub fn certificate_sha256(cert: &CertificateDer<'_>) -> String {
+    hex::encode(Sha256::digest(cert.as_ref()))
+}
+
+pub fn validate_pin(pin: &str) -> Result<(), TlsSetupError> {
+    parse_pin(pin).map(|_| ())
+}
+
+fn parse_pin(pin: &str) -> Result<[u8; 32], TlsSetupError> {
+    let mut decoded = [0_u8; 32];
+    hex::decode_to_slice(pin.trim(), &mut decoded).map_err(|err| {
+        TlsSetupError::InvalidPin(format!("'{}' is not a hex sha256 digest: {}", pin, err))
+    })?;
+    Ok(decoded)
+}
+
+fn server_name(endpoint: &str) -> io::Result<ServerName<'static>> {
+    let host = endpoint
+        .rsplit_once(':')
+        .map_or(endpoint, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    ServerName::try_from(host.to_string()).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid relay host '{}': {}", host, err),
+        )
+    })
+}
+
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pins: Vec<[u8; 32]>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let digest: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+        if self.pins.contains(&digest) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "relay certificate sha256 {} is not pinned",
+                hex::encode(digest)
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{RelayTlsAcceptor, RelayTlsConnector, certificate_sha256, validate_pin};
+
+    fn self_signed() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .expect("certificate should generate");
+        let key = PrivatePkcs8KeyDer::from(generated.signing_key.serialize_der());
+        (generated.cert.der().clone(), key.into())
+    }
+
+    async fn echo_over_tls_with(
+        cert: CertificateDer<'static>,
+        key: PrivateKeyDer<'static>,
+        pin: String,
+    ) -> std::io::Result<Vec<u8>> {
+        let acceptor = RelayTlsAcceptor::new(vec![cert], key).expect("acceptor");
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.expect("accept");
+            if let Ok(mut stream) = acceptor.accept(tcp).await {
+                let mut buf = [0_u8; 4];
+                if stream.read_exact(&mut buf).await.is_ok() {
+                    let _ = stream.write_all(&buf).await;
+                    let _ = stream.flush().await;
+                }
+            }
+        });
+
+        let connector = RelayTlsConnector::new(&[pin]).expect("connector");
+        let tcp = TcpStream::connect(addr).await?;
+        let mut stream = connector.connect(tcp, &addr.to_string()).await?;
+        assert!(stream.is_tls());
+        stream.write_all(b"ping").await?;
+        stream.flush().await?;
+        let mut echoed = vec![0_u8; 4];
+        stream.read_exact(&mut echoed).await?;
+        Ok(echoed)
+    }
+
+    #[tokio::test]
+    async fn connects_only_to_pinned_certificate() {
+        let (cert, key) = self_signed();
+        let pin = certificate_sha256(&cert);
+        let echoed = echo_over_tls_with(cert, key, pin)
+            .await
+            .expect("pinned certificate should be accepted");
+        assert_eq!(echoed, b"ping");
+
+        let (cert, key) = self_signed();
+        let other_pin = certificate_sha256(&self_signed().0);
+        let result = echo_over_tls_with(cert, key, other_pin).await;
+        assert!(result.is_err(), "unpinned certificate must be refused");
+    }
+
+    #[test]
+    fn rejects_malformed_pins() {
+        assert!(validate_pin(&hex::encode([1_u8; 32])).is_ok());
+        assert!(validate_pin("abcd").is_err());
+        assert!(validate_pin(&"zz".repeat(32)).is_err());
+        assert!(RelayTlsConnector::new(&[]).is_err());
+    }
+}
@@ -221,7 +221,8 @@ impl HandshakeAuthenticator {
         request: &HandshakeRequest,
     ) -> Result<&IdentityPublicKey, HandshakeAuthError> {
         match request {
-            HandshakeRequest::Agent { agent_id, .. } => {
+            HandshakeRequest::Agent { agent_id, .. }
+            | HandshakeRequest::AgentTunnel { agent_id, .. } => {
                 self.agent_keys.get(agent_id).ok_or_else(|| {
                     HandshakeAuthError::Unauthorized(format!(
                         "agent '{}' is not authorized",
@@ -1,5 +1,6 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
+    io::ErrorKind,
     net::SocketAddr,
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
@@ -7,26 +8,34 @@ use std::{
 
 use crate::{
     error::BoxError,
+    policy::send_assigned_policy,
     responses::{send_accept, send_challenge, send_reject},
-    state::{ServerState, WaitingAgent},
+    state::{AgentControl, ConnectedAgent, PendingTunnel, ServerState},
 };
-use alaric_lib::database::Database;
+use alaric_lib::database::{ClientGrant, Database};
 use alaric_lib::protocol::{
-    AgentId, ClientId, HandshakeErrorCode, HandshakeProofRequest, HandshakeRequest,
-    ListAgentsResponse, PROTOCOL_VERSION, SessionId, read_json_frame, write_json_frame,
+    AgentControlMessage, AgentId, ClientId, CommandId, DiscoveryPageRequest, DiscoveryQuery,
+    HandshakeErrorCode, HandshakeProofRequest, HandshakeRequest, ListAgentsResponse,
+    PROTOCOL_VERSION, ProtocolError, RelayControlMessage, SessionId, decode_discovery_cursor,
+    encode_discovery_cursor, read_json_frame, read_json_message, write_json_message,
 };
+use alaric_lib::transport::RelayStream;
 use serde_json::Value;
 use tokio::{
-    io::{AsyncReadExt, copy_bidirectional},
-    net::TcpStream,
-    sync::{oneshot, watch},
-    time::{Duration, MissedTickBehavior, interval},
+    io::{copy_bidirectional, split},
+    sync::{mpsc, oneshot, watch},
+    time::{Duration, MissedTickBehavior, interval, timeout},
 };
 use tracing::{info, warn};
 
-const PRESENCE_HEARTBEAT_INTERVAL_SECS: u64 = 10;
+const AGENT_CONTROL_QUEUE_CAPACITY: usize = 32;
+// How long a discovery connection waits for the client to ask for the next page.
+const DISCOVERY_PAGE_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
-pub async fn handle_connection(mut stream: TcpStream, state: ServerState) -> Result<(), BoxError> {
+pub async fn handle_connection(
+    mut stream: RelayStream,
+    state: ServerState,
+) -> Result<(), BoxError> {
     let peer = stream.peer_addr()?;
     let request = match read_json_frame::<_, HandshakeRequest>(&mut stream).await {
         Ok(request) => request,
@@ -179,32 +188,56 @@ pub async fn handle_connection(mut stream: TcpStream, state: ServerState) -> Res
         return Ok(());
     }
 
+    let max_message_bytes = request
+        .max_message_bytes()
+        .min(state.config.max_message_bytes);
     match request {
         HandshakeRequest::Agent {
             agent_id, metadata, ..
-        } => handle_agent(stream, state, peer, agent_id, metadata).await,
+        } => handle_agent(stream, state, peer, agent_id, metadata, max_message_bytes).await,
         HandshakeRequest::Client {
             client_id,
             target_agent_id,
             ..
-        } => handle_client(stream, state, peer, client_id, target_agent_id).await,
-        HandshakeRequest::ClientDiscovery { client_id, .. } => {
-            handle_client_discovery(stream, state, peer, client_id).await
+        } => {
+            handle_client(
+                stream,
+                state,
+                peer,
+                client_id,
+                target_agent_id,
+                max_message_bytes,
+            )
+            .await
+        }
+        HandshakeRequest::ClientDiscovery {
+            client_id, query, ..
+        } => {
+            handle_client_discovery(stream, state, peer, client_id, query, max_message_bytes).await
+        }
+        HandshakeRequest::AgentTunnel {
+            agent_id,
+            session_id,
+            ..
+        } => {
+            handle_agent_tunnel(stream, state, peer, agent_id, session_id, max_message_bytes).await
         }
     }
 }
 
 async fn handle_agent(
-    mut stream: TcpStream,
+    mut stream: RelayStream,
     state: ServerState,
     peer: SocketAddr,
     agent_id: AgentId,
     handshake_metadata: BTreeMap<String, String>,
+    max_message_bytes: usize,
 ) -> Result<(), BoxError> {
     let session_id = state.next_session_id();
     let agent_request = HandshakeRequest::agent(agent_id.clone());
     let presence_metadata = build_presence_metadata(&handshake_metadata);
-    let (tx, mut rx) = oneshot::channel::<TcpStream>();
+    let (control_tx, mut control_rx) =
+        mpsc::channel::<RelayControlMessage>(AGENT_CONTROL_QUEUE_CAPACITY);
     let duplicate_agent = {
         let mut registry = state.agents.write().await;
         if registry.contains_key(&agent_id) {
@@ -212,9 +245,10 @@ async fn handle_agent(
         } else {
             registry.insert(
                 agent_id.clone(),
-                WaitingAgent {
+                ConnectedAgent {
                     session_id,
-                    waiter: tx,
+                    control: control_tx.clone(),
+                    policy_subscribed: false,
                 },
             );
             false
@@ -248,8 +282,8 @@ async fn handle_agent(
         return Ok(());
     }
 
-    if let Err(err) = send_accept(&mut stream, session_id).await {
-        state.agents.write().await.remove(&agent_id);
+    if let Err(err) = send_accept(&mut stream, session_id, max_message_bytes).await {
+        unregister_agent(&state, &agent_id, session_id).await;
         return Err(Box::new(err));
     }
     if let Err(store_err) = &state
@@ -259,103 +293,90 @@ async fn handle_agent(
     {
         warn!("failed to persist agent waiting state: {}", store_err);
     }
-    let heartbeat_shutdown =
-        spawn_presence_heartbeat(state.database.clone(), session_id, agent_id.clone());
+    let heartbeat_shutdown = spawn_presence_heartbeat(
+        state.database.clone(),
+        state.config.presence_heartbeat_interval(),
+        session_id,
+        agent_id.clone(),
+    );
     info!(
-        "agent connected: {} (agent_id={}, session_id={}); waiting for client",
+        "agent connected: {} (agent_id={}, session_id={}); ready for client tunnels",
         peer, agent_id, session_id
     );
 
-    let mut probe = [0u8; 1];
-    let mut client_stream = tokio::select! {
-        matched = &mut rx => {
-            match matched {
-                Ok(client_stream) => client_stream,
-                Err(_) => {
-                    state.agents.write().await.remove(&agent_id);
-                    if let Err(store_err) = &state.database
-                            .record_agent_disconnected(
-                                session_id,
-                                &agent_id,
-                                "agent disconnected before client pairing",
-                                true,
-                            )
-                            .await
-                        {
-                            warn!("failed to persist agent disconnect: {}", store_err);
-                        }
-                    info!(
-                        "agent {} from {} disconnected before client pairing",
-                        agent_id, peer
-                    );
-                    stop_presence_heartbeat(&heartbeat_shutdown);
-                    return Ok(());
+    let (mut reader, mut writer) = split(stream);
+    let write_loop = async {
+        while let Some(request) = control_rx.recv().await {
+            match write_json_message(&mut writer, &request, max_message_bytes).await {
+                Ok(()) => {}
+                // Nothing was written, so the connection is still usable.
+                Err(err @ ProtocolError::MessageTooLarge { .. }) => {
+                    warn!("dropped control message for agent {}: {}", agent_id, err);
                 }
+                Err(err) => return Err(format!("failed to send control message: {}", err)),
             }
         }
-        read_result = stream.read(&mut probe) => {
-            match read_result {
-                Ok(0) => {
-                    info!(
-                        "agent {} from {} disconnected before client pairing",
-                        agent_id, peer
-                    );
+        Ok::<(), String>(())
+    };
+    let read_loop = async {
+        loop {
+            let message = match read_json_frame::<_, AgentControlMessage>(&mut reader).await {
+                Ok(message) => message,
+                Err(ProtocolError::Io(err)) if err.kind() == ErrorKind::UnexpectedEof => {
+                    return Ok(());
                 }
-                Ok(_) => {
-                    warn!(
-                        "agent {} from {} sent data before client pairing; closing connection",
-                        agent_id, peer
-                    );
+                Err(err) => return Err(format!("control connection error: {}", err)),
+            };
+            match message {
+                AgentControlMessage::UpdateMetadata { metadata } => {
+                    info!("agent {} updated its metadata", agent_id);
+                    if let Err(store_err) = &state
+                        .database
+                        .record_agent_metadata(
+                            session_id,
+                            &agent_id,
+                            &build_presence_metadata(&metadata),
+                        )
+                        .await
+                    {
+                        warn!("failed to persist agent metadata update: {}", store_err);
+                    }
                 }
-                Err(err) => {
-                    warn!(
-                        "error while waiting for agent {} from {}: {}",
-                        agent_id, peer, err
-                    );
+                AgentControlMessage::FetchPolicy => {
+                    if let Some(agent) = state
+                        .agents
+                        .write()
+                        .await
+                        .get_mut(&agent_id)
+                        .filter(|agent| agent.session_id == session_id)
+                    {
+                        agent.policy_subscribed = true;
+                    }
+                    send_assigned_policy(&state, &agent_id, &control_tx).await;
                 }
             }
-            state.agents.write().await.remove(&agent_id);
-            if let Err(store_err) = &state.database
-                    .record_agent_disconnected(
-                        session_id,
-                        &agent_id,
-                        "agent disconnected before client pairing",
-                        true,
-                    )
-                    .await
-                {
-                    warn!("failed to persist pre-pairing disconnect: {}", store_err);
-                }
-            stop_presence_heartbeat(&heartbeat_shutdown);
-            return Ok(());
         }
     };
+    let control_result = tokio::select! {
+        result = write_loop => result,
+        result = read_loop => result,
+    };
 
-    info!("paired client tunnel with agent {} from {}", agent_id, peer);
-    let tunnel_result = copy_bidirectional(&mut stream, &mut client_stream).await;
-    match &tunnel_result {
-        Ok((agent_to_client, client_to_agent)) => {
-            info!(
-                "tunnel closed for agent {}: {} bytes agent->client, {} bytes client->agent",
-                agent_id, agent_to_client, client_to_agent
+    stop_presence_heartbeat(&heartbeat_shutdown);
+    unregister_agent(&state, &agent_id, session_id).await;
+    let (reason, mark_disconnect_as_error) = match &control_result {
+        Ok(()) => ("agent disconnected".to_string(), false),
+        Err(message) => {
+            warn!(
+                "control connection for agent {} from {} failed: {}",
+                agent_id, peer, message
             );
+            (message.clone(), true)
         }
-        Err(err) => {
-            warn!("tunnel I/O error for agent {}: {}", agent_id, err);
-        }
-    }
-
-    stop_presence_heartbeat(&heartbeat_shutdown);
-    state.agents.write().await.remove(&agent_id);
-    let mark_disconnect_as_error = tunnel_result.is_err();
+    };
     if let Err(store_err) = &state
         .database
-        .record_agent_disconnected(
-            session_id,
-            &agent_id,
-            "agent disconnected",
-            mark_disconnect_as_error,
-        )
+        .record_agent_disconnected(session_id, &agent_id, &reason, mark_disconnect_as_error)
         .await
     {
         warn!("failed to persist agent disconnection: {}", store_err);
@@ -364,19 +385,169 @@ async fn handle_agent(
     Ok(())
 }
 
+async fn unregister_agent(state: &ServerState, agent_id: &AgentId, session_id: SessionId) {
+    let mut registry = state.agents.write().await;
+    if registry
+        .get(agent_id)
+        .is_some_and(|agent| agent.session_id == session_id)
+    {
+        registry.remove(agent_id);
+    }
+}
+
+async fn handle_agent_tunnel(
+    mut stream: RelayStream,
+    state: ServerState,
+    peer: SocketAddr,
+    agent_id: AgentId,
+    session_id: SessionId,
+    max_message_bytes: usize,
+) -> Result<(), BoxError> {
+    let tunnel_request = HandshakeRequest::agent_tunnel(agent_id.clone(), session_id);
+    let pending = {
+        let mut tunnels = state.tunnels.write().await;
+        if tunnels
+            .get(&session_id)
+            .is_some_and(|pending| pending.agent_id == agent_id)
+        {
+            tunnels.remove(&session_id)
+        } else {
+            None
+        }
+    };
+
+    let Some(pending) = pending else {
+        if let Err(store_err) = &state
+            .database
+            .record_session_rejection(
+                SessionId::new_random(),
+                Some(&tunnel_request),
+                HandshakeErrorCode::InvalidRequest,
+                &format!("no pending tunnel for session {}", session_id),
+                peer,
+            )
+            .await
+        {
+            warn!("failed to persist unknown-tunnel rejection: {}", store_err);
+        }
+        send_reject(
+            &mut stream,
+            HandshakeErrorCode::InvalidRequest,
+            format!("no pending tunnel for session {}", session_id),
+        )
+        .await?;
+        warn!(
+            "rejected tunnel from agent {} at {}: no pending session {}",
+            agent_id, peer, session_id
+        );
+        return Ok(());
+    };
+
+    send_accept(&mut stream, session_id, max_message_bytes).await?;
+    if pending.waiter.send(stream).is_err() {
+        warn!(
+            "client for tunnel session {} is no longer waiting (agent_id={})",
+            session_id, agent_id
+        );
+    }
+
+    Ok(())
+}
+
 async fn handle_client(
-    mut stream: TcpStream,
+    mut stream: RelayStream,
     state: ServerState,
     peer: SocketAddr,
     client_id: ClientId,
     target_agent_id: AgentId,
+    max_message_bytes: usize,
 ) -> Result<(), BoxError> {
     let client_request = HandshakeRequest::client(client_id.clone(), target_agent_id.clone());
-    let Some(waiting_agent) = state.agents.write().await.remove(&target_agent_id) else {
+    let allowed_commands = if state.config.enforce_client_grants {
+        match state
+            .database
+            .resolve_client_grant(&client_id, &target_agent_id)
+            .await
+        {
+            Ok(Some(ClientGrant::AllCommands)) => None,
+            Ok(Some(ClientGrant::Commands(command_ids))) => Some(
+                command_ids
+                    .into_iter()
+                    .filter_map(|command_id| match CommandId::new(command_id.as_str()) {
+                        Ok(command_id) => Some(command_id),
+                        // `aadmin grant` validates ids; this only catches rows written by hand.
+                        Err(err) => {
+                            warn!(
+                                "ignoring invalid command id '{}' in grant for client '{}' and agent '{}': {}",
+                                command_id, client_id, target_agent_id, err
+                            );
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Ok(None) => {
+                let message = format!(
+                    "client '{}' has no grant for agent '{}'",
+                    client_id, target_agent_id
+                );
+                reject_client(
+                    &mut stream,
+                    &state,
+                    peer,
+                    &client_request,
+                    HandshakeErrorCode::Forbidden,
+                    &message,
+                )
+                .await?;
+                warn!("rejected client {} from {}: {}", client_id, peer, message);
+                return Ok(());
+            }
+            Err(err) => {
+                warn!("failed to resolve client grant: {}", err);
+                reject_client(
+                    &mut stream,
+                    &state,
+                    peer,
+                    &client_request,
+                    HandshakeErrorCode::InternalError,
+                    "failed to resolve client grant",
+                )
+                .await?;
+                return Ok(());
+            }
+        }
+    } else {
+        None
+    };
+    let control = state
+        .agents
+        .read()
+        .await
+        .get(&target_agent_id)
+        .map(|agent| agent.control.clone());
+
+    let session_id = state.next_session_id();
+    let agent_stream = match control {
+        Some(control) => {
+            open_agent_tunnel(
+                &state,
+                &control,
+                session_id,
+                &client_id,
+                &target_agent_id,
+                allowed_commands,
+            )
+            .await
+        }
+        None => None,
+    };
+
+    let Some(mut agent_stream) = agent_stream else {
         if let Err(store_err) = &state
             .database
             .record_session_rejection(
-                SessionId::new_random(),
+                session_id,
                 Some(&client_request),
                 HandshakeErrorCode::AgentUnavailable,
                 &format!("target agent '{}' is not connected", target_agent_id),
@@ -402,25 +573,12 @@ async fn handle_client(
         return Ok(());
     };
 
-    let WaitingAgent {
-        session_id,
-        waiter: agent_waiter,
-    } = waiting_agent;
-
-    if let Err(err) = send_accept(&mut stream, session_id).await {
-        state.agents.write().await.insert(
-            target_agent_id.clone(),
-            WaitingAgent {
-                session_id,
-                waiter: agent_waiter,
-            },
-        );
-        return Err(Box::new(err));
-    }
+    let agent_peer = agent_stream.peer_addr()?;
+    send_accept(&mut stream, session_id, max_message_bytes).await?;
 
     if let Err(store_err) = &state
         .database
-        .record_client_pairing(session_id, &client_id, &target_agent_id, peer)
+        .record_client_pairing(session_id, &client_id, &target_agent_id, peer, agent_peer)
         .await
     {
         warn!("failed to persist client pairing: {}", store_err);
@@ -430,82 +588,159 @@ async fn handle_client(
         peer, client_id, target_agent_id, session_id
     );
 
-    if let Err(stream) = agent_waiter.send(stream) {
-        drop(stream);
-        warn!(
-            "failed to pair client {} from {} with agent {}: agent no longer waiting",
-            client_id, peer, target_agent_id
-        );
+    let tunnel_result = copy_bidirectional(&mut stream, &mut agent_stream).await;
+    match &tunnel_result {
+        Ok((client_to_agent, agent_to_client)) => {
+            info!(
+                "tunnel closed for agent {} (session_id={}): {} bytes agent->client, {} bytes client->agent",
+                target_agent_id, session_id, agent_to_client, client_to_agent
+            );
+        }
+        Err(err) => {
+            warn!(
+                "tunnel I/O error for agent {} (session_id={}): {}",
+                target_agent_id, session_id, err
+            );
+        }
     }
 
+    if let Err(store_err) = &state.database.record_session_completed(session_id).await {
+        warn!("failed to persist tunnel completion: {}", store_err);
+    }
+    Ok(())
+}
+
+async fn reject_client(
+    stream: &mut RelayStream,
+    state: &ServerState,
+    peer: SocketAddr,
+    client_request: &HandshakeRequest,
+    code: HandshakeErrorCode,
+    message: &str,
+) -> Result<(), BoxError> {
+    if let Err(store_err) = &state
+        .database
+        .record_session_rejection(
+            SessionId::new_random(),
+            Some(client_request),
+            code.clone(),
+            message,
+            peer,
+        )
+        .await
+    {
+        warn!("failed to persist client rejection: {}", store_err);
+    }
+    send_reject(stream, code, message).await?;
     Ok(())
 }
 
+async fn open_agent_tunnel(
+    state: &ServerState,
+    control: &AgentControl,
+    session_id: SessionId,
+    client_id: &ClientId,
+    target_agent_id: &AgentId,
+    allowed_commands: Option<Vec<CommandId>>,
+) -> Option<RelayStream> {
+    let (waiter, tunnel) = oneshot::channel::<RelayStream>();
+    state.tunnels.write().await.insert(
+        session_id,
+        PendingTunnel {
+            agent_id: target_agent_id.clone(),
+            waiter,
+        },
+    );
+
+    let request = RelayControlMessage::OpenTunnel {
+        session_id,
+        client_id: client_id.clone(),
+        allowed_commands,
+    };
+    let agent_stream = if control.send(request).await.is_ok() {
+        match timeout(state.config.tunnel_open_timeout(), tunnel).await {
+            Ok(Ok(agent_stream)) => Some(agent_stream),
+            Ok(Err(_)) => None,
+            Err(_) => {
+                warn!(
+                    "agent {} did not open a tunnel for session {} within {}s",
+                    target_agent_id, session_id, state.config.tunnel_open_timeout_secs
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if agent_stream.is_none() {
+        state.tunnels.write().await.remove(&session_id);
+    }
+    agent_stream
+}
+
 async fn handle_client_discovery(
-    mut stream: TcpStream,
+    mut stream: RelayStream,
     state: ServerState,
     peer: SocketAddr,
     client_id: ClientId,
+    query: DiscoveryQuery,
+    max_message_bytes: usize,
 ) -> Result<(), BoxError> {
-    let discovery_request = HandshakeRequest::client_discovery(client_id.clone());
+    let discovery_request = HandshakeRequest::client_discovery(client_id.clone(), query.clone());
     let database = &state.database;
 
-    let agents = match database.list_discoverable_agents().await {
-        Ok(agents) => agents,
-        Err(err) => {
-            warn!("failed to load discoverable agents: {}", err);
+    let after = match query.cursor.as_deref().map(decode_discovery_cursor) {
+        None => None,
+        Some(Some(after)) => Some(after),
+        Some(None) => {
             if let Err(store_err) = database
                 .record_session_rejection(
                     SessionId::new_random(),
                     Some(&discovery_request),
-                    HandshakeErrorCode::InternalError,
-                    &format!("failed to list agents: {}", err),
+                    HandshakeErrorCode::InvalidRequest,
+                    "invalid discovery cursor",
                     peer,
                 )
                 .await
             {
-                warn!("failed to persist discovery-list rejection: {}", store_err);
+                warn!(
+                    "failed to persist discovery-cursor rejection: {}",
+                    store_err
+                );
             }
             send_reject(
                 &mut stream,
-                HandshakeErrorCode::InternalError,
-                "failed to list agents",
+                HandshakeErrorCode::InvalidRequest,
+                "invalid discovery cursor",
             )
             .await?;
             return Ok(());
         }
     };
 
-    let groups = match database.list_discoverable_agent_groups().await {
-        Ok(groups) => groups,
-        Err(err) => {
-            warn!("failed to load discoverable agent groups: {}", err);
+    let first_page = match discovery_page(database, &query, after.as_ref()).await {
+        Ok(page) => page,
+        Err((message, detail)) => {
             if let Err(store_err) = database
                 .record_session_rejection(
                     SessionId::new_random(),
                     Some(&discovery_request),
                     HandshakeErrorCode::InternalError,
-                    &format!("failed to list agent groups: {}", err),
+                    &detail,
                     peer,
                 )
                 .await
             {
-                warn!("failed to persist discovery-group rejection: {}", store_err);
+                warn!("failed to persist discovery rejection: {}", store_err);
             }
-            send_reject(
-                &mut stream,
-                HandshakeErrorCode::InternalError,
-                "failed to list agent groups",
-            )
-            .await?;
+            send_reject(&mut stream, HandshakeErrorCode::InternalError, message).await?;
             return Ok(());
         }
     };
 
-    let (discovered_agents, discovered_groups) = (agents, groups);
-
     let session_id = state.next_session_id();
-    send_accept(&mut stream, session_id).await?;
+    send_accept(&mut stream, session_id, max_message_bytes).await?;
 
     if let Err(store_err) = state
         .database
@@ -515,30 +750,112 @@ async fn handle_client_discovery(
         warn!("failed to persist client discovery session: {}", store_err);
     }
 
-    let response = ListAgentsResponse::new(
-        current_unix_timestamp()?,
-        discovered_agents,
-        discovered_groups,
-    );
-    write_json_frame(&mut stream, &response).await?;
+    let mut page = first_page;
+    let mut sent_agents = 0;
+    loop {
+        sent_agents += page.agents.len();
+        let next_cursor = page.next_cursor.clone();
+        write_json_message(&mut stream, &page, max_message_bytes).await?;
+        let Some(next_cursor) = next_cursor else {
+            break;
+        };
+
+        // The client asks for the next page on this connection, or hangs up once it has enough.
+        let request = match timeout(
+            DISCOVERY_PAGE_REQUEST_TIMEOUT,
+            read_json_message::<_, DiscoveryPageRequest>(&mut stream, max_message_bytes),
+        )
+        .await
+        {
+            Ok(Ok(request)) => request,
+            Ok(Err(_)) | Err(_) => break,
+        };
+        if request.cursor != next_cursor {
+            warn!(
+                "client {} asked for a discovery page it was not offered (session_id={})",
+                client_id, session_id
+            );
+            break;
+        }
+        let after = decode_discovery_cursor(&next_cursor);
+        page = match discovery_page(database, &query, after.as_ref()).await {
+            Ok(page) => page,
+            Err(_) => break,
+        };
+    }
     info!(
         "client discovery completed: {} (client_id={}, session_id={}, agents={})",
-        peer,
-        client_id,
-        session_id,
-        response.agents.len()
+        peer, client_id, session_id, sent_agents
     );
     Ok(())
 }
 
+// Loads the page after `after`. Errors carry the message for the client and the detail to record.
+async fn discovery_page(
+    database: &Database,
+    query: &DiscoveryQuery,
+    after: Option<&AgentId>,
+) -> Result<ListAgentsResponse, (&'static str, String)> {
+    // One extra row tells whether another page follows.
+    let page_size = query.page_size() as usize;
+    let mut agents = database
+        .list_discoverable_agents(query, after, page_size as i64 + 1)
+        .await
+        .map_err(|err| {
+            warn!("failed to load discoverable agents: {}", err);
+            (
+                "failed to list agents",
+                format!("failed to list agents: {}", err),
+            )
+        })?;
+
+    // Groups are only sent with the first page.
+    let groups = if after.is_some() {
+        Vec::new()
+    } else {
+        database
+            .list_discoverable_agent_groups(query.group.as_ref())
+            .await
+            .map_err(|err| {
+                warn!("failed to load discoverable agent groups: {}", err);
+                (
+                    "failed to list agent groups",
+                    format!("failed to list agent groups: {}", err),
+                )
+            })?
+    };
+
+    let next_cursor = if agents.len() > page_size {
+        agents.truncate(page_size);
+        agents
+            .last()
+            .map(|agent| encode_discovery_cursor(&agent.agent_id))
+    } else {
+        None
+    };
+    let generated_at_unix = current_unix_timestamp().map_err(|err| {
+        (
+            "failed to list agents",
+            format!("failed to read the clock: {}", err),
+        )
+    })?;
+    Ok(ListAgentsResponse::new(
+        generated_at_unix,
+        agents,
+        groups,
+        next_cursor,
+    ))
+}
+
 fn spawn_presence_heartbeat(
     database: Arc<Database>,
+    heartbeat_interval: Duration,
     session_id: SessionId,
     agent_id: AgentId,
 ) -> Option<watch::Sender<bool>> {
     let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
     tokio::spawn(async move {
-        let mut ticker = interval(Duration::from_secs(PRESENCE_HEARTBEAT_INTERVAL_SECS));
+        let mut ticker = interval(heartbeat_interval);
         ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
         loop {
@@ -1,6 +1,7 @@
 pub mod auth;
 pub mod connection;
 mod error;
+pub mod policy;
 mod responses;
 pub mod state;
 
@@ -1,26 +1,48 @@
 use crate::signal::shutdown_signal;
-use alaric_lib::constants::DEFAULT_SERVER_PORT;
+use alaric_lib::config::AlaricConfig;
+use alaric_lib::constants::POLICY_NOTIFY_CHANNEL;
 use alaric_lib::database::Database;
-use alaric_server::{HandshakeAuthenticator, connection::handle_connection, state::ServerState};
+use alaric_lib::transport::{RelayStream, RelayTlsAcceptor};
+use alaric_server::{
+    HandshakeAuthenticator, connection::handle_connection, policy::push_policy_updates,
+    state::ServerState,
+};
 use std::error::Error;
 use std::sync::Arc;
-use tokio::{net::TcpListener, time::Duration};
+use tokio::{
+    net::TcpListener,
+    time::{Duration, timeout},
+};
 use tracing::{error, info, warn};
 
 mod signal;
 
+const TLS_ACCEPT_TIMEOUT_SECS: u64 = 10;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     tracing_subscriber::fmt::init();
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", DEFAULT_SERVER_PORT)).await?;
+    let config = AlaricConfig::load()?;
+    let listener = TcpListener::bind(config.server.listen_addr).await?;
+    let tls = match &config.server.tls {
+        Some(tls) => Some(RelayTlsAcceptor::from_pem_files(
+            &tls.cert_path,
+            &tls.key_path,
+        )?),
+        None => None,
+    };
     let database = Arc::new(Database::from_env().await?);
     let authenticator = HandshakeAuthenticator::from_database(&database).await?;
     let local_addr = listener.local_addr()?;
-    let state = ServerState::new(authenticator, database);
+    let state = ServerState::new(authenticator, database).with_config(config.server);
     let auth_refresh_task = tokio::spawn(refresh_listen(state.clone(), state.database.clone()));
     let shutdown = shutdown_signal();
     tokio::pin!(shutdown);
-    info!("server listening on {}", local_addr);
+    info!(
+        "server listening on {} ({})",
+        local_addr,
+        if tls.is_some() { "tls" } else { "plaintext" }
+    );
     loop {
         tokio::select! {
             _ = &mut shutdown => {
@@ -30,9 +52,30 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             }
             accept_result = listener.accept() => {
                 match accept_result {
-                    Ok((stream, _)) => {
+                    Ok((stream, peer)) => {
                         let state = state.clone();
+                        let tls = tls.clone();
                         tokio::spawn(async move {
+                            let stream = match tls {
+                                Some(tls) => {
+                                    let accept = timeout(
+                                        Duration::from_secs(TLS_ACCEPT_TIMEOUT_SECS),
+                                        tls.accept(stream),
+                                    );
+                                    match accept.await {
+                                        Ok(Ok(stream)) => stream,
+                                        Ok(Err(err)) => {
+                                            warn!("TLS handshake with {} failed: {}", peer, err);
+                                            return;
+                                        }
+                                        Err(_) => {
+                                            warn!("TLS handshake with {} timed out", peer);
+                                            return;
+                                        }
+                                    }
+                                }
+                                None => RelayStream::from(stream),
+                            };
                             if let Err(err) = handle_connection(stream, state).await {
                                 error!("connection handling failed: {}", err);
                             }
@@ -77,6 +120,11 @@ async fn refresh_listen(state: ServerState, database: Arc<Database>) {
                 }
             };
 
+            if notification.channel == POLICY_NOTIFY_CHANNEL {
+                push_policy_updates(&state).await;
+                continue;
+            }
+
             match HandshakeAuthenticator::from_database(&database).await {
                 Ok(authenticator) => {
                     state.replace_authenticator(authenticator).await;
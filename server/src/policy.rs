@@ -0,0 +1,65 @@
+use alaric_lib::protocol::{AgentId, RelayControlMessage};
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{info, warn};
+
+use crate::state::{AgentControl, ServerState};
+
+// Answers an agent's fetch; an absent assignment is reported so the agent can log it.
+pub(crate) async fn send_assigned_policy(
+    state: &ServerState,
+    agent_id: &AgentId,
+    control: &AgentControl,
+) {
+    let bundle = match state.database.resolve_agent_policy_bundle(agent_id).await {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            warn!(
+                "failed to resolve policy bundle for agent {}: {}",
+                agent_id, err
+            );
+            return;
+        }
+    };
+    if bundle.is_none() {
+        info!("no policy bundle assigned to agent {}", agent_id);
+    }
+    let _ = control
+        .send(RelayControlMessage::PolicyBundle { bundle })
+        .await;
+}
+
+// Re-sends the assigned bundle to every subscribed agent after a policy notification.
+pub async fn push_policy_updates(state: &ServerState) {
+    let subscribers = state
+        .agents
+        .read()
+        .await
+        .iter()
+        .filter(|(_, agent)| agent.policy_subscribed)
+        .map(|(agent_id, agent)| (agent_id.clone(), agent.control.clone()))
+        .collect::<Vec<_>>();
+
+    for (agent_id, control) in subscribers {
+        let bundle = match state.database.resolve_agent_policy_bundle(&agent_id).await {
+            Ok(Some(bundle)) => bundle,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!(
+                    "failed to resolve policy bundle for agent {}: {}",
+                    agent_id, err
+                );
+                continue;
+            }
+        };
+        match control.try_send(RelayControlMessage::PolicyBundle {
+            bundle: Some(bundle),
+        }) {
+            Ok(()) => info!("pushed policy bundle to agent {}", agent_id),
+            Err(TrySendError::Full(_)) => warn!(
+                "control queue for agent {} is full; policy bundle not pushed",
+                agent_id
+            ),
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
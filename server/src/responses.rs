@@ -2,10 +2,10 @@ use alaric_lib::protocol::{
     HandshakeAccepted, HandshakeChallenge, HandshakeErrorCode, HandshakeRejected,
     HandshakeResponse, PROTOCOL_VERSION, ProtocolError, SessionId, write_json_frame,
 };
-use tokio::net::TcpStream;
+use alaric_lib::transport::RelayStream;
 
 pub(crate) async fn send_challenge(
-    stream: &mut TcpStream,
+    stream: &mut RelayStream,
     challenge: HandshakeChallenge,
 ) -> Result<(), ProtocolError> {
     let response = HandshakeResponse::Challenge(challenge);
@@ -13,18 +13,20 @@ pub(crate) async fn send_challenge(
 }
 
 pub(crate) async fn send_accept(
-    stream: &mut TcpStream,
+    stream: &mut RelayStream,
     session_id: SessionId,
+    max_message_bytes: usize,
 ) -> Result<(), ProtocolError> {
     let response = HandshakeResponse::Accepted(HandshakeAccepted {
         protocol_version: PROTOCOL_VERSION,
         session_id,
+        max_message_bytes,
     });
     write_json_frame(stream, &response).await
 }
 
 pub(crate) async fn send_reject(
-    stream: &mut TcpStream,
+    stream: &mut RelayStream,
     code: HandshakeErrorCode,
     message: impl Into<String>,
 ) -> Result<(), ProtocolError> {
@@ -1,41 +1,60 @@
 use std::{collections::HashMap, sync::Arc};
 
 use alaric_lib::{
+    config::ServerConfig,
     database::Database,
-    protocol::{AgentId, SessionId},
-};
-use tokio::{
-    net::TcpStream,
-    sync::{RwLock, oneshot},
+    protocol::{AgentId, RelayControlMessage, SessionId},
+    transport::RelayStream,
 };
+use tokio::sync::{RwLock, mpsc, oneshot};
 
 use crate::auth::HandshakeAuthenticator;
 
-pub(crate) type AgentWaiter = oneshot::Sender<TcpStream>;
+pub(crate) type AgentControl = mpsc::Sender<RelayControlMessage>;
 
-pub(crate) struct WaitingAgent {
+pub(crate) struct ConnectedAgent {
     pub(crate) session_id: SessionId,
-    pub(crate) waiter: AgentWaiter,
+    pub(crate) control: AgentControl,
+    pub(crate) policy_subscribed: bool,
+}
+
+pub(crate) type AgentRegistry = Arc<RwLock<HashMap<AgentId, ConnectedAgent>>>;
+
+pub(crate) type TunnelWaiter = oneshot::Sender<RelayStream>;
+
+pub(crate) struct PendingTunnel {
+    pub(crate) agent_id: AgentId,
+    pub(crate) waiter: TunnelWaiter,
 }
 
-pub(crate) type AgentRegistry = Arc<RwLock<HashMap<AgentId, WaitingAgent>>>;
+pub(crate) type TunnelRegistry = Arc<RwLock<HashMap<SessionId, PendingTunnel>>>;
 
 #[derive(Clone)]
 pub struct ServerState {
     pub(crate) agents: AgentRegistry,
+    pub(crate) tunnels: TunnelRegistry,
     authenticator: Arc<RwLock<Arc<HandshakeAuthenticator>>>,
     pub database: Arc<Database>,
+    pub(crate) config: Arc<ServerConfig>,
 }
 
 impl ServerState {
     pub fn new(authenticator: HandshakeAuthenticator, database: Arc<Database>) -> Self {
         Self {
             agents: Arc::new(RwLock::new(HashMap::new())),
+            tunnels: Arc::new(RwLock::new(HashMap::new())),
             authenticator: Arc::new(RwLock::new(Arc::new(authenticator))),
             database,
+            config: Arc::new(ServerConfig::default()),
         }
     }
 
+    #[must_use]
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
     #[must_use]
     pub fn next_session_id(&self) -> SessionId {
         SessionId::new_random()
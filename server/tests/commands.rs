@@ -7,25 +7,32 @@ use std::{
 };
 
 use alaric_agent::{
-    policy::{ArgSpec, CommandSpec, Policy, ValidationRule},
-    session::run_secure_session,
+    policy::{
+        ArgSpec, CommandSpec, FetchRule, Policy, PutRule, ResourceLimits, Sandbox, StdinMode,
+        ValidationRule,
+    },
+    reload::SharedPolicy,
+    session::{TunnelContext, run_secure_session},
 };
 use alaric_lib::{
+    config::SessionConfig,
     database::Database,
     protocol::{
-        AgentId, AgentMessage, ClientId, ClientMessage, CommandId, HandshakeProofRequest,
-        HandshakeRequest, HandshakeResponse, IdentityBundle, IdentityPrincipal, OutputStream,
-        PeerAttestationInit, PeerAttestationPolicy, PeerAttestationResult, RejectionCode,
+        AgentId, AgentMessage, CancelSignal, ClientId, ClientMessage, CommandId,
+        DEFAULT_MAX_MESSAGE_BYTES, HandshakeProofRequest, HandshakeRequest, HandshakeResponse,
+        IdentityBundle, IdentityPrincipal, OutputStream, PeerAttestationInit,
+        PeerAttestationPolicy, PeerAttestationResult, RejectionCode, RelayControlMessage,
         RequestId, Role, SecureChannel, SessionId, TrustedIdentityKeys, build_auth_proof_ed25519,
-        build_peer_attestation_proof, decode_ed25519_public_key, read_json_frame, recv_secure_json,
-        send_secure_json, sign_identity_bundle_ed25519, verify_peer_attestation_proof,
-        write_json_frame,
+        build_peer_attestation_proof, decode_ed25519_public_key, noise_static_keypair_from_hex,
+        read_json_frame, recv_secure_json, send_secure_json, sign_identity_bundle_ed25519,
+        verify_peer_attestation_proof, write_json_frame,
     },
     security::noise::types::Keypair,
 };
 use alaric_server::connection::handle_connection;
 use alaric_server::state::ServerState;
 use alaric_server::{HandshakeAuthenticator, IdentityPublicKey};
+use sha2::{Digest, Sha256};
 use tokio::{
     net::{TcpListener, TcpStream},
     task::JoinHandle,
@@ -42,6 +49,8 @@ const CLIENT_PRIVATE_KEY_HEX: &str =
     "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb";
 const CLIENT_PUBLIC_KEY_HEX: &str =
     "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c";
+const CLIENT_NOISE_PRIVATE_KEY_HEX: &str =
+    "58ab4b2b5d6a9b7d2f1e0c3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c";
 const IDENTITY_SIGNING_KEY_ID: &str = "control-plane-v1";
 
 fn test_authenticator(
@@ -90,7 +99,7 @@ async fn spawn_server(
             };
             let state = state.clone();
             tokio::spawn(async move {
-                let _ = handle_connection(stream, state).await;
+                let _ = handle_connection(stream.into(), state).await;
             });
         }
     });
@@ -100,7 +109,7 @@ async fn spawn_server(
 async fn connect_agent(
     addr: std::net::SocketAddr,
     agent_id: &str,
-) -> Result<(TcpStream, SessionId), Box<dyn Error>> {
+) -> Result<TcpStream, Box<dyn Error>> {
     let mut agent = TcpStream::connect(addr).await?;
     let request = HandshakeRequest::agent(AgentId::new(agent_id)?);
     write_json_frame(&mut agent, &request).await?;
@@ -120,10 +129,47 @@ async fn connect_agent(
         read_json_frame::<_, HandshakeResponse>(&mut agent),
     )
     .await??;
-    let HandshakeResponse::Accepted(accepted) = final_response else {
+    let HandshakeResponse::Accepted(_) = final_response else {
         panic!("expected accepted response");
     };
-    Ok((agent, accepted.session_id))
+    Ok(agent)
+}
+
+async fn accept_tunnel(
+    addr: SocketAddr,
+    control: &mut TcpStream,
+    agent_id: &AgentId,
+) -> Result<(TcpStream, SessionId, ClientId), Box<dyn Error + Send + Sync>> {
+    let RelayControlMessage::OpenTunnel {
+        session_id,
+        client_id,
+        ..
+    } = timeout(
+        Duration::from_secs(2),
+        read_json_frame::<_, RelayControlMessage>(control),
+    )
+    .await??
+    else {
+        return Err("expected tunnel request".into());
+    };
+
+    let mut tunnel = TcpStream::connect(addr).await?;
+    let request = HandshakeRequest::agent_tunnel(agent_id.clone(), session_id);
+    write_json_frame(&mut tunnel, &request).await?;
+    let HandshakeResponse::Challenge(challenge) =
+        read_json_frame::<_, HandshakeResponse>(&mut tunnel).await?
+    else {
+        return Err("expected tunnel handshake challenge".into());
+    };
+    let proof =
+        build_auth_proof_ed25519(&request, &challenge, AGENT_KEY_ID, AGENT_PRIVATE_KEY_HEX)?;
+    write_json_frame(&mut tunnel, &HandshakeProofRequest::new(proof)).await?;
+    let HandshakeResponse::Accepted(accepted) =
+        read_json_frame::<_, HandshakeResponse>(&mut tunnel).await?
+    else {
+        return Err("expected accepted tunnel response".into());
+    };
+    Ok((tunnel, accepted.session_id, client_id))
 }
 
 async fn connect_client_secure(
@@ -161,7 +207,11 @@ async fn connect_client_secure(
 
     let mut secure = timeout(
         Duration::from_secs(2),
-        SecureChannel::handshake_xx_initiator(&mut client, Keypair::default_keypair()),
+        SecureChannel::handshake_xx_initiator(
+            &mut client,
+            Keypair::default_keypair(),
+            DEFAULT_MAX_MESSAGE_BYTES,
+        ),
     )
     .await??;
 
@@ -213,6 +263,14 @@ async fn connect_client_secure(
 }
 
 fn test_identity_bundle(agent_id: &str, client_id: &str) -> Result<IdentityBundle, Box<dyn Error>> {
+    test_identity_bundle_with_noise_key(agent_id, client_id, None)
+}
+
+fn test_identity_bundle_with_noise_key(
+    agent_id: &str,
+    client_id: &str,
+    client_noise_public_key: Option<String>,
+) -> Result<IdentityBundle, Box<dyn Error>> {
     let now_unix = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
     let signed_bundle = sign_identity_bundle_ed25519(
         now_unix + 300,
@@ -221,6 +279,7 @@ fn test_identity_bundle(agent_id: &str, client_id: &str) -> Result<IdentityBundl
             IdentityPrincipal {
                 key_id: AGENT_KEY_ID.to_string(),
                 public_key: AGENT_PUBLIC_KEY_HEX.to_string(),
+                noise_public_key: None,
             },
         )]),
         BTreeMap::from([(
@@ -228,6 +287,7 @@ fn test_identity_bundle(agent_id: &str, client_id: &str) -> Result<IdentityBundl
             IdentityPrincipal {
                 key_id: CLIENT_KEY_ID.to_string(),
                 public_key: CLIENT_PUBLIC_KEY_HEX.to_string(),
+                noise_public_key: client_noise_public_key,
             },
         )]),
         IDENTITY_SIGNING_KEY_ID,
@@ -260,7 +320,11 @@ async fn receive_until_terminal(
         .await??;
         let terminal = matches!(
             message,
-            AgentMessage::Completed { .. } | AgentMessage::Rejected { .. }
+            AgentMessage::Completed { .. }
+                | AgentMessage::FetchCompleted { .. }
+                | AgentMessage::PutCompleted { .. }
+                | AgentMessage::Cancelled { .. }
+                | AgentMessage::Rejected { .. }
         );
         messages.push(message);
         if terminal {
@@ -274,6 +338,9 @@ fn base_policy() -> Policy {
         version: 1,
         default_timeout_secs: 2,
         max_output_bytes: 4096,
+        max_input_bytes: None,
+        fetch: Vec::new(),
+        put: Vec::new(),
         commands: vec![
             CommandSpec {
                 id: "echo".to_string(),
@@ -288,6 +355,17 @@ fn base_policy() -> Policy {
                 }],
                 timeout_secs: None,
                 max_output_bytes: None,
+                stdin: StdinMode::None,
+                max_input_bytes: None,
+                run_as_user: None,
+                run_as_group: None,
+                supplementary_groups: Vec::new(),
+                limits: ResourceLimits::default(),
+                sandbox: Sandbox::default(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                env_passthrough: Vec::new(),
+                env_specs: Vec::new(),
             },
             CommandSpec {
                 id: "sleep".to_string(),
@@ -302,6 +380,36 @@ fn base_policy() -> Policy {
                 }],
                 timeout_secs: Some(1),
                 max_output_bytes: None,
+                stdin: StdinMode::None,
+                max_input_bytes: None,
+                run_as_user: None,
+                run_as_group: None,
+                supplementary_groups: Vec::new(),
+                limits: ResourceLimits::default(),
+                sandbox: Sandbox::default(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                env_passthrough: Vec::new(),
+                env_specs: Vec::new(),
+            },
+            CommandSpec {
+                id: "linger".to_string(),
+                program: "/bin/sleep".to_string(),
+                fixed_args: vec!["30".to_string()],
+                arg_specs: Vec::new(),
+                timeout_secs: Some(30),
+                max_output_bytes: None,
+                stdin: StdinMode::None,
+                max_input_bytes: None,
+                run_as_user: None,
+                run_as_group: None,
+                supplementary_groups: Vec::new(),
+                limits: ResourceLimits::default(),
+                sandbox: Sandbox::default(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                env_passthrough: Vec::new(),
+                env_specs: Vec::new(),
             },
             CommandSpec {
                 id: "flood".to_string(),
@@ -310,6 +418,55 @@ fn base_policy() -> Policy {
                 arg_specs: Vec::new(),
                 timeout_secs: None,
                 max_output_bytes: Some(64),
+                stdin: StdinMode::None,
+                max_input_bytes: None,
+                run_as_user: None,
+                run_as_group: None,
+                supplementary_groups: Vec::new(),
+                limits: ResourceLimits::default(),
+                sandbox: Sandbox::default(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                env_passthrough: Vec::new(),
+                env_specs: Vec::new(),
+            },
+            CommandSpec {
+                id: "binary".to_string(),
+                program: "/usr/bin/printf".to_string(),
+                fixed_args: vec!["\\377\\000\\201".to_string()],
+                arg_specs: Vec::new(),
+                timeout_secs: None,
+                max_output_bytes: None,
+                stdin: StdinMode::None,
+                max_input_bytes: None,
+                run_as_user: None,
+                run_as_group: None,
+                supplementary_groups: Vec::new(),
+                limits: ResourceLimits::default(),
+                sandbox: Sandbox::default(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                env_passthrough: Vec::new(),
+                env_specs: Vec::new(),
+            },
+            CommandSpec {
+                id: "cat".to_string(),
+                program: "/bin/cat".to_string(),
+                fixed_args: Vec::new(),
+                arg_specs: Vec::new(),
+                timeout_secs: None,
+                max_output_bytes: None,
+                stdin: StdinMode::Stream,
+                max_input_bytes: Some(16),
+                run_as_user: None,
+                run_as_group: None,
+                supplementary_groups: Vec::new(),
+                limits: ResourceLimits::default(),
+                sandbox: Sandbox::default(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                env_passthrough: Vec::new(),
+                env_specs: Vec::new(),
             },
         ],
     };
@@ -326,23 +483,32 @@ async fn allows_command_and_streams_output() -> Result<(), Box<dyn Error>> {
     let identity_bundle = test_identity_bundle("agent-cmd-ok", "client-cmd-ok")?;
     let (addr, server_task) =
         spawn_server(test_authenticator(&["agent-cmd-ok"], &["client-cmd-ok"])?).await?;
-    let (mut agent_stream, agent_session_id) = connect_agent(addr, "agent-cmd-ok").await?;
+    let mut agent_stream = connect_agent(addr, "agent-cmd-ok").await?;
     let agent_id = AgentId::new("agent-cmd-ok")?;
     let policy = base_policy();
     let attestation_policy = default_attestation_policy();
     let agent_identity_bundle = identity_bundle.clone();
 
     let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
         run_secure_session(
-            &mut agent_stream,
-            &policy,
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
             Keypair::default_keypair(),
-            agent_session_id,
+            &SessionConfig::default(),
             &agent_id,
             AGENT_KEY_ID,
             AGENT_PRIVATE_KEY_HEX,
-            &attestation_policy,
-            Some(&agent_identity_bundle),
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
         )
         .await
         .expect("agent secure session should succeed");
@@ -359,6 +525,7 @@ async fn allows_command_and_streams_output() -> Result<(), Box<dyn Error>> {
             request_id: RequestId(1),
             command_id: CommandId::new("echo").expect("valid command id"),
             args,
+            env: BTreeMap::new(),
         },
     )
     .await?;
@@ -376,7 +543,7 @@ async fn allows_command_and_streams_output() -> Result<(), Box<dyn Error>> {
             request_id: RequestId(1),
             stream: OutputStream::Stdout,
             chunk
-        } if chunk.contains("hello")
+        } if chunk.starts_with(b"hello")
     )));
     assert!(matches!(
         messages.last(),
@@ -384,10 +551,13 @@ async fn allows_command_and_streams_output() -> Result<(), Box<dyn Error>> {
             request_id: RequestId(1),
             exit_code: 0,
             timed_out: false,
-            truncated: false
+            truncated: false,
+            limit_exceeded: None,
+            input_exceeded: false,
         })
     ));
 
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
     timeout(Duration::from_secs(2), agent_task).await??;
     server_task.abort();
     let _ = server_task.await;
@@ -399,22 +569,31 @@ async fn rejects_unknown_command() -> Result<(), Box<dyn Error>> {
     let identity_bundle = test_identity_bundle("agent-unknown", "client-unknown")?;
     let (addr, server_task) =
         spawn_server(test_authenticator(&["agent-unknown"], &["client-unknown"])?).await?;
-    let (mut agent_stream, agent_session_id) = connect_agent(addr, "agent-unknown").await?;
+    let mut agent_stream = connect_agent(addr, "agent-unknown").await?;
     let agent_id = AgentId::new("agent-unknown")?;
     let policy = base_policy();
     let attestation_policy = default_attestation_policy();
     let agent_identity_bundle = identity_bundle.clone();
     let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
         run_secure_session(
-            &mut agent_stream,
-            &policy,
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
             Keypair::default_keypair(),
-            agent_session_id,
+            &SessionConfig::default(),
             &agent_id,
             AGENT_KEY_ID,
             AGENT_PRIVATE_KEY_HEX,
-            &attestation_policy,
-            Some(&agent_identity_bundle),
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
         )
         .await
         .expect("agent secure session should succeed");
@@ -429,6 +608,7 @@ async fn rejects_unknown_command() -> Result<(), Box<dyn Error>> {
             request_id: RequestId(2),
             command_id: CommandId::new("does_not_exist").expect("valid command id"),
             args: BTreeMap::new(),
+            env: BTreeMap::new(),
         },
     )
     .await?;
@@ -443,6 +623,7 @@ async fn rejects_unknown_command() -> Result<(), Box<dyn Error>> {
         })
     ));
 
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
     timeout(Duration::from_secs(2), agent_task).await??;
     server_task.abort();
     let _ = server_task.await;
@@ -457,22 +638,31 @@ async fn rejects_invalid_argument_value() -> Result<(), Box<dyn Error>> {
         &["client-invalid-arg"],
     )?)
     .await?;
-    let (mut agent_stream, agent_session_id) = connect_agent(addr, "agent-invalid-arg").await?;
+    let mut agent_stream = connect_agent(addr, "agent-invalid-arg").await?;
     let agent_id = AgentId::new("agent-invalid-arg")?;
     let policy = base_policy();
     let attestation_policy = default_attestation_policy();
     let agent_identity_bundle = identity_bundle.clone();
     let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
         run_secure_session(
-            &mut agent_stream,
-            &policy,
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
             Keypair::default_keypair(),
-            agent_session_id,
+            &SessionConfig::default(),
             &agent_id,
             AGENT_KEY_ID,
             AGENT_PRIVATE_KEY_HEX,
-            &attestation_policy,
-            Some(&agent_identity_bundle),
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
         )
         .await
         .expect("agent secure session should succeed");
@@ -494,6 +684,7 @@ async fn rejects_invalid_argument_value() -> Result<(), Box<dyn Error>> {
             request_id: RequestId(3),
             command_id: CommandId::new("echo").expect("valid command id"),
             args,
+            env: BTreeMap::new(),
         },
     )
     .await?;
@@ -508,6 +699,7 @@ async fn rejects_invalid_argument_value() -> Result<(), Box<dyn Error>> {
         })
     ));
 
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
     timeout(Duration::from_secs(2), agent_task).await??;
     server_task.abort();
     let _ = server_task.await;
@@ -519,22 +711,31 @@ async fn times_out_long_running_command() -> Result<(), Box<dyn Error>> {
     let identity_bundle = test_identity_bundle("agent-timeout", "client-timeout")?;
     let (addr, server_task) =
         spawn_server(test_authenticator(&["agent-timeout"], &["client-timeout"])?).await?;
-    let (mut agent_stream, agent_session_id) = connect_agent(addr, "agent-timeout").await?;
+    let mut agent_stream = connect_agent(addr, "agent-timeout").await?;
     let agent_id = AgentId::new("agent-timeout")?;
     let policy = base_policy();
     let attestation_policy = default_attestation_policy();
     let agent_identity_bundle = identity_bundle.clone();
     let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
         run_secure_session(
-            &mut agent_stream,
-            &policy,
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
             Keypair::default_keypair(),
-            agent_session_id,
+            &SessionConfig::default(),
             &agent_id,
             AGENT_KEY_ID,
             AGENT_PRIVATE_KEY_HEX,
-            &attestation_policy,
-            Some(&agent_identity_bundle),
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
         )
         .await
         .expect("agent secure session should succeed");
@@ -551,6 +752,7 @@ async fn times_out_long_running_command() -> Result<(), Box<dyn Error>> {
             request_id: RequestId(4),
             command_id: CommandId::new("sleep").expect("valid command id"),
             args,
+            env: BTreeMap::new(),
         },
     )
     .await?;
@@ -571,6 +773,577 @@ async fn times_out_long_running_command() -> Result<(), Box<dyn Error>> {
         })
     ));
 
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancels_running_command() -> Result<(), Box<dyn Error>> {
+    let identity_bundle = test_identity_bundle("agent-cancel", "client-cancel")?;
+    let (addr, server_task) =
+        spawn_server(test_authenticator(&["agent-cancel"], &["client-cancel"])?).await?;
+    let mut agent_stream = connect_agent(addr, "agent-cancel").await?;
+    let agent_id = AgentId::new("agent-cancel")?;
+    let policy = base_policy();
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
+        run_secure_session(
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
+            Keypair::default_keypair(),
+            &SessionConfig::default(),
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
+        )
+        .await
+        .expect("agent secure session should succeed");
+    });
+
+    let (mut client_stream, mut secure) =
+        connect_client_secure(addr, "client-cancel", "agent-cancel", &identity_bundle).await?;
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Execute {
+            request_id: RequestId(6),
+            command_id: CommandId::new("linger").expect("valid command id"),
+            args: BTreeMap::new(),
+            env: BTreeMap::new(),
+        },
+    )
+    .await?;
+
+    let started = timeout(
+        Duration::from_secs(3),
+        recv_secure_json::<_, AgentMessage>(&mut secure, &mut client_stream),
+    )
+    .await??;
+    assert!(matches!(
+        started,
+        AgentMessage::Started {
+            request_id: RequestId(6)
+        }
+    ));
+
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Cancel {
+            request_id: RequestId(6),
+            signal: CancelSignal::Terminate,
+        },
+    )
+    .await?;
+
+    let messages = receive_until_terminal(&mut secure, &mut client_stream).await?;
+    assert!(matches!(
+        messages.last(),
+        Some(AgentMessage::Cancelled {
+            request_id: RequestId(6),
+            signal: CancelSignal::Terminate,
+            forced: false,
+            ..
+        })
+    ));
+
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn streams_stdin_within_input_limit() -> Result<(), Box<dyn Error>> {
+    let identity_bundle = test_identity_bundle("agent-stdin", "client-stdin")?;
+    let (addr, server_task) =
+        spawn_server(test_authenticator(&["agent-stdin"], &["client-stdin"])?).await?;
+    let mut agent_stream = connect_agent(addr, "agent-stdin").await?;
+    let agent_id = AgentId::new("agent-stdin")?;
+    let policy = base_policy();
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
+        run_secure_session(
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
+            Keypair::default_keypair(),
+            &SessionConfig::default(),
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
+        )
+        .await
+        .expect("agent secure session should succeed");
+    });
+
+    let (mut client_stream, mut secure) =
+        connect_client_secure(addr, "client-stdin", "agent-stdin", &identity_bundle).await?;
+    for (request_id, data) in [(20, "hello\n"), (21, "this is well over sixteen bytes")] {
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &ClientMessage::Execute {
+                request_id: RequestId(request_id),
+                command_id: CommandId::new("cat").expect("valid command id"),
+                args: BTreeMap::new(),
+                env: BTreeMap::new(),
+            },
+        )
+        .await?;
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &ClientMessage::Stdin {
+                request_id: RequestId(request_id),
+                data: data.as_bytes().to_vec(),
+                eof: true,
+            },
+        )
+        .await?;
+    }
+
+    let mut echoed = Vec::new();
+    let mut terminal = Vec::new();
+    let mut oversized = Vec::new();
+    while terminal.len() < 2 {
+        let message = timeout(
+            Duration::from_secs(3),
+            recv_secure_json::<_, AgentMessage>(&mut secure, &mut client_stream),
+        )
+        .await??;
+        if matches!(
+            message,
+            AgentMessage::Started {
+                request_id: RequestId(21)
+            } | AgentMessage::Output {
+                request_id: RequestId(21),
+                ..
+            } | AgentMessage::Completed {
+                request_id: RequestId(21),
+                ..
+            } | AgentMessage::Rejected {
+                request_id: RequestId(21),
+                ..
+            }
+        ) {
+            oversized.push(message.clone());
+        }
+        match message {
+            AgentMessage::Output {
+                request_id: RequestId(20),
+                chunk,
+                ..
+            } => echoed.extend_from_slice(&chunk),
+            AgentMessage::Completed { .. }
+            | AgentMessage::Cancelled { .. }
+            | AgentMessage::Rejected { .. } => terminal.push(message),
+            _ => {}
+        }
+    }
+
+    assert_eq!(echoed, b"hello\n");
+    assert!(terminal.iter().any(|message| matches!(
+        message,
+        AgentMessage::Completed {
+            request_id: RequestId(20),
+            exit_code: 0,
+            ..
+        }
+    )));
+    // The request has already started, so it ends with `completed` rather than a rejection.
+    assert!(matches!(
+        oversized.as_slice(),
+        [
+            AgentMessage::Started {
+                request_id: RequestId(21)
+            },
+            ..,
+            AgentMessage::Completed {
+                request_id: RequestId(21),
+                input_exceeded: true,
+                ..
+            }
+        ]
+    ));
+    assert!(
+        !oversized
+            .iter()
+            .any(|message| matches!(message, AgentMessage::Rejected { .. }))
+    );
+
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn preserves_binary_output() -> Result<(), Box<dyn Error>> {
+    let identity_bundle = test_identity_bundle("agent-binary", "client-binary")?;
+    let (addr, server_task) =
+        spawn_server(test_authenticator(&["agent-binary"], &["client-binary"])?).await?;
+    let mut agent_stream = connect_agent(addr, "agent-binary").await?;
+    let agent_id = AgentId::new("agent-binary")?;
+    let policy = base_policy();
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
+        run_secure_session(
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
+            Keypair::default_keypair(),
+            &SessionConfig::default(),
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
+        )
+        .await
+        .expect("agent secure session should succeed");
+    });
+
+    let (mut client_stream, mut secure) =
+        connect_client_secure(addr, "client-binary", "agent-binary", &identity_bundle).await?;
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Execute {
+            request_id: RequestId(7),
+            command_id: CommandId::new("binary").expect("valid command id"),
+            args: BTreeMap::new(),
+            env: BTreeMap::new(),
+        },
+    )
+    .await?;
+
+    let messages = receive_until_terminal(&mut secure, &mut client_stream).await?;
+    let stdout = messages
+        .iter()
+        .filter_map(|message| match message {
+            AgentMessage::Output {
+                stream: OutputStream::Stdout,
+                chunk,
+                ..
+            } => Some(chunk.as_slice()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .concat();
+    assert_eq!(stdout, vec![0xff, 0x00, 0x81]);
+
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn fetches_allowed_file_in_chunks() -> Result<(), Box<dyn Error>> {
+    let fetch_dir = std::env::temp_dir().canonicalize()?;
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let fetch_path = fetch_dir.join(format!("alaric-fetch-{}.bin", nanos));
+    let contents = (0..40_000u32)
+        .map(|value| (value % 251) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write(&fetch_path, &contents)?;
+
+    let identity_bundle = test_identity_bundle("agent-fetch", "client-fetch")?;
+    let (addr, server_task) =
+        spawn_server(test_authenticator(&["agent-fetch"], &["client-fetch"])?).await?;
+    let mut agent_stream = connect_agent(addr, "agent-fetch").await?;
+    let agent_id = AgentId::new("agent-fetch")?;
+    let mut policy = base_policy();
+    policy.fetch = vec![FetchRule {
+        path: format!("{}/alaric-fetch-*.bin", fetch_dir.display()),
+        max_bytes: 64 * 1024,
+    }];
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
+        run_secure_session(
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
+            Keypair::default_keypair(),
+            &SessionConfig::default(),
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
+        )
+        .await
+        .expect("agent secure session should succeed");
+    });
+
+    let (mut client_stream, mut secure) =
+        connect_client_secure(addr, "client-fetch", "agent-fetch", &identity_bundle).await?;
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Fetch {
+            request_id: RequestId(30),
+            path: fetch_path.display().to_string(),
+        },
+    )
+    .await?;
+    let messages = receive_until_terminal(&mut secure, &mut client_stream).await?;
+
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Fetch {
+            request_id: RequestId(31),
+            path: "/etc/hostname".to_string(),
+        },
+    )
+    .await?;
+    let denied = receive_until_terminal(&mut secure, &mut client_stream).await?;
+
+    let _ = std::fs::remove_file(&fetch_path);
+
+    let chunks = messages
+        .iter()
+        .filter_map(|message| match message {
+            AgentMessage::FetchChunk { data, .. } => Some(data.as_slice()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert!(chunks.len() > 1, "file should arrive in several chunks");
+    assert_eq!(chunks.concat(), contents);
+    assert!(matches!(
+        messages[0],
+        AgentMessage::FetchStarted {
+            request_id: RequestId(30),
+            size: 40_000
+        }
+    ));
+    let expected_sha256 = hex::encode(Sha256::digest(&contents));
+    assert!(matches!(
+        messages.last(),
+        Some(AgentMessage::FetchCompleted {
+            request_id: RequestId(30),
+            size: 40_000,
+            sha256,
+        }) if *sha256 == expected_sha256
+    ));
+    assert!(matches!(
+        denied.last(),
+        Some(AgentMessage::Rejected {
+            request_id: RequestId(31),
+            code: RejectionCode::PolicyError,
+            ..
+        })
+    ));
+
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn puts_file_atomically_with_digest() -> Result<(), Box<dyn Error>> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let put_dir = std::env::temp_dir()
+        .canonicalize()?
+        .join(format!("alaric-put-{}", nanos));
+    std::fs::create_dir(&put_dir)?;
+    let put_path = put_dir.join("app.conf");
+    let contents = (0..20_000u32)
+        .map(|value| (value % 241) as u8)
+        .collect::<Vec<_>>();
+
+    let identity_bundle = test_identity_bundle("agent-put", "client-put")?;
+    let (addr, server_task) =
+        spawn_server(test_authenticator(&["agent-put"], &["client-put"])?).await?;
+    let mut agent_stream = connect_agent(addr, "agent-put").await?;
+    let agent_id = AgentId::new("agent-put")?;
+    let mut policy = base_policy();
+    policy.put = vec![PutRule {
+        directory: put_dir.display().to_string(),
+        max_bytes: 32 * 1024,
+        mode: Some("0640".to_string()),
+        owner: None,
+        group: None,
+    }];
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
+        run_secure_session(
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
+            Keypair::default_keypair(),
+            &SessionConfig::default(),
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
+        )
+        .await
+        .expect("agent secure session should succeed");
+    });
+
+    let (mut client_stream, mut secure) =
+        connect_client_secure(addr, "client-put", "agent-put", &identity_bundle).await?;
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Put {
+            request_id: RequestId(40),
+            path: put_path.display().to_string(),
+            size: contents.len() as u64,
+            mode: None,
+        },
+    )
+    .await?;
+    for (index, chunk) in contents.chunks(8 * 1024).enumerate() {
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &ClientMessage::PutChunk {
+                request_id: RequestId(40),
+                data: chunk.to_vec(),
+                eof: index == contents.len().div_ceil(8 * 1024) - 1,
+            },
+        )
+        .await?;
+    }
+    let messages = receive_until_terminal(&mut secure, &mut client_stream).await?;
+
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Put {
+            request_id: RequestId(41),
+            path: put_dir.join("oversized.conf").display().to_string(),
+            size: 4,
+            mode: None,
+        },
+    )
+    .await?;
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::PutChunk {
+            request_id: RequestId(41),
+            data: b"too long".to_vec(),
+            eof: true,
+        },
+    )
+    .await?;
+    let oversized = receive_until_terminal(&mut secure, &mut client_stream).await?;
+
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Put {
+            request_id: RequestId(42),
+            path: put_dir.join("script.sh").display().to_string(),
+            size: 0,
+            mode: Some(0o755),
+        },
+    )
+    .await?;
+    let bad_mode = receive_until_terminal(&mut secure, &mut client_stream).await?;
+
+    let written = std::fs::read(&put_path)?;
+    let written_mode =
+        std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&put_path)?.permissions())
+            & 0o777;
+    let leftovers = std::fs::read_dir(&put_dir)?.count();
+    let _ = std::fs::remove_dir_all(&put_dir);
+
+    let expected_sha256 = hex::encode(Sha256::digest(&contents));
+    assert!(matches!(
+        messages.last(),
+        Some(AgentMessage::PutCompleted {
+            request_id: RequestId(40),
+            size: 20_000,
+            sha256,
+            ..
+        }) if *sha256 == expected_sha256
+    ));
+    assert_eq!(written, contents);
+    assert_eq!(written_mode, 0o640);
+    assert_eq!(leftovers, 1, "temporary files should not be left behind");
+    assert!(matches!(
+        oversized.last(),
+        Some(AgentMessage::Rejected {
+            request_id: RequestId(41),
+            code: RejectionCode::InputLimit,
+            ..
+        })
+    ));
+    assert!(matches!(
+        bad_mode.last(),
+        Some(AgentMessage::Rejected {
+            request_id: RequestId(42),
+            code: RejectionCode::PolicyError,
+            ..
+        })
+    ));
+
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
     timeout(Duration::from_secs(2), agent_task).await??;
     server_task.abort();
     let _ = server_task.await;
@@ -585,22 +1358,31 @@ async fn truncates_output_at_limit() -> Result<(), Box<dyn Error>> {
         &["client-truncate"],
     )?)
     .await?;
-    let (mut agent_stream, agent_session_id) = connect_agent(addr, "agent-truncate").await?;
+    let mut agent_stream = connect_agent(addr, "agent-truncate").await?;
     let agent_id = AgentId::new("agent-truncate")?;
     let policy = base_policy();
     let attestation_policy = default_attestation_policy();
     let agent_identity_bundle = identity_bundle.clone();
     let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
         run_secure_session(
-            &mut agent_stream,
-            &policy,
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
             Keypair::default_keypair(),
-            agent_session_id,
+            &SessionConfig::default(),
             &agent_id,
             AGENT_KEY_ID,
             AGENT_PRIVATE_KEY_HEX,
-            &attestation_policy,
-            Some(&agent_identity_bundle),
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
         )
         .await
         .expect("agent secure session should succeed");
@@ -615,6 +1397,7 @@ async fn truncates_output_at_limit() -> Result<(), Box<dyn Error>> {
             request_id: RequestId(5),
             command_id: CommandId::new("flood").expect("valid command id"),
             args: BTreeMap::new(),
+            env: BTreeMap::new(),
         },
     )
     .await?;
@@ -643,8 +1426,465 @@ async fn truncates_output_at_limit() -> Result<(), Box<dyn Error>> {
         })
     ));
 
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn runs_concurrent_requests_in_one_session() -> Result<(), Box<dyn Error>> {
+    let identity_bundle = test_identity_bundle("agent-multiplex", "client-multiplex")?;
+    let (addr, server_task) = spawn_server(test_authenticator(
+        &["agent-multiplex"],
+        &["client-multiplex"],
+    )?)
+    .await?;
+    let mut agent_stream = connect_agent(addr, "agent-multiplex").await?;
+    let agent_id = AgentId::new("agent-multiplex")?;
+    let policy = base_policy();
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
+        run_secure_session(
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
+            Keypair::default_keypair(),
+            &SessionConfig::default(),
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
+        )
+        .await
+        .expect("agent secure session should succeed");
+    });
+
+    let (mut client_stream, mut secure) = connect_client_secure(
+        addr,
+        "client-multiplex",
+        "agent-multiplex",
+        &identity_bundle,
+    )
+    .await?;
+
+    let mut sleep_args = BTreeMap::new();
+    sleep_args.insert("seconds".to_string(), "3".to_string());
+    send_secure_json(
+        &mut secure,
+        &mut client_stream,
+        &ClientMessage::Execute {
+            request_id: RequestId(10),
+            command_id: CommandId::new("sleep").expect("valid command id"),
+            args: sleep_args,
+            env: BTreeMap::new(),
+        },
+    )
+    .await?;
+
+    for (request_id, text) in [(11, "first"), (12, "second")] {
+        let mut args = BTreeMap::new();
+        args.insert("text".to_string(), text.to_string());
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &ClientMessage::Execute {
+                request_id: RequestId(request_id),
+                command_id: CommandId::new("echo").expect("valid command id"),
+                args,
+                env: BTreeMap::new(),
+            },
+        )
+        .await?;
+    }
+
+    let mut terminal = Vec::new();
+    let mut outputs = Vec::new();
+    while terminal.len() < 3 {
+        let message = timeout(
+            Duration::from_secs(3),
+            recv_secure_json::<_, AgentMessage>(&mut secure, &mut client_stream),
+        )
+        .await??;
+        match message {
+            AgentMessage::Output {
+                request_id, chunk, ..
+            } => outputs.push((request_id, chunk)),
+            AgentMessage::Completed { request_id, .. }
+            | AgentMessage::Cancelled { request_id, .. }
+            | AgentMessage::Rejected { request_id, .. } => terminal.push(request_id),
+            _ => {}
+        }
+    }
+
+    assert_eq!(
+        terminal.last(),
+        Some(&RequestId(10)),
+        "echo requests should finish while the sleep is still running"
+    );
+    assert!(outputs.contains(&(RequestId(11), b"first\n".to_vec())));
+    assert!(outputs.contains(&(RequestId(12), b"second\n".to_vec())));
+
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn interleaves_output_of_concurrent_requests() -> Result<(), Box<dyn Error>> {
+    let identity_bundle = test_identity_bundle("agent-interleave", "client-interleave")?;
+    let (addr, server_task) = spawn_server(test_authenticator(
+        &["agent-interleave"],
+        &["client-interleave"],
+    )?)
+    .await?;
+    let mut agent_stream = connect_agent(addr, "agent-interleave").await?;
+    let agent_id = AgentId::new("agent-interleave")?;
+    let policy = base_policy();
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let agent_task = tokio::spawn(async move {
+        let (mut tunnel_stream, tunnel_session_id, tunnel_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open a data tunnel");
+        run_secure_session(
+            &mut tunnel_stream,
+            &SharedPolicy::from(policy),
+            Keypair::default_keypair(),
+            &SessionConfig::default(),
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &TunnelContext {
+                session_id: tunnel_session_id,
+                client_id: tunnel_client_id,
+                allowed_commands: None,
+                attestation_policy: &attestation_policy,
+                identity_bundle: Some(&agent_identity_bundle),
+            },
+        )
+        .await
+        .expect("agent secure session should succeed");
+    });
+
+    let (mut client_stream, mut secure) = connect_client_secure(
+        addr,
+        "client-interleave",
+        "agent-interleave",
+        &identity_bundle,
+    )
+    .await?;
+
+    // Two `cat` requests stay open together; each echoes its input back as it arrives.
+    for request_id in [31, 32] {
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &ClientMessage::Execute {
+                request_id: RequestId(request_id),
+                command_id: CommandId::new("cat").expect("valid command id"),
+                args: BTreeMap::new(),
+                env: BTreeMap::new(),
+            },
+        )
+        .await?;
+    }
+
+    let mut outputs = Vec::new();
+    for (request_id, data) in [(31, "a1\n"), (32, "b1\n"), (31, "a2\n"), (32, "b2\n")] {
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &ClientMessage::Stdin {
+                request_id: RequestId(request_id),
+                data: data.as_bytes().to_vec(),
+                eof: false,
+            },
+        )
+        .await?;
+        loop {
+            let message = timeout(
+                Duration::from_secs(2),
+                recv_secure_json::<_, AgentMessage>(&mut secure, &mut client_stream),
+            )
+            .await??;
+            if let AgentMessage::Output {
+                request_id, chunk, ..
+            } = message
+            {
+                outputs.push((request_id, chunk));
+                break;
+            }
+        }
+    }
+    assert_eq!(
+        outputs,
+        vec![
+            (RequestId(31), b"a1\n".to_vec()),
+            (RequestId(32), b"b1\n".to_vec()),
+            (RequestId(31), b"a2\n".to_vec()),
+            (RequestId(32), b"b2\n".to_vec()),
+        ],
+        "output of both requests should interleave while both run"
+    );
+
+    for request_id in [31, 32] {
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &ClientMessage::Stdin {
+                request_id: RequestId(request_id),
+                data: Vec::new(),
+                eof: true,
+            },
+        )
+        .await?;
+    }
+    let mut completed = Vec::new();
+    while completed.len() < 2 {
+        let message = timeout(
+            Duration::from_secs(2),
+            recv_secure_json::<_, AgentMessage>(&mut secure, &mut client_stream),
+        )
+        .await??;
+        if let AgentMessage::Completed {
+            request_id,
+            exit_code: 0,
+            ..
+        } = message
+        {
+            completed.push(request_id);
+        }
+    }
+    completed.sort_by_key(|request_id| request_id.0);
+    assert_eq!(completed, vec![RequestId(31), RequestId(32)]);
+
+    send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
     timeout(Duration::from_secs(2), agent_task).await??;
     server_task.abort();
     let _ = server_task.await;
     Ok(())
 }
+
+#[tokio::test]
+async fn serves_concurrent_clients_for_one_agent() -> Result<(), Box<dyn Error>> {
+    let identity_bundle = test_identity_bundle("agent-shared", "client-shared")?;
+    let (addr, server_task) =
+        spawn_server(test_authenticator(&["agent-shared"], &["client-shared"])?).await?;
+    let mut agent_stream = connect_agent(addr, "agent-shared").await?;
+    let agent_id = AgentId::new("agent-shared")?;
+    let policy = SharedPolicy::from(base_policy());
+    let attestation_policy = default_attestation_policy();
+    let agent_identity_bundle = identity_bundle.clone();
+    let session_config = SessionConfig::default();
+    let agent_task = tokio::spawn(async move {
+        let (mut first_tunnel, first_session_id, first_client_id) =
+            accept_tunnel(addr, &mut agent_stream, &agent_id)
+                .await
+                .expect("agent should open the first data tunnel");
+        let first_tunnel_context = TunnelContext {
+            session_id: first_session_id,
+            client_id: first_client_id,
+            allowed_commands: None,
+            attestation_policy: &attestation_policy,
+            identity_bundle: Some(&agent_identity_bundle),
+        };
+        let first = run_secure_session(
+            &mut first_tunnel,
+            &policy,
+            Keypair::default_keypair(),
+            &session_config,
+            &agent_id,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+            &first_tunnel_context,
+        );
+        let second = async {
+            let (mut second_tunnel, second_session_id, second_client_id) =
+                accept_tunnel(addr, &mut agent_stream, &agent_id)
+                    .await
+                    .expect("agent should open the second data tunnel");
+            run_secure_session(
+                &mut second_tunnel,
+                &policy,
+                Keypair::default_keypair(),
+                &session_config,
+                &agent_id,
+                AGENT_KEY_ID,
+                AGENT_PRIVATE_KEY_HEX,
+                &TunnelContext {
+                    session_id: second_session_id,
+                    client_id: second_client_id,
+                    allowed_commands: None,
+                    attestation_policy: &attestation_policy,
+                    identity_bundle: Some(&agent_identity_bundle),
+                },
+            )
+            .await
+        };
+        let (first, second) = tokio::join!(first, second);
+        first.expect("first agent secure session should succeed");
+        second.expect("second agent secure session should succeed");
+    });
+
+    let (mut first_stream, mut first_secure) =
+        connect_client_secure(addr, "client-shared", "agent-shared", &identity_bundle).await?;
+    let (mut second_stream, mut second_secure) =
+        connect_client_secure(addr, "client-shared", "agent-shared", &identity_bundle).await?;
+
+    for (request_id, secure, stream) in [
+        (21, &mut first_secure, &mut first_stream),
+        (22, &mut second_secure, &mut second_stream),
+    ] {
+        let mut args = BTreeMap::new();
+        args.insert("text".to_string(), "shared".to_string());
+        send_secure_json(
+            secure,
+            stream,
+            &ClientMessage::Execute {
+                request_id: RequestId(request_id),
+                command_id: CommandId::new("echo").expect("valid command id"),
+                args,
+                env: BTreeMap::new(),
+            },
+        )
+        .await?;
+
+        let messages = receive_until_terminal(secure, stream).await?;
+        assert!(matches!(
+            messages.last(),
+            Some(AgentMessage::Completed {
+                request_id: completed_request_id,
+                exit_code: 0,
+                ..
+            }) if *completed_request_id == RequestId(request_id)
+        ));
+    }
+
+    send_secure_json(
+        &mut second_secure,
+        &mut second_stream,
+        &ClientMessage::Close,
+    )
+    .await?;
+    send_secure_json(&mut first_secure, &mut first_stream, &ClientMessage::Close).await?;
+    timeout(Duration::from_secs(2), agent_task).await??;
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn enforces_bound_noise_static_key_without_attestation() -> Result<(), Box<dyn Error>> {
+    let client_noise_public_key = noise_static_keypair_from_hex(CLIENT_NOISE_PRIVATE_KEY_HEX)?
+        .get_public_key()
+        .as_bytes();
+    let identity_bundle = test_identity_bundle_with_noise_key(
+        "agent-noise",
+        "client-noise",
+        Some(hex::encode(client_noise_public_key)),
+    )?;
+    let attestation_policy = PeerAttestationPolicy::from_json(r#"{"default_mode":"disabled"}"#)?;
+
+    // (relay client id, client id claimed in the attestation init, static key, rejection reason)
+    for (relay_client_id, claimed_client_id, client_static_keypair, rejection) in [
+        (
+            "client-noise",
+            "client-noise",
+            noise_static_keypair_from_hex(CLIENT_NOISE_PRIVATE_KEY_HEX)?,
+            None,
+        ),
+        (
+            "client-noise",
+            "client-noise",
+            Keypair::default_keypair(),
+            Some("noise static key"),
+        ),
+        (
+            "client-noise",
+            "client-unbound",
+            Keypair::default_keypair(),
+            Some("does not match relay client id"),
+        ),
+        (
+            "client-unbound",
+            "client-unbound",
+            Keypair::default_keypair(),
+            Some("none for client 'client-unbound'"),
+        ),
+    ] {
+        let expect_accepted = rejection.is_none();
+        let (mut agent_stream, mut client_stream) = tokio::io::duplex(64 * 1024);
+        let agent_id = AgentId::new("agent-noise")?;
+        let relay_client_id = ClientId::new(relay_client_id)?;
+        let agent_identity_bundle = identity_bundle.clone();
+        let agent_attestation_policy = attestation_policy.clone();
+        let agent_task = tokio::spawn(async move {
+            run_secure_session(
+                &mut agent_stream,
+                &SharedPolicy::from(base_policy()),
+                Keypair::default_keypair(),
+                &SessionConfig::default(),
+                &agent_id,
+                AGENT_KEY_ID,
+                AGENT_PRIVATE_KEY_HEX,
+                &TunnelContext {
+                    session_id: SessionId::new_random(),
+                    client_id: relay_client_id,
+                    allowed_commands: None,
+                    attestation_policy: &agent_attestation_policy,
+                    identity_bundle: Some(&agent_identity_bundle),
+                },
+            )
+            .await
+            .is_ok()
+        });
+
+        let mut secure = SecureChannel::handshake_xx_initiator(
+            &mut client_stream,
+            client_static_keypair,
+            DEFAULT_MAX_MESSAGE_BYTES,
+        )
+        .await?;
+        send_secure_json(
+            &mut secure,
+            &mut client_stream,
+            &PeerAttestationInit {
+                client_id: ClientId::new(claimed_client_id)?,
+                proof: None,
+            },
+        )
+        .await?;
+        let result =
+            recv_secure_json::<_, PeerAttestationResult>(&mut secure, &mut client_stream).await?;
+        assert_eq!(result.accepted, expect_accepted);
+        match rejection {
+            None => {
+                send_secure_json(&mut secure, &mut client_stream, &ClientMessage::Close).await?;
+            }
+            Some(reason) => assert!(
+                result
+                    .message
+                    .is_some_and(|message| message.contains(reason))
+            ),
+        }
+
+        let session_ok = timeout(Duration::from_secs(2), agent_task).await??;
+        assert_eq!(session_ok, expect_accepted);
+    }
+    Ok(())
+}
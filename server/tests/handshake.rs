@@ -1,14 +1,21 @@
 use std::net::SocketAddr;
 use std::{collections::HashMap, error::Error, sync::Arc, time::Duration};
 
-use alaric_lib::database::Database;
+use alaric_lib::database::{
+    Database, GrantSetOutcome, GrantSubject, PolicyPublishOutcome, PolicyTarget,
+    principals::PrincipalKind,
+};
 use alaric_lib::protocol::{
-    AgentId, AuthProof, ClientId, HandshakeErrorCode, HandshakeProofRequest, HandshakeRequest,
-    HandshakeResponse, PROTOCOL_VERSION, SecureChannel, build_auth_proof_ed25519,
-    decode_ed25519_public_key, read_json_frame, write_json_frame,
+    AgentControlMessage, AgentGroupId, AgentId, AgentPresenceStatus, AuthProof, ClientId,
+    CommandId, DEFAULT_MAX_MESSAGE_BYTES, DiscoveryPageRequest, DiscoveryQuery, HandshakeErrorCode,
+    HandshakeProofRequest, HandshakeRequest, HandshakeResponse, ListAgentsResponse,
+    PROTOCOL_VERSION, RelayControlMessage, SecureChannel, build_auth_proof_ed25519,
+    decode_ed25519_public_key, read_json_frame, read_json_message, write_json_frame,
+    write_json_message,
 };
-use alaric_lib::security::noise::types::Keypair;
+use alaric_lib::{config::ServerConfig, security::noise::types::Keypair};
 use alaric_server::connection::handle_connection;
+use alaric_server::policy::push_policy_updates;
 use alaric_server::state::ServerState;
 use alaric_server::{HandshakeAuthenticator, IdentityPublicKey};
 use tokio::{
@@ -104,11 +111,16 @@ async fn perform_authenticated_handshake(
 
 async fn spawn_server(
     authenticator: HandshakeAuthenticator,
+) -> Result<(SocketAddr, JoinHandle<()>), Box<dyn Error>> {
+    let database = Arc::new(Database::from_env().await?);
+    spawn_server_with_state(ServerState::new(authenticator, database)).await
+}
+
+async fn spawn_server_with_state(
+    state: ServerState,
 ) -> Result<(SocketAddr, JoinHandle<()>), Box<dyn Error>> {
     let listener = TcpListener::bind("127.0.0.1:0").await?;
     let addr = listener.local_addr()?;
-    let database = Arc::new(Database::from_env().await?);
-    let state = ServerState::new(authenticator, database);
     let task = tokio::spawn(async move {
         loop {
             let Ok((stream, _)) = listener.accept().await else {
@@ -116,7 +128,7 @@ async fn spawn_server(
             };
             let state = state.clone();
             tokio::spawn(async move {
-                let _ = handle_connection(stream, state).await;
+                let _ = handle_connection(stream.into(), state).await;
             });
         }
     });
@@ -141,21 +153,52 @@ async fn accepts_handshake_and_routes_payload() -> Result<(), Box<dyn Error>> {
     };
 
     let agent_task = tokio::spawn(async move {
-        let mut agent_secure =
-            SecureChannel::handshake_xx_responder(&mut agent, Keypair::default_keypair())
+        let RelayControlMessage::OpenTunnel { session_id, .. } =
+            read_json_frame::<_, RelayControlMessage>(&mut agent)
                 .await
-                .expect("agent Noise XX handshake should succeed");
+                .expect("agent should receive a tunnel request")
+        else {
+            panic!("expected tunnel request");
+        };
+        assert_ne!(session_id, agent_accepted.session_id);
+
+        let mut tunnel = TcpStream::connect(addr)
+            .await
+            .expect("agent should connect a data tunnel");
+        let tunnel_response = perform_authenticated_handshake(
+            &mut tunnel,
+            HandshakeRequest::agent_tunnel(
+                AgentId::new("agent-route").expect("valid agent id"),
+                session_id,
+            ),
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+        )
+        .await
+        .expect("tunnel handshake should complete");
+        let HandshakeResponse::Accepted(tunnel_accepted) = tunnel_response else {
+            panic!("expected accepted response for agent tunnel");
+        };
+
+        let mut agent_secure = SecureChannel::handshake_xx_responder(
+            &mut tunnel,
+            Keypair::default_keypair(),
+            DEFAULT_MAX_MESSAGE_BYTES,
+        )
+        .await
+        .expect("agent Noise XX handshake should succeed");
 
         let received = agent_secure
-            .recv(&mut agent)
+            .recv(&mut tunnel)
             .await
             .expect("agent should receive encrypted client payload");
         assert_eq!(received, b"hello-agent");
 
         agent_secure
-            .send(&mut agent, b"hello-client")
+            .send(&mut tunnel, b"hello-client")
             .await
             .expect("agent should send encrypted response");
+        tunnel_accepted.session_id
     });
 
     let mut client = TcpStream::connect(addr).await?;
@@ -169,11 +212,14 @@ async fn accepts_handshake_and_routes_payload() -> Result<(), Box<dyn Error>> {
     let HandshakeResponse::Accepted(client_accepted) = client_response else {
         panic!("expected accepted response for client");
     };
-    assert_eq!(agent_accepted.session_id, client_accepted.session_id);
 
     let mut client_secure = timeout(
         Duration::from_secs(2),
-        SecureChannel::handshake_xx_initiator(&mut client, Keypair::default_keypair()),
+        SecureChannel::handshake_xx_initiator(
+            &mut client,
+            Keypair::default_keypair(),
+            DEFAULT_MAX_MESSAGE_BYTES,
+        ),
     )
     .await??;
 
@@ -181,7 +227,83 @@ async fn accepts_handshake_and_routes_payload() -> Result<(), Box<dyn Error>> {
     let response = timeout(Duration::from_secs(2), client_secure.recv(&mut client)).await??;
     assert_eq!(response, b"hello-client");
 
-    timeout(Duration::from_secs(2), agent_task).await??;
+    let tunnel_session_id = timeout(Duration::from_secs(2), agent_task).await??;
+    assert_eq!(tunnel_session_id, client_accepted.session_id);
+    drop(client);
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn accepts_tunnels_with_configured_message_limit() -> Result<(), Box<dyn Error>> {
+    const LIMIT: usize = 4096;
+    let database = Arc::new(Database::from_env().await?);
+    let state = ServerState::new(
+        test_authenticator(&["agent-limit"], &["client-limit"])?,
+        database,
+    )
+    .with_config(ServerConfig {
+        max_message_bytes: LIMIT,
+        ..ServerConfig::default()
+    });
+    let (addr, server_task) = spawn_server_with_state(state).await?;
+
+    let mut agent = TcpStream::connect(addr).await?;
+    let agent_response = perform_authenticated_handshake(
+        &mut agent,
+        agent_request("agent-limit")?,
+        AGENT_KEY_ID,
+        AGENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    assert!(matches!(
+        agent_response,
+        HandshakeResponse::Accepted(accepted) if accepted.max_message_bytes == LIMIT
+    ));
+
+    let agent_task = tokio::spawn(async move {
+        let RelayControlMessage::OpenTunnel { session_id, .. } =
+            read_json_frame::<_, RelayControlMessage>(&mut agent)
+                .await
+                .expect("agent should receive a tunnel request")
+        else {
+            panic!("expected tunnel request");
+        };
+        let mut tunnel = TcpStream::connect(addr)
+            .await
+            .expect("agent should connect a data tunnel");
+        perform_authenticated_handshake(
+            &mut tunnel,
+            HandshakeRequest::agent_tunnel(
+                AgentId::new("agent-limit").expect("valid agent id"),
+                session_id,
+            ),
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+        )
+        .await
+        .expect("tunnel handshake should complete")
+    });
+
+    let mut client = TcpStream::connect(addr).await?;
+    let client_response = perform_authenticated_handshake(
+        &mut client,
+        client_request("client-limit", "agent-limit")?,
+        CLIENT_KEY_ID,
+        CLIENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    assert!(matches!(
+        client_response,
+        HandshakeResponse::Accepted(accepted) if accepted.max_message_bytes == LIMIT
+    ));
+    let tunnel_response = timeout(Duration::from_secs(2), agent_task).await??;
+    assert!(matches!(
+        tunnel_response,
+        HandshakeResponse::Accepted(accepted) if accepted.max_message_bytes == LIMIT
+    ));
+
     drop(client);
     server_task.abort();
     let _ = server_task.await;
@@ -203,6 +325,7 @@ async fn rejects_unsupported_protocol_version() -> Result<(), Box<dyn Error>> {
             protocol_version: PROTOCOL_VERSION + 1,
             agent_id: AgentId::new("agent-bad-version")?,
             metadata: Default::default(),
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
         },
     )
     .await?;
@@ -392,3 +515,421 @@ async fn rejects_auth_with_unsupported_method() -> Result<(), Box<dyn Error>> {
     let _ = server_task.await;
     Ok(())
 }
+
+async fn recv_policy_bundle(agent: &mut TcpStream) -> Result<Option<String>, Box<dyn Error>> {
+    match timeout(
+        Duration::from_secs(2),
+        read_json_frame::<_, RelayControlMessage>(agent),
+    )
+    .await??
+    {
+        RelayControlMessage::PolicyBundle { bundle } => Ok(bundle),
+        other => Err(format!("expected policy bundle, got {:?}", other).into()),
+    }
+}
+
+#[tokio::test]
+async fn delivers_and_pushes_assigned_policy_bundles() -> Result<(), Box<dyn Error>> {
+    const AGENT: &str = "agent-policy";
+    const GROUP: &str = "policy-group";
+    const GROUP_BUNDLE: &str = r#"{"bundle":"group"}"#;
+    const AGENT_BUNDLE: &str = r#"{"bundle":"agent"}"#;
+
+    let database = Arc::new(Database::from_env().await?);
+    database
+        .admin_add_principal(PrincipalKind::Agent, AGENT, None, None)
+        .await?;
+    database.admin_create_agent_group(GROUP, None).await?;
+    database.admin_add_agent_to_group(GROUP, AGENT).await?;
+    database
+        .admin_unpublish_policy_bundle(PolicyTarget::Agent(AGENT))
+        .await?;
+    database
+        .admin_publish_policy_bundle(PolicyTarget::Group(GROUP), GROUP_BUNDLE)
+        .await?;
+
+    let state = ServerState::new(
+        test_authenticator(&[AGENT], &["client-policy"])?,
+        database.clone(),
+    );
+    let (addr, server_task) = spawn_server_with_state(state.clone()).await?;
+
+    let mut agent = TcpStream::connect(addr).await?;
+    let response = perform_authenticated_handshake(
+        &mut agent,
+        agent_request(AGENT)?,
+        AGENT_KEY_ID,
+        AGENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    assert!(matches!(response, HandshakeResponse::Accepted(_)));
+
+    write_json_frame(&mut agent, &AgentControlMessage::FetchPolicy).await?;
+    assert_eq!(
+        recv_policy_bundle(&mut agent).await?.as_deref(),
+        Some(GROUP_BUNDLE)
+    );
+
+    // An agent assignment takes precedence over the group it belongs to.
+    assert_eq!(
+        database
+            .admin_publish_policy_bundle(PolicyTarget::Agent(AGENT), AGENT_BUNDLE)
+            .await?,
+        PolicyPublishOutcome::Published
+    );
+    push_policy_updates(&state).await;
+    assert_eq!(
+        recv_policy_bundle(&mut agent).await?.as_deref(),
+        Some(AGENT_BUNDLE)
+    );
+
+    database
+        .admin_unpublish_policy_bundle(PolicyTarget::Agent(AGENT))
+        .await?;
+    push_policy_updates(&state).await;
+    assert_eq!(
+        recv_policy_bundle(&mut agent).await?.as_deref(),
+        Some(GROUP_BUNDLE)
+    );
+
+    drop(agent);
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+async fn discover(
+    addr: SocketAddr,
+    client_id: &str,
+    query: DiscoveryQuery,
+) -> Result<ListAgentsResponse, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = HandshakeRequest::client_discovery(ClientId::new(client_id)?, query);
+    let response = perform_authenticated_handshake(
+        &mut stream,
+        request,
+        CLIENT_KEY_ID,
+        CLIENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    let HandshakeResponse::Accepted(accepted) = response else {
+        return Err(format!("expected discovery to be accepted, got {:?}", response).into());
+    };
+    let page = timeout(
+        Duration::from_secs(2),
+        read_json_message::<_, ListAgentsResponse>(&mut stream, accepted.max_message_bytes),
+    )
+    .await??;
+    Ok(page)
+}
+
+fn agent_ids(response: &ListAgentsResponse) -> Vec<&str> {
+    response
+        .agents
+        .iter()
+        .map(|agent| agent.agent_id.as_str())
+        .collect()
+}
+
+#[tokio::test]
+async fn filters_and_pages_discovery_in_the_store() -> Result<(), Box<dyn Error>> {
+    const AGENTS: [&str; 3] = ["agent-disc-a", "agent-disc-b", "agent-disc-c"];
+    const GROUP: &str = "disc-group";
+    const CLIENT: &str = "client-disc";
+
+    let database = Arc::new(Database::from_env().await?);
+    for agent in AGENTS {
+        database
+            .admin_add_principal(PrincipalKind::Agent, agent, None, None)
+            .await?;
+    }
+    database.admin_create_agent_group(GROUP, None).await?;
+    database.admin_add_agent_to_group(GROUP, AGENTS[1]).await?;
+
+    let state = ServerState::new(test_authenticator(&AGENTS, &[CLIENT])?, database);
+    let (addr, server_task) = spawn_server_with_state(state).await?;
+
+    // The third agent never connects, so it stays offline.
+    let mut connected = Vec::new();
+    for (agent_id, tags) in [(AGENTS[0], "staging"), (AGENTS[1], "prod,region=ca-west")] {
+        let mut request = agent_request(agent_id)?;
+        if let HandshakeRequest::Agent { metadata, .. } = &mut request {
+            metadata.insert("tags".to_string(), tags.to_string());
+        }
+        let mut agent = TcpStream::connect(addr).await?;
+        let response = perform_authenticated_handshake(
+            &mut agent,
+            request,
+            AGENT_KEY_ID,
+            AGENT_PRIVATE_KEY_HEX,
+        )
+        .await?;
+        assert!(matches!(response, HandshakeResponse::Accepted(_)));
+        // The relay records presence before it starts serving control messages.
+        write_json_frame(&mut agent, &AgentControlMessage::FetchPolicy).await?;
+        recv_policy_bundle(&mut agent).await?;
+        connected.push(agent);
+    }
+
+    let prefix_query = DiscoveryQuery {
+        name_prefix: Some("agent-disc-".to_string()),
+        ..DiscoveryQuery::default()
+    };
+
+    let first = discover(
+        addr,
+        CLIENT,
+        DiscoveryQuery {
+            page_size: Some(2),
+            ..prefix_query.clone()
+        },
+    )
+    .await?;
+    assert_eq!(agent_ids(&first), AGENTS[..2]);
+    assert!(
+        first
+            .groups
+            .iter()
+            .any(|group| group.group_id.as_str() == GROUP)
+    );
+    let second = discover(
+        addr,
+        CLIENT,
+        DiscoveryQuery {
+            page_size: Some(2),
+            cursor: first.next_cursor.clone(),
+            ..prefix_query.clone()
+        },
+    )
+    .await?;
+    assert_eq!(agent_ids(&second), AGENTS[2..]);
+    assert_eq!(second.next_cursor, None);
+    assert!(second.groups.is_empty());
+
+    // Later pages can also be asked for on the first page's connection.
+    let mut stream = TcpStream::connect(addr).await?;
+    let response = perform_authenticated_handshake(
+        &mut stream,
+        HandshakeRequest::client_discovery(
+            ClientId::new(CLIENT)?,
+            DiscoveryQuery {
+                page_size: Some(1),
+                ..prefix_query.clone()
+            },
+        ),
+        CLIENT_KEY_ID,
+        CLIENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    let HandshakeResponse::Accepted(accepted) = response else {
+        return Err(format!("expected discovery to be accepted, got {:?}", response).into());
+    };
+    let mut paged = Vec::new();
+    loop {
+        let page = timeout(
+            Duration::from_secs(2),
+            read_json_message::<_, ListAgentsResponse>(&mut stream, accepted.max_message_bytes),
+        )
+        .await??;
+        paged.extend(agent_ids(&page).into_iter().map(str::to_string));
+        let Some(cursor) = page.next_cursor else {
+            break;
+        };
+        write_json_message(
+            &mut stream,
+            &DiscoveryPageRequest { cursor },
+            accepted.max_message_bytes,
+        )
+        .await?;
+    }
+    assert_eq!(paged, AGENTS);
+
+    // A cursor the relay did not offer ends the connection.
+    let mut stream = TcpStream::connect(addr).await?;
+    let response = perform_authenticated_handshake(
+        &mut stream,
+        HandshakeRequest::client_discovery(
+            ClientId::new(CLIENT)?,
+            DiscoveryQuery {
+                page_size: Some(1),
+                ..prefix_query.clone()
+            },
+        ),
+        CLIENT_KEY_ID,
+        CLIENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    let HandshakeResponse::Accepted(accepted) = response else {
+        return Err(format!("expected discovery to be accepted, got {:?}", response).into());
+    };
+    let page =
+        read_json_message::<_, ListAgentsResponse>(&mut stream, accepted.max_message_bytes).await?;
+    assert!(page.next_cursor.is_some());
+    write_json_message(
+        &mut stream,
+        &DiscoveryPageRequest {
+            cursor: first.next_cursor.clone().unwrap_or_default(),
+        },
+        accepted.max_message_bytes,
+    )
+    .await?;
+    assert!(
+        timeout(
+            Duration::from_secs(2),
+            read_json_message::<_, ListAgentsResponse>(&mut stream, accepted.max_message_bytes),
+        )
+        .await?
+        .is_err()
+    );
+
+    let tagged = discover(
+        addr,
+        CLIENT,
+        DiscoveryQuery {
+            status: Some(AgentPresenceStatus::Online),
+            tags: vec!["prod".to_string(), "region=ca-west".to_string()],
+            ..prefix_query.clone()
+        },
+    )
+    .await?;
+    assert_eq!(agent_ids(&tagged), [AGENTS[1]]);
+
+    let grouped = discover(
+        addr,
+        CLIENT,
+        DiscoveryQuery {
+            group: Some(AgentGroupId::new(GROUP)?),
+            ..prefix_query.clone()
+        },
+    )
+    .await?;
+    assert_eq!(agent_ids(&grouped), [AGENTS[1]]);
+    assert_eq!(grouped.groups.len(), 1);
+
+    let offline = discover(
+        addr,
+        CLIENT,
+        DiscoveryQuery {
+            status: Some(AgentPresenceStatus::Offline),
+            ..prefix_query.clone()
+        },
+    )
+    .await?;
+    assert_eq!(agent_ids(&offline), [AGENTS[2]]);
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let response = perform_authenticated_handshake(
+        &mut stream,
+        HandshakeRequest::client_discovery(
+            ClientId::new(CLIENT)?,
+            DiscoveryQuery {
+                cursor: Some("not a cursor!".to_string()),
+                ..prefix_query
+            },
+        ),
+        CLIENT_KEY_ID,
+        CLIENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    assert!(matches!(
+        response,
+        HandshakeResponse::Rejected(rejected) if rejected.code == HandshakeErrorCode::InvalidRequest
+    ));
+
+    drop(connected);
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn enforces_client_grants_when_enabled() -> Result<(), Box<dyn Error>> {
+    const AGENT: &str = "agent-grant";
+    const GROUP: &str = "grant-group";
+    const CLIENT: &str = "client-grant";
+
+    let database = Arc::new(Database::from_env().await?);
+    database
+        .admin_add_principal(PrincipalKind::Agent, AGENT, None, None)
+        .await?;
+    database
+        .admin_add_principal(PrincipalKind::Client, CLIENT, None, None)
+        .await?;
+    database.admin_create_agent_group(GROUP, None).await?;
+    database.admin_add_agent_to_group(GROUP, AGENT).await?;
+    database
+        .admin_revoke_client_grant(GrantSubject::Client(CLIENT), PolicyTarget::Group(GROUP))
+        .await?;
+
+    let state = ServerState::new(test_authenticator(&[AGENT], &[CLIENT])?, database.clone())
+        .with_config(ServerConfig {
+            enforce_client_grants: true,
+            ..ServerConfig::default()
+        });
+    let (addr, server_task) = spawn_server_with_state(state).await?;
+
+    let mut agent = TcpStream::connect(addr).await?;
+    let response = perform_authenticated_handshake(
+        &mut agent,
+        agent_request(AGENT)?,
+        AGENT_KEY_ID,
+        AGENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    assert!(matches!(response, HandshakeResponse::Accepted(_)));
+
+    let mut client = TcpStream::connect(addr).await?;
+    let response = perform_authenticated_handshake(
+        &mut client,
+        client_request(CLIENT, AGENT)?,
+        CLIENT_KEY_ID,
+        CLIENT_PRIVATE_KEY_HEX,
+    )
+    .await?;
+    assert!(matches!(
+        response,
+        HandshakeResponse::Rejected(rejected) if rejected.code == HandshakeErrorCode::Forbidden
+    ));
+
+    assert_eq!(
+        database
+            .admin_set_client_grant(
+                GrantSubject::Client(CLIENT),
+                PolicyTarget::Group(GROUP),
+                &["uptime".to_string()],
+            )
+            .await?,
+        GrantSetOutcome::Granted
+    );
+
+    // The relay cannot see command ids, so the grant travels to the agent with the tunnel.
+    let mut client = TcpStream::connect(addr).await?;
+    let request = client_request(CLIENT, AGENT)?;
+    let client_task = tokio::spawn(async move {
+        let _ = perform_authenticated_handshake(
+            &mut client,
+            request,
+            CLIENT_KEY_ID,
+            CLIENT_PRIVATE_KEY_HEX,
+        )
+        .await;
+    });
+    let open_tunnel = timeout(
+        Duration::from_secs(2),
+        read_json_frame::<_, RelayControlMessage>(&mut agent),
+    )
+    .await??;
+    let RelayControlMessage::OpenTunnel {
+        allowed_commands, ..
+    } = open_tunnel
+    else {
+        panic!("expected tunnel request, got {:?}", open_tunnel);
+    };
+    assert_eq!(allowed_commands, Some(vec![CommandId::new("uptime")?]));
+
+    client_task.abort();
+    drop(agent);
+    server_task.abort();
+    let _ = server_task.await;
+    Ok(())
+}